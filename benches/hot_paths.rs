@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use hachip::cpu::Cpu;
+use hachip::ppu::{Display, Ppu};
+
+// A cpu past reset with V0-VF set, for opcodes to work on.
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::new(Box::new(Ppu::new()));
+    cpu.reset();
+    for x in 0..16 {
+        // LD Vx, x * 3
+        cpu.step_with_opcode(0x6000 | (x << 8) | (x * 3)).unwrap();
+    }
+    cpu
+}
+
+// One opcode of each kind games spend their time in. Each run starts on a
+// fresh cpu, as the opcodes move PC and change what they work on.
+fn process_opcode(c: &mut Criterion) {
+    let opcodes = [
+        ("cls", 0x00E0),
+        ("add_byte", 0x7112),
+        ("add_registers", 0x8124),
+        ("shift", 0x810E),
+        ("load_i", 0xA300),
+        ("random", 0xC1FF),
+        ("draw", 0xD12F),
+        ("store_registers", 0xF555),
+    ];
+    let mut group = c.benchmark_group("process_opcode");
+    for (name, opcode) in opcodes {
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(cpu, |cpu| cpu.step_with_opcode(black_box(opcode)), BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+fn ppu_draw(c: &mut Criterion) {
+    let sprite = [0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x3C, 0x42, 0x81, 0x81, 0x42, 0x3C, 0xFF, 0x00, 0xFF, 0x00];
+    let mut group = c.benchmark_group("Ppu::draw");
+    // on a byte boundary, across two bytes, and wrapping around the corner
+    for (name, x, y) in [("aligned", 8, 4), ("unaligned", 13, 4), ("wrapping", 60, 28)] {
+        let mut ppu = Ppu::new();
+        group.bench_function(name, |b| b.iter(|| ppu.draw(black_box(x), black_box(y), black_box(&sprite), false)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process_opcode, ppu_draw);
+criterion_main!(benches);
@@ -0,0 +1,272 @@
+// Turns the sources in assets/ into Rust arrays (see src/assets.rs):
+//   font.hex       overlay bitmap font
+//   palettes.hex   display palettes
+//   icons/*.png    8x8 menu icons, one bit per pixel
+//   hachip.png     the window icon, RGBA
+//   roms/*.ch8     bundled ROMs, embedded with include_bytes!
+//   romdb.txt      settings for known ROMs, by hash
+// and writes the C header for src/ffi.rs.
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let assets = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("assets");
+    println!("cargo:rerun-if-changed={}", assets.display());
+
+    let mut out = String::new();
+    generate_font(&assets.join("font.hex"), &mut out);
+    generate_palettes(&assets.join("palettes.hex"), &mut out);
+    generate_icons(&assets.join("icons"), &mut out);
+    generate_app_icon(&assets.join("hachip.png"), &mut out);
+    generate_roms(&assets.join("roms"), &mut out);
+    generate_rom_database(&assets.join("romdb.txt"), &mut out);
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("assets.rs"), out).unwrap();
+
+    let ffi = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src").join("ffi.rs");
+    fs::write(out_dir.join("hachip.h"), generate_c_header(&ffi)).unwrap();
+}
+
+// Lines without comments or blank space.
+fn source_lines(path: &Path) -> Vec<String> {
+    println!("cargo:rerun-if-changed={}", path.display());
+    fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("{}: {}", path.display(), error))
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+fn parse_hex(value: &str, path: &Path) -> u32 {
+    u32::from_str_radix(value, 16)
+        .unwrap_or_else(|_| panic!("{}: {} is not hex", path.display(), value))
+}
+
+// Sorted entries of a directory with the given extension.
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|error| panic!("{}: {}", dir.display(), error))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new(extension)))
+        .collect();
+    files.sort();
+    files
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().unwrap().to_str().unwrap().to_string()
+}
+
+fn generate_font(path: &Path, out: &mut String) {
+    let mut glyphs: Vec<(u32, Vec<u32>)> = source_lines(path)
+        .iter()
+        .map(|line| {
+            let (code, rows) = line.split_at(line.find(':').expect("font line needs ':'"));
+            let rows = rows[1..].split_whitespace().map(|row| parse_hex(row, path)).collect();
+            (parse_hex(code.trim(), path), rows)
+        })
+        .collect();
+    glyphs.sort_by_key(|(code, _)| *code);
+
+    let first = glyphs[0].0;
+    let height = glyphs[0].1.len();
+    for (index, (code, rows)) in glyphs.iter().enumerate() {
+        assert_eq!(*code, first + index as u32, "font codes must be contiguous");
+        assert_eq!(rows.len(), height, "glyph {:X} has the wrong height", code);
+    }
+
+    writeln!(out, "pub const FONT_FIRST_CHAR: u8 = 0x{:02X};", first).unwrap();
+    writeln!(out, "pub const FONT_HEIGHT: usize = {};", height).unwrap();
+    writeln!(out, "pub static FONT: [[u8; {}]; {}] = [", height, glyphs.len()).unwrap();
+    for (_, rows) in glyphs {
+        let rows: Vec<String> = rows.iter().map(|row| format!("0x{:02X}", row)).collect();
+        writeln!(out, "    [{}],", rows.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+fn generate_palettes(path: &Path, out: &mut String) {
+    writeln!(out, "pub static PALETTES: &[(&str, [u32; 4])] = &[").unwrap();
+    for line in source_lines(path) {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap();
+        let mut colors: Vec<u32> = fields.map(|color| parse_hex(color, path)).collect();
+        match colors.len() {
+            2 => {
+                let foreground = colors[1];
+                colors.push(foreground);
+                colors.push(foreground);
+            }
+            4 => {}
+            _ => panic!("{}: palette {} needs 2 or 4 colors", path.display(), name),
+        }
+        let colors: Vec<String> = colors.iter().map(|color| format!("0x{:06X}", color)).collect();
+        writeln!(out, "    ({:?}, [{}]),", name, colors.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+fn generate_icons(dir: &Path, out: &mut String) {
+    for path in files_with_extension(dir, "png") {
+        let mut decoder = png::Decoder::new(File::open(&path).unwrap());
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((info.width, info.height), (8, 8), "{} must be 8x8", path.display());
+
+        let channels = info.color_type.samples();
+        let rows: Vec<String> = buffer[..info.buffer_size()]
+            .chunks(info.line_size)
+            .map(|line| {
+                let mut row = 0u8;
+                for x in 0..8 {
+                    // lit when the first (gray or red) channel is bright
+                    if line[x * channels] >= 0x80 {
+                        row |= 0x80 >> x;
+                    }
+                }
+                format!("0b{:08b}", row)
+            })
+            .collect();
+        let name = file_stem(&path).to_uppercase();
+        writeln!(out, "pub static ICON_{}: [u8; 8] = [{}];", name, rows.join(", ")).unwrap();
+    }
+}
+
+fn generate_app_icon(path: &Path, out: &mut String) {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::normalize_to_color8() | png::Transformations::ALPHA);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{} must have color", path.display());
+    assert_eq!(info.width, info.height, "{} must be square", path.display());
+    writeln!(out, "pub const APP_ICON_SIZE: u32 = {};", info.width).unwrap();
+    writeln!(out, "pub static APP_ICON: [u8; {}] = {:?};", info.buffer_size(), &buffer[..info.buffer_size()]).unwrap();
+}
+
+fn generate_roms(dir: &Path, out: &mut String) {
+    writeln!(out, "pub static ROMS: &[(&str, &[u8])] = &[").unwrap();
+    for path in files_with_extension(dir, "ch8") {
+        writeln!(out, "    ({:?}, include_bytes!({:?})),", file_stem(&path), path.display().to_string()).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+fn generate_rom_database(path: &Path, out: &mut String) {
+    writeln!(out, "pub static ROM_DATABASE: &[(u64, &str, u32, u16, &str, &str)] = &[").unwrap();
+    for line in source_lines(path) {
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        assert_eq!(fields.len(), 6, "{}: {} needs 6 fields", path.display(), line);
+        let hash = u64::from_str_radix(fields[0], 16)
+            .unwrap_or_else(|_| panic!("{}: {} is not a hash", path.display(), fields[0]));
+        let speed: u32 = fields[2]
+            .parse()
+            .unwrap_or_else(|_| panic!("{}: {} is not an instruction count", path.display(), fields[2]));
+        let load_address = u16::from_str_radix(fields[3], 16)
+            .unwrap_or_else(|_| panic!("{}: {} is not a load address", path.display(), fields[3]));
+        writeln!(
+            out,
+            "    (0x{:016x}, {:?}, {}, 0x{:03X}, {:?}, {:?}),",
+            hash, fields[1], speed, load_address, fields[4], fields[5]
+        )
+        .unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+// C spelling of the Rust types used in src/ffi.rs.
+fn c_type(rust: &str, path: &Path) -> String {
+    if let Some(pointee) = rust.strip_prefix("*mut ") {
+        return format!("{} *", c_type(pointee, path));
+    }
+    if let Some(pointee) = rust.strip_prefix("*const ") {
+        return format!("const {} *", c_type(pointee, path));
+    }
+    match rust {
+        "Emulator" => "hachip",
+        "bool" => "bool",
+        "u8" => "uint8_t",
+        "i16" => "int16_t",
+        "u32" => "uint32_t",
+        "usize" => "size_t",
+        _ => panic!("{}: no C type for {}", path.display(), rust),
+    }
+    .to_string()
+}
+
+// `type name`, or `type *name` for pointers.
+fn c_declaration(rust: &str, name: &str, path: &Path) -> String {
+    let c = c_type(rust, path);
+    if c.ends_with('*') {
+        format!("{}{}", c, name)
+    } else {
+        format!("{} {}", c, name)
+    }
+}
+
+// The constants and `extern "C"` functions of src/ffi.rs, with the plain
+// comments above them; `///` doc comments only concern Rust callers.
+fn generate_c_header(path: &Path) -> String {
+    println!("cargo:rerun-if-changed={}", path.display());
+    let source = fs::read_to_string(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    let mut out = String::new();
+    writeln!(out, "// hachip C API, generated from src/ffi.rs; print it with `hachip c-header`.").unwrap();
+    writeln!(out, "#ifndef HACHIP_H\n#define HACHIP_H\n").unwrap();
+    writeln!(out, "#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n").unwrap();
+    writeln!(out, "#ifdef __cplusplus\nextern \"C\" {{\n#endif\n").unwrap();
+
+    let mut comment = Vec::new();
+    let mut typedef = false;
+    for line in source.lines().take_while(|line| !line.starts_with("#[cfg(test)]")) {
+        if let Some(text) = line.strip_prefix("//").filter(|text| !text.starts_with('/')) {
+            comment.push(text.to_string());
+            continue;
+        }
+        if let Some(constant) = line.strip_prefix("pub const HACHIP_") {
+            let (name, value) = constant.split_once(':').unwrap();
+            let value = value.split_once('=').unwrap().1.trim().trim_end_matches(';');
+            writeln!(out, "#define HACHIP_{} {}", name, value).unwrap();
+        } else if let Some(function) = line.split_once("extern \"C\" fn ").map(|(_, rest)| rest) {
+            if !typedef {
+                writeln!(out, "\ntypedef struct hachip hachip;").unwrap();
+                typedef = true;
+            }
+            let (name, rest) = function.split_once('(').unwrap();
+            let (parameters, rest) = rest.split_once(')').unwrap();
+            let returned = rest.trim().trim_end_matches('{').trim();
+            let returned = match returned.strip_prefix("-> ") {
+                Some(rust) => c_declaration(rust, name, path),
+                None => format!("void {}", name),
+            };
+            let parameters: Vec<String> = parameters
+                .split(", ")
+                .filter(|parameter| !parameter.is_empty())
+                .map(|parameter| {
+                    let (name, rust) = parameter.split_once(": ").unwrap();
+                    c_declaration(rust, name, path)
+                })
+                .collect();
+            let parameters = if parameters.is_empty() { "void".to_string() } else { parameters.join(", ") };
+            writeln!(out).unwrap();
+            for text in &comment {
+                writeln!(out, "//{}", text).unwrap();
+            }
+            writeln!(out, "{}({});", returned, parameters).unwrap();
+        }
+        if !line.starts_with("///") && !line.starts_with("#[") {
+            comment.clear();
+        }
+    }
+
+    writeln!(out, "\n#ifdef __cplusplus\n}}\n#endif\n\n#endif").unwrap();
+    out
+}
@@ -0,0 +1,54 @@
+#![no_main]
+use hachip::cpu::Cpu;
+use hachip::ppu::Display;
+use hachip::quirks::Platform;
+use libfuzzer_sys::fuzz_target;
+
+// Draws nothing, so only the cpu is under test.
+struct NullDisplay;
+
+impl Display for NullDisplay {
+    fn cls(&mut self) {}
+
+    fn draw(&mut self, _x: usize, _y: usize, _sprite: &[u8], _clip: bool) -> bool {
+        false
+    }
+
+    fn set_pixel(&mut self, _x: usize, _y: usize, _val: u8) {}
+
+    fn get_pixel(&mut self, _x: usize, _y: usize) -> bool {
+        false
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        &[]
+    }
+
+    fn dirty_rows(&self) -> &[bool] {
+        &[]
+    }
+
+    fn clear_dirty(&mut self) {}
+
+    fn invalidate(&mut self) {}
+}
+
+// The first byte picks the platform (bits 0-1, an index into
+// `Platform::ALL`), strict mode (bit 2) and whether timers tick between
+// instructions (bit 3); the rest is a stream of big-endian opcodes. Errors are expected, panics are bugs.
+fuzz_target!(|data: &[u8]| {
+    let (settings, opcodes) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut cpu = Cpu::new(Box::new(NullDisplay));
+    cpu.quirks = Platform::ALL[(settings & 3) as usize].quirks();
+    cpu.strict = settings & 4 != 0;
+    cpu.reset();
+    for opcode in opcodes.chunks_exact(2) {
+        let _ = cpu.step_with_opcode(u16::from_be_bytes([opcode[0], opcode[1]]));
+        if settings & 8 != 0 {
+            cpu.vblank();
+        }
+    }
+});
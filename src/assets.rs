@@ -0,0 +1,53 @@
+// Assets embedded at build time from the assets/ directory; build.rs turns
+// the hex and PNG sources into the arrays included here.
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+// Glyph for an ASCII character. Lowercase letters use the uppercase glyphs
+// and anything outside the font falls back to '?'.
+pub fn glyph(c: char) -> &'static [u8; FONT_HEIGHT] {
+    let code = c.to_ascii_uppercase() as u32;
+    let index = code.wrapping_sub(FONT_FIRST_CHAR as u32) as usize;
+    match FONT.get(index) {
+        Some(glyph) => glyph,
+        None => &FONT[(b'?' - FONT_FIRST_CHAR) as usize],
+    }
+}
+
+pub fn palette(name: &str) -> Option<[u32; 4]> {
+    PALETTES
+        .iter()
+        .find(|(palette, _)| palette.eq_ignore_ascii_case(name))
+        .map(|(_, colors)| *colors)
+}
+
+pub fn rom(name: &str) -> Option<&'static [u8]> {
+    ROMS.iter().find(|(rom, _)| *rom == name).map(|(_, data)| *data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glyph, palette, rom, APP_ICON, APP_ICON_SIZE, ICON_PLAY};
+
+    #[test]
+    fn glyphs_cover_printable_ascii() {
+        assert_eq!(glyph('A'), glyph('a'));
+        assert_eq!(glyph('0'), &[0xE0, 0xA0, 0xA0, 0xA0, 0xE0]);
+        assert_eq!(glyph(' '), &[0; 5]);
+        assert_eq!(glyph('~'), glyph('?'), "unknown characters fall back to '?'");
+    }
+
+    #[test]
+    fn palettes_have_four_colors() {
+        assert_eq!(palette("classic"), Some([0x000000, 0xFFFFFF, 0xFFFFFF, 0xFFFFFF]));
+        assert_eq!(palette("OCTO").map(|colors| colors[2]), Some(0xFF6600));
+        assert_eq!(palette("missing"), None);
+    }
+
+    #[test]
+    fn icons_and_roms_are_embedded() {
+        assert_eq!(ICON_PLAY[2], 0b01100000);
+        assert_eq!(APP_ICON.len(), (APP_ICON_SIZE * APP_ICON_SIZE * 4) as usize);
+        assert_eq!(&rom("keypad").unwrap()[..2], &[0x00, 0xE0]);
+        assert!(rom("missing").is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::cpu::Cpu;
+use crate::session;
+use crate::state::SaveState;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+// Autosaves kept per ROM; the oldest is overwritten.
+pub const SLOTS: usize = 3;
+
+// Writes a save state every `interval` into a rotating set of files, so a
+// crash or an accidental quit loses at most one interval of progress.
+pub struct Autosave {
+    dir: PathBuf,
+    interval: Duration,
+    last: Instant,
+    next_slot: usize,
+}
+
+impl Autosave {
+    pub fn new(dir: PathBuf, interval: Duration) -> Autosave {
+        // continue after the newest existing file rather than overwriting it
+        let next_slot = latest_slot(&dir).map_or(0, |slot| (slot + 1) % SLOTS);
+        Autosave {
+            dir,
+            interval,
+            last: Instant::now(),
+            next_slot,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    pub fn write(&mut self, state: &SaveState) -> io::Result<PathBuf> {
+        self.last = Instant::now();
+        fs::create_dir_all(&self.dir)?;
+        let path = slot_path(&self.dir, self.next_slot);
+        session::write_atomically(&path, &state.to_bytes())?;
+        self.next_slot = (self.next_slot + 1) % SLOTS;
+        Ok(path)
+    }
+
+    // Saves the cpu when the interval has passed. Failing to save is logged
+    // and otherwise ignored.
+    pub fn tick(&mut self, cpu: &Cpu) {
+        if !self.due() {
+            return;
+        }
+        match self.write(&cpu.save_state()) {
+            Ok(path) => log::debug!("autosaved to {}", path.display()),
+            Err(error) => log::warn!("autosave to {} failed: {}", self.dir.display(), error),
+        }
+    }
+
+    // Loads the latest autosave into the cpu. Returns false, after logging
+    // why, if there is none or it can't be read.
+    pub fn restore_latest(&self, cpu: &mut Cpu) -> bool {
+        let path = match self.latest() {
+            Some(path) => path,
+            None => {
+                log::info!("no autosave in {}", self.dir.display());
+                return false;
+            }
+        };
+        let state = fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|bytes| SaveState::from_bytes(&bytes).map_err(|error| error.message));
+        match state {
+            Ok(state) => {
+                cpu.load_state(&state);
+                log::info!("loaded autosave {}", path.display());
+                true
+            }
+            Err(error) => {
+                log::warn!("autosave {}: {}", path.display(), error);
+                false
+            }
+        }
+    }
+
+    // The most recently written autosave, if any.
+    pub fn latest(&self) -> Option<PathBuf> {
+        latest_slot(&self.dir).map(|slot| slot_path(&self.dir, slot))
+    }
+}
+
+// Autosave directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("autosave", rom_path, rom)
+}
+
+fn slot_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("autosave-{}.state", slot))
+}
+
+fn latest_slot(dir: &Path) -> Option<usize> {
+    (0..SLOTS)
+        .filter_map(|slot| {
+            let modified = fs::metadata(slot_path(dir, slot)).and_then(|meta| meta.modified()).ok()?;
+            Some((modified, slot))
+        })
+        .max_by_key(|(modified, _): &(SystemTime, usize)| *modified)
+        .map(|(_, slot)| slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Autosave, SLOTS};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn rotates_through_slots_and_finds_the_latest() {
+        let dir = env::temp_dir().join(format!("hachip-autosave-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+
+        let mut autosave = Autosave::new(dir.clone(), Duration::from_secs(0));
+        assert!(autosave.due());
+        assert_eq!(autosave.latest(), None);
+
+        let mut written = Vec::new();
+        for _ in 0..SLOTS + 1 {
+            written.push(autosave.write(&cpu.save_state()).unwrap());
+            // mtime resolution can be coarse
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(written[0], written[SLOTS], "wraps around to the first slot");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), SLOTS);
+        assert_eq!(autosave.latest(), Some(written[SLOTS].clone()));
+
+        let mut restored = Cpu::new(Box::new(HeadlessDisplay::new()));
+        assert!(autosave.restore_latest(&mut restored));
+        assert_eq!(restored.save_state(), cpu.save_state());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
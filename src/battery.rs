@@ -0,0 +1,109 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::session;
+use crate::trace;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const FILE: &str = "battery.bin";
+
+// `--battery 0xE00..0x1000`: a stretch of memory kept on disk per ROM, like
+// the battery-backed RAM of a cartridge, so homebrew games can save there.
+// It is loaded when the ROM starts and written when the window closes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Battery {
+    dir: PathBuf,
+    range: Range<u16>,
+}
+
+impl Battery {
+    pub fn new(dir: &Path, range: Range<u16>) -> Battery {
+        Battery {
+            dir: dir.to_path_buf(),
+            range,
+        }
+    }
+
+    // Puts the saved memory into the cpu, if there is any. A file of another
+    // size (the range changed) fills what both have in common.
+    pub fn restore(&self, cpu: &mut Cpu) {
+        let path = self.dir.join(FILE);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let memory = &mut cpu.memory_mut()[self.range.start as usize..self.range.end as usize];
+                if bytes.len() != memory.len() {
+                    log::warn!("{} has {} bytes for a range of {}", path.display(), bytes.len(), memory.len());
+                }
+                let count = bytes.len().min(memory.len());
+                memory[..count].copy_from_slice(&bytes[..count]);
+                log::info!("loaded battery memory from {}", path.display());
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => log::warn!("battery memory {}: {}", path.display(), error),
+        }
+    }
+
+    pub fn save(&self, cpu: &Cpu) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(FILE);
+        session::write_atomically(&path, &cpu.memory()[self.range.start as usize..self.range.end as usize])
+    }
+
+    // Saves the memory; call once the frontend closes. Failing to save is
+    // logged and otherwise ignored.
+    pub fn finish(&self, cpu: &Cpu) {
+        match self.save(cpu) {
+            Ok(()) => log::info!("saved battery memory to {}", self.dir.display()),
+            Err(error) => log::warn!("saving battery memory to {} failed: {}", self.dir.display(), error),
+        }
+    }
+}
+
+// "0xE00..0x1000", end exclusive; None for ranges outside memory.
+pub fn parse_range(text: &str) -> Option<Range<u16>> {
+    trace::parse_range(text).filter(|range| range.end as usize <= MEMORY_SIZE)
+}
+
+// Battery memory directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("battery", rom_path, rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, Battery};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn keeps_the_range_across_runs() {
+        assert_eq!(parse_range("0xE00..0x1000"), Some(0xE00..0x1000));
+        assert_eq!(parse_range("0xE00..0x1001"), None);
+
+        let dir = env::temp_dir().join(format!("hachip-battery-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let battery = Battery::new(&dir, 0xE00..0x1000);
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        battery.restore(&mut cpu);
+        assert_eq!(cpu.memory()[0xE00], 0, "nothing saved yet");
+        cpu.memory_mut()[0xE00] = 7;
+        cpu.memory_mut()[0xFFF] = 9;
+        cpu.memory_mut()[0xDFF] = 1;
+        battery.finish(&cpu);
+
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        battery.restore(&mut cpu);
+        assert_eq!(cpu.memory()[0xE00], 7);
+        assert_eq!(cpu.memory()[0xFFF], 9);
+        assert_eq!(cpu.memory()[0xDFF], 0, "outside the range");
+
+        // a smaller range takes what fits
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        Battery::new(&dir, 0xE00..0xE01).restore(&mut cpu);
+        assert_eq!(cpu.memory()[0xE00..0xE02], [7, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
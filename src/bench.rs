@@ -0,0 +1,117 @@
+use crate::cpu::{Cpu, PROGRAM_START};
+use crate::headless::HeadlessDisplay;
+use crate::quirks::Quirks;
+use crate::scheduler::{self, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// `hachip bench`: a ROM run headless for `frames` frames without waiting
+// for the clock, timing the cpu and display alone. The numbers are only
+// comparable on the same machine, between builds, to catch the hot paths
+// getting slower.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Bench {
+    pub rom: Vec<u8>,
+    pub frames: u64,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub load_address: u16,
+}
+
+// What a run did and how long it took. `stopped` is why the cpu stopped
+// before the last frame, if it did; the counts are up to there.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Throughput {
+    pub frames: u64,
+    pub instructions: u64,
+    pub elapsed: Duration,
+    pub stopped: Option<String>,
+}
+
+impl Bench {
+    pub fn new(rom: Vec<u8>) -> Bench {
+        Bench {
+            rom,
+            frames: 100_000,
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            load_address: PROGRAM_START as u16,
+        }
+    }
+
+    pub fn run(&self) -> Throughput {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = self.quirks;
+        cpu.load_address = self.load_address;
+        // the same random numbers every run, for the same work
+        cpu.seed_rng(1);
+        cpu.reset();
+        let mut throughput = Throughput::default();
+        if let Err(error) = cpu.load(self.rom.clone()) {
+            throughput.stopped = Some(error.to_string());
+            return throughput;
+        }
+        let start = Instant::now();
+        while throughput.frames < self.frames {
+            match scheduler::run_frame(&mut cpu, self.instructions_per_frame) {
+                Ok(executed) => throughput.instructions += executed as u64,
+                Err(error) => {
+                    throughput.stopped = Some(format!("stopped in frame {}: {}", throughput.frames, error));
+                    break;
+                }
+            }
+            throughput.frames += 1;
+        }
+        throughput.elapsed = start.elapsed();
+        throughput
+    }
+}
+
+impl Throughput {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} frames, {} instructions in {:.3}s", self.frames, self.instructions, self.elapsed.as_secs_f64())?;
+        writeln!(f, "{:.0} instructions/s", self.instructions_per_second())?;
+        writeln!(f, "{:.0} frames/s", self.frames_per_second())?;
+        if let Some(stopped) = &self.stopped {
+            writeln!(f, "{}", stopped)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bench;
+
+    #[test]
+    fn counts_frames_and_instructions() {
+        // ADD V0, 1 / JP 0x200
+        let mut bench = Bench::new(vec![0x70, 0x01, 0x12, 0x00]);
+        bench.frames = 100;
+        bench.instructions_per_frame = 10;
+        let throughput = bench.run();
+        assert_eq!(throughput.frames, 100);
+        assert_eq!(throughput.instructions, 1000);
+        assert_eq!(throughput.stopped, None);
+        assert!(throughput.instructions_per_second() > 0.0);
+        assert!(throughput.to_string().starts_with("100 frames, 1000 instructions in "));
+
+        // 8128 isn't an instruction
+        let mut bench = Bench::new(vec![0x70, 0x01, 0x81, 0x28]);
+        bench.frames = 5;
+        let throughput = bench.run();
+        assert_eq!((throughput.frames, throughput.instructions), (0, 0));
+        assert!(throughput.stopped.unwrap().starts_with("stopped in frame 0: "));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::palette::Palette;
+use crate::profile::EmulationProfile;
+use crate::screenshot;
+use crate::session;
+use crate::state::SaveState;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Longest bookmark name, as much as fits on a line of the pause menu.
+pub const MAX_NAME: usize = 15;
+
+// Named save states of one ROM ("boss", "level 3"), each kept as
+// <name>.state with a <name>.png screenshot next to it, listed in the pause
+// menu for jumping back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmarks {
+    dir: PathBuf,
+}
+
+impl Bookmarks {
+    pub fn new(dir: PathBuf) -> Bookmarks {
+        Bookmarks { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Bookmark names, sorted. A missing directory has none.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "state"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
+    // Saves the state and its screen under `name`, replacing a bookmark of
+    // the same name.
+    pub fn save(&self, name: &str, state: &SaveState, palette: &Palette) -> io::Result<PathBuf> {
+        if !is_valid_name(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid bookmark name \"{}\"", name)));
+        }
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.state", name));
+        fs::write(&path, state.to_bytes())?;
+        let profile = state.profile().unwrap_or_else(|| EmulationProfile::new(Default::default()));
+        let png = screenshot::encode_png(&state.framebuffer, palette, screenshot::SCALE, &profile);
+        fs::write(path.with_extension("png"), png)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, name: &str) -> io::Result<SaveState> {
+        let bytes = fs::read(self.dir.join(format!("{}.state", name)))?;
+        SaveState::from_bytes(&bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.message))
+    }
+}
+
+// Bookmark directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("bookmarks", rom_path, rom)
+}
+
+// Names become file names, so they are kept to letters, digits, spaces,
+// '-' and '_'.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && name.len() <= MAX_NAME && name.chars().all(is_name_char)
+}
+
+pub fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bookmarks;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::palette::Palette;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn saves_lists_and_loads_named_states() {
+        let dir = env::temp_dir().join(format!("hachip-bookmarks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let bookmarks = Bookmarks::new(dir.clone());
+        assert!(bookmarks.names().is_empty());
+
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(vec![0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let start = cpu.save_state();
+        cpu.step().unwrap();
+        bookmarks.save("level 3", &cpu.save_state(), &Palette::default()).unwrap();
+        bookmarks.save("boss", &start, &Palette::default()).unwrap();
+        assert!(bookmarks.save("../escape", &start, &Palette::default()).is_err());
+        assert!(dir.join("boss.png").is_file(), "a screenshot goes with every bookmark");
+        assert_eq!(bookmarks.names(), vec!["boss", "level 3"]);
+
+        let mut restored = Cpu::new(Box::new(HeadlessDisplay::new()));
+        restored.load_state(&bookmarks.load("level 3").unwrap());
+        assert_eq!(restored.registers()[0], 0x2A);
+        assert!(bookmarks.load("missing").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
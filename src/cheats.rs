@@ -0,0 +1,158 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::errors::CheatError;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatKind {
+    // written before every frame
+    Frozen,
+    // written before the first frame only
+    Once,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub kind: CheatKind,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X}={:02X}", self.address, self.value)?;
+        if self.kind == CheatKind::Once {
+            write!(f, " once")?;
+        }
+        Ok(())
+    }
+}
+
+// `--cheats <file.cht>`: memory patches, one per line in hex ('#' starts a
+// comment):
+//
+//   3A0=03        keeps 03 at 3A0, written before every frame
+//   3A1=09 once   writes 09 to 3A1 before the first frame only
+//
+// The debugger's `freeze` adds frozen ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn parse(text: &str) -> Result<Cheats, CheatError> {
+        let mut cheats = Cheats::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| CheatError {
+                line: index + 1,
+                message,
+            };
+            let (patch, kind) = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                [patch] => (*patch, CheatKind::Frozen),
+                [patch, "once"] => (*patch, CheatKind::Once),
+                _ => return Err(error(format!("expected <address>=<value> [once], got `{}`", line))),
+            };
+            let (address, value) = patch.split_once('=').ok_or_else(|| error(format!("{} has no =", patch)))?;
+            let address = parse_address(address).ok_or_else(|| error(format!("bad address {}", address)))?;
+            let value = parse_value(value).ok_or_else(|| error(format!("bad value {}", value)))?;
+            cheats.cheats.push(Cheat { address, value, kind });
+        }
+        Ok(cheats)
+    }
+
+    pub fn load(path: &Path) -> Result<Cheats, CheatError> {
+        let text = fs::read_to_string(path).map_err(|error| CheatError {
+            line: 0,
+            message: format!("{}: {}", path.display(), error),
+        })?;
+        Cheats::parse(&text)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Keeps `value` at `address` from now on, instead of whatever was
+    // patched there.
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.unfreeze(address);
+        self.cheats.push(Cheat {
+            address,
+            value,
+            kind: CheatKind::Frozen,
+        });
+    }
+
+    // Drops the patches of `address`, returning whether there were any.
+    pub fn unfreeze(&mut self, address: u16) -> bool {
+        let count = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.address != address);
+        self.cheats.len() != count
+    }
+
+    // Writes the patches; call before every frame. One-shot patches are
+    // dropped once written.
+    pub fn apply(&mut self, cpu: &mut Cpu) {
+        if self.cheats.is_empty() {
+            return;
+        }
+        let memory = cpu.memory_mut();
+        for cheat in &self.cheats {
+            memory[cheat.address as usize] = cheat.value;
+        }
+        self.cheats.retain(|cheat| cheat.kind == CheatKind::Frozen);
+    }
+}
+
+// Hex, with or without 0x.
+fn parse_address(text: &str) -> Option<u16> {
+    let address = u16::from_str_radix(text.trim_start_matches("0x"), 16).ok()?;
+    Some(address).filter(|address| (*address as usize) < MEMORY_SIZE)
+}
+
+fn parse_value(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheatKind, Cheats};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn keeps_frozen_values_and_writes_one_shots_once() {
+        assert_eq!(Cheats::parse("3A0=03\nFFFF=01").unwrap_err().to_string(), "line 2: bad address FFFF");
+        assert!(Cheats::parse("3A0=100").is_err());
+        assert!(Cheats::parse("3A0=03 twice").is_err());
+
+        let mut cheats = Cheats::parse("# lives\n3A0=03\n0x3A1=0x09 once  # level\n").unwrap();
+        assert_eq!(cheats.list()[1].kind, CheatKind::Once);
+        assert_eq!(cheats.list()[1].to_string(), "3A1=09 once");
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cheats.apply(&mut cpu);
+        assert_eq!(cpu.memory()[0x3A0..0x3A2], [3, 9]);
+        cpu.memory_mut()[0x3A0] = 0;
+        cpu.memory_mut()[0x3A1] = 0;
+        cheats.apply(&mut cpu);
+        assert_eq!(cpu.memory()[0x3A0..0x3A2], [3, 0], "one-shots are written once");
+
+        cheats.freeze(0x3A0, 5);
+        cheats.freeze(0x300, 1);
+        assert_eq!(cheats.list().len(), 2, "freezing replaces a patch");
+        assert!(cheats.unfreeze(0x300));
+        assert!(!cheats.unfreeze(0x300));
+        cheats.apply(&mut cpu);
+        assert_eq!(cpu.memory()[0x3A0], 5);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::cpu::Cpu;
+use crate::frontend;
+use crate::movie::MovieMode;
+use crate::osd::Osd;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::scheduler::{Scheduler, SpeedMeter, DEFAULT_INSTRUCTIONS_PER_FRAME, MAX_TIME_SCALE, MIN_TIME_SCALE};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+// A change to a running emulator. Hotkeys, menus and remote control all
+// queue these instead of touching the cpu, and the frontend applies them
+// between frames, so no setting ever changes halfway through one.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Command {
+    // instructions per frame
+    Speed(u32),
+    // emulated time per real time in percent, see Scheduler::time_scale
+    TimeScale(u32),
+    FastForward(bool),
+    Palette(Palette),
+    Quirks(Quirks),
+    Strict(bool),
+    TogglePause,
+    // pause, or while paused run exactly one more frame
+    AdvanceFrame,
+    ToggleCrt,
+    // restart the loaded ROM; refused while a movie runs
+    Reset,
+    // the FPS and emulation speed box in the corner
+    ToggleSpeedMeter,
+}
+
+// Commands waiting for the next frame boundary. Clones share the queue, so
+// one can be handed to another thread.
+#[derive(Clone, Default)]
+pub struct CommandQueue {
+    pending: Arc<Mutex<Vec<Command>>>,
+}
+
+impl CommandQueue {
+    pub fn new() -> CommandQueue {
+        CommandQueue::default()
+    }
+
+    pub fn push(&self, command: Command) {
+        self.pending.lock().unwrap().push(command);
+    }
+
+    // The queued commands, oldest first, leaving the queue empty.
+    pub fn take(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+impl PartialEq for CommandQueue {
+    fn eq(&self, other: &CommandQueue) -> bool {
+        Arc::ptr_eq(&self.pending, &other.pending)
+    }
+}
+
+impl fmt::Debug for CommandQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CommandQueue {{ pending: {} }}", self.pending.lock().unwrap().len())
+    }
+}
+
+// The frontend settings commands change, next to the cpu and scheduler.
+pub struct RunState {
+    pub palette: Palette,
+    pub paused: bool,
+    // frames to run while paused, queued by AdvanceFrame
+    pub advance: u32,
+    pub crt_on: bool,
+    // program memory as loaded, for resets
+    pub program: Vec<u8>,
+    // what the last hotkey did
+    pub osd: Osd,
+    // counted always, shown in the OSD's corner when `show_speed`
+    pub speed: SpeedMeter,
+    pub show_speed: bool,
+}
+
+impl RunState {
+    // How many of the `due` frames to run. Time passes while paused, but
+    // isn't caught up on afterwards; only advanced frames run.
+    pub fn frames_to_run(&mut self, due: u32) -> u32 {
+        if self.paused {
+            std::mem::take(&mut self.advance)
+        } else {
+            due
+        }
+    }
+
+    // Call once per tick; puts a new measurement up when there is one.
+    pub fn update_speed(&mut self) {
+        if let Some(speed) = self.speed.measure() {
+            if self.show_speed {
+                self.osd.set_corner(Some(&speed.to_string()));
+            }
+        }
+    }
+}
+
+// Applies everything queued. Call only between frames.
+pub fn apply_pending(
+    queue: &CommandQueue,
+    cpu: &mut Cpu,
+    scheduler: &mut Scheduler,
+    state: &mut RunState,
+    movie: Option<&MovieMode>,
+) {
+    for command in queue.take() {
+        apply(command, cpu, scheduler, state, movie);
+    }
+}
+
+pub fn apply(command: Command, cpu: &mut Cpu, scheduler: &mut Scheduler, state: &mut RunState, movie: Option<&MovieMode>) {
+    log::debug!("applying {:?}", command);
+    match command {
+        Command::Speed(instructions) => {
+            scheduler.instructions_per_frame = instructions;
+            let percent = instructions * 100 / DEFAULT_INSTRUCTIONS_PER_FRAME;
+            state.osd.show(&format!("SPEED {}%", percent));
+        }
+        Command::TimeScale(percent) => {
+            scheduler.time_scale = percent.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+            state.osd.show(&format!("TIME {}%", scheduler.time_scale));
+        }
+        Command::FastForward(on) => {
+            scheduler.fast_forward = on;
+            if on {
+                state.osd.show("FAST FORWARD");
+            }
+        }
+        Command::Palette(palette) => {
+            state.palette = palette;
+            cpu.display_mut().invalidate();
+        }
+        Command::Quirks(quirks) => cpu.quirks = quirks,
+        Command::Strict(strict) => cpu.strict = strict,
+        Command::TogglePause => {
+            state.paused = !state.paused;
+            state.advance = 0;
+            state.osd.show(if state.paused { "PAUSED" } else { "RESUMED" });
+        }
+        Command::AdvanceFrame if state.paused => state.advance += 1,
+        Command::AdvanceFrame => {
+            state.paused = true;
+            state.osd.show("PAUSED");
+        }
+        Command::ToggleCrt => {
+            state.crt_on = !state.crt_on;
+            cpu.display_mut().invalidate();
+            state.osd.show(if state.crt_on { "CRT ON" } else { "CRT OFF" });
+        }
+        Command::Reset => {
+            frontend::soft_reset(cpu, &state.program, movie);
+            state.osd.show(if movie.is_some() { "NO RESET DURING MOVIES" } else { "RESET" });
+        }
+        // the numbers come up with the next measurement
+        Command::ToggleSpeedMeter => {
+            state.show_speed = !state.show_speed;
+            if !state.show_speed {
+                state.osd.set_corner(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_pending, Command, CommandQueue, RunState};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::osd::Osd;
+    use crate::palette::Palette;
+    use crate::quirks::Platform;
+    use crate::scheduler::{Scheduler, SpeedMeter};
+    use std::thread;
+
+    #[test]
+    fn queued_commands_wait_for_the_frame_boundary() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        let mut scheduler = Scheduler::new(10);
+        let mut state = RunState {
+            palette: Palette::default(),
+            paused: false,
+            advance: 0,
+            crt_on: false,
+            program: Vec::new(),
+            osd: Osd::new(),
+            speed: SpeedMeter::new(),
+            show_speed: false,
+        };
+
+        let queue = CommandQueue::new();
+        let remote = queue.clone();
+        thread::spawn(move || {
+            remote.push(Command::Speed(20));
+            remote.push(Command::Quirks(Platform::Schip.quirks()));
+            remote.push(Command::TogglePause);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(scheduler.instructions_per_frame, 10, "nothing changes until applied");
+
+        apply_pending(&queue, &mut cpu, &mut scheduler, &mut state, None);
+        assert_eq!(scheduler.instructions_per_frame, 20);
+        assert_eq!(cpu.quirks, Platform::Schip.quirks());
+        assert!(state.paused);
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn advancing_runs_single_frames_while_paused() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        let mut scheduler = Scheduler::new(10);
+        let mut state = RunState {
+            palette: Palette::default(),
+            paused: false,
+            advance: 0,
+            crt_on: false,
+            program: Vec::new(),
+            osd: Osd::new(),
+            speed: SpeedMeter::new(),
+            show_speed: false,
+        };
+        assert_eq!(state.frames_to_run(2), 2);
+
+        let queue = CommandQueue::new();
+        queue.push(Command::AdvanceFrame);
+        apply_pending(&queue, &mut cpu, &mut scheduler, &mut state, None);
+        assert!(state.paused, "the first press pauses");
+        assert_eq!(state.frames_to_run(2), 0);
+
+        queue.push(Command::AdvanceFrame);
+        queue.push(Command::AdvanceFrame);
+        apply_pending(&queue, &mut cpu, &mut scheduler, &mut state, None);
+        assert_eq!(state.frames_to_run(0), 2, "one frame per press, however much time passed");
+        assert_eq!(state.frames_to_run(5), 0);
+        assert!(state.paused);
+    }
+}
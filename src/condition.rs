@@ -0,0 +1,232 @@
+use crate::cpu::Cpu;
+use std::fmt;
+
+// A breakpoint condition over the cpu state, like
+// "V3 == 0x1F && I > 0x300": comparisons (==, !=, <, <=, >, >=) between
+// registers (V0-VF, I, PC, SP, DT, ST) and numbers (decimal, or hex with
+// 0x), joined with &&, || and ! and grouped with parentheses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    source: String,
+    expression: Expression,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Compare(Operand, Comparison, Operand),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Number(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Condition> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let expression = parser.or()?;
+        if parser.next != parser.tokens.len() {
+            return None;
+        }
+        Some(Condition {
+            source: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            expression,
+        })
+    }
+
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        self.expression.holds(cpu)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Expression {
+    fn holds(&self, cpu: &Cpu) -> bool {
+        match self {
+            Expression::Compare(left, comparison, right) => {
+                let (left, right) = (left.value(cpu), right.value(cpu));
+                match comparison {
+                    Comparison::Equal => left == right,
+                    Comparison::NotEqual => left != right,
+                    Comparison::Less => left < right,
+                    Comparison::LessOrEqual => left <= right,
+                    Comparison::Greater => left > right,
+                    Comparison::GreaterOrEqual => left >= right,
+                }
+            }
+            Expression::And(left, right) => left.holds(cpu) && right.holds(cpu),
+            Expression::Or(left, right) => left.holds(cpu) || right.holds(cpu),
+            Expression::Not(expression) => !expression.holds(cpu),
+        }
+    }
+}
+
+impl Operand {
+    fn parse(word: &str) -> Option<Operand> {
+        let upper = word.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "PC" => Operand::Pc,
+            "SP" => Operand::Sp,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            _ => match upper.strip_prefix('V') {
+                Some(register) if register.len() == 1 => Operand::V(usize::from_str_radix(register, 16).ok()?),
+                _ => match upper.strip_prefix("0X") {
+                    Some(hex) => Operand::Number(u16::from_str_radix(hex, 16).ok()?),
+                    None => Operand::Number(upper.parse().ok()?),
+                },
+            },
+        };
+        Some(operand)
+    }
+
+    fn value(self, cpu: &Cpu) -> u16 {
+        match self {
+            Operand::V(register) => cpu.registers()[register] as u16,
+            Operand::I => cpu.i() as u16,
+            Operand::Pc => cpu.pc(),
+            Operand::Sp => cpu.sp() as u16,
+            Operand::Dt => cpu.dt() as u16,
+            Operand::St => cpu.st() as u16,
+            Operand::Number(value) => value,
+        }
+    }
+}
+
+const OPERATORS: [&str; 11] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")"];
+
+// Words (registers and numbers) and operators; None on anything else.
+fn tokenize(text: &str) -> Option<Vec<&str>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let length = match OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+            Some(operator) => operator.len(),
+            None => rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len()),
+        };
+        if length == 0 {
+            return None;
+        }
+        tokens.push(&rest[..length]);
+        rest = rest[length..].trim_start();
+    }
+    Some(tokens)
+}
+
+// Recursive descent, from the loosest binding operator: || then && then !.
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn take(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.next).copied();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Option<Expression> {
+        let mut expression = self.and()?;
+        while self.peek() == Some("||") {
+            self.next += 1;
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Some(expression)
+    }
+
+    fn and(&mut self) -> Option<Expression> {
+        let mut expression = self.unary()?;
+        while self.peek() == Some("&&") {
+            self.next += 1;
+            expression = Expression::And(Box::new(expression), Box::new(self.unary()?));
+        }
+        Some(expression)
+    }
+
+    fn unary(&mut self) -> Option<Expression> {
+        match self.peek()? {
+            "!" => {
+                self.next += 1;
+                Some(Expression::Not(Box::new(self.unary()?)))
+            }
+            "(" => {
+                self.next += 1;
+                let expression = self.or()?;
+                (self.take()? == ")").then_some(expression)
+            }
+            _ => {
+                let left = Operand::parse(self.take()?)?;
+                let comparison = match self.take()? {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    _ => return None,
+                };
+                let right = Operand::parse(self.take()?)?;
+                Some(Expression::Compare(left, comparison, right))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn parses_and_evaluates_against_the_cpu() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V3, 0x1F / LD I, 0x301
+        cpu.load(vec![0x63, 0x1F, 0xA3, 0x01]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let holds = |text: &str| Condition::parse(text).unwrap().holds(&cpu);
+        assert!(holds("V3 == 0x1F && I > 0x300"));
+        assert!(holds("v3 == 31"));
+        assert!(!holds("V3 != 0x1F || PC < 0x204"));
+        assert!(holds("!(V0 > 0) && (SP == 0 || DT >= 1)"));
+        assert_eq!(Condition::parse("V3  ==0x1F").unwrap().to_string(), "V3 ==0x1F");
+
+        for bad in ["", "V3", "V3 == ", "VG == 1", "V3 = 1", "(V3 == 1", "V3 == 1 V4", "V3 == 1 & I > 2"].iter() {
+            assert_eq!(Condition::parse(bad), None, "{}", bad);
+        }
+    }
+}
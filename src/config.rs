@@ -0,0 +1,305 @@
+use crate::errors::ConfigError;
+use crate::keymap::{KeyboardLayout, KeymapProfile};
+use crate::palette::{self, Adjustments, Palette, MAX_BRIGHTNESS};
+use crate::ppu::{Color, Scaling, MAX_SCALE, MIN_SCALE};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// User settings, read from a file of `key = value` lines:
+//
+//   palette = amber
+//   fg = #FFB000
+//   palette.mine = 101820 8BE9FD
+//   invert = true
+//   brightness = 80
+//   on_alpha = 70
+//   autosave = 60
+//   resume = true
+//   roms = /home/me/chip8
+//   keymap = standard
+//   keymap.pong2 = two-player
+//   layout = azerty
+//   scaling = letterbox
+//   scale = 6
+//
+// '#' starts a comment line. Unknown keys only log a warning, so a config
+// written by a newer version still loads.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Config {
+    pub palette: Option<String>,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    // Custom palettes from `palette.<name>` keys.
+    pub palettes: Vec<(String, Palette)>,
+    // Dark-on-light video, and percentages for the brightness of every
+    // color and the opacity of lit pixels.
+    pub invert: Option<bool>,
+    pub brightness: Option<u8>,
+    pub on_alpha: Option<u8>,
+    // Seconds between autosaves, 0 for none.
+    pub autosave: Option<u64>,
+    // Save the game on closing and offer to continue from it.
+    pub resume: Option<bool>,
+    // Directory the ROM browser lists when no ROM is given.
+    pub roms: Option<PathBuf>,
+    // Which keys play the hexpad, and per ROM from `keymap.<rom>` keys, by
+    // file name without extension.
+    pub keymap: Option<KeymapProfile>,
+    pub rom_keymaps: Vec<(String, KeymapProfile)>,
+    // The host keyboard; frontends that can tell detect it otherwise.
+    pub layout: Option<KeyboardLayout>,
+    pub scaling: Option<Scaling>,
+    // Times the display is enlarged in a new window.
+    pub scale: Option<u32>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| ConfigError {
+                line: Some(index + 1),
+                message,
+            };
+            let (key, value) = match line.find('=') {
+                Some(at) => (line[..at].trim(), line[at + 1..].trim()),
+                None => return Err(error(format!("expected `key = value`, got `{}`", line))),
+            };
+            let keymap = || {
+                KeymapProfile::from_name(value).ok_or_else(|| error(format!("unknown keymap profile {}", value)))
+            };
+            let percent = |max: u8| match value.parse() {
+                Ok(percent) if percent <= max => Ok(percent),
+                _ => Err(error(format!("{} is not a percentage from 0 to {}", value, max))),
+            };
+            let boolean = || value.parse().map_err(|_| error(format!("{} is not true or false", value)));
+            let color = || {
+                palette::parse_color(value).ok_or_else(|| error(format!("{} is not a RRGGBB color", value)))
+            };
+
+            match key {
+                "palette" => config.palette = Some(value.to_string()),
+                "fg" => config.foreground = Some(color()?),
+                "bg" => config.background = Some(color()?),
+                "invert" => {
+                    config.invert = Some(boolean()?);
+                }
+                "brightness" => config.brightness = Some(percent(MAX_BRIGHTNESS)?),
+                "on_alpha" => config.on_alpha = Some(percent(100)?),
+                "autosave" => {
+                    let seconds = value.parse().map_err(|_| error(format!("{} is not a number of seconds", value)))?;
+                    config.autosave = Some(seconds);
+                }
+                "resume" => config.resume = Some(boolean()?),
+                "roms" => config.roms = Some(PathBuf::from(value)),
+                "keymap" => config.keymap = Some(keymap()?),
+                "layout" => {
+                    let layout =
+                        KeyboardLayout::from_name(value).ok_or_else(|| error(format!("unknown keyboard layout {}", value)))?;
+                    config.layout = Some(layout);
+                }
+                "scaling" => {
+                    let scaling = Scaling::from_name(value).ok_or_else(|| error(format!("unknown scaling {}", value)))?;
+                    config.scaling = Some(scaling);
+                }
+                "scale" => {
+                    let scale = value
+                        .parse()
+                        .ok()
+                        .filter(|scale| (MIN_SCALE..=MAX_SCALE).contains(scale))
+                        .ok_or_else(|| error(format!("{} is not a scale from {} to {}", value, MIN_SCALE, MAX_SCALE)))?;
+                    config.scale = Some(scale);
+                }
+                _ if key.starts_with("keymap.") => config.rom_keymaps.push((key["keymap.".len()..].to_string(), keymap()?)),
+                _ if key.starts_with("palette.") => {
+                    let palette = palette::parse_palette(value)
+                        .ok_or_else(|| error(format!("palette needs 2 or 4 colors, got `{}`", value)))?;
+                    config.palettes.push((key["palette.".len()..].to_string(), palette));
+                }
+                _ => log::warn!("config line {}: unknown key {}", index + 1, key),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|error| ConfigError {
+            line: None,
+            message: format!("{}: {}", path.display(), error),
+        })?;
+        Config::parse(&text)
+    }
+
+    // Writes the config as `load` reads it, creating its directory.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    // $XDG_CONFIG_HOME/hachip/config, falling back to %APPDATA% and then
+    // ~/.config.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("hachip").join("config"))
+    }
+
+    // A custom palette from this config, or else a built-in one.
+    pub fn find_palette(&self, name: &str) -> Option<Palette> {
+        self.palettes
+            .iter()
+            .rev()
+            .find(|(custom, _)| custom.eq_ignore_ascii_case(name))
+            .map(|(_, palette)| *palette)
+            .or_else(|| Palette::builtin(name))
+    }
+
+    // The palette to display with: the selected one (default if none) with
+    // the fg/bg overrides and the adjustments applied. None if the selected
+    // name is unknown.
+    pub fn display_palette(&self) -> Option<Palette> {
+        let mut palette = match &self.palette {
+            Some(name) => self.find_palette(name)?,
+            None => Palette::default(),
+        };
+        if let Some(color) = self.background {
+            palette.set_background(color);
+        }
+        if let Some(color) = self.foreground {
+            palette.set_foreground(color);
+        }
+        Some(self.adjustments().apply(&palette))
+    }
+
+    pub fn adjustments(&self) -> Adjustments {
+        let mut adjustments = Adjustments::default();
+        adjustments.inverted = self.invert.unwrap_or(adjustments.inverted);
+        adjustments.brightness = self.brightness.unwrap_or(adjustments.brightness);
+        adjustments.on_alpha = self.on_alpha.unwrap_or(adjustments.on_alpha);
+        adjustments
+    }
+}
+
+// The settings that are set, one `key = value` line each.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |color: Color| format!("{:02X}{:02X}{:02X}", color.r, color.g, color.b);
+        if let Some(name) = &self.palette {
+            writeln!(f, "palette = {}", name)?;
+        }
+        if let Some(color) = self.foreground {
+            writeln!(f, "fg = {}", hex(color))?;
+        }
+        if let Some(color) = self.background {
+            writeln!(f, "bg = {}", hex(color))?;
+        }
+        for (name, palette) in &self.palettes {
+            let colors: Vec<String> = palette.colors.iter().map(|color| hex(*color)).collect();
+            writeln!(f, "palette.{} = {}", name, colors.join(" "))?;
+        }
+        if let Some(invert) = self.invert {
+            writeln!(f, "invert = {}", invert)?;
+        }
+        if let Some(percent) = self.brightness {
+            writeln!(f, "brightness = {}", percent)?;
+        }
+        if let Some(percent) = self.on_alpha {
+            writeln!(f, "on_alpha = {}", percent)?;
+        }
+        if let Some(seconds) = self.autosave {
+            writeln!(f, "autosave = {}", seconds)?;
+        }
+        if let Some(resume) = self.resume {
+            writeln!(f, "resume = {}", resume)?;
+        }
+        if let Some(dir) = &self.roms {
+            writeln!(f, "roms = {}", dir.display())?;
+        }
+        if let Some(profile) = self.keymap {
+            writeln!(f, "keymap = {}", profile.name())?;
+        }
+        for (rom, profile) in &self.rom_keymaps {
+            writeln!(f, "keymap.{} = {}", rom, profile.name())?;
+        }
+        if let Some(layout) = self.layout {
+            writeln!(f, "layout = {}", layout.name())?;
+        }
+        if let Some(scaling) = self.scaling {
+            writeln!(f, "scaling = {}", scaling.name())?;
+        }
+        if let Some(scale) = self.scale {
+            writeln!(f, "scale = {}", scale)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::keymap::{KeyboardLayout, KeymapProfile};
+    use crate::palette::Palette;
+    use crate::ppu::{Color, Scaling};
+
+    #[test]
+    fn custom_palette_with_overrides() {
+        let config = Config::parse(
+            "# colors\n\
+             palette = Mine\n\
+             palette.mine = 101820 8BE9FD\n\
+             fg = #FF0000\n\
+             autosave = 60\n\
+             resume = true\n\
+             roms = /tmp/roms\n\
+             keymap.pong2 = two-player\n\
+             layout = dvorak\n\
+             scaling = letterbox\n\
+             scale = 4\n\
+             future = ignored\n",
+        )
+        .unwrap();
+
+        let palette = config.display_palette().unwrap();
+        assert_eq!(palette.background(), Color::from_rgb(0x101820));
+        assert_eq!(palette.foreground(), Color::rgb(0xFF, 0, 0));
+        assert_eq!(palette.color(2), Color::from_rgb(0x8BE9FD));
+        assert_eq!(config.find_palette("amber"), Palette::builtin("amber"));
+        assert_eq!(config.autosave, Some(60));
+        assert_eq!(config.resume, Some(true));
+        assert_eq!(config.roms, Some("/tmp/roms".into()));
+        let inverted = Config::parse("invert = true\n").unwrap().display_palette().unwrap();
+        assert_eq!(inverted.background(), Color::rgb(0xFF, 0xFF, 0xFF));
+        assert_eq!(config.rom_keymaps, vec![("pong2".to_string(), KeymapProfile::TwoPlayer)]);
+        assert_eq!(config.layout, Some(KeyboardLayout::Dvorak));
+        assert_eq!(config.scaling, Some(Scaling::Letterbox));
+        assert_eq!(config.scale, Some(4));
+        assert_eq!(Config::parse(&config.to_string()).unwrap(), config, "written as it is read");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = Config::parse("palette = amber\nfg = blue\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+        assert!(Config::parse("palette amber").is_err());
+        assert_eq!(Config::parse("x = 1\nbrightness = 250\n").unwrap_err().line, Some(2));
+        assert_eq!(Config::parse("scale = 21").unwrap_err().to_string(), "line 1: 21 is not a scale from 1 to 20");
+
+        let config = Config {
+            palette: Some("missing".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(config.display_palette(), None);
+    }
+}
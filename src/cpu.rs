@@ -1,15 +1,55 @@
-use crate::errors::EmulateCycleError;
+use crate::errors::{EmulateCycleError, LoadError, Violation};
+use crate::headless::Fnv;
+use crate::instruction::Instruction;
 use crate::keypad::Keypad;
-use crate::ppu::{Display, FONT_SET};
-use log;
+use crate::megachip::{self, Blend, MegaChip};
+use crate::opcodes;
+use crate::ppu::{Display, FONT_SET, WIDTH};
+use crate::profile::EmulationProfile;
+use crate::profiler::Profiler;
+use crate::quirks::Quirks;
+use crate::rng::{Draw, RandomSource, Rng};
+use crate::scheduler::FRAME_RATE;
+use crate::state::SaveState;
+use crate::trace::{Registers, Trace};
+use crate::vip_timing;
+use crate::watchpoint::MemoryAccess;
+use std::fmt;
+use std::time::Duration;
+
+// Where ROMs are loaded and execution starts, unless the cpu's
+// `load_address` says otherwise.
+pub const PROGRAM_START: usize = 0x200;
+// Where ETI-660 ROMs expect to be loaded.
+pub const ETI_660_START: usize = 0x600;
+pub const MEMORY_SIZE: usize = 4096;
+
+// How far ROMs may reach with `quirks`: past the 4K, memory grows to hold
+// Mega-Chip ROMs.
+pub fn memory_size(quirks: &Quirks) -> usize {
+    if quirks.megachip {
+        megachip::MEMORY_SIZE
+    } else {
+        MEMORY_SIZE
+    }
+}
+
+// Whether a ROM of `size` bytes fits in memory at `load_address`.
+pub fn check_rom_size(size: usize, load_address: u16, quirks: &Quirks) -> Result<(), LoadError> {
+    let max_size = memory_size(quirks).saturating_sub(load_address as usize);
+    if size > max_size {
+        return Err(LoadError { size, max_size });
+    }
+    Ok(())
+}
 
 pub struct Cpu {
-    // index register
-    i: u16,
+    // index register, 24 bits with the megachip quirk and 16 otherwise
+    i: u32,
     // program counter
     pc: u16,
-    // memory
-    memory: [u8; 4096],
+    // memory, MEMORY_SIZE bytes unless a Mega-Chip ROM reaches past them
+    memory: Vec<u8>,
     // registers
     v: [u8; 16],
     // th
@@ -24,6 +64,36 @@ pub struct Cpu {
     st: u8,
     // display
     display: Box<dyn Display>,
+    // interpreter-specific behaviours
+    pub quirks: Quirks,
+    // out-of-range keys and memory accesses are errors instead of wrapping
+    pub strict: bool,
+    // where `load` puts the ROM and `reset` starts execution
+    pub load_address: u16,
+    // instructions to log as they execute, none when None
+    pub trace: Option<Trace>,
+    // execution counts, none when None
+    pub profiler: Option<Profiler>,
+    // a draw is waiting for the vertical blank
+    vblank_wait: bool,
+    // with the vip_timing quirk, machine cycles left in this frame; below
+    // zero once an instruction ran over, which the next frame makes up for
+    cycles_left: i32,
+    // source of Cxkk random numbers
+    rng: Box<dyn RandomSource>,
+    // Cxkk draws not yet taken, while they are logged
+    draws: Option<Vec<Draw>>,
+    // data reads and writes not yet taken, while they are logged
+    memory_accesses: Option<Vec<MemoryAccess>>,
+    // vblanks since the last reset, the emulation clock
+    frames: u64,
+    // SUPER-CHIP's copies of the HP48's RPL user flags, which outlive a
+    // reset, and whether Fx75 wrote them since the last look
+    rpl_flags: [u8; 16],
+    rpl_flags_written: bool,
+    // with the megachip quirk, the screen, palette and sample, from the
+    // first Mega-Chip instruction on
+    mega: Option<Box<MegaChip>>,
 }
 
 impl Cpu {
@@ -31,388 +101,812 @@ impl Cpu {
         Cpu {
             i: 0,
             pc: 0,
-            memory: [0; 4096],
+            memory: vec![0; MEMORY_SIZE],
             v: [0; 16],
             display,
             keypad: Keypad::new(),
             stack: [0; 16],
             sp: 0,
             dt: 0,
-            st: 0
+            st: 0,
+            quirks: Quirks::default(),
+            strict: false,
+            load_address: PROGRAM_START as u16,
+            trace: None,
+            profiler: None,
+            vblank_wait: false,
+            cycles_left: vip_timing::FRAME_CYCLES,
+            rng: Box::new(Rng::from_entropy()),
+            draws: None,
+            memory_accesses: None,
+            frames: 0,
+            rpl_flags: [0; 16],
+            rpl_flags_written: false,
+            mega: None,
         }
     }
 
     pub fn reset(&mut self) {
         self.i = 0;
-        self.pc = 0x200;
-        self.memory = [0; 4096];
+        self.pc = self.load_address;
+        self.memory = vec![0; MEMORY_SIZE];
         self.v = [0; 16];
         self.stack = [0; 16];
         self.sp = 0;
         self.dt = 0;
         self.st = 0;
+        self.vblank_wait = false;
+        self.cycles_left = vip_timing::FRAME_CYCLES;
+        self.frames = 0;
+        self.mega = None;
         self.display.cls();
         self.memory[0..80].clone_from_slice(&FONT_SET[..80]);
     }
 
-    pub fn load(&mut self, data: Vec<u8>) {
-        for (idx, item) in data.iter().enumerate() {
-            self.memory[idx + 512] = *item;
+    // Copies the ROM to the load address, refusing ROMs that don't fit.
+    pub fn load(&mut self, data: Vec<u8>) -> Result<(), LoadError> {
+        check_rom_size(data.len(), self.load_address, &self.quirks)?;
+        self.load_truncated(data);
+        Ok(())
+    }
+
+    // Copies as much of the ROM as fits, for ROMs padded past the end of
+    // memory.
+    pub fn load_truncated(&mut self, data: Vec<u8>) {
+        let start = self.load_address as usize;
+        let end = (start + data.len()).min(memory_size(&self.quirks));
+        if end > self.memory.len() {
+            self.memory.resize(end, 0);
+        }
+        let size = data.len().min(self.memory.len() - start);
+        if size < data.len() {
+            log::warn!("ROM truncated to {} of its {} bytes", size, data.len());
         }
+        self.memory[start..start + size].copy_from_slice(&data[..size]);
         log::info!("ROM loaded");
     }
 
     pub fn execute_cycle(&mut self) {
-        let opcode: u16 = self.read_word();
-        self.process_opcode(opcode).unwrap();
+        self.step().unwrap();
+    }
+
+    pub fn step(&mut self) -> Result<(), EmulateCycleError> {
+        let opcode: u16 = self.read_word()?;
+        self.step_with_opcode(opcode)
+    }
+
+    // Moves past the instruction at PC without running it, to get by one
+    // that failed.
+    pub fn skip_instruction(&mut self) {
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    // Executes `opcode` as if it had been fetched at PC, whatever memory
+    // holds there. For fuzzing the decoder with arbitrary opcode streams.
+    pub fn step_with_opcode(&mut self, opcode: u16) -> Result<(), EmulateCycleError> {
+        let pc = self.pc;
+        let result = if !self.trace.as_ref().is_some_and(|trace| trace.matches(pc, opcode)) {
+            self.process_opcode(opcode)
+        } else {
+            let before = self.trace_registers();
+            let result = self.process_opcode(opcode);
+            if let Some(trace) = &self.trace {
+                trace.log(pc, opcode, &before, &self.trace_registers());
+            }
+            result
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, opcode, self.pc);
+        }
+        if self.quirks.vip_timing && result.is_ok() {
+            let skipped = self.pc == pc.wrapping_add(4);
+            let vx = self.v[(opcode >> 8 & 0xF) as usize];
+            self.cycles_left -= vip_timing::cycles(opcode, skipped, vx) as i32;
+        }
+        // a failed instruction leaves the PC on it, whatever it got to
+        if result.is_err() {
+            self.pc = pc;
+        }
+        result
+    }
+
+    fn trace_registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            i: self.i,
+            sp: self.sp,
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    pub fn display(&self) -> &dyn Display {
+        self.display.as_ref()
+    }
+
+    pub fn display_mut(&mut self) -> &mut dyn Display {
+        self.display.as_mut()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> u32 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    // Makes Cxkk deterministic from here on, for replays.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.set_rng(Box::new(Rng::new(seed)));
+    }
+
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    // Keeps every Cxkk draw from here on until taken.
+    pub fn log_draws(&mut self) {
+        self.draws.get_or_insert_with(Vec::new);
+    }
+
+    // The draws logged since the last call, empty when not logging.
+    pub fn take_draws(&mut self) -> Vec<Draw> {
+        self.draws.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Keeps every data read and write instructions make from here on until
+    // taken, for watchpoints.
+    pub fn log_memory_accesses(&mut self) {
+        self.memory_accesses.get_or_insert_with(Vec::new);
+    }
+
+    // The accesses logged since the last call, empty when not logging.
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.memory_accesses.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // The settings this cpu runs with, for artifacts and error reports.
+    pub fn profile(&self) -> EmulationProfile {
+        EmulationProfile::new(self.quirks)
+    }
+
+    // The buzzer sounds while the sound timer is non-zero.
+    pub fn sound_playing(&self) -> bool {
+        self.st > 0
+    }
+
+    // The Mega-Chip state, once a Mega-Chip instruction ran.
+    pub fn mega(&self) -> Option<&MegaChip> {
+        self.mega.as_deref()
+    }
+
+    // Adds the 060n sample playing, if any, to `out` at `sample_rate` and
+    // moves it on. Returns whether one played.
+    pub fn render_sample(&mut self, out: &mut [i16], sample_rate: u32) -> bool {
+        match &mut self.mega {
+            Some(mega) => mega.render_sound(&self.memory, out, sample_rate),
+            None => false,
+        }
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            dt: self.dt,
+            st: self.st,
+            vblank_wait: self.vblank_wait,
+            v: self.v,
+            stack: self.stack,
+            memory: self.memory.clone(),
+            framebuffer: self.display.framebuffer().to_vec(),
+            profile: Some(self.profile()),
+            mega: self.mega.as_deref().cloned(),
+        }
+    }
+
+    // A hash of what a save state holds: registers, timers, stack, memory
+    // and the screen. Netplay and `hachip verify` compare it to catch two
+    // runs drifting apart, so it is built from the values byte by byte, in
+    // a fixed order and with words big-endian, and is the same on every
+    // platform. The top of I and the Mega-Chip state come last, when there
+    // is one. The bytes are hashed where they are rather than copied out,
+    // as netplay does this every frame.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = Fnv::default();
+        hash.extend((self.i as u16).to_be_bytes());
+        hash.extend(self.pc.to_be_bytes());
+        hash.extend([self.sp, self.dt, self.st, self.vblank_wait as u8]);
+        hash.extend(&self.v);
+        for address in &self.stack {
+            hash.extend(address.to_be_bytes());
+        }
+        hash.extend(&self.memory);
+        hash.extend(self.display.framebuffer());
+        if let Some(mega) = &self.mega {
+            hash.extend([(self.i >> 16) as u8]);
+            mega.to_bytes(&mut hash);
+        }
+        hash.finish()
+    }
+
+    // Quirks are kept; a state saved with other ones may not resume
+    // correctly, which is logged.
+    pub fn load_state(&mut self, state: &SaveState) {
+        if let Some(profile) = state.profile {
+            if profile != self.profile() {
+                log::warn!("save state was made with {}, running with {}", profile, self.profile());
+            }
+        }
+        self.i = state.i;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.dt = state.dt;
+        self.st = state.st;
+        self.vblank_wait = state.vblank_wait;
+        self.v = state.v;
+        self.stack = state.stack;
+        self.memory = state.memory.clone();
+        self.mega = state.mega.clone().map(Box::new);
+        for (index, pixel) in state.framebuffer.iter().enumerate() {
+            self.display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+        self.display.invalidate();
+    }
+
+    fn read_word(&self) -> Result<u16, EmulateCycleError> {
+        self.read_word_at(self.pc as usize)
     }
 
-    fn read_word(&self) -> u16 {
-        let code1: u16 = self.memory[self.pc as usize] as u16;
-        let code2: u16 = self.memory[(self.pc + 1) as usize] as u16;
-        code1 << 8 | code2
+    fn read_word_at(&self, address: usize) -> Result<u16, EmulateCycleError> {
+        let code1: u16 = self.memory[self.wrap(address)?] as u16;
+        let code2: u16 = self.memory[self.wrap(address + 1)?] as u16;
+        Ok(code1 << 8 | code2)
+    }
+
+    // What I holds onto: 24 bits on Mega-Chip, 16 like the VIP otherwise.
+    fn i_mask(&self) -> u32 {
+        if self.quirks.megachip {
+            0xFF_FFFF
+        } else {
+            0xFFFF
+        }
+    }
+
+    fn mega_mut(&mut self) -> &mut MegaChip {
+        self.mega.get_or_insert_with(Default::default)
+    }
+
+    // I + offset as a memory index.
+    fn address(&self, offset: usize) -> Result<usize, EmulateCycleError> {
+        self.wrap(self.i as usize + offset)
+    }
+
+    // Data accesses at I + offset go through these, so they can be logged.
+    fn read_data(&mut self, offset: usize) -> Result<u8, EmulateCycleError> {
+        let address = self.address(offset)?;
+        self.log_access(address, false);
+        Ok(self.memory[address])
+    }
+
+    fn write_data(&mut self, offset: usize, value: u8) -> Result<(), EmulateCycleError> {
+        let address = self.address(offset)?;
+        self.log_access(address, true);
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    fn log_access(&mut self, address: usize, write: bool) {
+        // watchpoints only reach the first 64K of Mega-Chip memory
+        if let (Some(accesses), true) = (&mut self.memory_accesses, address <= 0xFFFF) {
+            accesses.push(MemoryAccess { address: address as u16, write });
+        }
+    }
+
+    fn wrap(&self, address: usize) -> Result<usize, EmulateCycleError> {
+        if address < self.memory.len() {
+            Ok(address)
+        } else if self.strict {
+            Err(EmulateCycleError::violation(Violation::MemoryWrap(address)))
+        } else {
+            Ok(address % self.memory.len())
+        }
+    }
+
+    fn key(&self, x: usize) -> Result<u8, EmulateCycleError> {
+        let key = self.v[x];
+        if key > 0xF && self.strict {
+            return Err(EmulateCycleError::violation(Violation::KeyOutOfRange(key)));
+        }
+        Ok(key & 0xF)
+    }
+
+    // Stores the result and flag of 8xy4-8xyE. With x = F one overwrites
+    // the other: the flag is written last unless the vf_result quirk is on.
+    fn write_alu(&mut self, x: usize, result: u8, flag: u8) {
+        if self.quirks.vf_result {
+            self.v[0xF] = flag;
+            self.v[x] = result;
+        } else {
+            self.v[x] = result;
+            self.v[0xF] = flag;
+        }
     }
 
     fn process_opcode(&mut self, opcode: u16) -> Result<(), EmulateCycleError> {
-        match opcode {
-            0x00E0 => {
+        match Instruction::decode(opcode) {
+            Some(instruction) if self.quirks.megachip || !instruction.is_megachip() => self.execute(instruction),
+            _ => {
+                self.pc += 2;
+                Err(EmulateCycleError::violation(Violation::UnknownOpcode(opcode)))
+            }
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), EmulateCycleError> {
+        match instruction {
+            Instruction::MegaOff => {
+                // 0010 - MEGAOFF
+                // Back to the CHIP-8 screen, redrawn whole.
+                self.mega_mut().set_on(false);
+                self.display.invalidate();
+                self.pc += 2;
+            }
+            Instruction::MegaOn => {
+                // 0011 - MEGAON
+                // Switch to the 256x192 Mega-Chip screen.
+                self.mega_mut().set_on(true);
+                self.pc += 2;
+            }
+            Instruction::ScrollUp(lines) => {
+                // 00Bn - SCRU n
+                // Scroll the Mega-Chip screen up n lines.
+                self.mega_mut().scroll_up(lines as usize);
+                self.pc += 2;
+            }
+            Instruction::Cls => {
                 // 00E0 - CLS
                 // Clear the display.
-                self.display.cls();
+                // On the Mega-Chip screen this shows what was drawn since
+                // the last one, then clears it.
+                match &mut self.mega {
+                    Some(mega) if mega.on() => mega.show(),
+                    _ => self.display.cls(),
+                }
                 self.pc += 2;
             }
-            0x1000 ..= 0x1FFF => {
-                // 1nnn - JP addr
-                // Jump to location nnn.
-                self.pc = opcode & 0x0FFF;
-            },
-            0x00EE => {
+            Instruction::Ret => {
                 // 00EE - RET
                 // Return from a subroutine.
                 // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-                println!("sp: {:X}", self.sp);
-                println!("val: {:X}", self.stack[self.sp as usize]);
+                log::debug!("sp: {:X}", self.sp);
+                if self.sp == 0 {
+                    return Err(EmulateCycleError::violation(Violation::StackUnderflow));
+                }
 
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
                 self.stack[self.sp as usize] = 0xBEEF;
                 self.pc += 2;
-            },
-            0x2000 ..= 0x2FFF => {
+            }
+            Instruction::LoadLongI(high) => {
+                // 01nn nnnn - LDHI I, nnnnnn
+                // Set I to 24 bits: nn and the word after the instruction.
+                let low = self.read_word_at(self.pc as usize + 2)?;
+                self.i = (high as u32) << 16 | low as u32;
+                self.pc += 4;
+            }
+            Instruction::LoadPalette(count) => {
+                // 02nn - LDPAL nn
+                // Load nn colours, 4 bytes ARGB each, from I into the
+                // palette from index 1 on.
+                let mut colors = Vec::with_capacity(count as usize * 4);
+                for offset in 0..count as usize * 4 {
+                    colors.push(self.read_data(offset)?);
+                }
+                self.mega_mut().load_palette(&colors);
+                self.pc += 2;
+            }
+            Instruction::SpriteWidth(width) => {
+                // 03nn - SPRW nn
+                self.mega_mut().set_sprite_width(width);
+                self.pc += 2;
+            }
+            Instruction::SpriteHeight(height) => {
+                // 04nn - SPRH nn
+                self.mega_mut().set_sprite_height(height);
+                self.pc += 2;
+            }
+            Instruction::Alpha(alpha) => {
+                // 05nn - ALPHA nn
+                // Set how bright 00E0 shows the screen.
+                self.mega_mut().set_alpha(alpha);
+                self.pc += 2;
+            }
+            Instruction::PlaySound(once) => {
+                // 060n - DIGISND n
+                // Play the sample at I, looping when n = 0.
+                let mut header = [0; 6];
+                for (offset, byte) in header.iter_mut().enumerate() {
+                    *byte = self.read_data(offset)?;
+                }
+                let address = self.i;
+                self.mega_mut().play(address, header, once == 0);
+                self.pc += 2;
+            }
+            Instruction::StopSound => {
+                // 0700 - STOPSND
+                self.mega_mut().stop();
+                self.pc += 2;
+            }
+            Instruction::BlendMode(mode) => {
+                // 080n - BMODE n
+                // Set how sprites mix with the screen.
+                self.mega_mut().set_blend(Blend::from_nibble(mode).unwrap_or_default());
+                self.pc += 2;
+            }
+            Instruction::CollisionColor(index) => {
+                // 09nn - CCOL nn
+                // Set the palette index sprites collide with.
+                self.mega_mut().set_collision_color(index);
+                self.pc += 2;
+            }
+            Instruction::Jump(address) => {
+                // 1nnn - JP addr
+                // Jump to location nnn.
+                self.pc = address;
+            }
+            Instruction::Call(address) => {
                 // 2nnn - CALL addr
                 // Call subroutine at nnn.
                 // Increment the stack pointer, put the current program counter on the top of the stack,
                 // then the program counter is then set to nnn.
+                if self.sp as usize == self.stack.len() {
+                    return Err(EmulateCycleError::violation(Violation::StackOverflow));
+                }
                 self.stack[self.sp as usize] = self.pc;
-                self.pc = opcode & 0x0FFF;
+                self.pc = address;
                 self.sp += 1;
 
-                // TODO better error handling if there was a stack overflow?
-                println!("call subroutine at {:X}", opcode);
-            },
-            0x3000 ..= 0x3FFF => {
+                log::debug!("call subroutine at {:X}", address);
+            }
+            Instruction::SkipIfByte(x, kk) => {
                 // 3xkk - SE Vx, byte
                 // Skip next instruction if Vx = kk.
                 // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                if self.v[x as usize] == kk as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            },
-            0x4000..=0x4FFF => {
+                self.skip_if(self.v[x as usize] == kk);
+            }
+            Instruction::SkipUnlessByte(x, kk) => {
                 // 4xkk - SNE Vx, byte
                 // Skip next instruction if Vx != kk.
                 //The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                if self.v[x as usize] != kk as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.v[x as usize] != kk);
             }
-            0x5000..=0x5FFF => {
+            Instruction::SkipIfEqual(x, y) => {
                 // 5xy0 - SE Vx, Vy
                 // Skip next instruction if Vx = Vy.
-                let x = (opcode & 0x0F00) >> 8;
-                let y = (opcode & 0x00F0) >> 4;
-                if self.v[x as usize] == self.v[y as usize] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.v[x as usize] == self.v[y as usize]);
             }
-            0x6000 ..= 0x6FFF => {
+            Instruction::LoadByte(x, kk) => {
                 // 6xkk - LD Vx, byte
                 // The interpreter puts the value kk into register Vx.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                self.v[x as usize] = kk as u8;
+                self.v[x as usize] = kk;
                 self.pc += 2;
-            },
-            0x7000 ..= 0x7FFF => {
+            }
+            Instruction::AddByte(x, kk) => {
                 // 7xkk - ADD Vx, byte
                 // Set Vx = Vx + kk.
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let kk = (opcode & 0x00FF) as u8;
-
-                let (result, _) = self.v[x].overflowing_add(kk);
-                self.v[x] = result;
-                self.pc += 2;
-            },
-            0x8000..=0x8FFF => {
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let y = ((opcode & 0x00F0) >> 4) as usize;
-                let subcode = opcode & 0x000F;
-                match subcode {
-                    0 => {
-                        // 8xy0 - LD Vx, Vy
-                        // Set Vx = Vy.
-                        self.v[x] = self.v[y];
-                        self.pc += 2;
-                    }
-                    1 => {
-                        // 8xy1 - OR Vx, Vy
-                        // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] |= self.v[y];
-                        self.pc += 2;
-                    }
-                    2 => {
-                        // 8xy2 - AND Vx, Vy
-                        // Set Vx = Vx AND Vy.
-                        // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] &= self.v[y];
-                        self.pc += 2;
-                    }
-                    3 => {
-                        // 8xy3 - XOR Vx, Vy
-                        // Set Vx = Vx XOR Vy.
-                        // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] ^= self.v[y];
-                        self.pc += 2;
-                    }
-                    4 => {
-                        // 8xy4 - ADD Vx, Vy
-                        // Set Vx = Vx + Vy, set VF = carry.
-                        // The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
-                        let (value, did_overflow) = self.v[x].overflowing_add(self.v[y]);
-                        if did_overflow {
-                            self.v[0xF] = 1;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    5 => {
-                        // 8xy5 - SUB Vx, Vy
-                        // Set Vx = Vx - Vy, set VF = NOT borrow.
-                        // If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
-                        let (value, _) = self.v[x].overflowing_sub(self.v[y]);
-                        if self.v[x] > self.v[y] {
-                            self.v[0xF] = 1;
-                        } else {
-                            self.v[0xF] = 0;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    6 => {
-                        // 8xy6 - SHR Vx {, Vy}
-                        // Set Vx = Vx SHR 1.
-                        // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-                        self.v[0xF] = self.v[x] & 0x1;
-                        self.v[x] >>= 1;
-                        self.pc += 2;
-                    }
-                    7 => {
-                        // 8xy7 - SUBN Vx, Vy
-                        // Set Vx = Vy - Vx, set VF = NOT borrow.
-                        // If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
-                        let (value, did_overflow) = self.v[x].overflowing_sub(self.v[y]);
-                        if did_overflow {
-                            self.v[0xF] = 0;
-                        } else {
-                            self.v[0xF] = 1;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    0xE => {
-                        // 8xyE - SHL Vx {, Vy}
-                        // Set Vx = Vx SHL 1.
-                        // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-                        self.v[0xF] = self.v[x] & 0x80;
-                        self.v[x] <<= 1;
-                        self.pc += 2;
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} opcode not handled a", opcode) };
-                        return Err(error);
-                    }
+                let (result, _) = self.v[x as usize].overflowing_add(kk);
+                self.v[x as usize] = result;
+                self.pc += 2;
+            }
+            Instruction::Load(x, y) => {
+                // 8xy0 - LD Vx, Vy
+                // Set Vx = Vy.
+                self.v[x as usize] = self.v[y as usize];
+                self.pc += 2;
+            }
+            Instruction::Or(x, y) => {
+                // 8xy1 - OR Vx, Vy
+                // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] |= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            }
+            Instruction::And(x, y) => {
+                // 8xy2 - AND Vx, Vy
+                // Set Vx = Vx AND Vy.
+                // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] &= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
                 }
+                self.pc += 2;
+            }
+            Instruction::Xor(x, y) => {
+                // 8xy3 - XOR Vx, Vy
+                // Set Vx = Vx XOR Vy.
+                // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] ^= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            }
+            Instruction::Add(x, y) => {
+                // 8xy4 - ADD Vx, Vy
+                // Set Vx = Vx + Vy, set VF = carry.
+                // The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
+                let (value, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.write_alu(x as usize, value, carry as u8);
+                self.pc += 2;
             }
-            0x9000..=0x9FFF => {
+            Instruction::Sub(x, y) => {
+                // 8xy5 - SUB Vx, Vy
+                // Set Vx = Vx - Vy, set VF = NOT borrow.
+                // If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
+                let (value, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.write_alu(x as usize, value, !borrow as u8);
+                self.pc += 2;
+            }
+            Instruction::ShiftRight(x, y) => {
+                // 8xy6 - SHR Vx {, Vy}
+                // Set Vx = Vx SHR 1, or Vy SHR 1 with the shift quirk.
+                // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+                let source = self.shift_source(x, y);
+                self.write_alu(x as usize, source >> 1, source & 0x1);
+                self.pc += 2;
+            }
+            Instruction::SubReversed(x, y) => {
+                // 8xy7 - SUBN Vx, Vy
+                // Set Vx = Vy - Vx, set VF = NOT borrow.
+                // If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
+                let (value, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.write_alu(x as usize, value, !borrow as u8);
+                self.pc += 2;
+            }
+            Instruction::ShiftLeft(x, y) => {
+                // 8xyE - SHL Vx {, Vy}
+                // Set Vx = Vx SHL 1, or Vy SHL 1 with the shift quirk.
+                // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+                let source = self.shift_source(x, y);
+                self.write_alu(x as usize, source << 1, source >> 7);
+                self.pc += 2;
+            }
+            Instruction::SkipUnlessEqual(x, y) => {
                 // 9xy0 - SNE Vx, Vy
                 // Skip next instruction if Vx != Vy.
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let y = ((opcode & 0x00F0) >> 4) as usize;
-                if self.v[x] != self.v[y] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            },
-            0xA000..=0xAFFF => {
+                self.skip_if(self.v[x as usize] != self.v[y as usize]);
+            }
+            Instruction::LoadI(address) => {
                 // Annn - LD I, addr
                 // Set I = nnn.
                 // The value of register I is set to nnn.
-
-                self.i = opcode & 0x0FFF;
+                self.i = address as u32;
                 self.pc += 2;
-            },
-            0xB000..=0xBFFF => {
+            }
+            Instruction::JumpOffset(address) => {
                 // Bnnn - JP V0, addr
                 // Jump to location nnn + V0.
                 // The program counter is set to nnn plus the value of V0.
-                let address = opcode & 0x0FFF;
-                self.pc = (self.v[0x0] as u16) + address;
+                // With the jump quirk this is Bxnn - jump to xnn + Vx.
+                let offset = if self.quirks.jump_vx {
+                    self.v[(address >> 8) as usize]
+                } else {
+                    self.v[0x0]
+                };
+                self.pc = (offset as u16) + address;
             }
-            0xC000..=0xCFFF => {
+            Instruction::Random(x, kk) => {
                 // Cxkk - RND Vx, byte
                 // Set Vx = random byte AND kk.
                 // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = (opcode & 0x00FF) as u8;
-
-                let mut buf = [0u8; 1];
-                getrandom::getrandom(&mut buf).unwrap();
-                let random = buf[0];
+                let random = self.rng.draw(self.frames);
+                if let Some(draws) = &mut self.draws {
+                    draws.push(Draw { frame: self.frames, value: random });
+                }
 
                 self.v[x as usize] = random & kk;
                 self.pc += 2;
             }
-            0xD000 ..= 0xDFFF => {
+            Instruction::Draw(x, y, height) => {
                 // Dxyn - DRW Vx, Vy, nibble
                 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-                let x: usize = self.v[((opcode & 0x0F00) >> 8) as usize] as usize;
-                let y: usize = self.v[((opcode & 0x00F0) >> 4) as usize] as usize;
-                let height: usize = (opcode & 0x000F) as usize;
-                let sprite: &[u8] = &self.memory[self.i as usize .. (self.i + height as u16) as usize];
+                let x: usize = self.v[x as usize] as usize;
+                let y: usize = self.v[y as usize] as usize;
+                let height: usize = height as usize;
+                if self.mega.as_ref().is_some_and(|mega| mega.on()) {
+                    self.v[0xF] = self.draw_mega(x, y, height)? as u8;
+                    self.pc += 2;
+                    return Ok(());
+                }
+                // on the stack, so drawing doesn't allocate
+                let mut sprite = [0u8; 15];
+                for (row, byte) in sprite.iter_mut().take(height).enumerate() {
+                    *byte = self.read_data(row)?;
+                }
 
-                let collision = self.display.draw(x, y, sprite) as u8;
+                let collision = self.display.draw(x, y, &sprite[..height], self.quirks.clip_sprites) as u8;
                 self.v[0xF] = collision;
                 self.pc += 2;
+
+                // The VIP draws in lores only during the vertical blank.
+                if self.quirks.display_wait {
+                    self.vblank_wait = true;
+                }
             }
-            0xE000 ..= 0xEFFF => {
-                let x = (opcode & 0x0F00) >> 8;
-                let code = opcode & 0x00FF;
-                match code {
-                    0x9E => {
-                        // Ex9E - SKP Vx
-                        // Skip next instruction if key with the value of Vx is pressed.
-                        if self.keypad.is_key_down(self.v[x as usize]) {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        // ExA1 - SKNP Vx
-                        // Skip next instruction if key with the value of Vx is not pressed.
-                        // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position;
-                        if !self.keypad.is_key_down(self.v[x as usize]) {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} opcode not handled b", opcode) };
-                        return Err(error);
-                    }
+            Instruction::SkipIfKey(x) => {
+                // Ex9E - SKP Vx
+                // Skip next instruction if key with the value of Vx is pressed.
+                let key = self.key(x as usize)?;
+                self.skip_if(self.keypad.is_key_down(key));
+            }
+            Instruction::SkipUnlessKey(x) => {
+                // ExA1 - SKNP Vx
+                // Skip next instruction if key with the value of Vx is not pressed.
+                // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position;
+                let key = self.key(x as usize)?;
+                self.skip_if(!self.keypad.is_key_down(key));
+            }
+            Instruction::LoadDelay(x) => {
+                // Fx07 - LD Vx, DT
+                // Set Vx = delay timer value.
+                self.v[x as usize] = self.dt;
+                self.pc += 2;
+            }
+            Instruction::WaitKey(x) => {
+                // Fx0A - LD Vx, K
+                // Wait for a key press, store the value of the key in Vx.
+                // All execution stops until a key is pressed, then the value of that key is stored in Vx.
+                // Like the VIP it goes on once the key is released again;
+                // until then PC stays here and the instruction runs again.
+                if let Some(key) = self.keypad.take_released() {
+                    self.v[x as usize] = key;
+                    self.pc += 2;
                 }
             }
-            0xF000 ..= 0xFFFF => {
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let code = opcode & 0x00FF;
-                match code {
-                    0x07 => {
-                        // Fx07 - LD Vx, DT
-                        // Set Vx = delay timer value.
-                        self.v[x] = self.dt;
-                    }
-                    0x0A => {
-                        // Fx0A - LD Vx, K
-                        // Wait for a key press, store the value of the key in Vx.
-                        // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                        for (i, key) in self.keypad.keys.iter().enumerate() {
-                            if *key {
-                                self.v[x] = i as u8;
-                                self.pc +=2;
-                            }
-                        }
-                    }
-                    0x15 => {
-                        // Fx15 - LD DT, Vx
-                        // Set delay timer = Vx.
-                        self.dt = self.v[x];
-                    }
-                    0x18 => {
-                        // Fx18 - LD ST, Vx
-                        // Set sound timer = Vx.
-                        self.st = self.v[x];
-                    }
-                    0x1E => {
-                        // Fx1E - ADD I, Vx
-                        // Set I = I + Vx.
-                        self.i += self.v[x] as u16;
-                    }
-                    0x29 => {
-                        // Fx29 - LD F, Vx
-                        // Set I = location of sprite for digit Vx.
-                        self.i = self.v[x] as u16 * 5;
-                    }
-                    0x33 => {
-                        // Fx33 - LD B, Vx
-                        // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                        self.memory[self.i as usize] = (self.v[x] / 100) as u8;
-                        self.memory[(self.i + 1) as usize] = (self.v[x] / 10) as u8 % 10;
-                        self.memory[(self.i + 2) as usize] = (self.v[x] % 100) as u8 % 10;
-                    }
-                    0x55 => {
-                        // Fx55 - LD [I], Vx
-                        // Store registers V0 through Vx in memory starting at location I.
-                        // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
-                        for offset in 0..=x {
-                            self.memory[(self.i + offset as u16) as usize] = self.v[offset];
-                        }
-                    }
-                    0x65 => {
-                        // Fx65 - LD Vx, [I]
-                        // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-                        for offset in 0..=x {
-                            self.v[offset] = self.memory[(self.i + offset as u16) as usize];
-                        }
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} F opcode not handled", opcode) };
-                        return Err(error);
-                    }
+            Instruction::SetDelay(x) => {
+                // Fx15 - LD DT, Vx
+                // Set delay timer = Vx.
+                self.dt = self.v[x as usize];
+                self.pc += 2;
+            }
+            Instruction::SetSound(x) => {
+                // Fx18 - LD ST, Vx
+                // Set sound timer = Vx.
+                self.st = self.v[x as usize];
+                self.pc += 2;
+            }
+            Instruction::AddI(x) => {
+                // Fx1E - ADD I, Vx
+                // Set I = I + Vx.
+                // I wraps like the VIP's 16-bit register; memory accesses
+                // through it wrap (or fail in strict mode) on their own.
+                self.i = self.i.wrapping_add(self.v[x as usize] as u32) & self.i_mask();
+                self.pc += 2;
+            }
+            Instruction::LoadFont(x) => {
+                // Fx29 - LD F, Vx
+                // Set I = location of sprite for digit Vx.
+                self.i = self.v[x as usize] as u32 * 5;
+                self.pc += 2;
+            }
+            Instruction::StoreBcd(x) => {
+                // Fx33 - LD B, Vx
+                // Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                let vx = self.v[x as usize];
+                self.write_data(0, vx / 100)?;
+                self.write_data(1, vx / 10 % 10)?;
+                self.write_data(2, vx % 100 % 10)?;
+                self.pc += 2;
+            }
+            Instruction::Store(x) => {
+                // Fx55 - LD [I], Vx
+                // Store registers V0 through Vx in memory starting at location I.
+                // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
+                for offset in 0..=x as usize {
+                    self.write_data(offset, self.v[offset])?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.i = (self.i + x as u32 + !self.quirks.increment_i_by_x as u32) & self.i_mask();
                 }
                 self.pc += 2;
             }
-            _ => {
+            Instruction::Restore(x) => {
+                // Fx65 - LD Vx, [I]
+                // The interpreter reads values from memory starting at location I into registers V0 through Vx.
+                for offset in 0..=x as usize {
+                    self.v[offset] = self.read_data(offset)?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.i = (self.i + x as u32 + !self.quirks.increment_i_by_x as u32) & self.i_mask();
+                }
                 self.pc += 2;
-                let error = EmulateCycleError { message: format!("{:X} opcode not handled d", opcode) };
-                return Err(error);
             }
+            Instruction::StoreFlags(x) => {
+                // Fx75 - LD R, Vx
+                // Store V0 through Vx in the RPL user flags.
+                let x = x as usize;
+                self.rpl_flags[..=x].copy_from_slice(&self.v[..=x]);
+                self.rpl_flags_written = true;
+                self.pc += 2;
+            }
+            Instruction::RestoreFlags(x) => {
+                // Fx85 - LD Vx, R
+                // Read V0 through Vx from the RPL user flags.
+                let x = x as usize;
+                self.v[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+                self.pc += 2;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Dxyn on the Mega-Chip screen: the sprite-size palette indices at I,
+    // or, from below the program where the font is, n rows of a glyph.
+    fn draw_mega(&mut self, x: usize, y: usize, height: usize) -> Result<bool, EmulateCycleError> {
+        if (self.i as usize) < PROGRAM_START {
+            let mut glyph = [0u8; 15];
+            for (row, byte) in glyph.iter_mut().take(height).enumerate() {
+                *byte = self.read_data(row)?;
+            }
+            return Ok(self.mega_mut().draw_glyph(x, y, &glyph[..height]));
+        }
+        let (width, height) = self.mega_mut().sprite_size();
+        // a failed read drops the buffer, and the next draw starts another
+        let mut sprite = self.mega_mut().take_sprite_buffer();
+        for offset in 0..width * height {
+            sprite.push(self.read_data(offset)?);
         }
+        Ok(self.mega_mut().draw_buffer(x, y, width, sprite))
+    }
 
+    // What 8xy6/8xyE shift: Vy on the VIP, Vx elsewhere.
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_vy {
+            self.v[y as usize]
+        } else {
+            self.v[x as usize]
+        }
+    }
+
+    // Moves past the next instruction when `condition` holds, to the next
+    // one otherwise.
+    fn skip_if(&mut self, condition: bool) {
+        self.pc += if condition { 4 } else { 2 };
+    }
+
+    // Called once per 60Hz frame by the scheduler.
+    pub fn vblank(&mut self) {
         // Decrease timers
         if self.dt > 0 {
             self.dt -= 1;
@@ -420,40 +914,312 @@ impl Cpu {
         if self.st > 0 {
             self.st -= 1;
         }
+        self.vblank_wait = false;
+        // time left over in a frame isn't kept, time overrun is
+        self.cycles_left = self.cycles_left.min(0) + vip_timing::FRAME_CYCLES;
+        self.frames += 1;
+        self.keypad.end_frame();
+    }
 
-        Ok(())
+    // 60Hz frames emulated since the last reset.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn emulated_time(&self) -> Duration {
+        Duration::from_secs(self.frames) / FRAME_RATE
+    }
+
+    pub fn rpl_flags(&self) -> [u8; 16] {
+        self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
+    }
+
+    // Whether Fx75 wrote the flags since the last call, for saving them.
+    pub fn take_rpl_flags_written(&mut self) -> bool {
+        std::mem::take(&mut self.rpl_flags_written)
+    }
+
+    // True while the cpu is held until the next vblank: by a draw, or with
+    // the vip_timing quirk, by the frame's cycles running out.
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.vblank_wait || (self.quirks.vip_timing && self.cycles_left <= 0)
+    }
+}
+
+// Machine state for debuggers, error reports and test failures: the
+// registers as a grid, timers, the stack from the bottom with the top marked
+// and the instruction at PC.
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "PC {:04X}  I {:04X}  SP {}  DT {:02X}  ST {:02X}", self.pc, self.i, self.sp, self.dt, self.st)?;
+        for (index, values) in self.v.chunks(4).enumerate() {
+            let cells: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {:02X}", index * 4 + column, value))
+                .collect();
+            writeln!(f, "{}", cells.join("  "))?;
+        }
+        if self.sp == 0 {
+            writeln!(f, "stack empty")?;
+        }
+        let depth = (self.sp as usize).min(self.stack.len());
+        for (level, address) in self.stack[..depth].iter().enumerate() {
+            let marker = if level + 1 == depth { '>' } else { ' ' };
+            writeln!(f, "stack {}{:2} {:04X}", marker, level, address)?;
+        }
+        match self.memory.get(self.pc as usize..self.pc as usize + 2) {
+            Some(bytes) => {
+                let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+                write!(f, "next  {:04X}  {}", opcode, opcodes::disassemble(opcode))
+            }
+            None => write!(f, "next  PC out of memory"),
+        }
+    }
+}
+
+impl fmt::Debug for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Cpu;
-    use std::ptr::null;
+    use super::{Cpu, ETI_660_START};
+    use crate::megachip;
+    use crate::errors::Violation;
+    use crate::instruction::Instruction;
+    use crate::opcodes;
     use crate::ppu::Display;
+    use crate::quirks::Platform;
+    use crate::rng::Rng;
+    use proptest::prelude::*;
 
     struct MockDisplay {}
     impl Display for MockDisplay {
         fn cls(&mut self) {}
 
-        fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        fn draw(&mut self, _x: usize, _y: usize, _sprite: &[u8], _clip: bool) -> bool {
             false
         }
 
-        fn set_pixel(&mut self, x: usize, y: usize, on: u8) {}
+        fn set_pixel(&mut self, _x: usize, _y: usize, _on: u8) {}
 
-        fn get_pixel(&mut self, x: usize, y: usize) -> bool {
+        fn get_pixel(&mut self, _x: usize, _y: usize) -> bool {
             false
         }
+
+        fn framebuffer(&self) -> &[u8] {
+            &[]
+        }
+
+        fn dirty_rows(&self) -> &[bool] {
+            &[]
+        }
+
+        fn clear_dirty(&mut self) {}
+
+        fn invalidate(&mut self) {}
     }
 
     fn make_display() -> Box<dyn Display> {
         Box::new(MockDisplay{})
     }
 
+    #[test]
+    fn state_printout() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.reset();
+        cpu.load(vec![0x22, 0x04, 0x00, 0x00, 0x63, 0x42]).unwrap();
+        cpu.step().unwrap();
+        cpu.v[0xA] = 0xFF;
+
+        let text = cpu.to_string();
+        assert!(text.starts_with("PC 0204  I 0000  SP 1  DT 00  ST 00\n"), "{}", text);
+        assert!(text.contains("V8 00  V9 00  VA FF  VB 00\n"), "{}", text);
+        assert!(text.contains("stack > 0 0200\n"), "{}", text);
+        assert!(text.ends_with("next  6342  LD V3, #42"), "{}", text);
+    }
+
+    #[test]
+    fn state_hash_covers_the_saved_state() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.reset();
+        cpu.load(vec![0x60, 0x2A, 0x12, 0x02]).unwrap();
+        // pinned, so a change to what goes in or its order shows up here
+        assert_eq!(cpu.state_hash(), 0xfcab_2882_72ad_099d);
+        let state = cpu.save_state();
+        let before = cpu.state_hash();
+        cpu.step().unwrap();
+        assert_ne!(cpu.state_hash(), before);
+        cpu.load_state(&state);
+        assert_eq!(cpu.state_hash(), before);
+        cpu.stack[15] = 0x0100;
+        assert_ne!(cpu.state_hash(), before, "the stack counts, all of it");
+    }
+
+    #[test]
+    fn decoder_matches_the_opcode_table() {
+        for opcode in 0..=0xFFFF {
+            let mut cpu = Cpu::new(make_display());
+            cpu.quirks = Platform::MegaChip.quirks();
+            // something to return from
+            cpu.sp = 1;
+            let decoded = cpu.process_opcode(opcode).is_ok();
+            assert_eq!(decoded, opcodes::lookup(opcode).is_some(), "{:04X}", opcode);
+
+            // and the Mega-Chip ones are unknown without the quirk
+            let mut cpu = Cpu::new(make_display());
+            cpu.sp = 1;
+            let mega = Instruction::decode(opcode).is_some_and(Instruction::is_megachip);
+            assert_eq!(cpu.process_opcode(opcode).is_ok(), opcodes::lookup(opcode).is_some() && !mega, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn strict_mode_reports_violations() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.strict = true;
+        cpu.v[1] = 0x10;
+        let violation = |cpu: &mut Cpu, opcode| cpu.process_opcode(opcode).unwrap_err().violation;
+
+        assert_eq!(violation(&mut cpu, 0xE19E), Some(Violation::KeyOutOfRange(0x10)));
+        cpu.i = 0xFFE;
+        assert_eq!(violation(&mut cpu, 0xF233), Some(Violation::MemoryWrap(0x1000)));
+        assert_eq!(violation(&mut cpu, 0x00EE), Some(Violation::StackUnderflow));
+        cpu.sp = 16;
+        assert_eq!(violation(&mut cpu, 0x2200), Some(Violation::StackOverflow));
+    }
+
+    #[test]
+    fn lenient_mode_wraps() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.v[1] = 0x10;
+        cpu.keypad.set_key(0, true);
+        cpu.process_opcode(0xE19E).unwrap();
+        assert_eq!(cpu.pc, 4, "key 0x10 reads key 0");
+
+        cpu.v[2] = 234;
+        cpu.i = 0xFFE;
+        cpu.process_opcode(0xF233).unwrap();
+        assert_eq!(cpu.memory[0], 4, "the last digit wraps to address 0");
+
+        let error = cpu.process_opcode(0x8128).unwrap_err();
+        assert_eq!(error.violation, Some(Violation::UnknownOpcode(0x8128)));
+        assert_eq!(error.message, "8128 opcode not handled");
+    }
+
+    #[test]
+    fn adding_to_i_wraps_instead_of_overflowing() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.reset();
+        // LD V0, #FF / LD I, #FFF, then ADD I, V0 until I passes 0xFFFF
+        cpu.step_with_opcode(0x60FF).unwrap();
+        cpu.step_with_opcode(0xAFFF).unwrap();
+        for _ in 0..300 {
+            cpu.step_with_opcode(0xF01E).unwrap();
+        }
+        assert_eq!(cpu.i, (0xFFF + 300 * 0xFF) % 0x10000);
+        // LD [I], V0 with I near the top of its range
+        cpu.quirks.load_store_increment_i = true;
+        cpu.i = 0xFFFF;
+        cpu.step_with_opcode(0xF055).unwrap();
+        assert_eq!(cpu.i, 0);
+    }
+
+    #[test]
+    fn mega_chip_draws_palette_sprites_shown_by_cls() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::MegaChip.quirks();
+        cpu.reset();
+        // a 2x2 sprite of entries 1 and 2 at 0x300, their colours at 0x310
+        cpu.memory[0x300..0x304].copy_from_slice(&[1, 2, 0, 1]);
+        cpu.memory[0x310..0x318].copy_from_slice(&[0xFF, 0x10, 0x20, 0x30, 0xFF, 0x40, 0x50, 0x60]);
+        // MEGAON / LD I, 0x310 / LDPAL 2 / SPRW 2 / SPRH 2 / LD V1, 4 /
+        // LD V2, 5 / LD I, 0x300 / DRW V1, V2, 0
+        for opcode in [0x0011, 0xA310, 0x0202, 0x0302, 0x0402, 0x6104, 0x6205, 0xA300, 0xD120] {
+            cpu.step_with_opcode(opcode).unwrap();
+        }
+        assert_eq!(cpu.v[0xF], 0);
+        assert_eq!(cpu.mega().unwrap().palette()[1..3], [0xFF10_2030, 0xFF40_5060]);
+        assert!(cpu.mega().unwrap().screen().iter().all(|pixel| *pixel == 0), "not shown before CLS");
+        cpu.step_with_opcode(0x00E0).unwrap();
+        let screen = cpu.mega().unwrap().screen();
+        let at = |x: usize, y: usize| screen[y * megachip::WIDTH + x];
+        assert_eq!((at(4, 5), at(5, 5), at(4, 6), at(5, 6)), (0xFF10_2030, 0xFF40_5060, 0xFF00_0000, 0xFF10_2030));
+
+        // CCOL 2, then the same sprite one pixel right lands on entry 2
+        cpu.step_with_opcode(0xD120).unwrap();
+        cpu.step_with_opcode(0x0902).unwrap();
+        cpu.step_with_opcode(0x6105).unwrap();
+        cpu.step_with_opcode(0xD120).unwrap();
+        assert_eq!(cpu.v[0xF], 1);
+
+        // MEGAOFF goes back to the CHIP-8 screen
+        cpu.step_with_opcode(0x0010).unwrap();
+        assert!(!cpu.mega().unwrap().on());
+    }
+
+    #[test]
+    fn mega_chip_has_24_bit_i_and_memory_past_4k() {
+        let mut cpu = Cpu::new(make_display());
+        assert!(cpu.load(vec![0; 0x2000]).is_err());
+        cpu.quirks = Platform::MegaChip.quirks();
+        cpu.reset();
+        // LDHI I, #001234 / ADD I, V0
+        let mut rom = vec![0x01, 0x00, 0x12, 0x34, 0xF0, 0x1E];
+        rom.resize(0x2000, 0xAB);
+        cpu.load(rom).unwrap();
+        assert_eq!(cpu.memory().len(), 0x2200);
+        cpu.step().unwrap();
+        assert_eq!((cpu.i, cpu.pc), (0x1234, 0x204));
+        cpu.v[0] = 0x10;
+        cpu.step().unwrap();
+        assert_eq!(cpu.i, 0x1244);
+        assert_eq!(cpu.read_data(0).unwrap(), 0xAB);
+        cpu.i = 0xFF_FFFF;
+        cpu.step_with_opcode(0xF01E).unwrap();
+        assert_eq!(cpu.i, 0xF);
+
+        cpu.reset();
+        assert_eq!((cpu.memory().len(), cpu.mega().is_none()), (super::MEMORY_SIZE, true));
+    }
+
+    #[test]
+    fn loads_and_starts_at_the_load_address() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.load_address = ETI_660_START as u16;
+        cpu.reset();
+        cpu.load(vec![0x63, 0x42]).unwrap();
+        assert_eq!(cpu.pc, 0x600);
+        assert_eq!(cpu.memory[0x200], 0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.v[3], 0x42);
+    }
+
+    #[test]
+    fn oversized_roms_are_refused_or_truncated() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.reset();
+        let error = cpu.load(vec![0xAA; 3585]).unwrap_err();
+        assert_eq!(error.to_string(), "ROM is 3585 bytes, but only 3584 fit in memory");
+        assert_eq!(cpu.memory[0x200], 0, "nothing is loaded");
+
+        cpu.load_truncated(vec![0xAA; 3585]);
+        assert_eq!(cpu.memory[0xFFF], 0xAA);
+        cpu.load_address = ETI_660_START as u16;
+        assert!(cpu.load(vec![0; 2561]).is_err());
+    }
+
     #[test]
     fn opcode_jp() {
         let mut cpu = Cpu::new(make_display());
-        cpu.process_opcode(0x1A2A);
+        cpu.process_opcode(0x1A2A).unwrap();
         assert_eq!(cpu.pc, 0x0A2A, "the program counter is updated");
     }
 
@@ -463,7 +1229,7 @@ mod tests {
         let addr = 0x23;
         cpu.pc = addr;
 
-        cpu.process_opcode(0x2ABC);
+        cpu.process_opcode(0x2ABC).unwrap();
 
         assert_eq!(cpu.pc, 0x0ABC, "the program counter is updated to the new address");
         assert_eq!(cpu.sp, 1, "the stack pointer is incremented");
@@ -476,11 +1242,11 @@ mod tests {
         cpu.v[1] = 0xFE;
 
         // vx == kk
-        cpu.process_opcode(0x31FE);
+        cpu.process_opcode(0x31FE).unwrap();
         assert_eq!(cpu.pc, 4, "the stack pointer skips");
 
         // vx != kk
-        cpu.process_opcode(0x31FA);
+        cpu.process_opcode(0x31FA).unwrap();
         assert_eq!(cpu.pc, 6, "the stack pointer is incremented");
     }
 
@@ -490,11 +1256,11 @@ mod tests {
         cpu.v[1] = 0xFE;
 
         // vx == kk
-        cpu.process_opcode(0x41FE);
+        cpu.process_opcode(0x41FE).unwrap();
         assert_eq!(cpu.pc, 2, "the stack pointer is incremented");
 
         // vx != kk
-        cpu.process_opcode(0x41FA);
+        cpu.process_opcode(0x41FA).unwrap();
         assert_eq!(cpu.pc, 6, "the stack pointer skips");
     }
 
@@ -506,11 +1272,11 @@ mod tests {
         cpu.v[3] = 3;
 
         // vx == vy
-        cpu.process_opcode(0x5230);
+        cpu.process_opcode(0x5230).unwrap();
         assert_eq!(cpu.pc, 4, "the stack pointer skips");
 
         // vx != vy
-        cpu.process_opcode(0x5130);
+        cpu.process_opcode(0x5130).unwrap();
         assert_eq!(cpu.pc, 6, "the stack pointer is incremented");
     }
 
@@ -522,11 +1288,11 @@ mod tests {
         cpu.v[3] = 3;
 
         // vx == vy
-        cpu.process_opcode(0x9230);
+        cpu.process_opcode(0x9230).unwrap();
         assert_eq!(cpu.pc, 2, "the stack pointer is incremented");
 
         // vx != vy
-        cpu.process_opcode(0x9130);
+        cpu.process_opcode(0x9130).unwrap();
         assert_eq!(cpu.pc, 6, "the stack pointer skips");
     }
 
@@ -535,7 +1301,7 @@ mod tests {
         let mut cpu = Cpu::new(make_display());
         cpu.v[1] = 3;
 
-        cpu.process_opcode(0x7101);
+        cpu.process_opcode(0x7101).unwrap();
         assert_eq!(cpu.v[1], 4, "Vx was incremented by one");
     }
 
@@ -545,7 +1311,7 @@ mod tests {
         cpu.v[1] = 3;
         cpu.v[0] = 0;
 
-        cpu.process_opcode(0x8010);
+        cpu.process_opcode(0x8010).unwrap();
         assert_eq!(cpu.v[0], 3, "Vx was loaded with vy");
     }
 
@@ -555,7 +1321,7 @@ mod tests {
         cpu.v[2] = 0b01101100;
         cpu.v[3] = 0b11001110;
 
-        cpu.process_opcode(0x8231);
+        cpu.process_opcode(0x8231).unwrap();
         assert_eq!(cpu.v[2], 0b11101110, "Vx was loaded with vx OR vy");
     }
 
@@ -565,7 +1331,7 @@ mod tests {
         cpu.v[2] = 0b01101100;
         cpu.v[3] = 0b11001110;
 
-        cpu.process_opcode(0x8232);
+        cpu.process_opcode(0x8232).unwrap();
         assert_eq!(cpu.v[2], 0b01001100, "Vx was loaded with vx AND vy");
     }
 
@@ -575,10 +1341,34 @@ mod tests {
         cpu.v[2] = 0b01101100;
         cpu.v[3] = 0b11001110;
 
-        cpu.process_opcode(0x8233);
+        cpu.process_opcode(0x8233).unwrap();
         assert_eq!(cpu.v[2], 0b10100010, "Vx was loaded with vx XOR vy");
     }
 
+    #[test]
+    fn opcode_logic_resets_vf_on_vip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Vip.quirks();
+
+        for opcode in [0x8231, 0x8232, 0x8233].iter() {
+            cpu.v[0xF] = 1;
+            cpu.process_opcode(*opcode).unwrap();
+            assert_eq!(cpu.v[0xF], 0, "VF is cleared by {:X}", opcode);
+        }
+    }
+
+    #[test]
+    fn opcode_logic_keeps_vf_on_schip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Schip.quirks();
+
+        for opcode in [0x8231, 0x8232, 0x8233].iter() {
+            cpu.v[0xF] = 1;
+            cpu.process_opcode(*opcode).unwrap();
+            assert_eq!(cpu.v[0xF], 1, "VF is untouched by {:X}", opcode);
+        }
+    }
+
     #[test]
     fn opcode_add_vx_vy() {
         let mut cpu = Cpu::new(make_display());
@@ -586,11 +1376,11 @@ mod tests {
         cpu.v[2] = 100;
         cpu.v[3] = 250;
 
-        cpu.process_opcode(0x8124);
+        cpu.process_opcode(0x8124).unwrap();
         assert_eq!(cpu.v[1], 110, "Vx was loaded with vx + vy");
         assert_eq!(cpu.v[0xF], 0, "no overflow occured");
 
-        cpu.process_opcode(0x8134);
+        cpu.process_opcode(0x8134).unwrap();
         assert_eq!(cpu.v[1], 0x68, "Vx was loaded with vx + vy");
         assert_eq!(cpu.v[0xF], 1, "overflow occured");
     }
@@ -602,14 +1392,15 @@ mod tests {
         cpu.v[1] = 4;
         cpu.v[2] = 3;
         cpu.v[3] = 2;
-        cpu.i = 0x300;
+        let i = 0x300;
+        cpu.i = i as u32;
 
         // load v0 - v2 into memory at i
-        cpu.process_opcode(0xF255);
-        assert_eq!(cpu.memory[cpu.i as usize], 5, "V0 was loaded into memory at i");
-        assert_eq!(cpu.memory[cpu.i as usize + 1], 4, "V1 was loaded into memory at i + 1");
-        assert_eq!(cpu.memory[cpu.i as usize + 2], 3, "V2 was loaded into memory at i + 2");
-        assert_eq!(cpu.memory[cpu.i as usize + 3], 0, "i + 3 was not loaded");
+        cpu.process_opcode(0xF255).unwrap();
+        assert_eq!(cpu.memory[i], 5, "V0 was loaded into memory at i");
+        assert_eq!(cpu.memory[i + 1], 4, "V1 was loaded into memory at i + 1");
+        assert_eq!(cpu.memory[i + 2], 3, "V2 was loaded into memory at i + 2");
+        assert_eq!(cpu.memory[i + 3], 0, "i + 3 was not loaded");
     }
 
     #[test]
@@ -619,7 +1410,7 @@ mod tests {
         cpu.v[2] = 234;
 
         // load v0 - v2 from memory at i
-        cpu.process_opcode(0xF233);
+        cpu.process_opcode(0xF233).unwrap();
         assert_eq!(cpu.memory[cpu.i as usize], 2, "hundreds");
         assert_eq!(cpu.memory[cpu.i as usize + 1], 3, "tens");
         assert_eq!(cpu.memory[cpu.i as usize + 2], 4, "digits");
@@ -636,13 +1427,164 @@ mod tests {
 
 
         // load v0 - v2 from memory at i
-        cpu.process_opcode(0xF265);
+        cpu.process_opcode(0xF265).unwrap();
         assert_eq!(cpu.v[0], 5, "V0 was loaded from memory at i");
         assert_eq!(cpu.v[1], 4, "V1 was loaded from memory at i + 1");
         assert_eq!(cpu.v[2], 3, "V2 was loaded from memory at i + 2");
         assert_eq!(cpu.v[3], 0, "i + 3 was not loaded");
     }
 
+    #[test]
+    fn opcode_ld_i_vx_increments_i_on_vip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Vip.quirks();
+        cpu.i = 0x300;
+
+        cpu.process_opcode(0xF255).unwrap();
+        assert_eq!(cpu.i, 0x303, "i points past the last stored register");
+
+        cpu.process_opcode(0xF065).unwrap();
+        assert_eq!(cpu.i, 0x304, "i points past the last loaded register");
+    }
+
+    #[test]
+    fn opcode_ld_i_vx_leaves_i_on_the_last_register_on_chip48() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Chip48.quirks();
+        cpu.i = 0x300;
+
+        cpu.process_opcode(0xF255).unwrap();
+        assert_eq!(cpu.i, 0x302, "i points at the last stored register");
+
+        cpu.process_opcode(0xF065).unwrap();
+        assert_eq!(cpu.i, 0x302, "i is unchanged after loading V0 only");
+    }
+
+    #[test]
+    fn opcode_ld_i_vx_keeps_i_on_schip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Schip.quirks();
+        cpu.i = 0x300;
+
+        cpu.process_opcode(0xF255).unwrap();
+        assert_eq!(cpu.i, 0x300, "i is unchanged after a store");
+
+        cpu.process_opcode(0xF065).unwrap();
+        assert_eq!(cpu.i, 0x300, "i is unchanged after a load");
+    }
+
+    #[test]
+    fn opcode_jp_v0_addr() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Vip.quirks();
+        cpu.v[0] = 0x10;
+        cpu.v[2] = 0x40;
+
+        cpu.process_opcode(0xB234).unwrap();
+        assert_eq!(cpu.pc, 0x244, "the program counter is nnn + V0");
+    }
+
+    #[test]
+    fn opcode_jp_vx_addr_on_schip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Schip.quirks();
+        cpu.v[0] = 0x10;
+        cpu.v[2] = 0x40;
+
+        cpu.process_opcode(0xB234).unwrap();
+        assert_eq!(cpu.pc, 0x274, "the program counter is xnn + Vx");
+    }
+
+    #[test]
+    fn opcode_shr_shl_shift_vy_on_vip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Vip.quirks();
+        cpu.v[1] = 0x05;
+        cpu.v[2] = 0x14;
+
+        cpu.process_opcode(0x8126).unwrap();
+        assert_eq!((cpu.v[1], cpu.v[0xF]), (0x0A, 0), "V1 = V2 >> 1");
+        cpu.process_opcode(0x812E).unwrap();
+        assert_eq!((cpu.v[1], cpu.v[0xF]), (0x28, 0), "V1 = V2 << 1");
+    }
+
+    #[test]
+    fn opcode_shr_shl_shift_vx_in_place_on_chip48_and_schip() {
+        for platform in [Platform::Chip48, Platform::Schip] {
+            let mut cpu = Cpu::new(make_display());
+            cpu.quirks = platform.quirks();
+            cpu.v[1] = 0x05;
+            cpu.v[2] = 0x14;
+
+            cpu.process_opcode(0x8126).unwrap();
+            assert_eq!((cpu.v[1], cpu.v[0xF]), (0x02, 1), "{:?}: V1 >>= 1, y ignored", platform);
+            cpu.process_opcode(0x812E).unwrap();
+            assert_eq!((cpu.v[1], cpu.v[0xF]), (0x04, 0), "{:?}: V1 <<= 1, y ignored", platform);
+        }
+    }
+
+    #[test]
+    fn opcode_ld_vx_k_waits_for_a_key_release() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.pc = 0x200;
+
+        cpu.process_opcode(0xF30A).unwrap();
+        assert_eq!(cpu.pc, 0x200, "no key held, still waiting");
+
+        cpu.keypad.set_key(7, true);
+        cpu.keypad.set_key(9, true);
+        cpu.process_opcode(0xF30A).unwrap();
+        cpu.vblank();
+        cpu.process_opcode(0xF30A).unwrap();
+        assert_eq!(cpu.pc, 0x200, "keys held, still waiting");
+        assert_eq!(cpu.v[3], 0);
+
+        cpu.keypad.set_key(9, false);
+        cpu.process_opcode(0xF30A).unwrap();
+        assert_eq!(cpu.pc, 0x202, "the released key ends the wait");
+        assert_eq!(cpu.v[3], 9);
+        cpu.process_opcode(0xF40A).unwrap();
+        assert_eq!(cpu.pc, 0x202, "the same release isn't taken twice");
+    }
+
+    #[test]
+    fn opcode_drw_waits_for_vblank_on_vip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Vip.quirks();
+
+        cpu.process_opcode(0xD015).unwrap();
+        assert!(cpu.waiting_for_vblank(), "the draw holds the cpu");
+
+        cpu.vblank();
+        assert!(!cpu.waiting_for_vblank(), "the vblank releases the cpu");
+    }
+
+    #[test]
+    fn opcode_drw_does_not_wait_on_schip() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.quirks = Platform::Schip.quirks();
+
+        cpu.process_opcode(0xD015).unwrap();
+        assert!(!cpu.waiting_for_vblank());
+    }
+
+    #[test]
+    fn vblank_decrements_timers() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.dt = 2;
+        cpu.st = 1;
+        cpu.keypad.set_key(3, true);
+
+        cpu.vblank();
+        assert_eq!(cpu.dt, 1);
+        assert_eq!(cpu.st, 0);
+        assert!(!cpu.keypad.just_pressed(3), "a new frame for the keypad edges");
+
+        cpu.vblank();
+        assert_eq!(cpu.dt, 0);
+        assert_eq!(cpu.st, 0, "timers stop at zero");
+    }
+
     #[test]
     fn opcode_ret() {
         let mut cpu = Cpu::new(make_display());
@@ -650,9 +1592,9 @@ mod tests {
         cpu.pc = addr;
 
         // jump to 0x0ABC
-        cpu.process_opcode(0x2ABC);
+        cpu.process_opcode(0x2ABC).unwrap();
         // return
-        cpu.process_opcode(0x00EE);
+        cpu.process_opcode(0x00EE).unwrap();
 
         assert_eq!(cpu.pc, 0x25, "the program counter is updated to the new address");
         assert_eq!(cpu.sp, 0, "the stack pointer is decremented");
@@ -663,15 +1605,15 @@ mod tests {
     fn opcode_ld_i_addr() {
         let mut cpu = Cpu::new(make_display());
 
-        cpu.process_opcode(0x61AA);
+        cpu.process_opcode(0x61AA).unwrap();
         assert_eq!(cpu.v[1], 0xAA, "V1 is set");
         assert_eq!(cpu.pc, 2, "the program counter is advanced two bytes");
 
-        cpu.process_opcode(0x621A);
+        cpu.process_opcode(0x621A).unwrap();
         assert_eq!(cpu.v[2], 0x1A, "V2 is set");
         assert_eq!(cpu.pc, 4, "the program counter is advanced two bytes");
 
-        cpu.process_opcode(0x6A15);
+        cpu.process_opcode(0x6A15).unwrap();
         assert_eq!(cpu.v[10], 0x15, "V10 is set");
         assert_eq!(cpu.pc, 6, "the program counter is advanced two bytes");
     }
@@ -679,10 +1621,133 @@ mod tests {
     #[test]
     fn opcode_axxx() {
         let mut cpu = Cpu::new(make_display());
-        cpu.process_opcode(0xAFAF);
+        cpu.process_opcode(0xAFAF).unwrap();
 
         assert_eq!(cpu.i, 0x0FAF, "the 'i' register is updated");
         assert_eq!(cpu.pc, 2, "the program counter is advanced two bytes");
     }
 
+    #[test]
+    fn random_opcode_streams_never_panic() {
+        let mut rng = Rng::new(0xF022);
+        for _ in 0..200 {
+            let mut cpu = Cpu::new(make_display());
+            cpu.quirks = if rng.next_u8() & 1 == 0 { Platform::Vip.quirks() } else { Platform::Schip.quirks() };
+            cpu.strict = rng.next_u8() & 1 == 0;
+            cpu.reset();
+            // errors are fine, panics are bugs
+            for _ in 0..5000 {
+                let _ = cpu.step_with_opcode(rng.next_u64() as u16);
+                cpu.vblank();
+            }
+        }
+    }
+
+    // Vx and VF after 8xyN by the standard semantics: VF is the carry, NOT
+    // borrow or shifted-out bit, as 0 or 1.
+    fn alu(subcode: u16, vx: u8, vy: u8) -> (u8, u8) {
+        match subcode {
+            4 => {
+                let (sum, carry) = vx.overflowing_add(vy);
+                (sum, carry as u8)
+            }
+            5 => (vx.wrapping_sub(vy), (vx >= vy) as u8),
+            // the VIP's shifts, which shift Vy
+            6 => (vy >> 1, vy & 1),
+            7 => (vy.wrapping_sub(vx), (vy >= vx) as u8),
+            _ => (vy << 1, vy >> 7),
+        }
+    }
+
+    // Runs 812N with V1 = vx, V2 = vy and a stale VF, returning V1 and VF.
+    fn run_alu(subcode: u16, vx: u8, vy: u8, vf: u8) -> (u8, u8) {
+        let mut cpu = Cpu::new(make_display());
+        cpu.v[1] = vx;
+        cpu.v[2] = vy;
+        cpu.v[0xF] = vf;
+        cpu.process_opcode(0x8120 | subcode).unwrap();
+        (cpu.v[1], cpu.v[0xF])
+    }
+
+    #[test]
+    fn alu_flags_for_every_operand_pair() {
+        let mut cpu = Cpu::new(make_display());
+        for subcode in [4, 5, 6, 7, 0xE].iter() {
+            for vx in 0..=255u8 {
+                for vy in 0..=255u8 {
+                    cpu.v[1] = vx;
+                    cpu.v[2] = vy;
+                    cpu.v[0xF] = 0xAA;
+                    cpu.pc = 0x200;
+                    cpu.process_opcode(0x8120 | subcode).unwrap();
+                    assert_eq!((cpu.v[1], cpu.v[0xF]), alu(*subcode, vx, vy), "812{:X} with {}, {}", subcode, vx, vy);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vf_result_quirk_keeps_the_result_in_vf() {
+        let mut cpu = Cpu::new(make_display());
+        cpu.v[0xF] = 200;
+        cpu.v[2] = 100;
+        cpu.process_opcode(0x8F24).unwrap();
+        assert_eq!(cpu.v[0xF], 1, "the carry is written last");
+
+        cpu.quirks.vf_result = true;
+        cpu.v[0xF] = 200;
+        cpu.process_opcode(0x8F24).unwrap();
+        assert_eq!(cpu.v[0xF], 44, "the sum is written last");
+        cpu.v[0] = 0x81;
+        cpu.process_opcode(0x8F0E).unwrap();
+        assert_eq!(cpu.v[0xF], 0x02);
+    }
+
+    proptest! {
+        #[test]
+        fn add_sets_vf_to_the_carry(vx: u8, vy: u8, vf: u8) {
+            prop_assert_eq!(run_alu(4, vx, vy, vf), alu(4, vx, vy));
+        }
+
+        #[test]
+        fn sub_sets_vf_to_not_borrow(vx: u8, vy: u8, vf: u8) {
+            prop_assert_eq!(run_alu(5, vx, vy, vf), alu(5, vx, vy));
+        }
+
+        #[test]
+        fn shr_sets_vf_to_the_low_bit(vx: u8, vy: u8, vf: u8) {
+            prop_assert_eq!(run_alu(6, vx, vy, vf), alu(6, vx, vy));
+        }
+
+        #[test]
+        fn subn_sets_vf_to_not_borrow(vx: u8, vy: u8, vf: u8) {
+            prop_assert_eq!(run_alu(7, vx, vy, vf), alu(7, vx, vy));
+        }
+
+        #[test]
+        fn shl_sets_vf_to_the_high_bit(vx: u8, vy: u8, vf: u8) {
+            prop_assert_eq!(run_alu(0xE, vx, vy, vf), alu(0xE, vx, vy));
+        }
+
+        // With VF as x or y, the operation reads VF before the flag is
+        // written, and the flag is written last.
+        #[test]
+        fn vf_operands_are_read_before_the_flag(
+            subcode in prop::sample::select(vec![4u16, 5, 6, 7, 0xE]),
+            v: u8,
+            vf: u8,
+        ) {
+            let mut cpu = Cpu::new(make_display());
+            cpu.v[2] = v;
+            cpu.v[0xF] = vf;
+            cpu.process_opcode(0x8F20 | subcode).unwrap();
+            prop_assert_eq!(cpu.v[0xF], alu(subcode, vf, v).1, "8F2{:X}", subcode);
+
+            let mut cpu = Cpu::new(make_display());
+            cpu.v[1] = v;
+            cpu.v[0xF] = vf;
+            cpu.process_opcode(0x81F0 | subcode).unwrap();
+            prop_assert_eq!((cpu.v[1], cpu.v[0xF]), alu(subcode, v, vf), "81F{:X}", subcode);
+        }
+    }
 }
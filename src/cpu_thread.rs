@@ -0,0 +1,378 @@
+use crate::autosave::Autosave;
+use crate::battery::Battery;
+use crate::command::{self, RunState};
+use crate::cpu::Cpu;
+use crate::emulator::Emulator;
+use crate::errors::EmulateCycleError;
+use crate::frontend::{self, FrontendOptions};
+use crate::input_macro::InputMacro;
+use crate::movie::MovieMode;
+use crate::netplay::Netplay;
+use crate::osd::Osd;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::playclock::PlayClock;
+use crate::present::PresentInfo;
+use crate::recording::{self, Recording};
+use crate::resume::Resume;
+use crate::rpl_flags::FlagStore;
+use crate::scheduler::{FrameLimiter, Scheduler, SpeedMeter};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Frames waiting for the frontend. Further ones are dropped, so a slow
+// present skips frames instead of holding up the emulation.
+const QUEUED_FRAMES: usize = 2;
+
+// The emulation side of a window frontend: the Emulator, which runs the
+// frames, and everything that runs along with them.
+pub struct Machine {
+    // has the cheats and script hooks; its instructions per frame follow
+    // the scheduler's
+    pub emulator: Emulator,
+    pub scheduler: Scheduler,
+    pub state: RunState,
+    pub clock: PlayClock,
+    pub movie: Option<MovieMode>,
+    pub netplay: Option<Netplay>,
+    pub input_macro: InputMacro,
+    pub recording: Option<Recording>,
+    pub autosave: Option<Autosave>,
+    pub flags: Option<FlagStore>,
+    pub battery: Option<Battery>,
+    // see Resume; saving on close is up to the frontend, which knows
+    // what menu is up
+    pub resume: Option<Resume>,
+    pub phosphor: Option<Phosphor>,
+    pub options: FrontendOptions,
+    // why the cpu stopped, until the frontend takes it; no frames run
+    // meanwhile
+    pub error: Option<EmulateCycleError>,
+    // the host's keys, as the frontend last sent them
+    keys: [bool; 16],
+    // a menu is up: time passes without frames
+    held: bool,
+}
+
+// A frame for the frontend to show, with what it needs to draw it.
+pub struct Frame {
+    pub framebuffer: Vec<u8>,
+    // the Mega-Chip screen, shown in place of the framebuffer while it is on
+    pub mega: Option<Vec<u32>>,
+    pub phosphor: Option<Phosphor>,
+    pub palette: Palette,
+    pub crt_on: bool,
+    pub osd: Osd,
+    pub paused: bool,
+    // the cpu stopped on an error, see Machine::error
+    pub failed: bool,
+    // see PlayClock
+    pub played: Duration,
+    // see Scheduler::scale
+    pub speed: u32,
+    // frames since the reset, and that as emulated time
+    pub frame: u64,
+    pub emulated: Duration,
+}
+
+enum Request {
+    Keys([bool; 16]),
+    Hold(bool),
+    Presented,
+    // send the machine over until it is given back
+    Lend,
+    Give(Box<Machine>),
+    Quit,
+}
+
+impl Frame {
+    // What present hooks see of it, at this moment.
+    pub fn present_info(&self) -> PresentInfo<'_> {
+        PresentInfo {
+            frame: self.frame,
+            emulated: self.emulated,
+            wall: Instant::now(),
+            played: self.played,
+            framebuffer: &self.framebuffer,
+        }
+    }
+}
+
+impl Machine {
+    // Starts the movie, if there is one, on `cpu`.
+    pub fn new(mut cpu: Cpu, options: &FrontendOptions) -> Machine {
+        let movie = options.movie.clone();
+        if let Some(movie) = &movie {
+            movie.start(&mut cpu);
+        }
+        let flags = options.flags_dir.as_deref().map(FlagStore::new);
+        if let Some(flags) = &flags {
+            flags.restore(&mut cpu);
+        }
+        let battery = frontend::restore_battery(&mut cpu, options);
+        Machine {
+            scheduler: Scheduler::new(options.instructions_per_frame),
+            state: RunState {
+                palette: options.palette,
+                paused: false,
+                advance: 0,
+                crt_on: options.crt.tube(),
+                program: frontend::program(&cpu),
+                osd: Osd::new(),
+                speed: SpeedMeter::new(),
+                show_speed: options.show_speed,
+            },
+            clock: PlayClock::new(),
+            movie,
+            netplay: options.netplay.clone(),
+            input_macro: InputMacro::new(),
+            recording: None,
+            autosave: options
+                .autosave_dir
+                .clone()
+                .map(|dir| Autosave::new(dir, options.autosave_interval)),
+            flags,
+            battery,
+            resume: options.resume_dir.clone().map(Resume::new),
+            phosphor: options.phosphor.map(Phosphor::new),
+            options: options.clone(),
+            error: None,
+            keys: [false; 16],
+            held: false,
+            emulator: frontend::emulator(cpu, options),
+        }
+    }
+
+    // Applies the queued commands and runs the frames that came due,
+    // returning how many.
+    pub fn tick(&mut self) -> Result<u32, EmulateCycleError> {
+        let commands = self.options.commands.clone();
+        command::apply_pending(&commands, self.emulator.cpu_mut(), &mut self.scheduler, &mut self.state, self.movie.as_ref());
+        let frames = self.state.frames_to_run(self.scheduler.frames_due());
+        for _ in 0..frames {
+            for (key, &pressed) in self.keys.iter().enumerate() {
+                self.emulator.key_event(key as u8, pressed);
+            }
+            let cpu = self.emulator.cpu_mut();
+            self.input_macro.frame(&mut cpu.keypad);
+            frontend::netplay_frame(&mut self.netplay, cpu);
+            if let Some(movie) = &mut self.movie {
+                movie.frame(cpu);
+            }
+            self.emulator.instructions_per_frame = self.scheduler.instructions_per_frame;
+            let executed = self.emulator.step_frame()?;
+            self.state.speed.frame(executed);
+            let framebuffer = self.emulator.framebuffer();
+            recording::record_frame(&mut self.recording, framebuffer, self.options.screenshot_dir.as_deref());
+            if let Some(phosphor) = &mut self.phosphor {
+                phosphor.update(framebuffer);
+            }
+        }
+        self.clock.advance(frames);
+        if let Some(autosave) = &mut self.autosave {
+            autosave.tick(self.emulator.cpu());
+        }
+        if let Some(flags) = &self.flags {
+            flags.tick(self.emulator.cpu_mut());
+        }
+        self.state.update_speed();
+        Ok(frames)
+    }
+
+    // Saves the recording, the movie and the battery memory; call once the
+    // frontend closes.
+    pub fn finish(&mut self) {
+        recording::stop(&mut self.recording, self.options.screenshot_dir.as_deref());
+        if let Some(movie) = &mut self.movie {
+            movie.finish(self.emulator.cpu_mut());
+        }
+        if let Some(battery) = &self.battery {
+            battery.finish(self.emulator.cpu());
+        }
+    }
+
+    fn frame(&mut self) -> Frame {
+        let frame = Frame {
+            framebuffer: self.emulator.framebuffer().to_vec(),
+            mega: self.emulator.mega_screen().map(<[u32]>::to_vec),
+            phosphor: self.phosphor.clone(),
+            palette: self.state.palette,
+            crt_on: self.state.crt_on,
+            osd: self.state.osd.clone(),
+            paused: self.state.paused,
+            failed: self.error.is_some(),
+            played: self.clock.played(),
+            speed: self.scheduler.scale(),
+            frame: self.emulator.cpu().frame_count(),
+            emulated: self.emulator.cpu().emulated_time(),
+        };
+        self.emulator.cpu_mut().display_mut().clear_dirty();
+        frame
+    }
+}
+
+// Runs a Machine on a thread of its own, paced by its own frame clock, so
+// a blocked present or a paused window doesn't stall the emulation. The
+// frontend sends keys in and takes frames out; settings go through the
+// machine's command queue as before.
+pub struct CpuThread {
+    requests: Sender<Request>,
+    frames: Receiver<Frame>,
+    lent: Receiver<Box<Machine>>,
+    thread: JoinHandle<()>,
+}
+
+impl CpuThread {
+    pub fn spawn(machine: Machine) -> CpuThread {
+        let (requests, requests_in) = mpsc::channel();
+        let (frames_out, frames) = mpsc::sync_channel(QUEUED_FRAMES);
+        let (lent_out, lent) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("cpu".to_string())
+            .spawn(move || run(Box::new(machine), requests_in, frames_out, lent_out))
+            .unwrap();
+        CpuThread {
+            requests,
+            frames,
+            lent,
+            thread,
+        }
+    }
+
+    pub fn set_keys(&self, keys: [bool; 16]) {
+        let _ = self.requests.send(Request::Keys(keys));
+    }
+
+    // While held the game stands still, and its time isn't caught up on
+    // afterwards; for menus drawn over it.
+    pub fn hold(&self, held: bool) {
+        let _ = self.requests.send(Request::Hold(held));
+    }
+
+    // Counts a present for the speed meter.
+    pub fn presented(&self) {
+        let _ = self.requests.send(Request::Presented);
+    }
+
+    // Calls `f` with the machine between two of its frames. The emulation
+    // waits meanwhile, so keep it short. None once the emulation stopped.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Machine) -> R) -> Option<R> {
+        self.requests.send(Request::Lend).ok()?;
+        let mut machine = self.lent.recv().ok()?;
+        let result = f(&mut machine);
+        let _ = self.requests.send(Request::Give(machine));
+        Some(result)
+    }
+
+    // The newest frame, waiting up to `timeout` for one. Disconnected once
+    // the emulation stopped.
+    pub fn next_frame(&self, timeout: Duration) -> Result<Frame, RecvTimeoutError> {
+        let frame = self.frames.recv_timeout(timeout)?;
+        Ok(self.frames.try_iter().last().unwrap_or(frame))
+    }
+
+    // Stops the emulation, returning the machine.
+    pub fn stop(self) -> Machine {
+        let _ = self.requests.send(Request::Quit);
+        self.thread.join().unwrap();
+        *self.lent.recv().unwrap()
+    }
+}
+
+fn run(
+    mut machine: Box<Machine>,
+    requests: Receiver<Request>,
+    frames: SyncSender<Frame>,
+    lent: Sender<Box<Machine>>,
+) {
+    let mut limiter = FrameLimiter::new(false);
+    loop {
+        while let Ok(request) = requests.try_recv() {
+            match request {
+                Request::Keys(keys) => machine.keys = keys,
+                Request::Hold(held) => machine.held = held,
+                Request::Presented => machine.state.speed.present(),
+                Request::Lend => {
+                    if lent.send(machine).is_err() {
+                        return;
+                    }
+                    machine = loop {
+                        match requests.recv() {
+                            Ok(Request::Give(machine)) => break machine,
+                            Ok(_) => {}
+                            // the frontend went away with the machine
+                            Err(_) => return,
+                        }
+                    };
+                }
+                Request::Give(_) => {}
+                Request::Quit => {
+                    let _ = lent.send(machine);
+                    return;
+                }
+            }
+        }
+
+        let mut failed = false;
+        let ran = if machine.held || machine.error.is_some() {
+            machine.scheduler.frames_due();
+            0
+        } else {
+            match machine.tick() {
+                Ok(frames) => frames,
+                Err(error) => {
+                    machine.error = Some(error);
+                    failed = true;
+                    0
+                }
+            }
+        };
+        if ran > 0 || failed || machine.state.osd.update() {
+            if let Err(TrySendError::Disconnected(_)) = frames.try_send(machine.frame()) {
+                let _ = lent.send(machine);
+                return;
+            }
+        }
+        limiter.wait(&machine.scheduler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuThread, Machine};
+    use crate::cpu::Cpu;
+    use crate::frontend::FrontendOptions;
+    use crate::headless::HeadlessDisplay;
+    use crate::ppu::{HEIGHT, WIDTH};
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_frames_on_its_own_thread() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V0, 5 / SKNP V0 / ADD V1, 1 / JP 0x202
+        cpu.load(vec![0x60, 0x05, 0xE0, 0xA1, 0x71, 0x01, 0x12, 0x02]).unwrap();
+        let cpu_thread = CpuThread::spawn(Machine::new(cpu, &FrontendOptions::default()));
+
+        let frame = cpu_thread.next_frame(Duration::from_secs(1)).unwrap();
+        assert_eq!(frame.framebuffer.len(), WIDTH * HEIGHT);
+        assert_eq!(cpu_thread.with(|machine| machine.emulator.cpu().registers()[1]), Some(0));
+        let mut keys = [false; 16];
+        keys[5] = true;
+        cpu_thread.set_keys(keys);
+        let frame = cpu_thread.next_frame(Duration::from_secs(1)).unwrap();
+        cpu_thread.next_frame(Duration::from_secs(1)).unwrap();
+        assert!(cpu_thread.with(|machine| machine.emulator.cpu().registers()[1]).unwrap() > 0, "the key reached the game");
+
+        cpu_thread.hold(true);
+        // what was queued before the hold
+        while cpu_thread.next_frame(Duration::from_millis(100)).is_ok() {}
+        assert_eq!(cpu_thread.next_frame(Duration::from_millis(50)).err(), Some(RecvTimeoutError::Timeout), "held");
+
+        let machine = cpu_thread.stop();
+        assert!(machine.error.is_none());
+        assert!(machine.emulator.cpu().frame_count() > frame.frame);
+    }
+}
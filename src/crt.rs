@@ -0,0 +1,187 @@
+use crate::palette;
+use crate::ppu::{Color, HEIGHT, WIDTH};
+
+// Output pixels per display pixel when the effects are rendered on the cpu;
+// enough for scanlines and the grid to be visible before the final scaling.
+pub const CRT_SCALE: usize = 8;
+
+// Barrel distortion strength of the curvature approximation.
+const CURVATURE: f32 = 0.06;
+
+// Width of grid lines as a fraction of a display pixel: one output pixel
+// at CRT_SCALE.
+const GRID_LINE_WIDTH: f32 = 1.0 / CRT_SCALE as f32;
+
+// Thin lines between display pixels, blended over the picture. Unlike the
+// CRT `grid`, which darkens each pixel's border, the lines have their own
+// color and are kept when the CRT effects are toggled off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridLines {
+    pub color: Color,
+    // 0 to 100
+    pub alpha: u8,
+}
+
+impl GridLines {
+    // "<alpha percent>[,RRGGBB]", black when no color is given.
+    pub fn parse(text: &str) -> Option<GridLines> {
+        let (alpha, color) = match text.split_once(',') {
+            Some((alpha, color)) => (alpha, palette::parse_color(color.trim())?),
+            None => (text, Color::rgb(0, 0, 0)),
+        };
+        let alpha = alpha.trim().parse().ok().filter(|alpha| *alpha <= 100)?;
+        Some(GridLines { color, alpha })
+    }
+}
+
+// Retro-look post-processing applied when the display is upscaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CrtEffects {
+    // Darkens the lower part of every display row.
+    pub scanlines: bool,
+    // Darkens the border of every display pixel, like an LCD.
+    pub grid: bool,
+    // Bulges the picture like a curved tube, black outside it.
+    pub curvature: bool,
+    pub grid_lines: Option<GridLines>,
+}
+
+impl CrtEffects {
+    // All effects, used when toggling on without a configured set.
+    pub const ALL: CrtEffects = CrtEffects {
+        scanlines: true,
+        grid: true,
+        curvature: true,
+        grid_lines: None,
+    };
+
+    // Parses a comma separated list like "scanlines,curvature".
+    pub fn from_names(names: &str) -> Option<CrtEffects> {
+        let mut effects = CrtEffects::default();
+        for name in names.split(',').map(str::trim) {
+            match name {
+                "scanlines" => effects.scanlines = true,
+                "grid" => effects.grid = true,
+                "curvature" => effects.curvature = true,
+                "all" => effects = CrtEffects::ALL,
+                _ => return None,
+            }
+        }
+        Some(effects)
+    }
+
+    // Whether there is anything to sample.
+    pub fn enabled(&self) -> bool {
+        self.tube() || self.grid_lines.is_some()
+    }
+
+    // Whether any of the effects F8 toggles is on.
+    pub fn tube(&self) -> bool {
+        self.scanlines || self.grid || self.curvature
+    }
+
+    // The effects left on when the CRT look is toggled off.
+    pub fn without_tube(&self) -> CrtEffects {
+        CrtEffects {
+            grid_lines: self.grid_lines,
+            ..CrtEffects::default()
+        }
+    }
+
+    // Color of pixel (x, y) in a width x height image of the display;
+    // `source` gives the color of a display pixel.
+    pub fn sample<F>(&self, x: usize, y: usize, width: usize, height: usize, source: F) -> Color
+    where
+        F: Fn(usize, usize) -> Color,
+    {
+        let mut u = (x as f32 + 0.5) / width as f32;
+        let mut v = (y as f32 + 0.5) / height as f32;
+        if self.curvature {
+            let (cx, cy) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+            let (cx, cy) = (cx * (1.0 + CURVATURE * cy * cy), cy * (1.0 + CURVATURE * cx * cx));
+            if cx.abs() > 1.0 || cy.abs() > 1.0 {
+                return Color::rgb(0, 0, 0);
+            }
+            u = (cx + 1.0) / 2.0;
+            v = (cy + 1.0) / 2.0;
+        }
+
+        let (px, py) = (u * WIDTH as f32, v * HEIGHT as f32);
+        let (sx, sy) = ((px as usize).min(WIDTH - 1), (py as usize).min(HEIGHT - 1));
+        let (fx, fy) = (px.fract(), py.fract());
+
+        // brightness out of 256
+        let mut light = 256;
+        if self.scanlines && fy > 0.65 {
+            light = light * 5 / 10;
+        }
+        if self.grid && (fx < 0.12 || fy < 0.12) {
+            light = light * 7 / 10;
+        }
+        let color = source(sx, sy);
+        let dim = |channel: u8| (channel as u32 * light / 256) as u8;
+        let color = Color::rgb(dim(color.r), dim(color.g), dim(color.b));
+        match self.grid_lines {
+            Some(lines) if fx < GRID_LINE_WIDTH || fy < GRID_LINE_WIDTH => {
+                palette::mix(color, lines.color, lines.alpha as u32, 100)
+            }
+            _ => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrtEffects, GridLines, CRT_SCALE};
+    use crate::ppu::{Color, HEIGHT, WIDTH};
+
+    const WHITE: Color = Color::rgb(255, 255, 255);
+
+    #[test]
+    fn parses_effect_lists() {
+        let effects = CrtEffects::from_names("scanlines, curvature").unwrap();
+        assert!(effects.scanlines && effects.curvature && !effects.grid);
+        assert_eq!(CrtEffects::from_names("all"), Some(CrtEffects::ALL));
+        assert_eq!(CrtEffects::from_names("blur"), None);
+        assert!(!CrtEffects::default().enabled());
+    }
+
+    #[test]
+    fn scanlines_darken_the_bottom_of_each_row() {
+        let effects = CrtEffects::from_names("scanlines").unwrap();
+        let (width, height) = (WIDTH * CRT_SCALE, HEIGHT * CRT_SCALE);
+        let sample = |y| effects.sample(0, y, width, height, |_, _| WHITE);
+
+        assert_eq!(sample(0), WHITE);
+        assert_eq!(sample(CRT_SCALE - 1), Color::rgb(127, 127, 127));
+        assert_eq!(sample(CRT_SCALE), WHITE);
+    }
+
+    #[test]
+    fn curvature_blacks_out_the_corners() {
+        let effects = CrtEffects::from_names("curvature").unwrap();
+        let (width, height) = (WIDTH * CRT_SCALE, HEIGHT * CRT_SCALE);
+
+        assert_eq!(effects.sample(0, 0, width, height, |_, _| WHITE), Color::rgb(0, 0, 0));
+        assert_eq!(effects.sample(width / 2, height / 2, width, height, |_, _| WHITE), WHITE);
+    }
+
+    #[test]
+    fn grid_lines_blend_between_pixels() {
+        let lines = GridLines::parse("50,#0000FF").unwrap();
+        assert_eq!(lines.color, Color::rgb(0, 0, 0xFF));
+        assert_eq!(GridLines::parse("25").unwrap().color, Color::rgb(0, 0, 0));
+        assert_eq!(GridLines::parse("101"), None);
+
+        let effects = CrtEffects {
+            grid_lines: Some(lines),
+            ..CrtEffects::ALL
+        };
+        let effects = effects.without_tube();
+        assert!(effects.enabled() && !effects.tube());
+        let (width, height) = (WIDTH * CRT_SCALE, HEIGHT * CRT_SCALE);
+        let sample = |x| effects.sample(x, 1, width, height, |_, _| WHITE);
+        assert_eq!(sample(CRT_SCALE), Color::rgb(127, 127, 255), "first column of a pixel");
+        assert_eq!(sample(CRT_SCALE + 1), WHITE);
+    }
+}
@@ -0,0 +1,484 @@
+use crate::cheats::{Cheat, Cheats};
+use crate::condition::Condition;
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::debugger::{Debugger, Limit, Stop};
+use crate::profiler::Profiler;
+use crate::ram_search::{Filter, RamSearch};
+use crate::watchpoint::Watchpoint;
+use crate::ppu::{HEIGHT, WIDTH};
+use std::io::{self, BufRead, Write};
+
+// Rows of 16 bytes in the memory panel.
+const MEMORY_ROWS: usize = 16;
+// Search results listed in the status line.
+const LISTED_CANDIDATES: usize = 8;
+// How long `c` runs without hitting a breakpoint: a minute of play.
+const CONTINUE_FRAMES: u64 = 60 * 60;
+
+const INVERSE: &str = "\x1b[7m";
+const UNDERLINE: &str = "\x1b[4m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+const CLEAR: &str = "\x1b[2J\x1b[H";
+
+const HELP: &str = "s [n] step  n next  fin finish  f [n] frames  c continue  b <addr> [if <condition>] breakpoint  \
+                    w [read|write] <addr|Vx> watchpoint  w clear  freeze <addr> [value]  unfreeze <addr>  \
+                    search <value>|changed|unchanged|increased|decreased|reset|watch|unwatch  \
+                    m [addr] memory at addr (or PC)  k <key> toggle key  profile [reset]  q quit";
+
+// `--debug-tui`: a terminal debugger. Each command redraws the screen, the
+// cpu state and a hex view of memory with PC inverted, I underlined and
+// bytes changed by the last command in yellow. An empty line repeats the
+// last command.
+//
+// The cpu is profiled from the start, unless it already has a profiler;
+// `profile` shows the hot spots, loops and instruction mix so far.
+//
+// `cheats` are written before every frame, starting now; `freeze` and
+// `unfreeze` change them. `search` narrows down where a value lives, and
+// `search watch` keeps the addresses found on screen.
+//
+// `terminal_size` gives the columns and rows before every redraw, None when
+// unknown. The screen is centered and drawn in half blocks when it fits,
+// in braille (half as tall) when only that fits; below that the debugger
+// only asks for a bigger terminal and holds every command but `q`.
+pub fn run(
+    cpu: &mut Cpu,
+    instructions_per_frame: u32,
+    cheats: Cheats,
+    input: impl BufRead,
+    mut output: impl Write,
+    mut terminal_size: impl FnMut() -> Option<(usize, usize)>,
+) -> io::Result<()> {
+    let mut debugger = Debugger::new(cpu, instructions_per_frame);
+    debugger.cheats = cheats;
+    debugger.cheats.apply(cpu);
+    let mut ram_search = RamSearch::new();
+    ram_search.reset(cpu);
+    if cpu.profiler.is_none() {
+        cpu.profiler = Some(Profiler::new(cpu.frame_count()));
+    }
+    // None follows PC
+    let mut view: Option<usize> = None;
+    let mut status = String::from(HELP);
+    let mut last = String::new();
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{}", CLEAR)?;
+        let fits = draw(cpu, &debugger, &ram_search, view, &status, terminal_size(), &mut output)?;
+        write!(output, "> ")?;
+        output.flush()?;
+        debugger.mark(cpu);
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        let line = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        if !fits && command != "q" {
+            continue;
+        }
+        let argument = words.next();
+        let count = argument.and_then(|count| count.parse().ok()).unwrap_or(1);
+        let address = argument.and_then(|address| usize::from_str_radix(address.trim_start_matches("0x"), 16).ok());
+        status = match (command, address) {
+            ("s", _) => describe(debugger.run(cpu, Limit::Instructions(count))),
+            ("n", _) | ("next", _) => describe(debugger.step_over(cpu, CONTINUE_FRAMES)),
+            ("fin", _) | ("finish", _) => match debugger.step_out(cpu, CONTINUE_FRAMES) {
+                Some(stop) => describe(stop),
+                None => "not in a subroutine".to_string(),
+            },
+            ("f", _) => describe(debugger.run(cpu, Limit::Frames(count))),
+            ("c", _) => describe(debugger.run(cpu, Limit::Frames(CONTINUE_FRAMES))),
+            ("b", Some(address)) | ("break", Some(address)) if address < MEMORY_SIZE => match line.split_once(" if ") {
+                Some((_, condition)) => match Condition::parse(condition) {
+                    Some(condition) => {
+                        let status = format!("breakpoint at {:04X} if {} set", address, condition);
+                        debugger.set_conditional_breakpoint(address as u16, condition);
+                        status
+                    }
+                    None => format!("can't read condition {}", condition),
+                },
+                None => {
+                    let set = debugger.toggle_breakpoint(address as u16);
+                    format!("breakpoint at {:04X} {}", address, if set { "set" } else { "cleared" })
+                }
+            },
+            ("w", _) if argument == Some("clear") => {
+                debugger.clear_watchpoints();
+                "watchpoints cleared".to_string()
+            }
+            ("w", _) => match Watchpoint::parse(&line[command.len()..]) {
+                Some(watchpoint) if debugger.add_watchpoint(watchpoint) => format!("watchpoint {} set", watchpoint),
+                Some(watchpoint) => format!("watchpoint {} already set", watchpoint),
+                None => HELP.to_string(),
+            },
+            ("freeze", Some(address)) if address < MEMORY_SIZE => {
+                // the byte there now, unless a value is given
+                let value = match words.next() {
+                    Some(value) => u8::from_str_radix(value.trim_start_matches("0x"), 16).ok(),
+                    None => Some(cpu.memory()[address]),
+                };
+                match value {
+                    Some(value) => {
+                        debugger.cheats.freeze(address as u16, value);
+                        cpu.memory_mut()[address] = value;
+                        format!("{:04X} frozen at {:02X}", address, value)
+                    }
+                    None => HELP.to_string(),
+                }
+            }
+            ("unfreeze", Some(address)) if address < MEMORY_SIZE => {
+                if debugger.cheats.unfreeze(address as u16) {
+                    format!("{:04X} unfrozen", address)
+                } else {
+                    format!("{:04X} isn't frozen", address)
+                }
+            }
+            ("search", _) => match argument {
+                None => list_candidates(&ram_search),
+                Some("reset") => {
+                    ram_search.reset(cpu);
+                    "search starts over".to_string()
+                }
+                Some("watch") => format!("watching {} addresses", ram_search.watch_candidates()),
+                Some("unwatch") => {
+                    ram_search.unwatch();
+                    "watch cleared".to_string()
+                }
+                Some(filter) => match Filter::parse(filter) {
+                    Some(filter) => {
+                        ram_search.search(cpu, filter);
+                        list_candidates(&ram_search)
+                    }
+                    None => HELP.to_string(),
+                },
+            },
+            ("m", None) => {
+                view = None;
+                "memory follows PC".to_string()
+            }
+            ("m", Some(address)) if address < MEMORY_SIZE => {
+                view = Some(address);
+                format!("memory at {:04X}", address)
+            }
+            ("k", Some(key)) if key < 16 => {
+                let down = !cpu.keypad.is_key_down(key as u8);
+                cpu.keypad.set_key(key as u8, down);
+                format!("key {:X} {}", key, if down { "down" } else { "up" })
+            }
+            ("profile", _) if argument == Some("reset") => {
+                cpu.profiler = Some(Profiler::new(cpu.frame_count()));
+                "profile reset".to_string()
+            }
+            ("profile", _) => match &cpu.profiler {
+                Some(profiler) => profiler.summary(cpu),
+                None => "not profiling".to_string(),
+            },
+            ("q", _) => return Ok(()),
+            _ => HELP.to_string(),
+        };
+        last = line;
+    }
+}
+
+fn list_candidates(ram_search: &RamSearch) -> String {
+    match ram_search.candidates() {
+        None => "no search yet".to_string(),
+        Some(candidates) => {
+            let listed: Vec<String> = candidates.iter().take(LISTED_CANDIDATES).map(|address| format!("{:04X}", address)).collect();
+            let more = if candidates.len() > LISTED_CANDIDATES { " ..." } else { "" };
+            format!("{} addresses left: {}{}", candidates.len(), listed.join(" "), more)
+        }
+    }
+}
+
+fn describe(stop: Stop) -> String {
+    match stop {
+        Stop::Done => String::new(),
+        Stop::Breakpoint(address) => format!("breakpoint at {:04X}", address),
+        Stop::Watchpoint(hit) => format!(
+            "watchpoint {}: {} by {:04X}",
+            hit.watchpoint,
+            if hit.write { "written" } else { "read" },
+            hit.pc
+        ),
+        Stop::Error(error) => format!("stopped: {}", error),
+    }
+}
+
+// The screen, the cpu state, the memory panel, the breakpoints, any
+// watchpoints, frozen and watched bytes and the status line, or only a request to resize when they
+// don't fit the terminal. Returns whether they fit.
+fn draw(
+    cpu: &Cpu,
+    debugger: &Debugger,
+    ram_search: &RamSearch,
+    view: Option<usize>,
+    status: &str,
+    terminal_size: Option<(usize, usize)>,
+    output: &mut impl Write,
+) -> io::Result<bool> {
+    let mut panels = format!("{}\n", cpu);
+    let start = view.unwrap_or_else(|| (cpu.pc() as usize).saturating_sub(0x40));
+    for line in hex_view(cpu, debugger, start, MEMORY_ROWS) {
+        panels.push_str(&line);
+        panels.push('\n');
+    }
+    let breakpoints: Vec<String> = debugger
+        .breakpoints()
+        .iter()
+        .map(|address| match debugger.condition(*address) {
+            Some(condition) => format!("{:04X} if {}", address, condition),
+            None => format!("{:04X}", address),
+        })
+        .collect();
+    let breakpoints = if breakpoints.is_empty() { "none".to_string() } else { breakpoints.join(", ") };
+    panels.push_str(&format!("breakpoints: {}\n", breakpoints));
+    if !debugger.watchpoints().is_empty() {
+        let watchpoints: Vec<String> = debugger.watchpoints().iter().map(Watchpoint::to_string).collect();
+        panels.push_str(&format!("watchpoints: {}\n", watchpoints.join(", ")));
+    }
+    if !debugger.cheats.is_empty() {
+        let cheats: Vec<String> = debugger.cheats.list().iter().map(Cheat::to_string).collect();
+        panels.push_str(&format!("frozen: {}\n", cheats.join(", ")));
+    }
+    if !ram_search.watched().is_empty() {
+        let watched: Vec<String> = ram_search
+            .watched()
+            .iter()
+            .map(|address| format!("{:04X}={:02X}", address, cpu.memory()[*address as usize]))
+            .collect();
+        panels.push_str(&format!("watch: {}\n", watched.join(" ")));
+    }
+
+    let framebuffer = cpu.display().framebuffer();
+    let (screen, width) = match terminal_size {
+        None => (screen(framebuffer), 0),
+        Some((columns, rows)) => {
+            // status lines wrap, and the prompt takes a line of its own
+            let status_rows: usize = status.lines().map(|line| line.chars().count().div_ceil(columns.max(1)).max(1)).sum();
+            let other_rows = panels.lines().count() + status_rows + 1;
+            match screen_mode(columns, rows, other_rows) {
+                Some(ScreenMode::Blocks) => (screen(framebuffer), columns),
+                Some(ScreenMode::Braille) => (braille(framebuffer), columns),
+                None => {
+                    writeln!(
+                        output,
+                        "terminal too small: {}x{}, the debugger needs {}x{}",
+                        columns,
+                        rows,
+                        HEX_WIDTH,
+                        other_rows + HEIGHT / 4
+                    )?;
+                    return Ok(false);
+                }
+            }
+        }
+    };
+    let indent = " ".repeat(width.saturating_sub(screen.lines().next().map_or(0, |line| line.chars().count())) / 2);
+    for line in screen.lines() {
+        writeln!(output, "{}{}", indent, line)?;
+    }
+    writeln!(output, "{}{}", panels, status)?;
+    Ok(true)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScreenMode {
+    // two pixels per character, 64x16
+    Blocks,
+    // eight pixels per character, 32x8
+    Braille,
+}
+
+// Characters in a hex view line, the widest panel.
+const HEX_WIDTH: usize = 5 + 16 * 3;
+
+// The biggest screen that fits next to `other_rows` of panels.
+fn screen_mode(columns: usize, rows: usize, other_rows: usize) -> Option<ScreenMode> {
+    if columns < HEX_WIDTH {
+        None
+    } else if columns >= WIDTH && rows >= other_rows + HEIGHT / 2 {
+        Some(ScreenMode::Blocks)
+    } else if rows >= other_rows + HEIGHT / 4 {
+        Some(ScreenMode::Braille)
+    } else {
+        None
+    }
+}
+
+// The framebuffer in braille characters, 2x4 pixels each.
+pub fn braille(framebuffer: &[u8]) -> String {
+    // dot bits by row, left column then right
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut text = String::with_capacity((WIDTH / 2 + 1) * HEIGHT / 4 * 3);
+    for y in (0..HEIGHT).step_by(4) {
+        for x in (0..WIDTH).step_by(2) {
+            let mut dots = 0;
+            for (row, bits) in DOTS.iter().enumerate() {
+                for (column, bit) in bits.iter().enumerate() {
+                    if framebuffer[(y + row) * WIDTH + x + column] != 0 {
+                        dots |= bit;
+                    }
+                }
+            }
+            text.push(std::char::from_u32(0x2800 + dots).unwrap_or(' '));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// The framebuffer in half-height block characters, two rows per line.
+pub fn screen(framebuffer: &[u8]) -> String {
+    let mut text = String::with_capacity((WIDTH + 1) * HEIGHT / 2 * 3);
+    for rows in framebuffer.chunks(WIDTH * 2) {
+        let (top, bottom) = rows.split_at(WIDTH);
+        for (upper, lower) in top.iter().zip(bottom) {
+            text.push(match (*upper == 1, *lower == 1) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// `rows` lines of 16 bytes from the row holding `start`, kept inside memory:
+// "0200  60 01 A3 00 ...".
+pub fn hex_view(cpu: &Cpu, debugger: &Debugger, start: usize, rows: usize) -> Vec<String> {
+    let start = (start & !0xF).min(MEMORY_SIZE - rows * 16);
+    let pc = cpu.pc() as usize;
+    let i = cpu.i() as usize;
+    (0..rows)
+        .map(|row| {
+            let address = start + row * 16;
+            let mut line = format!("{:04X} ", address);
+            for offset in 0..16 {
+                let byte = address + offset;
+                let mut style = String::new();
+                if byte == pc || byte == pc + 1 {
+                    style.push_str(INVERSE);
+                }
+                if byte == i {
+                    style.push_str(UNDERLINE);
+                }
+                if debugger.changed(cpu, byte) {
+                    style.push_str(YELLOW);
+                }
+                let end = if style.is_empty() { "" } else { RESET };
+                line.push_str(&format!(" {}{:02X}{}", style, cpu.memory()[byte], end));
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{braille, hex_view, run, screen, screen_mode, ScreenMode, INVERSE, RESET, UNDERLINE, YELLOW};
+    use crate::cheats::Cheats;
+    use crate::cpu::Cpu;
+    use crate::debugger::Debugger;
+    use crate::headless::HeadlessDisplay;
+    use std::io::Cursor;
+
+    fn new_cpu() -> Cpu {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD I, 0x20A / LD V0, 7 / LD [I], V0 / JP 0x206
+        cpu.load(vec![0xA2, 0x0A, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x06]).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn hex_view_highlights_pc_i_and_changes() {
+        let mut cpu = new_cpu();
+        let mut debugger = Debugger::new(&cpu, 10);
+        for _ in 0..3 {
+            debugger.step(&mut cpu).unwrap();
+        }
+
+        let lines = hex_view(&cpu, &debugger, 0x205, 2);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0200  A2 0A 60 07 F0 55 "), "{}", lines[0]);
+        assert!(lines[0].contains(&format!("{}12{}", INVERSE, RESET)), "PC: {}", lines[0]);
+        assert!(lines[0].contains(&format!("{}07{}", YELLOW, RESET)), "changed: {}", lines[0]);
+        assert!(lines[0].contains(&format!("{}00{}", UNDERLINE, RESET)), "I past the store: {}", lines[0]);
+        assert!(lines[1].starts_with("0210  00"));
+        assert!(hex_view(&cpu, &debugger, 0xFFF, 4)[3].starts_with("0FF0"), "kept inside memory");
+
+        let mut framebuffer = vec![0; 64 * 32];
+        framebuffer[0] = 1;
+        framebuffer[64 + 1] = 1;
+        assert!(screen(&framebuffer).starts_with("▀▄ "));
+        assert!(braille(&framebuffer).starts_with("\u{2811}\u{2800}"));
+    }
+
+    #[test]
+    fn commands_step_and_set_breakpoints() {
+        let mut cpu = new_cpu();
+        let mut output = Vec::new();
+        run(&mut cpu, 10, Cheats::default(), Cursor::new("s 2\n\nb 206\nc\nprofile\nq\n"), &mut output, || None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(cpu.pc(), 0x206, "stepped twice, twice more, then continued back to the breakpoint");
+        assert!(output.contains("breakpoint at 0206 set"));
+        assert!(output.contains("breakpoint at 0206\n"));
+        assert!(output.contains("breakpoints: 0206"));
+        assert!(output.contains("loop 0206-0206 x"), "profiled: {}", output);
+    }
+
+    #[test]
+    fn conditional_breakpoints_wait_for_their_condition() {
+        let mut cpu = new_cpu();
+        let mut output = Vec::new();
+        let commands = "break 0x206 if V0 == 7 && I > 0x209\nc\nb 204 if V0 = 7\nq\n";
+        run(&mut cpu, 10, Cheats::default(), Cursor::new(commands), &mut output, || None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(cpu.pc(), 0x206);
+        assert!(output.contains("breakpoint at 0206 if V0 == 7 && I > 0x209 set"));
+        assert!(output.contains("breakpoints: 0206 if V0 == 7 && I > 0x209"));
+        assert!(output.contains("can't read condition V0 = 7"));
+    }
+
+    #[test]
+    fn watch_command_stops_on_memory_writes() {
+        let mut cpu = new_cpu();
+        let mut output = Vec::new();
+        run(&mut cpu, 10, Cheats::default(), Cursor::new("w write 20A\nc\nw clear\nq\n"), &mut output, || None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(cpu.pc(), 0x206);
+        assert!(output.contains("watchpoint write 020A set"));
+        assert!(output.contains("watchpoints: write 020A"));
+        assert!(output.contains("watchpoint write 020A: written by 0204"), "{}", output);
+        assert!(output.contains("watchpoints cleared"));
+    }
+
+    #[test]
+    fn screen_adapts_to_the_terminal_size() {
+        assert_eq!(screen_mode(80, 60, 30), Some(ScreenMode::Blocks));
+        assert_eq!(screen_mode(60, 60, 30), Some(ScreenMode::Braille), "too narrow for blocks");
+        assert_eq!(screen_mode(80, 40, 30), Some(ScreenMode::Braille));
+        assert_eq!(screen_mode(80, 37, 30), None);
+        assert_eq!(screen_mode(40, 60, 30), None, "too narrow for the hex view");
+
+        let mut cpu = new_cpu();
+        let mut sizes = vec![(100, 80), (80, 20), (80, 20)].into_iter();
+        let mut output = Vec::new();
+        run(&mut cpu, 10, Cheats::default(), Cursor::new("s\ns\nq\n"), &mut output, || sizes.next()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(cpu.pc(), 0x202, "held while the terminal was too small");
+        assert!(output.contains(&format!("\n{}{}\n", " ".repeat(18), " ".repeat(64))), "centered");
+        assert!(output.contains("terminal too small: 80x20"));
+    }
+}
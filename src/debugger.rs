@@ -0,0 +1,338 @@
+use crate::cheats::Cheats;
+use crate::condition::Condition;
+use crate::cpu::Cpu;
+use crate::errors::EmulateCycleError;
+use crate::watchpoint::{RegisterUse, Watchpoint};
+
+// How far `Debugger::run` goes when nothing stops it first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Limit {
+    Instructions(u64),
+    Frames(u64),
+    // until the stack is shallower than `depth` calls, or `frames` passed
+    Return { depth: u8, frames: u64 },
+}
+
+// Why `Debugger::run` returned.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Stop {
+    // the limit was reached
+    Done,
+    // PC reached a breakpoint whose condition, if any, holds; the
+    // instruction there is not executed yet
+    Breakpoint(u16),
+    // the instruction just executed hit a watchpoint
+    Watchpoint(Hit),
+    Error(EmulateCycleError),
+}
+
+// An instruction at `pc` reading or writing what a watchpoint watches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub watchpoint: Watchpoint,
+    pub pc: u16,
+    pub write: bool,
+}
+
+// Runs a cpu instruction by instruction, with the same frame boundaries as
+// `scheduler::run_frame`, stopping at breakpoints, conditional or not, and
+// watchpoints. Also remembers memory as
+// it was at the last stop, so views can show what changed since.
+pub struct Debugger {
+    pub instructions_per_frame: u32,
+    // written before every frame
+    pub cheats: Cheats,
+    breakpoints: Vec<u16>,
+    // conditions of the breakpoints that have one
+    conditions: Vec<(u16, Condition)>,
+    watchpoints: Vec<Watchpoint>,
+    // instructions run in the current frame
+    executed: u32,
+    snapshot: Vec<u8>,
+}
+
+impl Debugger {
+    pub fn new(cpu: &Cpu, instructions_per_frame: u32) -> Debugger {
+        Debugger {
+            instructions_per_frame,
+            cheats: Cheats::default(),
+            breakpoints: Vec::new(),
+            conditions: Vec::new(),
+            watchpoints: Vec::new(),
+            executed: 0,
+            snapshot: cpu.memory().to_vec(),
+        }
+    }
+
+    // Sets or clears a breakpoint, returning whether it is now set.
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        match self.breakpoints.iter().position(|breakpoint| *breakpoint == address) {
+            Some(index) => {
+                self.breakpoints.remove(index);
+                self.conditions.retain(|(breakpoint, _)| *breakpoint != address);
+                false
+            }
+            None => {
+                self.breakpoints.push(address);
+                self.breakpoints.sort_unstable();
+                true
+            }
+        }
+    }
+
+    // Sets a breakpoint that only stops when the condition holds, replacing
+    // any breakpoint at the address.
+    pub fn set_conditional_breakpoint(&mut self, address: u16, condition: Condition) {
+        if !self.breakpoints.contains(&address) {
+            self.toggle_breakpoint(address);
+        }
+        self.conditions.retain(|(breakpoint, _)| *breakpoint != address);
+        self.conditions.push((address, condition));
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    pub fn condition(&self, address: u16) -> Option<&Condition> {
+        self.conditions
+            .iter()
+            .find(|(breakpoint, _)| *breakpoint == address)
+            .map(|(_, condition)| condition)
+    }
+
+    // Whether a breakpoint stops the cpu where it is.
+    pub fn breaks_at(&self, cpu: &Cpu) -> bool {
+        self.breakpoints.contains(&cpu.pc()) && self.condition(cpu.pc()).is_none_or(|condition| condition.holds(cpu))
+    }
+
+    // Adds a watchpoint, returning false if it was already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    // Returns whether the watchpoint was set.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|set| set != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Executes one instruction, followed by the vblank when it ends the
+    // frame, and returns the first watchpoint it hit.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<Option<Hit>, EmulateCycleError> {
+        if self.watchpoints.is_empty() {
+            cpu.step()?;
+            self.end_instruction(cpu);
+            return Ok(None);
+        }
+
+        cpu.log_memory_accesses();
+        let pc = cpu.pc();
+        let opcode = opcode_at_pc(cpu);
+        let result = cpu.step();
+        let memory = cpu.take_memory_accesses();
+        result?;
+        self.end_instruction(cpu);
+
+        let mut registers = RegisterUse::of(opcode, &cpu.quirks);
+        if cpu.pc() == pc {
+            // Fx0A still waiting for a key wrote nothing
+            registers.written = 0;
+        }
+        Ok(self.watchpoints.iter().find_map(|watchpoint| {
+            let write = watchpoint.hit(&memory, registers)?;
+            Some(Hit {
+                watchpoint: *watchpoint,
+                pc,
+                write,
+            })
+        }))
+    }
+
+    fn end_instruction(&mut self, cpu: &mut Cpu) {
+        self.executed += 1;
+        if self.executed >= self.instructions_per_frame || cpu.waiting_for_vblank() {
+            cpu.vblank();
+            self.executed = 0;
+            self.cheats.apply(cpu);
+        }
+    }
+
+    // Runs until the limit, an error, a breakpoint or a watchpoint. The
+    // instruction at PC always runs, so continuing from a breakpoint moves
+    // on.
+    pub fn run(&mut self, cpu: &mut Cpu, limit: Limit) -> Stop {
+        let start_frame = cpu.frame_count();
+        let mut executed = 0;
+        loop {
+            let done = match limit {
+                Limit::Instructions(instructions) => executed >= instructions,
+                Limit::Frames(frames) => cpu.frame_count() - start_frame >= frames,
+                Limit::Return { depth, frames } => {
+                    (executed > 0 && cpu.sp() < depth) || cpu.frame_count() - start_frame >= frames
+                }
+            };
+            if done {
+                return Stop::Done;
+            }
+            if executed > 0 && self.breaks_at(cpu) {
+                return Stop::Breakpoint(cpu.pc());
+            }
+            let hit = self.step(cpu);
+            executed += 1;
+            match hit {
+                Ok(Some(hit)) => return Stop::Watchpoint(hit),
+                Ok(None) => {}
+                Err(error) => return Stop::Error(error),
+            }
+        }
+    }
+
+    // Runs a CALL at PC until it returns, stepping over the subroutine;
+    // any other instruction is a single step. Gives up after `frames`.
+    pub fn step_over(&mut self, cpu: &mut Cpu, frames: u64) -> Stop {
+        let limit = if opcode_at_pc(cpu) & 0xF000 == 0x2000 {
+            Limit::Return {
+                depth: cpu.sp() + 1,
+                frames,
+            }
+        } else {
+            Limit::Instructions(1)
+        };
+        self.run(cpu, limit)
+    }
+
+    // Runs until the subroutine PC is in returns; None outside of one.
+    pub fn step_out(&mut self, cpu: &mut Cpu, frames: u64) -> Option<Stop> {
+        if cpu.sp() == 0 {
+            return None;
+        }
+        let depth = cpu.sp();
+        Some(self.run(cpu, Limit::Return { depth, frames }))
+    }
+
+    // Whether the byte at `address` changed since the last `mark`.
+    pub fn changed(&self, cpu: &Cpu, address: usize) -> bool {
+        self.snapshot.get(address) != cpu.memory().get(address)
+    }
+
+    // Remembers memory as it is now; call once a stop has been shown.
+    pub fn mark(&mut self, cpu: &Cpu) {
+        self.snapshot.copy_from_slice(cpu.memory());
+    }
+}
+
+// 0 when PC is out of memory, which no instruction decodes from.
+fn opcode_at_pc(cpu: &Cpu) -> u16 {
+    let pc = cpu.pc() as usize;
+    cpu.memory()
+        .get(pc..pc + 2)
+        .map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, Limit, Stop};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::watchpoint::Watchpoint;
+
+    // LD V0, 1 / LD I, 0x300 / LD [I], V0 / JP 0x200
+    const LOOP: [u8; 8] = [0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+    #[test]
+    fn stops_at_breakpoints_and_tracks_changes() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(LOOP.to_vec()).unwrap();
+        let mut debugger = Debugger::new(&cpu, 2);
+
+        assert!(debugger.toggle_breakpoint(0x206));
+        assert!(matches!(debugger.run(&mut cpu, Limit::Frames(10)), Stop::Breakpoint(0x206)));
+        assert_eq!(cpu.frame_count(), 1, "two instructions per frame");
+        assert!(debugger.changed(&cpu, 0x300));
+        assert!(!debugger.changed(&cpu, 0x200));
+
+        debugger.mark(&cpu);
+        assert!(!debugger.changed(&cpu, 0x300));
+        assert!(matches!(debugger.run(&mut cpu, Limit::Instructions(2)), Stop::Done));
+        assert_eq!(cpu.pc(), 0x202);
+
+        assert!(!debugger.toggle_breakpoint(0x206));
+        assert!(matches!(debugger.run(&mut cpu, Limit::Frames(3)), Stop::Done));
+        assert_eq!(cpu.frame_count(), 5);
+    }
+
+    // CALL 0x206 / ADD V1, 1 / JP 0x204 / ADD V0, 1 / CALL 0x20C / RET / RET
+    const CALLS: [u8; 14] = [0x22, 0x06, 0x71, 0x01, 0x12, 0x04, 0x70, 0x01, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0xEE];
+
+    #[test]
+    fn steps_over_and_out_of_subroutines() {
+        let new_cpu = || {
+            let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+            cpu.reset();
+            cpu.load(CALLS.to_vec()).unwrap();
+            cpu
+        };
+        let mut cpu = new_cpu();
+        let mut debugger = Debugger::new(&cpu, 10);
+        assert!(debugger.step_out(&mut cpu, 10).is_none(), "not in a subroutine");
+        assert!(matches!(debugger.step_over(&mut cpu, 10), Stop::Done));
+        assert_eq!((cpu.pc(), cpu.sp(), cpu.registers()[0]), (0x202, 0, 1), "the call ran through");
+        assert!(matches!(debugger.step_over(&mut cpu, 10), Stop::Done));
+        assert_eq!(cpu.pc(), 0x204, "other instructions step");
+
+        let mut cpu = new_cpu();
+        debugger.run(&mut cpu, Limit::Instructions(3));
+        assert_eq!((cpu.pc(), cpu.sp()), (0x20C, 2));
+        assert!(matches!(debugger.step_out(&mut cpu, 10), Some(Stop::Done)));
+        assert_eq!((cpu.pc(), cpu.sp()), (0x20A, 1), "back in the outer subroutine");
+
+        let mut cpu = new_cpu();
+        assert!(debugger.toggle_breakpoint(0x20C));
+        assert!(matches!(debugger.step_over(&mut cpu, 10), Stop::Breakpoint(0x20C)), "breakpoints still stop");
+    }
+
+    #[test]
+    fn stops_after_instructions_hitting_watchpoints() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(LOOP.to_vec()).unwrap();
+        let mut debugger = Debugger::new(&cpu, 2);
+
+        assert!(debugger.add_watchpoint(Watchpoint::parse("write 0x300").unwrap()));
+        assert!(!debugger.add_watchpoint(Watchpoint::parse("write 300").unwrap()));
+        match debugger.run(&mut cpu, Limit::Frames(10)) {
+            Stop::Watchpoint(hit) => assert_eq!((hit.pc, hit.write), (0x204, true)),
+            stop => panic!("{:?}", stop),
+        }
+        assert_eq!(cpu.pc(), 0x206, "the store has run");
+
+        debugger.clear_watchpoints();
+        assert!(debugger.add_watchpoint(Watchpoint::parse("read V0").unwrap()));
+        assert!(debugger.add_watchpoint(Watchpoint::parse("write V0").unwrap()));
+        match debugger.run(&mut cpu, Limit::Frames(10)) {
+            Stop::Watchpoint(hit) => assert_eq!((hit.pc, hit.watchpoint.to_string()), (0x200, "write V0".to_string())),
+            stop => panic!("{:?}", stop),
+        }
+        match debugger.run(&mut cpu, Limit::Frames(10)) {
+            Stop::Watchpoint(hit) => assert_eq!((hit.pc, hit.write), (0x204, false)),
+            stop => panic!("{:?}", stop),
+        }
+    }
+}
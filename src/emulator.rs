@@ -0,0 +1,275 @@
+use crate::cheats::Cheats;
+use crate::cpu::Cpu;
+use crate::errors::{EmulateCycleError, LoadError};
+use crate::megachip::MegaChip;
+use crate::playclock;
+use crate::ppu::Display;
+use crate::scheduler::{self, DEFAULT_INSTRUCTIONS_PER_FRAME, FRAME_RATE};
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptHooks;
+use std::time::Duration;
+
+// Rate of the samples from `Emulator::audio_samples`.
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+const TONE_HZ: u32 = 440;
+const VOLUME: i16 = 0x1000;
+
+// What a frontend shows of the running game, in its window title.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Status {
+    pub rom_name: Option<String>,
+    pub paused: bool,
+    // emulated time per real time in percent, fast-forward included
+    pub speed: u32,
+    pub played: Duration,
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Status {
+            rom_name: None,
+            paused: false,
+            speed: 100,
+            played: Duration::from_secs(0),
+        }
+    }
+}
+
+impl Status {
+    // "hachip — Pong [paused]  1:02", or "[x2 speed]" when running at
+    // another speed, with the play clock.
+    pub fn title(&self) -> String {
+        let mut title = "hachip".to_string();
+        if let Some(name) = &self.rom_name {
+            title += &format!(" \u{2014} {}", name);
+        }
+        if self.paused {
+            title += " [paused]";
+        } else if self.speed != 100 {
+            title += &format!(" [x{} speed]", self.speed as f64 / 100.0);
+        }
+        format!("{}  {}", title, playclock::format_play_time(self.played))
+    }
+}
+
+// The machine as a frontend sees it: load a ROM, feed it keys, run it a
+// frame at a time and take the picture and sound of each frame. The cpu
+// stays reachable for settings (quirks, seeds, tracing) and debugging.
+pub struct Emulator {
+    cpu: Cpu,
+    rom: Vec<u8>,
+    // Shown in the status; the file name or the title.
+    pub rom_name: Option<String>,
+    pub instructions_per_frame: u32,
+    // Memory patches written before every frame.
+    pub cheats: Cheats,
+    // Script called along with every frame.
+    #[cfg(feature = "scripting")]
+    pub hooks: Option<ScriptHooks>,
+    // mono, one frame's worth
+    audio: Vec<i16>,
+    phase: u32,
+}
+
+impl Emulator {
+    pub fn new(display: Box<dyn Display>) -> Emulator {
+        let mut cpu = Cpu::new(display);
+        cpu.reset();
+        Emulator::from_cpu(cpu)
+    }
+
+    // Wraps a cpu set up by the caller, with its program already loaded.
+    // The program is taken out of memory as it is now, for `reset`.
+    pub fn from_cpu(cpu: Cpu) -> Emulator {
+        let rom = cpu.memory()[cpu.load_address as usize..].to_vec();
+        Emulator {
+            cpu,
+            rom,
+            rom_name: None,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            cheats: Cheats::default(),
+            #[cfg(feature = "scripting")]
+            hooks: None,
+            audio: vec![0; SAMPLES_PER_FRAME],
+            phase: 0,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    // Resets the machine and loads `rom`, refusing ROMs that don't fit.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        self.cpu.reset();
+        self.cpu.load(rom.to_vec())?;
+        self.rom = rom.to_vec();
+        Ok(())
+    }
+
+    // Starts the loaded ROM over.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        // the size was checked by load_rom
+        self.cpu.load_truncated(self.rom.clone());
+    }
+
+    pub fn key_event(&mut self, key: u8, pressed: bool) {
+        self.cpu.keypad.set_key(key & 0xF, pressed);
+    }
+
+    // Runs one 60Hz frame and renders its sound, returning the
+    // instructions executed.
+    pub fn step_frame(&mut self) -> Result<u32, EmulateCycleError> {
+        self.cheats.apply(&mut self.cpu);
+        #[cfg(feature = "scripting")]
+        let result = match &self.hooks {
+            Some(hooks) => hooks.run_frame(&mut self.cpu, self.instructions_per_frame),
+            None => scheduler::run_frame(&mut self.cpu, self.instructions_per_frame),
+        };
+        #[cfg(not(feature = "scripting"))]
+        let result = scheduler::run_frame(&mut self.cpu, self.instructions_per_frame);
+        self.render_audio();
+        result
+    }
+
+    // The ROM and how long it has run, in emulated time; frontends that
+    // pause or change the speed fill in the rest.
+    pub fn status(&self) -> Status {
+        Status {
+            rom_name: self.rom_name.clone(),
+            played: self.cpu.emulated_time(),
+            ..Status::default()
+        }
+    }
+
+    // A byte per pixel, row by row, 1 for lit.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.display().framebuffer()
+    }
+
+    // The 256x192 Mega-Chip screen as 0xAARRGGBB, in place of the
+    // framebuffer while a ROM has it on.
+    pub fn mega_screen(&self) -> Option<&[u32]> {
+        self.cpu.mega().filter(|mega| mega.on()).map(MegaChip::screen)
+    }
+
+    // The last frame's sound, SAMPLES_PER_FRAME samples at SAMPLE_RATE.
+    pub fn audio_samples(&self) -> &[i16] {
+        &self.audio
+    }
+
+    // Square wave while the sound timer runs, silence otherwise, with any
+    // Mega-Chip sample on top.
+    fn render_audio(&mut self) {
+        let half_period = SAMPLE_RATE / TONE_HZ / 2;
+        let playing = self.cpu.sound_playing();
+        for sample in self.audio.iter_mut() {
+            *sample = if !playing {
+                0
+            } else if (self.phase / half_period) & 1 == 0 {
+                VOLUME
+            } else {
+                -VOLUME
+            };
+            self.phase = self.phase.wrapping_add(1);
+        }
+        self.cpu.render_sample(&mut self.audio, SAMPLE_RATE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Emulator, Status, SAMPLES_PER_FRAME};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::megachip;
+    use crate::ppu::WIDTH;
+    use crate::quirks::Platform;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_frames_with_keys_picture_and_sound() {
+        let mut emulator = Emulator::new(Box::new(HeadlessDisplay::new()));
+        assert!(emulator.load_rom(&[0; 4000]).is_err());
+        // waits for 5, then draws a 5 and beeps:
+        // LD V0, 5 / SKP V0 / JP 0x202 / LD F, V0 / DRW V0, V0, 5 / LD ST, V0 / JP 0x20C
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0x12, 0x0C];
+        emulator.load_rom(&rom).unwrap();
+        emulator.step_frame().unwrap();
+        assert!(emulator.framebuffer().iter().all(|pixel| *pixel == 0));
+        assert_eq!(emulator.audio_samples(), &[0; SAMPLES_PER_FRAME][..]);
+
+        emulator.key_event(5, true);
+        emulator.step_frame().unwrap();
+        assert_eq!(emulator.framebuffer()[5 * WIDTH + 5], 1);
+        // the draw waited for the next frame
+        emulator.step_frame().unwrap();
+        assert_eq!(emulator.audio_samples().len(), SAMPLES_PER_FRAME);
+        assert!(emulator.audio_samples().iter().any(|sample| *sample != 0));
+
+        emulator.reset();
+        assert!(emulator.framebuffer().iter().all(|pixel| *pixel == 0));
+        assert_eq!(emulator.cpu().memory()[0x200..0x20E], rom);
+    }
+
+    #[test]
+    fn reset_reloads_the_program_of_a_wrapped_cpu() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.load_address = 0x600;
+        cpu.reset();
+        // LD V0, 7 / LD [I], V0 over its own first byte / JP 0x604
+        cpu.load(vec![0x60, 0x07, 0xA6, 0x00, 0xF0, 0x55, 0x16, 0x04]).unwrap();
+        let mut emulator = Emulator::from_cpu(cpu);
+        emulator.step_frame().unwrap();
+        assert_eq!(emulator.cpu().memory()[0x600], 0x07);
+
+        emulator.reset();
+        assert_eq!(emulator.cpu().memory()[0x600..0x608], [0x60, 0x07, 0xA6, 0x00, 0xF0, 0x55, 0x16, 0x04]);
+        assert_eq!(emulator.cpu().pc(), 0x600);
+    }
+
+    #[test]
+    fn shows_the_mega_chip_screen_and_plays_its_sample() {
+        let mut emulator = Emulator::new(Box::new(HeadlessDisplay::new()));
+        emulator.cpu_mut().quirks = Platform::MegaChip.quirks();
+        // MEGAON / LDHI I, #00020C / DIGISND 0 / CLS / JP 0x20A, then a
+        // looped sample at 8000 Hz
+        let rom = [
+            0x00, 0x11, 0x01, 0x00, 0x02, 0x0C, 0x06, 0x00, 0x00, 0xE0, 0x12, 0x0A, 0x1F, 0x40, 0x00, 0x00, 0x04, 0x00,
+            0xFF, 0x00, 0xFF, 0x00,
+        ];
+        assert_eq!(emulator.mega_screen(), None);
+        emulator.load_rom(&rom).unwrap();
+        emulator.step_frame().unwrap();
+        assert_eq!(emulator.mega_screen().unwrap().len(), megachip::WIDTH * megachip::HEIGHT);
+        assert!(emulator.audio_samples().iter().any(|sample| *sample > 0));
+        assert!(emulator.audio_samples().iter().any(|sample| *sample < 0));
+
+        emulator.reset();
+        assert_eq!(emulator.mega_screen(), None);
+    }
+
+    #[test]
+    fn status_makes_the_window_title() {
+        let mut emulator = Emulator::new(Box::new(HeadlessDisplay::new()));
+        assert_eq!(emulator.status().title(), "hachip  0:00");
+        emulator.rom_name = Some("Pong".to_string());
+        let mut status = emulator.status();
+        status.played = Duration::from_secs(62);
+        assert_eq!(status.title(), "hachip \u{2014} Pong  1:02");
+        status.speed = 200;
+        assert_eq!(status.title(), "hachip \u{2014} Pong [x2 speed]  1:02");
+        status.speed = 50;
+        assert_eq!(status.title(), "hachip \u{2014} Pong [x0.5 speed]  1:02");
+        status.paused = true;
+        assert_eq!(status.title(), "hachip \u{2014} Pong [paused]  1:02");
+        assert_eq!(Status::default().speed, 100);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::cpu::Cpu;
+use crate::errors::EmulateCycleError;
+use crate::opcodes;
+use crate::ppu::{Display, WIDTH};
+use crate::state::SaveState;
+use crate::text::{Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+
+// Characters that fit on a line.
+const LINE_CHARS: usize = 16;
+const IGNORE: usize = 0;
+const STEP: usize = 1;
+const RESET: usize = 2;
+
+// How the game goes on once the error screen closes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recovery {
+    // from where the cpu is, past the failed instruction
+    Resume,
+    // from a soft reset, for the frontend to do
+    Reset,
+    // not at all: the frontend stops with the error, as it used to
+    Quit,
+}
+
+// What a window shows when an instruction fails, instead of closing: the
+// PC, the opcode and its disassembly, and a choice to skip the instruction
+// and go on, step past it one instruction at a time, or reset. Back quits
+// with the error. Like the pause menu it draws on the emulated display, so
+// the game is kept aside while it is up.
+pub struct ErrorScreen {
+    game: SaveState,
+    error: EmulateCycleError,
+    // whether the instruction at the PC is the one that failed, or the
+    // next one after a step
+    failed: bool,
+    selected: usize,
+    input: UiInput,
+}
+
+impl ErrorScreen {
+    pub fn open(cpu: &Cpu, error: EmulateCycleError) -> ErrorScreen {
+        log::error!("{:03X}: {}", cpu.pc(), error);
+        ErrorScreen {
+            game: cpu.save_state(),
+            error,
+            failed: true,
+            selected: IGNORE,
+            input: UiInput::new(),
+        }
+    }
+
+    // The error shown, which is the latest one after steps.
+    pub fn error(&self) -> &EmulateCycleError {
+        &self.error
+    }
+
+    pub fn into_error(self) -> EmulateCycleError {
+        self.error
+    }
+
+    // Returns how to go on once the screen has closed, with the cpu back in
+    // the game.
+    pub fn key_down(&mut self, key: UiKey, cpu: &mut Cpu) -> Option<Recovery> {
+        let recovery = match self.input.key_down(key)? {
+            UiAction::Confirm if self.selected == IGNORE => Recovery::Resume,
+            UiAction::Confirm if self.selected == STEP => {
+                self.step(cpu);
+                return None;
+            }
+            UiAction::Confirm => Recovery::Reset,
+            UiAction::Back => Recovery::Quit,
+            action => {
+                self.move_selection(action);
+                return None;
+            }
+        };
+        cpu.load_state(&self.game);
+        if recovery == Recovery::Resume && self.failed {
+            cpu.skip_instruction();
+        }
+        Some(recovery)
+    }
+
+    // Runs the next instruction, skipping the failed one first. A new
+    // failure replaces the one shown.
+    fn step(&mut self, cpu: &mut Cpu) {
+        cpu.load_state(&self.game);
+        if self.failed {
+            cpu.skip_instruction();
+        }
+        let pc = cpu.pc();
+        match cpu.step() {
+            Ok(()) => self.failed = false,
+            Err(error) => {
+                log::error!("{:03X}: {}", pc, error);
+                self.error = error;
+                self.failed = true;
+            }
+        }
+        self.game = cpu.save_state();
+    }
+
+    fn move_selection(&mut self, action: UiAction) {
+        match action {
+            UiAction::Up => self.selected = self.selected.saturating_sub(1),
+            UiAction::Down => self.selected = (self.selected + 1).min(RESET),
+            _ => {}
+        }
+    }
+
+    // Draws the screen over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let pc = self.game.pc;
+        let memory = &self.game.memory;
+        let byte = |address: u16| memory.get(address as usize).copied().unwrap_or_default() as u16;
+        let opcode = byte(pc) << 8 | byte(pc.wrapping_add(1));
+        let mut lines = vec![
+            format!("{} AT {:03X}", if self.failed { "ERROR" } else { "STEP" }, pc),
+            format!("{:04X} {}", opcode, opcodes::disassemble(opcode)),
+        ];
+        let options = [if self.failed { "IGNORE" } else { "RESUME" }, "STEP", "RESET"];
+        for (row, label) in options.iter().enumerate() {
+            let marker = if row == self.selected { '>' } else { ' ' };
+            lines.push(format!("{}{}", marker, label));
+        }
+
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        for (row, line) in lines.iter().enumerate() {
+            let line: String = line.chars().take(LINE_CHARS).collect();
+            text.draw_text(0, (row * LINE_HEIGHT) as i32, &line);
+        }
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+    }
+}
+
+impl UiScreen for ErrorScreen {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.move_selection(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorScreen, Recovery};
+    use crate::cpu::Cpu;
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::ui_input::UiKey;
+
+    #[test]
+    fn skips_or_steps_past_the_failed_instruction() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // ADD V0, 1 / bad / ADD V0, 1 / bad / JP 0x200
+        cpu.load(vec![0x70, 0x01, 0xE1, 0xFF, 0x70, 0x01, 0xE1, 0xFF, 0x12, 0x00]).unwrap();
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+        assert_eq!(cpu.pc(), 0x202);
+
+        let mut screen = ErrorScreen::open(&cpu, error);
+        screen.render(cpu.display_mut());
+        assert!(headless::dump_text(cpu.display().framebuffer()).contains('#'));
+        assert_eq!(screen.key_down(UiKey::Down, &mut cpu), None);
+        assert_eq!(screen.key_down(UiKey::Enter, &mut cpu), None, "steps");
+        assert_eq!(cpu.registers()[0], 2);
+        assert_eq!(screen.key_down(UiKey::Enter, &mut cpu), None, "fails again");
+        assert_eq!(screen.key_down(UiKey::Up, &mut cpu), None);
+        assert_eq!(screen.key_down(UiKey::Enter, &mut cpu), Some(Recovery::Resume));
+        assert_eq!(cpu.pc(), 0x208, "past the second bad opcode");
+        assert!(!cpu.display().framebuffer().contains(&1), "the game's screen is back");
+
+        let error = cpu.step().and_then(|_| cpu.step()).and_then(|_| cpu.step()).unwrap_err();
+        let mut screen = ErrorScreen::open(&cpu, error);
+        assert_eq!(screen.key_down(UiKey::Escape, &mut cpu), Some(Recovery::Quit));
+        assert_eq!(cpu.pc(), 0x202);
+        assert!(screen.error().to_string().contains("E1FF"), "{}", screen.error());
+    }
+}
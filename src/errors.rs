@@ -1,8 +1,60 @@
 use std::error;
 use std::fmt;
 
+#[non_exhaustive]
 pub struct EmulateCycleError {
     pub message: String,
+    // The rule the program broke, when the error is one.
+    pub violation: Option<Violation>,
+}
+
+impl EmulateCycleError {
+    pub fn violation(violation: Violation) -> EmulateCycleError {
+        EmulateCycleError {
+            message: violation.to_string(),
+            violation: Some(violation),
+        }
+    }
+}
+
+// Behaviour a conforming program doesn't rely on. Unknown opcodes and stack
+// errors always stop the cpu; the others only in strict mode, and are
+// otherwise wrapped into range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Violation {
+    UnknownOpcode(u16),
+    // Ex9E/ExA1 with a key above F
+    KeyOutOfRange(u8),
+    // an access past the end of memory, at the unwrapped address
+    MemoryWrap(usize),
+    StackOverflow,
+    StackUnderflow,
+}
+
+impl Violation {
+    // Stable name for test runners sorting failures by kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Violation::UnknownOpcode(_) => "unknown-opcode",
+            Violation::KeyOutOfRange(_) => "key-out-of-range",
+            Violation::MemoryWrap(_) => "memory-wrap",
+            Violation::StackOverflow => "stack-overflow",
+            Violation::StackUnderflow => "stack-underflow",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::UnknownOpcode(opcode) => write!(f, "{:04X} opcode not handled", opcode),
+            Violation::KeyOutOfRange(key) => write!(f, "key {:#X} is out of range", key),
+            Violation::MemoryWrap(address) => write!(f, "memory access at {:#X} wraps around", address),
+            Violation::StackOverflow => write!(f, "stack overflow"),
+            Violation::StackUnderflow => write!(f, "return with an empty stack"),
+        }
+    }
 }
 
 impl fmt::Display for EmulateCycleError {
@@ -15,8 +67,8 @@ impl fmt::Debug for EmulateCycleError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "EmulateCycleError {{ message: {} }}",
-            self.message
+            "EmulateCycleError {{ message: {}, violation: {:?} }}",
+            self.message, self.violation
         )
     }
 }
@@ -26,4 +78,188 @@ impl error::Error for EmulateCycleError {
         // Generic error, underlying cause isn't tracked.
         None
     }
-}
\ No newline at end of file
+}
+
+// A ROM too large to fit in memory at its load address.
+#[non_exhaustive]
+pub struct LoadError {
+    pub size: usize,
+    pub max_size: usize,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ROM is {} bytes, but only {} fit in memory", self.size, self.max_size)
+    }
+}
+
+impl fmt::Debug for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LoadError {{ size: {}, max_size: {} }}", self.size, self.max_size)
+    }
+}
+
+impl error::Error for LoadError {}
+
+// A config file that could not be read or parsed. `line` is 1-based and
+// missing when the file itself could not be read.
+#[non_exhaustive]
+pub struct ConfigError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ConfigError {{ line: {:?}, message: {} }}",
+            self.line, self.message
+        )
+    }
+}
+
+impl error::Error for ConfigError {}
+
+// A save state that could not be decoded.
+#[non_exhaustive]
+pub struct StateError {
+    pub message: String,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StateError {{ message: {} }}", self.message)
+    }
+}
+
+impl error::Error for StateError {}
+
+// A test script that could not be read or parsed. `line` is 1-based, zero
+// when the file itself could not be read.
+#[non_exhaustive]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl fmt::Debug for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ScriptError {{ line: {}, message: {} }}",
+            self.line, self.message
+        )
+    }
+}
+
+impl error::Error for ScriptError {}
+
+// A cheat file that could not be read or parsed. `line` is 1-based, zero
+// when the file itself could not be read.
+#[non_exhaustive]
+pub struct CheatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl fmt::Debug for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CheatError {{ line: {}, message: {} }}", self.line, self.message)
+    }
+}
+
+impl error::Error for CheatError {}
+
+// A hook script that could not be read or compiled.
+#[non_exhaustive]
+pub struct HookError {
+    pub message: String,
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HookError {{ message: {} }}", self.message)
+    }
+}
+
+impl error::Error for HookError {}
+
+// A movie file that could not be read or parsed.
+#[non_exhaustive]
+pub struct MovieError {
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MovieError {{ message: {} }}", self.message)
+    }
+}
+
+impl error::Error for MovieError {}
+
+// A ROM patch that could not be read or parsed.
+#[non_exhaustive]
+pub struct PatchError {
+    pub message: String,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PatchError {{ message: {} }}", self.message)
+    }
+}
+
+impl error::Error for PatchError {}
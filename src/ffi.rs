@@ -0,0 +1,133 @@
+// C API for embedding the core: build with
+// `cargo build --release --lib --no-default-features --features ffi`, link
+// the resulting cdylib and include the header from `hachip c-header`, which
+// build.rs generates from the functions and comments in this file. Keep
+// every signature on one line so the generator can read it.
+use crate::emulator::Emulator;
+use crate::headless::HeadlessDisplay;
+use std::slice;
+
+pub const HACHIP_WIDTH: usize = 64;
+pub const HACHIP_HEIGHT: usize = 32;
+pub const HACHIP_SAMPLE_RATE: u32 = 44100;
+
+pub const HEADER: &str = include_str!(concat!(env!("OUT_DIR"), "/hachip.h"));
+
+// A machine with nothing loaded; free it with hachip_free.
+#[no_mangle]
+pub extern "C" fn hachip_new() -> *mut Emulator {
+    Box::into_raw(Box::new(Emulator::new(Box::new(HeadlessDisplay::new()))))
+}
+
+// Frees a machine from hachip_new; null is ignored.
+/// # Safety
+/// `emulator` must be null or come from `hachip_new`, and not be used after.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_free(emulator: *mut Emulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+// Resets the machine and loads `size` bytes from `data`; false if the ROM
+// doesn't fit in memory.
+/// # Safety
+/// `emulator` must come from `hachip_new` and `data` hold `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_load_rom(emulator: *mut Emulator, data: *const u8, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts(data, size);
+    match (*emulator).load_rom(rom) {
+        Ok(()) => true,
+        Err(error) => {
+            log::error!("{}", error);
+            false
+        }
+    }
+}
+
+// Starts the loaded ROM over.
+/// # Safety
+/// `emulator` must come from `hachip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_reset(emulator: *mut Emulator) {
+    (*emulator).reset();
+}
+
+// Runs one 60Hz frame; false once the program stopped on a bad instruction.
+/// # Safety
+/// `emulator` must come from `hachip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_step_frame(emulator: *mut Emulator) -> bool {
+    match (*emulator).step_frame() {
+        Ok(_) => true,
+        Err(error) => {
+            log::error!("{}", error);
+            false
+        }
+    }
+}
+
+// Presses (or releases) hex key `key`, 0 to F.
+/// # Safety
+/// `emulator` must come from `hachip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_key_event(emulator: *mut Emulator, key: u8, pressed: bool) {
+    (*emulator).key_event(key, pressed);
+}
+
+// HACHIP_WIDTH * HACHIP_HEIGHT bytes, row by row, 1 for lit, valid until
+// the next call on `emulator`.
+/// # Safety
+/// `emulator` must come from `hachip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_framebuffer(emulator: *const Emulator) -> *const u8 {
+    (*emulator).framebuffer().as_ptr()
+}
+
+// The last frame's sound, 16-bit mono at HACHIP_SAMPLE_RATE; the number
+// of samples is written to `count`.
+/// # Safety
+/// `emulator` must come from `hachip_new` and `count` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn hachip_audio_samples(emulator: *const Emulator, count: *mut usize) -> *const i16 {
+    let samples = (*emulator).audio_samples();
+    *count = samples.len();
+    samples.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{SAMPLES_PER_FRAME, SAMPLE_RATE};
+    use crate::ppu::{HEIGHT, WIDTH};
+
+    #[test]
+    fn runs_a_rom_through_the_c_api() {
+        assert_eq!((HACHIP_WIDTH, HACHIP_HEIGHT, HACHIP_SAMPLE_RATE), (WIDTH, HEIGHT, SAMPLE_RATE));
+        assert!(HEADER.contains("#define HACHIP_WIDTH 64\n"));
+        assert!(HEADER.contains("typedef struct hachip hachip;\n"));
+        assert!(HEADER.contains("\nbool hachip_load_rom(hachip *emulator, const uint8_t *data, size_t size);\n"));
+        assert!(HEADER.contains("\nconst int16_t *hachip_audio_samples(const hachip *emulator, size_t *count);\n"));
+        assert!(HEADER.contains("// Runs one 60Hz frame; false once the program stopped on a bad instruction.\n"));
+        assert!(!HEADER.contains("Safety"));
+
+        // waits for 5, then draws a 5: LD V0, 5 / SKP V0 / JP 0x202 / LD F, V0 / DRW V0, V0, 5 / JP 0x20A
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0A];
+        unsafe {
+            let emulator = hachip_new();
+            assert!(!hachip_load_rom(emulator, [0; 4000].as_ptr(), 4000));
+            assert!(hachip_load_rom(emulator, rom.as_ptr(), rom.len()));
+            hachip_key_event(emulator, 5, true);
+            assert!(hachip_step_frame(emulator));
+            let framebuffer = slice::from_raw_parts(hachip_framebuffer(emulator), HACHIP_WIDTH * HACHIP_HEIGHT);
+            assert_eq!(framebuffer[5 * WIDTH + 5], 1);
+            let mut count = 0;
+            assert!(!hachip_audio_samples(emulator, &mut count).is_null());
+            assert_eq!(count, SAMPLES_PER_FRAME);
+            hachip_free(emulator);
+        }
+    }
+}
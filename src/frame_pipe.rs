@@ -0,0 +1,132 @@
+use crate::cpu::Cpu;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::scheduler::{self, Scheduler};
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+
+// Bytes after the length prefix: frame number, width, height, then a byte
+// per pixel.
+pub const PAYLOAD_LENGTH: usize = 8 + 2 + 2 + WIDTH * HEIGHT;
+
+// A line read from the controlling program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    // "press 5" / "release 5"
+    Key(u8, bool),
+    // "quit"
+    Quit,
+}
+
+impl Input {
+    pub fn parse(line: &str) -> Option<Input> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["press", key] | ["release", key] => {
+                let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16)?;
+                Some(Input::Key(key, words[0] == "press"))
+            }
+            ["quit"] => Some(Input::Quit),
+            _ => None,
+        }
+    }
+}
+
+// One frame as written to the pipe: a little-endian u32 length, then the
+// frame number (u64), width and height (u16 each) and the pixels, row by
+// row, 1 for lit and 0 for dark. All little-endian.
+pub fn encode(frame: u64, framebuffer: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + PAYLOAD_LENGTH);
+    bytes.extend_from_slice(&(PAYLOAD_LENGTH as u32).to_le_bytes());
+    bytes.extend_from_slice(&frame.to_le_bytes());
+    bytes.extend_from_slice(&(WIDTH as u16).to_le_bytes());
+    bytes.extend_from_slice(&(HEIGHT as u16).to_le_bytes());
+    bytes.extend(framebuffer.iter().map(|pixel| (*pixel != 0) as u8));
+    bytes
+}
+
+// `--frame-pipe`: runs the ROM in real time without a window, writing every
+// frame to `output` and applying the key presses received on `input`
+// before each one. Ends on `Input::Quit`, or once the reader goes away.
+pub fn serve(cpu: &mut Cpu, instructions_per_frame: u32, mut output: impl Write, input: Receiver<Input>) -> io::Result<()> {
+    let mut scheduler = Scheduler::new(instructions_per_frame);
+    loop {
+        for _ in 0..scheduler.frames_due() {
+            loop {
+                match input.try_recv() {
+                    Ok(Input::Key(key, pressed)) => cpu.keypad.set_key(key, pressed),
+                    Ok(Input::Quit) => return Ok(()),
+                    Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            scheduler::run_frame(cpu, instructions_per_frame).map_err(|error| io::Error::other(error.to_string()))?;
+            match output.write_all(&encode(cpu.frame_count(), cpu.display().framebuffer())) {
+                Err(error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result?,
+            }
+        }
+        output.flush()?;
+        thread::sleep(scheduler.time_to_next_frame());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, serve, Input, PAYLOAD_LENGTH};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::ppu::WIDTH;
+    use std::io::{self, Write};
+    use std::sync::mpsc;
+
+    // Quits after `frames` frames, like a reader that stops listening.
+    struct Reader {
+        written: Vec<u8>,
+        frames: usize,
+    }
+
+    impl Write for Reader {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            if self.written.len() / (4 + PAYLOAD_LENGTH) == self.frames {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.written.extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_length_prefixed_frames_and_reads_keys() {
+        assert_eq!(Input::parse("press a"), Some(Input::Key(0xA, true)));
+        assert_eq!(Input::parse("release 5"), Some(Input::Key(5, false)));
+        assert_eq!(Input::parse("quit"), Some(Input::Quit));
+        assert_eq!(Input::parse("press 10"), None);
+
+        let mut framebuffer = vec![0; WIDTH * 32];
+        framebuffer[1] = 1;
+        let frame = encode(7, &framebuffer);
+        assert_eq!(frame.len(), 4 + PAYLOAD_LENGTH);
+        assert_eq!(frame[..16], [0x0C, 0x08, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 64, 0, 32, 0]);
+        assert_eq!(frame[16..18], [0, 1]);
+
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // waits for 5, then draws a 5: LD V0, 5 / SKP V0 / JP 0x202 / LD F, V0 / DRW V0, V0, 5 / JP 0x20A
+        cpu.load(vec![0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0A]).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Input::Key(5, true)).unwrap();
+        let mut reader = Reader {
+            written: Vec::new(),
+            frames: 2,
+        };
+        serve(&mut cpu, 10, &mut reader, receiver).unwrap();
+        assert_eq!(reader.written.len(), 2 * (4 + PAYLOAD_LENGTH), "stopped once the reader went away");
+        assert_eq!(reader.written[4], 1, "frame numbers count from the first frame");
+        let pixel = |frame: usize, x: usize, y: usize| reader.written[frame * (4 + PAYLOAD_LENGTH) + 16 + y * WIDTH + x];
+        assert_eq!(pixel(1, 5, 5), 1, "drawn once 5 was pressed");
+    }
+}
@@ -0,0 +1,392 @@
+use crate::autosave::{self, Autosave};
+use crate::battery::{self, Battery};
+use crate::bookmarks::{self, Bookmarks};
+use crate::cheats::Cheats;
+use crate::command::{Command, CommandQueue};
+use crate::config::Config;
+use crate::cpu::Cpu;
+use crate::crt::CrtEffects;
+use crate::emulator::Emulator;
+use crate::input_profile::{self, InputProfile};
+use crate::keymap::{self, KeyboardLayout, KeymapProfile};
+use crate::movie::MovieMode;
+use crate::netplay::Netplay;
+use crate::palette::Palette;
+use crate::ppu::{Scaling, DEFAULT_SCALE, MAX_SCALE, MIN_SCALE};
+use crate::recent;
+use crate::resume::{self, Resume};
+use crate::romdb;
+use crate::rpl_flags::{self, FlagStore};
+use crate::save_slots::{self, SaveSlots};
+use crate::scheduler::DEFAULT_INSTRUCTIONS_PER_FRAME;
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptHooks;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Settings shared by the window frontends.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct FrontendOptions {
+    pub instructions_per_frame: u32,
+    pub scaling: Scaling,
+    // Times the display is enlarged in a new window; the window keys grow
+    // and shrink it from there.
+    pub scale: u32,
+    pub palette: Palette,
+    pub keymap: KeymapProfile,
+    // Profiles for ROMs picked in the launcher, by file name without
+    // extension; the others use `keymap`.
+    pub rom_keymaps: Vec<(String, KeymapProfile)>,
+    // The host keyboard, detected where the frontend can when None.
+    pub layout: Option<KeyboardLayout>,
+    // This ROM's keys on top of the keymap profile's; ROMs picked in the
+    // launcher look theirs up.
+    pub input_profile: Option<InputProfile>,
+    // Decay time of the anti-flicker filter, off when None.
+    pub phosphor: Option<Duration>,
+    // Effects shown when the CRT filter is on. It starts on if any are set
+    // and F8 toggles it; toggling on with none set shows all of them.
+    pub crt: CrtEffects,
+    // Where to keep this ROM's autosaves; autosaving is off when None.
+    // F9 loads the latest one.
+    pub autosave_dir: Option<PathBuf>,
+    // Zero turns autosaving off for ROMs picked in the launcher.
+    pub autosave_interval: Duration,
+    // Where this ROM's bookmarks are kept; the pause menu (F3) offers none
+    // when None.
+    pub bookmark_dir: Option<PathBuf>,
+    // Where this ROM's RPL user flags (Fx75/Fx85) are kept between runs;
+    // they last only as long as the window when None.
+    pub flags_dir: Option<PathBuf>,
+    // Save the game on closing and offer to continue from it (`--resume`),
+    // and where this ROM's is kept; nothing is kept unless both are set.
+    pub resume: bool,
+    pub resume_dir: Option<PathBuf>,
+    // The numbered save states F1 picks from; off when None.
+    pub save_slots: Option<SaveSlots>,
+    // Memory kept on disk per ROM (`--battery`), and where this ROM's is
+    // kept; nothing is kept unless both are set.
+    pub battery: Option<Range<u16>>,
+    pub battery_dir: Option<PathBuf>,
+    // Where F12 saves screenshots and F10 recordings; both are off when None.
+    pub screenshot_dir: Option<PathBuf>,
+    // Input movie to record or play back; the cpu must already be seeded
+    // with its seed, for movies without logged random draws.
+    pub movie: Option<MovieMode>,
+    // Lockstep session with another instance, already connected; the cpu
+    // must be seeded with the agreed seed.
+    pub netplay: Option<Netplay>,
+    // Memory patches written before every frame.
+    pub cheats: Cheats,
+    // Script called along with every frame.
+    #[cfg(feature = "scripting")]
+    pub hooks: Option<ScriptHooks>,
+    // The ROM's name in the window title, see `rom_name`.
+    pub rom_name: Option<String>,
+    // Show the ROM browser for this directory first; the cpu from
+    // `init_cpu` then runs the ROM picked there.
+    pub rom_dir: Option<PathBuf>,
+    // ROMs picked there that don't fit in memory are cut short instead of
+    // refused.
+    pub truncate_roms: bool,
+    // ROMs picked there that the ROM database knows switch to its platform,
+    // speed and load address.
+    pub rom_database: bool,
+    // Setting changes from outside the frontend, applied between frames.
+    // Hotkeys go through the same queue.
+    pub commands: CommandQueue,
+    // Open a second window with the registers, timers and call stack,
+    // updated every frame.
+    pub watch_window: bool,
+    // Start with the speed meter (F4) up.
+    pub show_speed: bool,
+    // Pace frames by the display's refresh rather than the frame limiter's
+    // sleep; smoother on 60Hz displays.
+    pub vsync: bool,
+    // Draw a hexpad next to the display that plays with the mouse or
+    // touches (SDL window only).
+    pub touch_pad: bool,
+    // Show the first-run setup before anything else and save its choices
+    // to this config file; this run uses them too.
+    pub setup: Option<PathBuf>,
+}
+
+// How long an idle frontend waits for events before looking at its
+// command queue again.
+pub const IDLE_WAKE: Duration = Duration::from_millis(250);
+
+// Whether a frontend's loop runs on the frame clock or blocks on events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopMode {
+    Running,
+    // nothing changes on screen until an event or a command arrives
+    Idle,
+}
+
+// `menu` is None without a menu or ROM browser open, otherwise whether it
+// is idle. Menus keep the frame clock while they repeat a held key; a paused
+// game needs it for nothing.
+pub fn loop_mode(paused: bool, menu: Option<bool>) -> LoopMode {
+    match menu {
+        Some(true) => LoopMode::Idle,
+        Some(false) => LoopMode::Running,
+        None if paused => LoopMode::Idle,
+        None => LoopMode::Running,
+    }
+}
+
+// The title the ROM database knows the ROM by, or else its file name
+// without extension.
+pub fn rom_name(path: &Path, rom: &[u8]) -> String {
+    match romdb::lookup(rom) {
+        Some(info) => info.title.to_string(),
+        None => path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy().into_owned(),
+    }
+}
+
+// The scale after Ctrl+= (grow) or Ctrl+- (shrink), kept from MIN_SCALE
+// to MAX_SCALE.
+pub fn step_scale(scale: u32, grow: bool) -> u32 {
+    if grow {
+        (scale + 1).min(MAX_SCALE)
+    } else {
+        scale.saturating_sub(1).max(MIN_SCALE)
+    }
+}
+
+// The window pixels per point a display of `dpi` dots per inch needs for
+// the window to come out as large as on a 96 DPI one, in whole steps so the
+// CHIP-8 pixels stay square and sharp.
+pub fn dpi_factor(dpi: f32) -> u32 {
+    ((dpi / 96.0).round() as u32).max(1)
+}
+
+// Program memory as loaded, kept by frontends for soft resets.
+pub fn program(cpu: &Cpu) -> Vec<u8> {
+    cpu.memory()[cpu.load_address as usize..].to_vec()
+}
+
+// The battery memory of the ROM given on the command line, restored into
+// `cpu`. Do this before taking the program for soft resets, so they go
+// back to what was saved.
+pub fn restore_battery(cpu: &mut Cpu, options: &FrontendOptions) -> Option<Battery> {
+    let battery = Battery::new(options.battery_dir.as_deref()?, options.battery.clone()?);
+    battery.restore(cpu);
+    Some(battery)
+}
+
+// Resets the cpu and reloads the program, keeping the quirks. Refused while
+// a movie runs, since movies only replay from a power-on.
+pub fn soft_reset(cpu: &mut Cpu, program: &[u8], movie: Option<&MovieMode>) {
+    if movie.is_some() {
+        log::info!("reset is disabled during movie recording and playback");
+        return;
+    }
+    cpu.reset();
+    // the program came out of memory, so it fits
+    cpu.load_truncated(program.to_vec());
+    log::info!("reset");
+}
+
+// The Emulator a window frontend drives `cpu` through, with the cheats
+// and script hooks of `options`.
+pub fn emulator(cpu: Cpu, options: &FrontendOptions) -> Emulator {
+    let mut emulator = Emulator::from_cpu(cpu);
+    emulator.instructions_per_frame = options.instructions_per_frame;
+    emulator.cheats = options.cheats.clone();
+    #[cfg(feature = "scripting")]
+    {
+        emulator.hooks = options.hooks.clone();
+    }
+    emulator
+}
+
+// Swaps this frame's keys with the other player's. When that fails the
+// game goes on alone.
+pub fn netplay_frame(netplay: &mut Option<Netplay>, cpu: &mut Cpu) {
+    if let Some(session) = netplay {
+        match session.exchange(&cpu.keypad.keys, cpu.state_hash()) {
+            Ok(keys) => cpu.keypad.keys = keys,
+            Err(error) => {
+                log::error!("netplay: {}; playing on alone", error);
+                *netplay = None;
+            }
+        }
+    }
+}
+
+// Saves the settings picked in the setup to `path` and returns `options`
+// with them applied. A config that can't be saved is logged; the setup then
+// shows again on the next start.
+pub fn finish_setup(options: &FrontendOptions, config: &Config, path: &Path) -> FrontendOptions {
+    match config.save(path) {
+        Ok(()) => log::info!("saved the settings to {}", path.display()),
+        Err(error) => log::error!("{}: {}", path.display(), error),
+    }
+    let mut options = options.clone();
+    options.setup = None;
+    if let Some(palette) = config.display_palette() {
+        options.palette = palette;
+    }
+    options.keymap = config.keymap.unwrap_or(options.keymap);
+    options.scaling = config.scaling.unwrap_or(options.scaling);
+    if options.rom_dir.is_some() {
+        options.rom_dir = config.roms.clone().or(options.rom_dir);
+    }
+    options
+}
+
+// What a frontend keeps of a ROM picked in the launcher.
+pub struct Launched {
+    // program memory as loaded, for soft resets
+    pub program: Vec<u8>,
+    // None when autosaving is off
+    pub autosave: Option<Autosave>,
+    pub bookmarks: Option<Bookmarks>,
+    pub save_slots: Option<SaveSlots>,
+    pub resume: Option<Resume>,
+    // the flags are already restored into the cpu
+    pub flags: Option<FlagStore>,
+    // restored into the cpu as well
+    pub battery: Option<Battery>,
+    pub keymap: KeymapProfile,
+    pub input_profile: Option<InputProfile>,
+    pub rom_name: String,
+}
+
+// Loads a ROM picked in the launcher.
+pub fn launch(cpu: &mut Cpu, path: &Path, options: &FrontendOptions) -> io::Result<Launched> {
+    let rom = fs::read(path)?;
+    recent::remember(path);
+    // quirks and speed are applied before the first frame runs
+    if let Some(info) = romdb::detect(&rom).filter(|_| options.rom_database) {
+        cpu.load_address = info.load_address;
+        options.commands.push(Command::Quirks(info.platform.quirks()));
+        options.commands.push(Command::Speed(info.instructions_per_frame));
+    }
+    cpu.reset();
+    if options.truncate_roms {
+        cpu.load_truncated(rom.clone());
+    } else {
+        cpu.load(rom.clone()).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+    }
+    let rom_path = path.to_string_lossy();
+    let flags = rpl_flags::rom_dir(&rom_path, &rom).map(|dir| FlagStore::new(&dir));
+    if let Some(flags) = &flags {
+        flags.restore(cpu);
+    }
+    let battery = match (&options.battery, battery::rom_dir(&rom_path, &rom)) {
+        (Some(range), Some(dir)) => Some(Battery::new(&dir, range.clone())),
+        _ => None,
+    };
+    if let Some(battery) = &battery {
+        battery.restore(cpu);
+    }
+    let autosave = match autosave::rom_dir(&rom_path, &rom) {
+        Some(dir) if options.autosave_interval > Duration::from_secs(0) => Some(Autosave::new(dir, options.autosave_interval)),
+        _ => None,
+    };
+    Ok(Launched {
+        program: program(cpu),
+        autosave,
+        bookmarks: bookmarks::rom_dir(&rom_path, &rom).map(Bookmarks::new),
+        save_slots: save_slots::rom_dir(&rom_path, &rom).map(|dir| SaveSlots::new(dir, &rom)),
+        resume: resume::rom_dir(&rom_path, &rom).filter(|_| options.resume).map(Resume::new),
+        flags,
+        battery,
+        keymap: keymap::for_rom(&options.rom_keymaps, path).unwrap_or(options.keymap),
+        input_profile: input_profile::for_rom(&rom),
+        rom_name: rom_name(path, &rom),
+    })
+}
+
+impl Default for FrontendOptions {
+    fn default() -> FrontendOptions {
+        FrontendOptions {
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            scaling: Scaling::default(),
+            scale: DEFAULT_SCALE,
+            palette: Palette::default(),
+            keymap: KeymapProfile::default(),
+            rom_keymaps: Vec::new(),
+            layout: None,
+            input_profile: None,
+            phosphor: None,
+            crt: CrtEffects::default(),
+            autosave_dir: None,
+            autosave_interval: autosave::DEFAULT_INTERVAL,
+            bookmark_dir: None,
+            flags_dir: None,
+            resume: false,
+            resume_dir: None,
+            save_slots: None,
+            battery: None,
+            battery_dir: None,
+            screenshot_dir: None,
+            movie: None,
+            netplay: None,
+            cheats: Cheats::default(),
+            #[cfg(feature = "scripting")]
+            hooks: None,
+            rom_name: None,
+            rom_dir: None,
+            truncate_roms: false,
+            rom_database: true,
+            commands: CommandQueue::new(),
+            watch_window: false,
+            show_speed: false,
+            vsync: false,
+            touch_pad: false,
+            setup: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dpi_factor, loop_mode, program, soft_reset, step_scale, LoopMode};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn soft_reset_restarts_the_loaded_program() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V0, 5 / LD [I], V0 at 0x200, overwriting the program
+        cpu.load(vec![0x60, 0x05, 0xA2, 0x00, 0xF0, 0x55]).unwrap();
+        let loaded = program(&cpu);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.memory()[0x200], 0x05);
+
+        soft_reset(&mut cpu, &loaded, None);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.registers()[0], 0);
+        assert_eq!(cpu.memory()[0x200], 0x60);
+    }
+
+    #[test]
+    fn idles_while_paused_or_in_a_still_menu() {
+        assert_eq!(loop_mode(false, None), LoopMode::Running);
+        assert_eq!(loop_mode(true, None), LoopMode::Idle);
+        assert_eq!(loop_mode(false, Some(true)), LoopMode::Idle);
+        assert_eq!(loop_mode(true, Some(false)), LoopMode::Running, "repeating a held key");
+    }
+
+    #[test]
+    fn scale_steps_stay_in_range() {
+        assert_eq!(step_scale(10, true), 11);
+        assert_eq!(step_scale(20, true), 20);
+        assert_eq!(step_scale(2, false), 1);
+        assert_eq!(step_scale(1, false), 1);
+        assert_eq!(dpi_factor(96.0), 1);
+        assert_eq!(dpi_factor(72.0), 1);
+        assert_eq!(dpi_factor(192.0), 2);
+        assert_eq!(dpi_factor(0.0), 1, "unknown");
+    }
+}
@@ -0,0 +1,428 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::debugger::{Debugger, Hit, Limit, Stop};
+use crate::scheduler::FRAME_RATE;
+use crate::state::SaveState;
+use crate::watchpoint::{WatchKind, WatchTarget, Watchpoint};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Register numbers, in `g` packet order: V0-VF, then I and PC (16 bits,
+// little-endian), then SP, DT and ST.
+const I: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DT: usize = 19;
+const ST: usize = 20;
+const REGISTERS: usize = 21;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0">"#,
+    r#"<feature name="org.hachip.chip8">"#,
+    r#"<reg name="v0" bitsize="8" regnum="0"/><reg name="v1" bitsize="8"/><reg name="v2" bitsize="8"/>"#,
+    r#"<reg name="v3" bitsize="8"/><reg name="v4" bitsize="8"/><reg name="v5" bitsize="8"/>"#,
+    r#"<reg name="v6" bitsize="8"/><reg name="v7" bitsize="8"/><reg name="v8" bitsize="8"/>"#,
+    r#"<reg name="v9" bitsize="8"/><reg name="va" bitsize="8"/><reg name="vb" bitsize="8"/>"#,
+    r#"<reg name="vc" bitsize="8"/><reg name="vd" bitsize="8"/><reg name="ve" bitsize="8"/>"#,
+    r#"<reg name="vf" bitsize="8"/><reg name="i" bitsize="16" type="data_ptr"/>"#,
+    r#"<reg name="pc" bitsize="16" type="code_ptr"/><reg name="sp" bitsize="8"/>"#,
+    r#"<reg name="dt" bitsize="8"/><reg name="st" bitsize="8"/>"#,
+    r#"</feature></target>"#
+);
+
+// Signals in stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// What a packet asks of the connection loop.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    Detach,
+    // like Detach, without a reply
+    Kill,
+}
+
+// `--gdb <address>`: a GDB remote serial protocol server for one debugger
+// connection at a time. CHIP-8 has no gdb architecture, so registers are
+// described with a target.xml; memory, software breakpoints, watchpoints
+// (on memory, through the debugger's), stepping and continuing work from
+// gdb or any debug adapter speaking the protocol. Ctrl-C interrupts a
+// continue. Returns when the client detaches or kills the program.
+pub fn serve(cpu: &mut Cpu, instructions_per_frame: u32, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    log::info!("gdb stub listening on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    log::info!("gdb connected from {}", peer);
+    let mut connection = Connection::new(stream);
+    let mut stub = Stub::new(cpu, instructions_per_frame);
+    loop {
+        let packet = match connection.read_packet()? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        match stub.handle(cpu, &packet) {
+            Action::Reply(reply) => connection.send(&reply)?,
+            Action::Step => connection.send(&stop_reply(stub.debugger.run(cpu, Limit::Instructions(1))))?,
+            Action::Continue => {
+                let reply = stub.continue_running(cpu, &mut connection)?;
+                connection.send(&reply)?;
+            }
+            Action::Detach => {
+                connection.send("OK")?;
+                return Ok(());
+            }
+            Action::Kill => return Ok(()),
+        }
+    }
+}
+
+// An accepted client, framing packets as `$data#checksum` and acknowledging
+// each with `+`.
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        Connection { stream }
+    }
+
+    // The next packet's data, None once the client hung up.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            // acks and stray interrupts before the packet are skipped
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.stream.write_all(frame(data).as_bytes())?;
+        self.stream.flush()
+    }
+
+    // Whether the client sent Ctrl-C, without waiting for it.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(read) => Ok(read == 1 && byte[0] == 0x03),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+struct Stub {
+    debugger: Debugger,
+}
+
+impl Stub {
+    fn new(cpu: &Cpu, instructions_per_frame: u32) -> Stub {
+        Stub {
+            debugger: Debugger::new(cpu, instructions_per_frame),
+        }
+    }
+
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Action {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => format!("S{:02x}", SIGTRAP),
+            Some(b'g') => hex(&registers(cpu)),
+            Some(b'G') => match unhex(&packet[1..]) {
+                Some(bytes) if bytes.len() == registers(cpu).len() => {
+                    set_registers(cpu, &bytes);
+                    "OK".to_string()
+                }
+                _ => error(),
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(register) if register < REGISTERS => hex(&register_bytes(cpu, register)),
+                _ => error(),
+            },
+            Some(b'P') => match packet[1..].split_once('=').and_then(|(register, value)| {
+                let register = usize::from_str_radix(register, 16).ok().filter(|register| *register < REGISTERS)?;
+                Some((register, unhex(value)?))
+            }) {
+                Some((register, value)) if value.len() == register_bytes(cpu, register).len() => {
+                    let mut bytes = registers(cpu);
+                    let offset = register_offset(register);
+                    bytes[offset..offset + value.len()].copy_from_slice(&value);
+                    set_registers(cpu, &bytes);
+                    "OK".to_string()
+                }
+                _ => error(),
+            },
+            Some(b'm') => match parse_range(&packet[1..]) {
+                Some((address, length)) => hex(&cpu.memory()[address..address + length]),
+                None => error(),
+            },
+            Some(b'M') => match packet[1..].split_once(':').and_then(|(range, data)| Some((parse_range(range)?, unhex(data)?))) {
+                Some(((address, length), data)) if data.len() == length => {
+                    cpu.memory_mut()[address..address + length].copy_from_slice(&data);
+                    "OK".to_string()
+                }
+                _ => error(),
+            },
+            Some(b'c') if packet.len() == 1 => return Action::Continue,
+            Some(b's') if packet.len() == 1 => return Action::Step,
+            Some(b'Z') | Some(b'z') => self.breakpoint(packet),
+            Some(b'D') => return Action::Detach,
+            Some(b'k') => return Action::Kill,
+            Some(b'H') => "OK".to_string(),
+            _ if packet.starts_with("qSupported") => "PacketSize=1000;qXfer:features:read+".to_string(),
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let range = &packet["qXfer:features:read:target.xml:".len()..];
+                match range.split_once(',').and_then(|(offset, length)| {
+                    Some((usize::from_str_radix(offset, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+                }) {
+                    Some((offset, length)) => {
+                        let start = offset.min(TARGET_XML.len());
+                        let end = (start + length).min(TARGET_XML.len());
+                        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, &TARGET_XML[start..end])
+                    }
+                    None => error(),
+                }
+            }
+            _ if packet == "qAttached" => "1".to_string(),
+            // anything else is unsupported, which the empty reply says
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    // Z0/Z1 set and z0/z1 clear breakpoints; 2, 3 and 4 are write, read
+    // and access watchpoints, one per byte of their length.
+    fn breakpoint(&mut self, packet: &str) -> String {
+        let set = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(|address| u16::from_str_radix(address, 16).ok());
+        let length = fields.next().and_then(|length| u16::from_str_radix(length, 16).ok());
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) if (address as usize) < MEMORY_SIZE => (address, length),
+            _ => return error(),
+        };
+        let kind = match kind {
+            Some("0") | Some("1") => {
+                if self.debugger.breakpoints().contains(&address) != set {
+                    self.debugger.toggle_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => WatchKind::Write,
+            Some("3") => WatchKind::Read,
+            Some("4") => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let end = (address as usize + length.max(1) as usize).min(MEMORY_SIZE) as u16;
+        for address in address..end {
+            let watchpoint = Watchpoint {
+                target: WatchTarget::Memory(address),
+                kind,
+            };
+            if set {
+                self.debugger.add_watchpoint(watchpoint);
+            } else {
+                self.debugger.remove_watchpoint(&watchpoint);
+            }
+        }
+        "OK".to_string()
+    }
+
+    // Runs a frame at a time, paced at 60Hz so timers keep their speed,
+    // until something stops it, and returns the stop reply.
+    fn continue_running(&mut self, cpu: &mut Cpu, connection: &mut Connection) -> io::Result<String> {
+        let frame = Duration::from_secs(1) / FRAME_RATE;
+        let mut first = true;
+        loop {
+            // run only checks breakpoints after its first instruction
+            if !first && self.debugger.breaks_at(cpu) {
+                return Ok(format!("S{:02x}", SIGTRAP));
+            }
+            first = false;
+            match self.debugger.run(cpu, Limit::Frames(1)) {
+                Stop::Done => {}
+                stop => return Ok(stop_reply(stop)),
+            }
+            if connection.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            thread::sleep(frame);
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Watchpoint(Hit { watchpoint, .. }) => match watchpoint.target {
+            WatchTarget::Memory(address) => {
+                let name = match watchpoint.kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, name, address)
+            }
+            WatchTarget::Register(_) => format!("S{:02x}", SIGTRAP),
+        },
+        Stop::Error(error) => {
+            log::warn!("gdb stub: {}", error);
+            format!("S{:02x}", SIGILL)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn registers(cpu: &Cpu) -> Vec<u8> {
+    let state = cpu.save_state();
+    (0..REGISTERS).flat_map(|register| state_register(&state, register)).collect()
+}
+
+fn register_bytes(cpu: &Cpu, register: usize) -> Vec<u8> {
+    state_register(&cpu.save_state(), register)
+}
+
+fn state_register(state: &SaveState, register: usize) -> Vec<u8> {
+    match register {
+        I => (state.i as u16).to_le_bytes().to_vec(),
+        PC => state.pc.to_le_bytes().to_vec(),
+        SP => vec![state.sp],
+        DT => vec![state.dt],
+        ST => vec![state.st],
+        _ => vec![state.v[register]],
+    }
+}
+
+// Where a register starts in the `g` packet.
+fn register_offset(register: usize) -> usize {
+    match register {
+        0..=I => register,
+        PC => I + 2,
+        _ => register + 2,
+    }
+}
+
+fn set_registers(cpu: &mut Cpu, bytes: &[u8]) {
+    let mut state = cpu.save_state();
+    state.v.copy_from_slice(&bytes[..16]);
+    state.i = state.i & 0xFF_0000 | u16::from_le_bytes([bytes[register_offset(I)], bytes[register_offset(I) + 1]]) as u32;
+    state.pc = u16::from_le_bytes([bytes[register_offset(PC)], bytes[register_offset(PC) + 1]]);
+    state.sp = bytes[register_offset(SP)].min(15);
+    state.dt = bytes[register_offset(DT)];
+    state.st = bytes[register_offset(ST)];
+    cpu.load_state(&state);
+}
+
+// "addr,length", kept inside memory.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    if address >= MEMORY_SIZE {
+        return None;
+    }
+    Some((address, length.min(MEMORY_SIZE - address)))
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn frame(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame, Action, Stub};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    fn reply(stub: &mut Stub, cpu: &mut Cpu, packet: &str) -> String {
+        match stub.handle(cpu, packet) {
+            Action::Reply(reply) => reply,
+            action => panic!("{:?}", action),
+        }
+    }
+
+    #[test]
+    fn maps_registers_memory_and_breakpoints() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V0, 1 / LD I, 0x300 / LD [I], V0 / JP 0x200
+        cpu.load(vec![0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]).unwrap();
+        let mut stub = Stub::new(&cpu, 10);
+        assert_eq!(frame("OK"), "$OK#9a");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "g"), format!("{}00000002000000", "00".repeat(16)));
+        assert_eq!(reply(&mut stub, &mut cpu, "m200,4"), "6001a300");
+        assert_eq!(reply(&mut stub, &mut cpu, "p11"), "0002", "PC is register 17");
+        assert_eq!(reply(&mut stub, &mut cpu, "P3=2a"), "OK");
+        assert_eq!(cpu.registers()[3], 0x2A);
+        assert_eq!(reply(&mut stub, &mut cpu, "M300,2:beef"), "OK");
+        assert_eq!(cpu.memory()[0x301], 0xEF);
+        assert_eq!(reply(&mut stub, &mut cpu, "m1000,1"), "E01");
+        assert!(reply(&mut stub, &mut cpu, "qXfer:features:read:target.xml:0,20").starts_with("m<?xml"));
+
+        assert_eq!(stub.handle(&mut cpu, "s"), Action::Step);
+        assert_eq!(stub.handle(&mut cpu, "k"), Action::Kill);
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,204,2"), "OK");
+        assert_eq!(stub.debugger.breakpoints(), [0x204]);
+        assert_eq!(reply(&mut stub, &mut cpu, "z0,204,2"), "OK");
+        assert!(stub.debugger.breakpoints().is_empty());
+        assert_eq!(reply(&mut stub, &mut cpu, "Z2,300,1"), "OK");
+        assert_eq!(stub.debugger.watchpoints().len(), 1);
+        assert_eq!(reply(&mut stub, &mut cpu, "vMustReplyEmpty"), "");
+    }
+}
@@ -0,0 +1,122 @@
+use crate::megachip;
+use crate::ppu::{Ppu, HEIGHT, WIDTH};
+
+// The ppu only keeps the framebuffer, so running without a window needs
+// nothing more than a bare one.
+pub type HeadlessDisplay = Ppu;
+
+// One line per row, '#' for a lit pixel and '.' for a dark one.
+pub fn dump_text(framebuffer: &[u8]) -> String {
+    let mut text = String::with_capacity((WIDTH + 1) * HEIGHT);
+    for row in framebuffer.chunks(WIDTH) {
+        for pixel in row {
+            text.push(if *pixel == 1 { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// The Mega-Chip screen the same way, '#' for a pixel that isn't black.
+pub fn dump_mega_text(screen: &[u32]) -> String {
+    let mut text = String::with_capacity((megachip::WIDTH + 1) * megachip::HEIGHT);
+    for row in screen.chunks(megachip::WIDTH) {
+        for pixel in row {
+            text.push(if pixel & 0xFF_FFFF != 0 { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// The hash of the Mega-Chip screen, its pixels as 0xAARRGGBB big-endian.
+pub fn hash_mega(screen: &[u32]) -> u64 {
+    let mut hash = Fnv::default();
+    hash.extend(screen.iter().flat_map(|pixel| pixel.to_be_bytes()));
+    hash.finish()
+}
+
+// 64-bit FNV-1a over the framebuffer. Unlike std's hashers the result is
+// stable between builds, so it can be stored as an expected value in CI.
+pub fn hash(framebuffer: &[u8]) -> u64 {
+    let mut hash = Fnv::default();
+    hash.extend(framebuffer);
+    hash.finish()
+}
+
+// The same hash taken a piece at a time, for state that is hashed where it
+// lies instead of being copied into one buffer first.
+#[derive(Clone, Copy, Debug)]
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    pub fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+impl Extend<u8> for Fnv {
+    fn extend<T: IntoIterator<Item = u8>>(&mut self, bytes: T) {
+        for byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+impl<'a> Extend<&'a u8> for Fnv {
+    fn extend<T: IntoIterator<Item = &'a u8>>(&mut self, bytes: T) {
+        self.extend(bytes.into_iter().copied());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump_mega_text, dump_text, hash, hash_mega, Fnv, HeadlessDisplay};
+    use crate::megachip;
+    use crate::ppu::{Display, HEIGHT, WIDTH};
+
+    #[test]
+    fn dump_text_marks_lit_pixels() {
+        let mut display = HeadlessDisplay::new();
+        display.draw(0, 0, &[0b10100000], false);
+
+        let text = dump_text(display.framebuffer());
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), HEIGHT);
+        assert_eq!(lines[0].len(), WIDTH);
+        assert!(lines[0].starts_with("#.#."));
+        assert!(lines[1].chars().all(|c| c == '.'));
+
+        let mut screen = vec![0xFF00_0000; megachip::WIDTH * megachip::HEIGHT];
+        screen[2] = 0xFF00_0100;
+        let text = dump_mega_text(&screen);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!((lines.len(), lines[0].len()), (megachip::HEIGHT, megachip::WIDTH));
+        assert!(lines[0].starts_with("..#."));
+        assert_ne!(hash_mega(&screen), hash_mega(&vec![0xFF00_0000; screen.len()]));
+    }
+
+    #[test]
+    fn hash_is_stable_and_depends_on_content() {
+        let mut display = HeadlessDisplay::new();
+        let blank = hash(display.framebuffer());
+        assert_eq!(blank, hash(&[0; WIDTH * HEIGHT]));
+
+        display.draw(3, 4, &[0xFF], false);
+        assert_ne!(blank, hash(display.framebuffer()));
+
+        // taken a piece at a time it comes out the same
+        let mut pieces = Fnv::default();
+        pieces.extend(&display.framebuffer()[..100]);
+        pieces.extend(display.framebuffer()[100..].iter().copied());
+        assert_eq!(pieces.finish(), hash(display.framebuffer()));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::keypad::Keypad;
+use crate::movie;
+use crate::scheduler::FRAME_RATE;
+
+// Longest macro kept; recording stops by itself after it.
+pub const MAX_FRAMES: usize = 60 * FRAME_RATE as usize;
+
+#[derive(Clone, Debug, PartialEq)]
+enum MacroState {
+    Idle,
+    Recording,
+    // the next frame to play
+    Playing(usize),
+}
+
+// A short input recording made and replayed during live play, for
+// practicing a tricky section or showing a bug: the keypad state of every
+// frame, like a movie, but only kept for the session and replayable at any
+// point. F5 starts and stops recording, F6 plays it back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMacro {
+    frames: Vec<u16>,
+    state: MacroState,
+}
+
+impl Default for InputMacro {
+    fn default() -> InputMacro {
+        InputMacro::new()
+    }
+}
+
+impl InputMacro {
+    pub fn new() -> InputMacro {
+        InputMacro {
+            frames: Vec::new(),
+            state: MacroState::Idle,
+        }
+    }
+
+    // Starts recording over the previous macro, or stops a recording.
+    pub fn toggle_recording(&mut self) {
+        if self.state == MacroState::Recording {
+            self.state = MacroState::Idle;
+            log::info!("recorded a {} frame macro", self.frames.len());
+        } else {
+            self.frames.clear();
+            self.state = MacroState::Recording;
+            log::info!("recording a macro");
+        }
+    }
+
+    // Plays the macro from its start; ignored while recording or before
+    // anything was recorded.
+    pub fn play(&mut self) {
+        match self.state {
+            MacroState::Recording => log::info!("stop recording before playing the macro"),
+            _ if self.frames.is_empty() => log::info!("no macro recorded"),
+            _ => self.state = MacroState::Playing(0),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.state == MacroState::Recording
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.state, MacroState::Playing(_))
+    }
+
+    // Length in frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Call before running each frame, after live input reached the keypad:
+    // a recording keeps the keypad state, a playback replaces it.
+    pub fn frame(&mut self, keypad: &mut Keypad) {
+        match self.state {
+            MacroState::Idle => {}
+            MacroState::Recording => {
+                self.frames.push(movie::keypad_mask(keypad));
+                if self.frames.len() == MAX_FRAMES {
+                    self.toggle_recording();
+                }
+            }
+            MacroState::Playing(next) => {
+                movie::set_keypad(keypad, self.frames[next]);
+                self.state = if next + 1 < self.frames.len() {
+                    MacroState::Playing(next + 1)
+                } else {
+                    MacroState::Idle
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputMacro;
+    use crate::keypad::Keypad;
+
+    #[test]
+    fn records_and_replays_keypad_states() {
+        let mut input = InputMacro::new();
+        let mut keypad = Keypad::new();
+        input.play();
+        assert!(!input.is_playing(), "nothing to play yet");
+
+        input.toggle_recording();
+        for key in [5, 5, 7].iter() {
+            keypad.press(vec![*key]);
+            input.frame(&mut keypad);
+        }
+        input.toggle_recording();
+        assert_eq!(input.len(), 3);
+
+        keypad.press(vec![]);
+        input.play();
+        let mut played = Vec::new();
+        while input.is_playing() {
+            input.frame(&mut keypad);
+            played.push((0..16).find(|key| keypad.is_key_down(*key)));
+        }
+        assert_eq!(played, vec![Some(5), Some(5), Some(7)]);
+
+        keypad.press(vec![1]);
+        input.frame(&mut keypad);
+        assert!(keypad.is_key_down(1), "live input is back after the macro");
+    }
+}
@@ -0,0 +1,144 @@
+use crate::keymap::HostKey;
+use crate::keypad::Keypad;
+use crate::scheduler::FRAME_RATE;
+use std::fmt;
+
+// Presses a second a turbo key makes unless given.
+pub const DEFAULT_TURBO_RATE: u32 = 10;
+// Frames a sequence holds each key, then leaves it up for as many, so the
+// same key twice in a row makes two presses.
+pub const SEQUENCE_STEP_FRAMES: usize = 4;
+
+// What a host key does besides playing one CHIP-8 key.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum KeyMacro {
+    // Presses and releases `key` `rate` times a second while held.
+    Turbo { key: u8, rate: u32 },
+    // Plays the keys one after another on a press, whether or not the host
+    // key is still held.
+    Sequence(Vec<u8>),
+}
+
+impl fmt::Display for KeyMacro {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyMacro::Turbo { key, rate } if *rate == DEFAULT_TURBO_RATE => write!(f, "turbo {:X}", key),
+            KeyMacro::Turbo { key, rate } => write!(f, "turbo {:X} {}", key, rate),
+            KeyMacro::Sequence(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| format!("{:X}", key)).collect();
+                write!(f, "{}", keys.join(" "))
+            }
+        }
+    }
+}
+
+// The layer between the host keyboard and the keypad playing the macros
+// of an input profile. Frontends report which of the macros' host keys are
+// held and call `frame` once per frame, after the plain keys reached the
+// keypad.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputMapping {
+    macros: Vec<(HostKey, KeyMacro)>,
+    // frames each macro's host key has been down, None while up
+    held: Vec<Option<usize>>,
+    // sequences playing: the macro and the frame into it
+    playing: Vec<(usize, usize)>,
+}
+
+impl InputMapping {
+    pub fn new(macros: Vec<(HostKey, KeyMacro)>) -> InputMapping {
+        InputMapping {
+            held: vec![None; macros.len()],
+            macros,
+            playing: Vec::new(),
+        }
+    }
+
+    // The host keys, in the order `set_held` numbers them.
+    pub fn host_keys(&self) -> impl Iterator<Item = HostKey> + '_ {
+        self.macros.iter().map(|(host_key, _)| *host_key)
+    }
+
+    pub fn set_held(&mut self, index: usize, held: bool) {
+        match (self.held[index], held) {
+            (None, true) => {
+                self.held[index] = Some(0);
+                if let KeyMacro::Sequence(_) = self.macros[index].1 {
+                    // a press while playing starts over
+                    self.playing.retain(|(playing, _)| *playing != index);
+                    self.playing.push((index, 0));
+                }
+            }
+            (Some(_), false) => self.held[index] = None,
+            _ => {}
+        }
+    }
+
+    // Presses the keys the macros hold this frame on top of the keypad's.
+    pub fn frame(&mut self, keypad: &mut Keypad) {
+        for ((_, key_macro), held) in self.macros.iter().zip(self.held.iter_mut()) {
+            if let (KeyMacro::Turbo { key, rate }, Some(frames)) = (key_macro, held) {
+                let period = (FRAME_RATE / (*rate).max(1)).max(2) as usize;
+                if *frames % period < period / 2 {
+                    keypad.set_key(*key, true);
+                }
+                *frames += 1;
+            }
+        }
+        let macros = &self.macros;
+        self.playing.retain_mut(|(index, frame)| {
+            let keys = match &macros[*index].1 {
+                KeyMacro::Sequence(keys) => keys,
+                _ => return false,
+            };
+            let step = *frame / (SEQUENCE_STEP_FRAMES * 2);
+            if step >= keys.len() {
+                return false;
+            }
+            if *frame % (SEQUENCE_STEP_FRAMES * 2) < SEQUENCE_STEP_FRAMES {
+                keypad.set_key(keys[step], true);
+            }
+            *frame += 1;
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputMapping, KeyMacro, SEQUENCE_STEP_FRAMES};
+    use crate::keymap::HostKey;
+    use crate::keypad::Keypad;
+
+    #[test]
+    fn plays_turbo_keys_and_sequences() {
+        let mut mapping = InputMapping::new(vec![
+            (HostKey::Char(' '), KeyMacro::Turbo { key: 5, rate: 15 }),
+            (HostKey::Char('Z'), KeyMacro::Sequence(vec![1, 1, 2])),
+        ]);
+        assert_eq!(mapping.host_keys().collect::<Vec<HostKey>>(), vec![HostKey::Char(' '), HostKey::Char('Z')]);
+        let frame = |mapping: &mut InputMapping| {
+            let mut keypad = Keypad::new();
+            mapping.frame(&mut keypad);
+            (0..16).filter(|key| keypad.is_key_down(*key)).collect::<Vec<u8>>()
+        };
+
+        mapping.set_held(0, true);
+        let turbo: Vec<bool> = (0..8).map(|_| frame(&mut mapping) == vec![5]).collect();
+        assert_eq!(turbo, vec![true, true, false, false, true, true, false, false]);
+        mapping.set_held(0, false);
+        assert_eq!(frame(&mut mapping), vec![]);
+
+        mapping.set_held(1, true);
+        mapping.set_held(1, false);
+        let played: Vec<Vec<u8>> = (0..SEQUENCE_STEP_FRAMES * 6 + 1).map(|_| frame(&mut mapping)).collect();
+        let presses = played.windows(2).filter(|pair| pair[0].is_empty() && !pair[1].is_empty()).count();
+        assert_eq!(played[0], vec![1]);
+        assert_eq!(presses, 2, "the second 1 and the 2, after the first");
+        assert_eq!(played[SEQUENCE_STEP_FRAMES * 4], vec![2]);
+        assert_eq!(played.last(), Some(&vec![]), "done");
+        assert_eq!(KeyMacro::Sequence(vec![1, 1, 0xA]).to_string(), "1 1 A");
+        assert_eq!(KeyMacro::Turbo { key: 5, rate: 10 }.to_string(), "turbo 5");
+    }
+}
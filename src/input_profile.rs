@@ -0,0 +1,217 @@
+use crate::config::Config;
+use crate::errors::ConfigError;
+use crate::headless;
+use crate::input_mapping::{InputMapping, KeyMacro, DEFAULT_TURBO_RATE};
+use crate::keymap::HostKey;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A ROM's own keys, kept in the config directory as
+// input/<ROM hash>.keys (the hash `hachip info` prints), one host key and
+// the CHIP-8 key it plays per line ('#' starts a comment):
+//
+//   # Tetris: the arrows, and up to rotate
+//   left = 5
+//   right = 6
+//   up = 4
+//   down = 7
+//   space = turbo 5      presses 5 ten times a second while held
+//   x = turbo 5 20       twenty times
+//   z = 4 4 6            plays 4, 4 and 6 on a press
+//
+// Keys are named as on a QWERTY keyboard, numpad keys `kp0` to `kp9`,
+// `kp/`, `kp*`, `kp-`, `kp+`, `kp.` and `kpenter`. They are added to the
+// keymap profile's, taking over the ones they share with it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputProfile {
+    bindings: Vec<(HostKey, u8)>,
+    macros: Vec<(HostKey, KeyMacro)>,
+}
+
+impl InputProfile {
+    pub fn parse(text: &str) -> Result<InputProfile, ConfigError> {
+        let mut profile = InputProfile::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| ConfigError {
+                line: Some(index + 1),
+                message,
+            };
+            // split at the last =, so `= = 5` binds the = key
+            let (name, value) = line
+                .rsplit_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or_else(|| error(format!("expected <host key> = <CHIP-8 key>, got `{}`", line)))?;
+            let host_key = HostKey::from_name(name).ok_or_else(|| error(format!("unknown host key {}", name)))?;
+            let key = |value: &str| parse_key(value).ok_or_else(|| error(format!("{} is not a CHIP-8 key, 0 to F", value)));
+            match value.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                [single] => profile.bind(host_key, key(single)?),
+                ["turbo", turbo_key, rate @ ..] if rate.len() <= 1 => {
+                    let rate = match rate.first() {
+                        Some(rate) => rate
+                            .parse()
+                            .ok()
+                            .filter(|rate| (1..=30).contains(rate))
+                            .ok_or_else(|| error(format!("{} is not a turbo rate, 1 to 30 a second", rate)))?,
+                        None => DEFAULT_TURBO_RATE,
+                    };
+                    profile.bind_macro(host_key, KeyMacro::Turbo { key: key(turbo_key)?, rate });
+                }
+                [_, ..] if !value.starts_with("turbo") => {
+                    let keys = value.split_whitespace().map(key).collect::<Result<Vec<u8>, ConfigError>>()?;
+                    profile.bind_macro(host_key, KeyMacro::Sequence(keys));
+                }
+                _ => return Err(error(format!("expected <CHIP-8 key>, turbo <CHIP-8 key> [<rate>] or a sequence, got `{}`", value))),
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn load(path: &Path) -> Result<InputProfile, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|error| ConfigError {
+            line: None,
+            message: format!("{}: {}", path.display(), error),
+        })?;
+        InputProfile::parse(&text)
+    }
+
+    // Binding a host key again replaces what it played.
+    pub fn bind(&mut self, host_key: HostKey, key: u8) {
+        self.unbind(host_key);
+        self.bindings.push((host_key, key));
+    }
+
+    pub fn bind_macro(&mut self, host_key: HostKey, key_macro: KeyMacro) {
+        self.unbind(host_key);
+        self.macros.push((host_key, key_macro));
+    }
+
+    fn unbind(&mut self, host_key: HostKey) {
+        self.bindings.retain(|(bound, _)| *bound != host_key);
+        self.macros.retain(|(bound, _)| *bound != host_key);
+    }
+
+    pub fn bindings(&self) -> &[(HostKey, u8)] {
+        &self.bindings
+    }
+
+    pub fn macros(&self) -> &[(HostKey, KeyMacro)] {
+        &self.macros
+    }
+
+    // The layer playing the macros.
+    pub fn mapping(&self) -> InputMapping {
+        InputMapping::new(self.macros.clone())
+    }
+
+    // A keymap profile's (host key, CHIP-8 key) pairs with this profile's
+    // on top. Keys with a macro play nothing else.
+    pub fn apply(&self, keys: &[(HostKey, u8)]) -> Vec<(HostKey, u8)> {
+        let bound = |host_key: &HostKey| {
+            self.bindings.iter().any(|(bound, _)| bound == host_key) || self.macros.iter().any(|(bound, _)| bound == host_key)
+        };
+        let mut applied: Vec<(HostKey, u8)> = keys
+            .iter()
+            .filter(|(host_key, _)| !bound(host_key))
+            .copied()
+            .collect();
+        applied.extend_from_slice(&self.bindings);
+        applied
+    }
+}
+
+impl fmt::Display for InputProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (host_key, key) in &self.bindings {
+            writeln!(f, "{} = {:X}", host_key.name(), key)?;
+        }
+        for (host_key, key_macro) in &self.macros {
+            writeln!(f, "{} = {}", host_key.name(), key_macro)?;
+        }
+        Ok(())
+    }
+}
+
+// One hex digit.
+fn parse_key(text: &str) -> Option<u8> {
+    u8::from_str_radix(text, 16).ok().filter(|_| text.len() == 1)
+}
+
+// Where the input profile of a ROM is looked for.
+pub fn rom_path(rom: &[u8]) -> Option<PathBuf> {
+    let config = Config::default_path()?;
+    Some(config.parent()?.join("input").join(format!("{:016x}.keys", headless::hash(rom))))
+}
+
+// The ROM's input profile, if it has one. One that can't be read is logged
+// and not applied.
+pub fn for_rom(rom: &[u8]) -> Option<InputProfile> {
+    let path = rom_path(rom)?;
+    if !path.exists() {
+        return None;
+    }
+    match InputProfile::load(&path) {
+        Ok(profile) => {
+            log::info!("input profile {}", path.display());
+            Some(profile)
+        }
+        Err(error) => {
+            log::warn!("input profile {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputProfile;
+    use crate::input_mapping::KeyMacro;
+    use crate::keymap::{HostKey, KeymapProfile};
+
+    #[test]
+    fn binds_host_keys_over_the_profile() {
+        let text = "# arrows\nup = 2\nLeft=4\nkp6 = 6\n= = 8\nq = a\nq = B\n";
+        let profile = InputProfile::parse(text).unwrap();
+        assert_eq!(
+            profile.bindings(),
+            &[
+                (HostKey::Up, 0x2),
+                (HostKey::Left, 0x4),
+                (HostKey::Numpad('6'), 0x6),
+                (HostKey::Char('='), 0x8),
+                (HostKey::Char('Q'), 0xB),
+            ]
+        );
+        assert_eq!(InputProfile::parse(&profile.to_string()).unwrap(), profile);
+
+        let keys = profile.apply(KeymapProfile::Standard.keys());
+        assert_eq!(keys.len(), 20, "Q is rebound, the rest added");
+        assert!(keys.contains(&(HostKey::Char('Q'), 0xB)));
+        assert!(!keys.contains(&(HostKey::Char('Q'), 0x4)));
+        assert!(keys.contains(&(HostKey::Char('W'), 0x5)));
+
+        assert_eq!(InputProfile::parse("up 2").unwrap_err().to_string(), "line 1: expected <host key> = <CHIP-8 key>, got `up 2`");
+        assert_eq!(InputProfile::parse("\nhome = 2").unwrap_err().to_string(), "line 2: unknown host key home");
+        assert_eq!(InputProfile::parse("up = 10").unwrap_err().to_string(), "line 1: 10 is not a CHIP-8 key, 0 to F");
+
+        let profile = InputProfile::parse("space = turbo 5\nw = turbo a 20\nz = 4 4 6\nx = 7\nx = 1 2").unwrap();
+        assert_eq!(
+            profile.macros(),
+            &[
+                (HostKey::Char(' '), KeyMacro::Turbo { key: 5, rate: 10 }),
+                (HostKey::Char('W'), KeyMacro::Turbo { key: 0xA, rate: 20 }),
+                (HostKey::Char('Z'), KeyMacro::Sequence(vec![4, 4, 6])),
+                (HostKey::Char('X'), KeyMacro::Sequence(vec![1, 2])),
+            ]
+        );
+        assert!(profile.bindings().is_empty(), "x was rebound to a sequence");
+        assert_eq!(InputProfile::parse(&profile.to_string()).unwrap(), profile);
+        assert_eq!(profile.apply(KeymapProfile::Standard.keys()).len(), 13, "W, Z and X play their macros only");
+        assert_eq!(InputProfile::parse("w = turbo 5 60").unwrap_err().to_string(), "line 1: 60 is not a turbo rate, 1 to 30 a second");
+        assert!(InputProfile::parse("w = turbo 5 10 20").is_err());
+    }
+}
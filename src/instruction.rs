@@ -0,0 +1,284 @@
+use std::fmt;
+
+// An opcode taken apart: what to do and the registers (x, y as 0-F),
+// bytes (kk), addresses (nnn) and nibbles (n) to do it with. The cpu
+// decodes every opcode it runs into one and executes that, and the
+// disassembler prints them. Variants are in opcode order. The Mega-Chip
+// ones (see is_megachip) only run with the megachip quirk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Instruction {
+    // 0010
+    MegaOff,
+    // 0011
+    MegaOn,
+    // 00Bn
+    ScrollUp(u8),
+    // 00E0, showing the Mega-Chip screen while it is on
+    Cls,
+    // 00EE
+    Ret,
+    // 01nn, followed by the low 16 bits of I
+    LoadLongI(u8),
+    // 02nn
+    LoadPalette(u8),
+    // 03nn
+    SpriteWidth(u8),
+    // 04nn
+    SpriteHeight(u8),
+    // 05nn
+    Alpha(u8),
+    // 060n
+    PlaySound(u8),
+    // 0700
+    StopSound,
+    // 080n
+    BlendMode(u8),
+    // 09nn
+    CollisionColor(u8),
+    // 1nnn
+    Jump(u16),
+    // 2nnn
+    Call(u16),
+    // 3xkk
+    SkipIfByte(u8, u8),
+    // 4xkk
+    SkipUnlessByte(u8, u8),
+    // 5xy0
+    SkipIfEqual(u8, u8),
+    // 6xkk
+    LoadByte(u8, u8),
+    // 7xkk
+    AddByte(u8, u8),
+    // 8xy0
+    Load(u8, u8),
+    // 8xy1
+    Or(u8, u8),
+    // 8xy2
+    And(u8, u8),
+    // 8xy3
+    Xor(u8, u8),
+    // 8xy4
+    Add(u8, u8),
+    // 8xy5
+    Sub(u8, u8),
+    // 8xy6, shifting Vy instead of Vx with the shift_vy quirk
+    ShiftRight(u8, u8),
+    // 8xy7
+    SubReversed(u8, u8),
+    // 8xyE, likewise
+    ShiftLeft(u8, u8),
+    // 9xy0
+    SkipUnlessEqual(u8, u8),
+    // Annn
+    LoadI(u16),
+    // Bnnn, read as Bxnn with the jump_vx quirk
+    JumpOffset(u16),
+    // Cxkk
+    Random(u8, u8),
+    // Dxyn, with Mega-Chip sprites while its screen is on
+    Draw(u8, u8, u8),
+    // Ex9E
+    SkipIfKey(u8),
+    // ExA1
+    SkipUnlessKey(u8),
+    // Fx07
+    LoadDelay(u8),
+    // Fx0A
+    WaitKey(u8),
+    // Fx15
+    SetDelay(u8),
+    // Fx18
+    SetSound(u8),
+    // Fx1E
+    AddI(u8),
+    // Fx29
+    LoadFont(u8),
+    // Fx33
+    StoreBcd(u8),
+    // Fx55
+    Store(u8),
+    // Fx65
+    Restore(u8),
+    // Fx75
+    StoreFlags(u8),
+    // Fx85
+    RestoreFlags(u8),
+}
+
+impl Instruction {
+    // None for opcodes the cpu rejects. Like the VIP, 5xy0 and 9xy0 don't
+    // look at their last nibble.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nnn = opcode & 0x0FFF;
+        let x = (opcode >> 8 & 0xF) as u8;
+        let y = (opcode >> 4 & 0xF) as u8;
+        let kk = opcode as u8;
+        let n = (opcode & 0xF) as u8;
+        let instruction = match opcode >> 12 {
+            0x0 => match opcode {
+                0x0010 => Instruction::MegaOff,
+                0x0011 => Instruction::MegaOn,
+                0x00B0..=0x00BF => Instruction::ScrollUp(n),
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x0100..=0x01FF => Instruction::LoadLongI(kk),
+                0x0200..=0x02FF => Instruction::LoadPalette(kk),
+                0x0300..=0x03FF => Instruction::SpriteWidth(kk),
+                0x0400..=0x04FF => Instruction::SpriteHeight(kk),
+                0x0500..=0x05FF => Instruction::Alpha(kk),
+                0x0600..=0x060F => Instruction::PlaySound(n),
+                0x0700 => Instruction::StopSound,
+                0x0800..=0x080F => Instruction::BlendMode(n),
+                0x0900..=0x09FF => Instruction::CollisionColor(kk),
+                _ => return None,
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipIfByte(x, kk),
+            0x4 => Instruction::SkipUnlessByte(x, kk),
+            0x5 => Instruction::SkipIfEqual(x, y),
+            0x6 => Instruction::LoadByte(x, kk),
+            0x7 => Instruction::AddByte(x, kk),
+            0x8 => match n {
+                0x0 => Instruction::Load(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::Add(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubReversed(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => return None,
+            },
+            0x9 => Instruction::SkipUnlessEqual(x, y),
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpOffset(nnn),
+            0xC => Instruction::Random(x, kk),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match kk {
+                0x9E => Instruction::SkipIfKey(x),
+                0xA1 => Instruction::SkipUnlessKey(x),
+                _ => return None,
+            },
+            _ => match kk {
+                0x07 => Instruction::LoadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LoadFont(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Restore(x),
+                0x75 => Instruction::StoreFlags(x),
+                0x85 => Instruction::RestoreFlags(x),
+                _ => return None,
+            },
+        };
+        Some(instruction)
+    }
+
+    // Whether it is one of Mega-Chip's, which other platforms reject.
+    pub fn is_megachip(self) -> bool {
+        matches!(
+            self,
+            Instruction::MegaOff
+                | Instruction::MegaOn
+                | Instruction::ScrollUp(_)
+                | Instruction::LoadLongI(_)
+                | Instruction::LoadPalette(_)
+                | Instruction::SpriteWidth(_)
+                | Instruction::SpriteHeight(_)
+                | Instruction::Alpha(_)
+                | Instruction::PlaySound(_)
+                | Instruction::StopSound
+                | Instruction::BlendMode(_)
+                | Instruction::CollisionColor(_)
+        )
+    }
+}
+
+// Assembly text, like "ADD V3, #01", with the mnemonics of the opcode
+// table.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::MegaOff => write!(f, "MEGAOFF"),
+            Instruction::MegaOn => write!(f, "MEGAON"),
+            Instruction::ScrollUp(n) => write!(f, "SCRU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::LoadLongI(nn) => write!(f, "LDHI #{:02X}", nn),
+            Instruction::LoadPalette(nn) => write!(f, "LDPAL #{:02X}", nn),
+            Instruction::SpriteWidth(nn) => write!(f, "SPRW #{:02X}", nn),
+            Instruction::SpriteHeight(nn) => write!(f, "SPRH #{:02X}", nn),
+            Instruction::Alpha(nn) => write!(f, "ALPHA #{:02X}", nn),
+            Instruction::PlaySound(n) => write!(f, "DIGISND {}", n),
+            Instruction::StopSound => write!(f, "STOPSND"),
+            Instruction::BlendMode(n) => write!(f, "BMODE {}", n),
+            Instruction::CollisionColor(nn) => write!(f, "CCOL #{:02X}", nn),
+            Instruction::Jump(nnn) => write!(f, "JP #{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL #{:03X}", nnn),
+            Instruction::SkipIfByte(x, kk) => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            Instruction::SkipUnlessByte(x, kk) => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            Instruction::SkipIfEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, kk) => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Instruction::Load(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, _) => write!(f, "SHR V{:X}", x),
+            Instruction::SubReversed(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, _) => write!(f, "SHL V{:X}", x),
+            Instruction::SkipUnlessEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, #{:03X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, #{:03X}", nnn),
+            Instruction::Random(x, kk) => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipUnlessKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Restore(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::RestoreFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+    use crate::opcodes;
+
+    #[test]
+    fn decodes_what_the_opcode_table_lists() {
+        assert_eq!(Instruction::decode(0x8AB4), Some(Instruction::Add(0xA, 0xB)));
+        assert_eq!(Instruction::decode(0xD125), Some(Instruction::Draw(1, 2, 5)));
+        assert_eq!(Instruction::decode(0x5121), Some(Instruction::SkipIfEqual(1, 2)));
+        assert_eq!(Instruction::decode(0x0123), Some(Instruction::LoadLongI(0x23)));
+        assert!(Instruction::decode(0x0123).unwrap().is_megachip());
+        assert_eq!(Instruction::decode(0x0A00), None);
+        for opcode in 0..=0xFFFF {
+            let entry = opcodes::lookup(opcode);
+            let instruction = Instruction::decode(opcode);
+            assert_eq!(instruction.is_some(), entry.is_some(), "{:04X}", opcode);
+            if let (Some(instruction), Some(entry)) = (instruction, entry) {
+                let text = instruction.to_string();
+                assert_eq!(text.split(' ').next(), entry.mnemonic.split(' ').next(), "{:04X}", opcode);
+                assert_eq!(text.split(' ').count(), entry.mnemonic.split(' ').count(), "{:04X} {}", opcode, text);
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::path::Path;
+
+// A key on the host keyboard, by position: a character key is named by what
+// it shows on a QWERTY keyboard, whatever the layout, and a numeric keypad
+// key by its digit or operator. Frontends translate these to their own key
+// codes, scan codes where they have them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    Char(char),
+    Numpad(char),
+    NumpadEnter,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl HostKey {
+    // What input profiles call the key: "q", "1", "space", "kp8",
+    // "kpenter", "up".
+    pub fn name(self) -> String {
+        match self {
+            HostKey::Char(' ') => "space".to_string(),
+            HostKey::Char(c) => c.to_ascii_lowercase().to_string(),
+            HostKey::Numpad(c) => format!("kp{}", c),
+            HostKey::NumpadEnter => "kpenter".to_string(),
+            HostKey::Up => "up".to_string(),
+            HostKey::Down => "down".to_string(),
+            HostKey::Left => "left".to_string(),
+            HostKey::Right => "right".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HostKey> {
+        let name = name.to_ascii_lowercase();
+        let mut chars = name.chars();
+        match (name.as_str(), chars.next(), chars.next()) {
+            ("space", _, _) => Some(HostKey::Char(' ')),
+            ("kpenter", _, _) => Some(HostKey::NumpadEnter),
+            ("up", _, _) => Some(HostKey::Up),
+            ("down", _, _) => Some(HostKey::Down),
+            ("left", _, _) => Some(HostKey::Left),
+            ("right", _, _) => Some(HostKey::Right),
+            (_, Some(c), None) if c.is_ascii_graphic() => Some(HostKey::Char(c.to_ascii_uppercase())),
+            _ => match name.strip_prefix("kp").map(|rest| rest.chars().collect::<Vec<char>>()).as_deref() {
+                Some([c]) if NUMPAD.contains(&HostKey::Numpad(*c)) => Some(HostKey::Numpad(*c)),
+                _ => None,
+            },
+        }
+    }
+}
+
+// Host keys of the standard profile, laid out like the hexpad:
+//   1 2 3 4      1 2 3 C
+//   Q W E R  ->  4 5 6 D
+//   A S D F      7 8 9 E
+//   Z X C V      A 0 B F
+const STANDARD: [(HostKey, u8); 16] = [
+    (HostKey::Char('1'), 0x1),
+    (HostKey::Char('2'), 0x2),
+    (HostKey::Char('3'), 0x3),
+    (HostKey::Char('4'), 0xC),
+    (HostKey::Char('Q'), 0x4),
+    (HostKey::Char('W'), 0x5),
+    (HostKey::Char('E'), 0x6),
+    (HostKey::Char('R'), 0xD),
+    (HostKey::Char('A'), 0x7),
+    (HostKey::Char('S'), 0x8),
+    (HostKey::Char('D'), 0x9),
+    (HostKey::Char('F'), 0xE),
+    (HostKey::Char('Z'), 0xA),
+    (HostKey::Char('X'), 0x0),
+    (HostKey::Char('C'), 0xB),
+    (HostKey::Char('V'), 0xF),
+];
+
+// Two-player games (Pong 2, Paddles, ...) give the left player keys from
+// the left three hexpad columns and the right player C, D, E and F. Player
+// one keeps those columns where they are; player two moves to the arrow
+// keys, so the two don't share a hand.
+const TWO_PLAYER: [(HostKey, u8); 16] = [
+    (HostKey::Char('1'), 0x1),
+    (HostKey::Char('2'), 0x2),
+    (HostKey::Char('3'), 0x3),
+    (HostKey::Char('Q'), 0x4),
+    (HostKey::Char('W'), 0x5),
+    (HostKey::Char('E'), 0x6),
+    (HostKey::Char('A'), 0x7),
+    (HostKey::Char('S'), 0x8),
+    (HostKey::Char('D'), 0x9),
+    (HostKey::Char('Z'), 0xA),
+    (HostKey::Char('X'), 0x0),
+    (HostKey::Char('C'), 0xB),
+    (HostKey::Up, 0xC),
+    (HostKey::Down, 0xD),
+    (HostKey::Left, 0xE),
+    (HostKey::Right, 0xF),
+];
+
+// The numeric keypad plays the hexpad by value: the digits are themselves,
+// A to F the operators around them.
+const NUMPAD: [HostKey; 16] = [
+    HostKey::Numpad('0'),
+    HostKey::Numpad('1'),
+    HostKey::Numpad('2'),
+    HostKey::Numpad('3'),
+    HostKey::Numpad('4'),
+    HostKey::Numpad('5'),
+    HostKey::Numpad('6'),
+    HostKey::Numpad('7'),
+    HostKey::Numpad('8'),
+    HostKey::Numpad('9'),
+    HostKey::Numpad('/'),
+    HostKey::Numpad('*'),
+    HostKey::Numpad('-'),
+    HostKey::Numpad('+'),
+    HostKey::NumpadEnter,
+    HostKey::Numpad('.'),
+];
+
+// The hexpad's rows, as on the COSMAC VIP.
+pub const HEXPAD_ROWS: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+// `--layout`: the host keyboard. Frontends reading scan codes play the
+// hexpad on the same physical keys whatever the letters on them, so there
+// the layout only names the keys; frontends reading key codes need it to
+// find those keys. The numpad layout plays on the numeric keypad instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+    Numpad,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 4] =
+        [KeyboardLayout::Qwerty, KeyboardLayout::Azerty, KeyboardLayout::Dvorak, KeyboardLayout::Numpad];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyboardLayout::Qwerty => "qwerty",
+            KeyboardLayout::Azerty => "azerty",
+            KeyboardLayout::Dvorak => "dvorak",
+            KeyboardLayout::Numpad => "numpad",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyboardLayout> {
+        KeyboardLayout::ALL.iter().copied().find(|layout| layout.name() == name)
+    }
+
+    // The layout of a keyboard whose key in the QWERTY Q position types
+    // `q`, as a frontend asks its windowing system.
+    pub fn detect(q: char) -> KeyboardLayout {
+        match q.to_ascii_uppercase() {
+            'A' => KeyboardLayout::Azerty,
+            '\'' => KeyboardLayout::Dvorak,
+            _ => KeyboardLayout::Qwerty,
+        }
+    }
+
+    // What the key in a QWERTY position types on this layout, upper case.
+    pub fn label(self, host_key: HostKey) -> HostKey {
+        let c = match host_key {
+            HostKey::Char(c) => c.to_ascii_uppercase(),
+            _ => return host_key,
+        };
+        let (qwerty, layout) = match self {
+            KeyboardLayout::Azerty => ("QWAZM;", "AZQW,M"),
+            KeyboardLayout::Dvorak => ("QWERTYUIOPSDFGHJKL;ZXCVBN", "',.PYFGCRLOEUIDHTNS;QJKXB"),
+            KeyboardLayout::Qwerty | KeyboardLayout::Numpad => ("", ""),
+        };
+        match qwerty.chars().position(|key| key == c) {
+            Some(index) => HostKey::Char(layout.chars().nth(index).unwrap_or(c)),
+            None => HostKey::Char(c),
+        }
+    }
+}
+
+// Which host keys play the hexpad.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum KeymapProfile {
+    #[default]
+    Standard,
+    TwoPlayer,
+}
+
+impl KeymapProfile {
+    pub const ALL: [KeymapProfile; 2] = [KeymapProfile::Standard, KeymapProfile::TwoPlayer];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeymapProfile::Standard => "standard",
+            KeymapProfile::TwoPlayer => "two-player",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeymapProfile> {
+        KeymapProfile::ALL.iter().copied().find(|profile| profile.name() == name)
+    }
+
+    // (host key, CHIP-8 key) pairs covering all 16 CHIP-8 keys.
+    pub fn keys(self) -> &'static [(HostKey, u8)] {
+        match self {
+            KeymapProfile::Standard => &STANDARD,
+            KeymapProfile::TwoPlayer => &TWO_PLAYER,
+        }
+    }
+
+    // The profile's keys on a layout: the same positions, except on the
+    // numpad layout, which moves the character keys to the numeric keypad.
+    pub fn layout_keys(self, layout: KeyboardLayout) -> Vec<(HostKey, u8)> {
+        self.keys()
+            .iter()
+            .map(|(host_key, key)| match (layout, host_key) {
+                (KeyboardLayout::Numpad, HostKey::Char(_)) => (NUMPAD[*key as usize], *key),
+                _ => (*host_key, *key),
+            })
+            .collect()
+    }
+
+    // The keys playing the hexpad as printed on the layout's keyboard, row
+    // by row: "1 2 3 4 / Q W E R / A S D F / Z X C V".
+    pub fn describe(self, layout: KeyboardLayout) -> String {
+        let keys = self.layout_keys(layout);
+        let rows: Vec<String> = HEXPAD_ROWS
+            .iter()
+            .map(|row| {
+                let labels: Vec<String> = row
+                    .iter()
+                    .filter_map(|key| keys.iter().find(|(_, mapped)| mapped == key))
+                    .map(|(host_key, _)| layout.label(*host_key).name().to_ascii_uppercase())
+                    .collect();
+                labels.join(" ")
+            })
+            .collect();
+        rows.join(" / ")
+    }
+
+    // The player, 1 or 2, whose side of the keyboard a CHIP-8 key is on;
+    // always 1 for single player profiles. Gamepads will be assigned by it.
+    pub fn player(self, key: u8) -> u8 {
+        match self {
+            KeymapProfile::TwoPlayer if key >= 0xC => 2,
+            _ => 1,
+        }
+    }
+
+    // `KeyboardEvent.code` values, the physical keys, to CHIP-8 keys as a
+    // JS object, for the browser pages.
+    pub fn to_js(self, layout: KeyboardLayout) -> String {
+        let entries: Vec<String> = self
+            .layout_keys(layout)
+            .iter()
+            .map(|(host_key, key)| {
+                let name = match host_key {
+                    HostKey::Char(c) if c.is_ascii_digit() => format!("Digit{}", c),
+                    HostKey::Char(c) => format!("Key{}", c.to_ascii_uppercase()),
+                    HostKey::Numpad(c) => match c {
+                        '/' => "NumpadDivide".to_string(),
+                        '*' => "NumpadMultiply".to_string(),
+                        '-' => "NumpadSubtract".to_string(),
+                        '+' => "NumpadAdd".to_string(),
+                        '.' => "NumpadDecimal".to_string(),
+                        _ => format!("Numpad{}", c),
+                    },
+                    HostKey::NumpadEnter => "NumpadEnter".to_string(),
+                    HostKey::Up => "ArrowUp".to_string(),
+                    HostKey::Down => "ArrowDown".to_string(),
+                    HostKey::Left => "ArrowLeft".to_string(),
+                    HostKey::Right => "ArrowRight".to_string(),
+                };
+                format!("\"{}\": {}", name, key)
+            })
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+}
+
+// The profile chosen for a ROM in `(name, profile)` pairs, matching the
+// ROM's file name without extension, ignoring case.
+pub fn for_rom(profiles: &[(String, KeymapProfile)], rom_path: &Path) -> Option<KeymapProfile> {
+    let stem = rom_path.file_stem()?.to_string_lossy();
+    profiles
+        .iter()
+        .rev()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&stem))
+        .map(|(_, profile)| *profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{for_rom, HostKey, KeyboardLayout, KeymapProfile};
+    use std::path::Path;
+
+    #[test]
+    fn profiles_cover_the_hexpad_once() {
+        for profile in KeymapProfile::ALL.iter() {
+            let mut keys: Vec<u8> = profile.keys().iter().map(|(_, key)| *key).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, (0..16).collect::<Vec<u8>>(), "{}", profile.name());
+            assert_eq!(KeymapProfile::from_name(profile.name()), Some(*profile));
+        }
+
+        let two_player = KeymapProfile::TwoPlayer;
+        assert!(two_player.keys().contains(&(HostKey::Up, 0xC)));
+        assert_eq!(two_player.player(0x1), 1);
+        assert_eq!(two_player.player(0xD), 2);
+        assert_eq!(KeymapProfile::Standard.player(0xD), 1);
+
+        let profiles = vec![("pong2".to_string(), two_player)];
+        assert_eq!(for_rom(&profiles, Path::new("roms/PONG2.ch8")), Some(two_player));
+        assert_eq!(for_rom(&profiles, Path::new("roms/pong.ch8")), None);
+    }
+
+    #[test]
+    fn layouts_name_and_move_the_keys() {
+        let standard = KeymapProfile::Standard;
+        assert_eq!(standard.describe(KeyboardLayout::Qwerty), "1 2 3 4 / Q W E R / A S D F / Z X C V");
+        assert_eq!(standard.describe(KeyboardLayout::Azerty), "1 2 3 4 / A Z E R / Q S D F / W X C V");
+        assert_eq!(standard.describe(KeyboardLayout::Dvorak), "1 2 3 4 / ' , . P / A O E U / ; Q J K");
+        assert_eq!(standard.layout_keys(KeyboardLayout::Dvorak), standard.keys(), "same positions");
+        assert_eq!(
+            KeymapProfile::TwoPlayer.describe(KeyboardLayout::Numpad),
+            "KP1 KP2 KP3 UP / KP4 KP5 KP6 DOWN / KP7 KP8 KP9 LEFT / KP/ KP0 KP* RIGHT"
+        );
+        assert!(standard.to_js(KeyboardLayout::Numpad).contains("\"NumpadEnter\": 14"));
+        assert!(standard.to_js(KeyboardLayout::Qwerty).starts_with("{\"Digit1\": 1, "));
+
+        assert_eq!(KeyboardLayout::detect('a'), KeyboardLayout::Azerty);
+        assert_eq!(KeyboardLayout::detect('\''), KeyboardLayout::Dvorak);
+        assert_eq!(KeyboardLayout::detect('q'), KeyboardLayout::Qwerty);
+        for layout in KeyboardLayout::ALL.iter() {
+            assert_eq!(KeyboardLayout::from_name(layout.name()), Some(*layout));
+        }
+    }
+}
@@ -1,10 +1,21 @@
 pub struct Keypad {
     pub keys: [bool; 16],
+    // the keys as they were at the end of the last frame, for the edges
+    previous: [bool; 16],
+}
+
+impl Default for Keypad {
+    fn default() -> Keypad {
+        Keypad::new()
+    }
 }
 
 impl Keypad {
     pub fn new() -> Keypad {
-        Keypad { keys: [false; 16] }
+        Keypad {
+            keys: [false; 16],
+            previous: [false; 16],
+        }
     }
 
     pub fn press(&mut self, indexes: Vec<u8>) {
@@ -14,6 +25,10 @@ impl Keypad {
         }
     }
 
+    pub fn set_key(&mut self, index: u8, pressed: bool) {
+        self.keys[index as usize] = pressed;
+    }
+
     fn reset(&mut self) {
         for i in 0..16 {
             self.keys[i] = false
@@ -23,4 +38,54 @@ impl Keypad {
     pub fn is_key_down(&self, index: u8) -> bool {
         self.keys[index as usize]
     }
+
+    // Down now and up at the end of the last frame. Unlike the level, an
+    // edge is seen once however long the key is held, so a key waited for
+    // or bound to an action triggers once per press.
+    pub fn just_pressed(&self, index: u8) -> bool {
+        self.keys[index as usize] && !self.previous[index as usize]
+    }
+
+    // Up now and down at the end of the last frame.
+    pub fn just_released(&self, index: u8) -> bool {
+        !self.keys[index as usize] && self.previous[index as usize]
+    }
+
+    // The first key released this frame, which isn't reported again until
+    // it is pressed and released anew, so two Fx0A in one frame don't both
+    // take the same release.
+    pub fn take_released(&mut self) -> Option<u8> {
+        let key = (0..16).find(|key| self.just_released(*key))?;
+        self.previous[key as usize] = false;
+        Some(key)
+    }
+
+    // Starts a new frame for the edges; the cpu calls it on every vertical
+    // blank.
+    pub fn end_frame(&mut self) {
+        self.previous = self.keys;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keypad;
+
+    #[test]
+    fn edges_last_one_frame() {
+        let mut keypad = Keypad::new();
+        keypad.set_key(5, true);
+        assert!(keypad.just_pressed(5));
+        assert!(!keypad.just_released(5));
+        keypad.end_frame();
+        assert!(keypad.is_key_down(5));
+        assert!(!keypad.just_pressed(5), "held, not pressed again");
+
+        keypad.press(vec![7]);
+        assert!(keypad.just_released(5));
+        assert!(keypad.just_pressed(7));
+        keypad.end_frame();
+        assert!(!keypad.just_released(5));
+        assert!(!keypad.just_pressed(7));
+    }
 }
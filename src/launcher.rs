@@ -0,0 +1,191 @@
+use crate::ppu::{Display, HEIGHT, WIDTH};
+use crate::text::{self, Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Menu rows under the title line.
+const ROWS: usize = HEIGHT / LINE_HEIGHT - 1;
+// Characters that fit next to the selection marker.
+const NAME_CHARS: usize = WIDTH / text::ADVANCE - 1;
+
+// What the player picked in the launcher.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Choice {
+    Rom(PathBuf),
+    Quit,
+}
+
+// The ROM browser shown when hachip starts without a ROM: recently played
+// ROMs, marked with '*', then the `.ch8` files of a directory, one per line,
+// drawn on the emulated display. Arrows or the hexpad d-pad move, left and
+// right page, Enter picks.
+pub struct Launcher {
+    dir: PathBuf,
+    roms: Vec<PathBuf>,
+    // how many of `roms` come from the recent list
+    recent: usize,
+    selected: usize,
+    top: usize,
+    input: UiInput,
+}
+
+impl Launcher {
+    // An unreadable directory shows as empty, with the error logged. Recent
+    // ROMs that no longer exist are left out.
+    pub fn new(dir: &Path, recent: &[PathBuf]) -> Launcher {
+        let mut roms: Vec<PathBuf> = recent.iter().filter(|rom| rom.is_file()).cloned().collect();
+        let recent = roms.len();
+        let listed = list_roms(dir).unwrap_or_else(|error| {
+            log::warn!("ROM directory {}: {}", dir.display(), error);
+            Vec::new()
+        });
+        for rom in listed {
+            let absolute = fs::canonicalize(&rom).unwrap_or_else(|_| rom.clone());
+            if !roms[..recent].contains(&absolute) {
+                roms.push(rom);
+            }
+        }
+        Launcher {
+            dir: dir.to_path_buf(),
+            roms,
+            recent,
+            selected: 0,
+            top: 0,
+            input: UiInput::new(),
+        }
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    pub fn selected(&self) -> Option<&Path> {
+        self.roms.get(self.selected).map(PathBuf::as_path)
+    }
+
+    pub fn key_down(&mut self, key: UiKey) -> Option<Choice> {
+        let action = self.input.key_down(key)?;
+        self.act(action)
+    }
+
+    fn act(&mut self, action: UiAction) -> Option<Choice> {
+        let last = self.roms.len().saturating_sub(1);
+        match action {
+            UiAction::Up => self.selected = self.selected.saturating_sub(1),
+            UiAction::Down => self.selected = (self.selected + 1).min(last),
+            UiAction::Left => self.selected = self.selected.saturating_sub(ROWS),
+            UiAction::Right => self.selected = (self.selected + ROWS).min(last),
+            UiAction::Confirm => return self.selected().map(|rom| Choice::Rom(rom.to_path_buf())),
+            UiAction::Back => return Some(Choice::Quit),
+        }
+        // keep the selection on screen
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + ROWS {
+            self.top = self.selected + 1 - ROWS;
+        }
+        None
+    }
+
+    // Draws the menu over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        let title = format!("ROMS {}/{}", (self.selected + 1).min(self.roms.len()), self.roms.len());
+        text.draw_text(0, 0, &title);
+        if self.roms.is_empty() {
+            let name = self.dir.file_name().unwrap_or_else(|| self.dir.as_os_str());
+            let message = format!("NO .CH8 FILES IN {}", name.to_string_lossy());
+            text.draw_text(0, LINE_HEIGHT as i32, &text::wrap(&message, WIDTH).join("\n"));
+        }
+        for (row, rom) in self.roms.iter().enumerate().skip(self.top).take(ROWS) {
+            let y = ((row - self.top + 1) * LINE_HEIGHT) as i32;
+            let name: String = rom
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().chars().take(NAME_CHARS).collect())
+                .unwrap_or_default();
+            let marker = if row == self.selected {
+                '>'
+            } else if row < self.recent {
+                '*'
+            } else {
+                ' '
+            };
+            text.draw_text(0, y, &format!("{}{}", marker, name));
+        }
+
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+    }
+}
+
+impl UiScreen for Launcher {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.act(action);
+    }
+}
+
+// The `.ch8` files in `dir`, sorted by name.
+pub fn list_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ch8")))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Choice, Launcher, ROWS};
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::ppu::Display;
+    use crate::ui_input::{UiKey, UiScreen};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn lists_and_picks_roms() {
+        let dir = env::temp_dir().join(format!("hachip-launcher-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["pong.ch8", "BLITZ.CH8", "notes.txt", "tetris.ch8", "ufo.ch8", "wipe.ch8", "zero.ch8"].iter() {
+            fs::write(dir.join(name), [0x12, 0x00]).unwrap();
+        }
+
+        let mut launcher = Launcher::new(&dir, &[]);
+        assert_eq!(launcher.roms().len(), 6, "only .ch8 files are listed");
+        assert_eq!(launcher.selected(), Some(dir.join("BLITZ.CH8").as_path()));
+
+        let recent = [dir.join("missing.ch8"), fs::canonicalize(dir.join("ufo.ch8")).unwrap()];
+        let with_recent = Launcher::new(&dir, &recent);
+        assert_eq!(with_recent.roms().len(), 6, "recent ROMs are not listed twice");
+        assert_eq!(with_recent.selected(), Some(recent[1].as_path()));
+
+        let mut display = HeadlessDisplay::new();
+        launcher.render(&mut display);
+        assert!(headless::dump_text(display.framebuffer()).contains('#'));
+
+        for _ in 0..ROWS {
+            assert_eq!(launcher.key_down(UiKey::Down), None);
+            launcher.key_up(UiKey::Down);
+        }
+        assert_eq!(launcher.top, 1, "the list scrolls with the selection");
+        assert_eq!(launcher.key_down(UiKey::Right), None);
+        assert_eq!(launcher.key_down(UiKey::Enter), Some(Choice::Rom(dir.join("zero.ch8"))));
+        assert_eq!(launcher.key_down(UiKey::Escape), Some(Choice::Quit));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! hachip is a CHIP-8 emulator core that frontends (SDL, winit, the browser,
+//! or anything implementing [`PixelGrid`]/[`Display`]) can drive.
+//!
+//! The items re-exported here and from [`prelude`] are the public API and
+//! follow semver: while the crate is 0.x, breaking changes only ship with a
+//! minor version bump. Enums and structs that are likely to grow (platforms,
+//! quirks, errors) are `#[non_exhaustive]`, so adding a variant or field is
+//! not a breaking change. Module internals not re-exported here may change in
+//! any release.
+
+pub mod assets;
+pub mod autosave;
+pub mod battery;
+pub mod bench;
+pub mod bookmarks;
+pub mod cheats;
+pub mod command;
+pub mod condition;
+pub mod config;
+pub mod cpu;
+pub mod cpu_thread;
+pub mod crt;
+pub mod debug_tui;
+pub mod debugger;
+pub mod emulator;
+pub mod error_screen;
+pub mod errors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frame_pipe;
+pub mod frontend;
+pub mod gdb_stub;
+pub mod headless;
+pub mod input_macro;
+pub mod input_mapping;
+pub mod input_profile;
+pub mod instruction;
+pub mod keymap;
+pub mod keypad;
+pub mod launcher;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod logging;
+pub mod megachip;
+#[cfg(feature = "pixels-frontend")]
+pub mod pixels_frontend;
+pub mod montecarlo;
+pub mod movie;
+pub mod netplay;
+pub mod opcodes;
+pub mod osd;
+pub mod palette;
+pub mod patch;
+pub mod pause_menu;
+pub mod phosphor;
+pub mod playclock;
+pub mod ppu;
+pub mod prelude;
+pub mod present;
+pub mod profile;
+pub mod profiler;
+pub mod quirks;
+pub mod ram_search;
+pub mod recent;
+pub mod recording;
+pub mod remote_display;
+pub mod resume;
+pub mod rng;
+pub mod romdb;
+pub mod rpl_flags;
+pub mod save_slots;
+pub mod scheduler;
+pub mod screenshot;
+pub mod script;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod session;
+pub mod slot_picker;
+pub mod state;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod testing;
+pub mod text;
+pub mod touch_pad;
+pub mod trace;
+pub mod ui_input;
+pub mod verify;
+pub mod vip_timing;
+pub mod watch_panel;
+pub mod watchpoint;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(all(feature = "web", not(target_arch = "wasm32")))]
+pub mod web_build;
+pub mod wizard;
+
+pub use crate::cpu::Cpu;
+pub use crate::emulator::Emulator;
+pub use crate::errors::EmulateCycleError;
+pub use crate::frontend::FrontendOptions;
+pub use crate::headless::HeadlessDisplay;
+pub use crate::keypad::Keypad;
+pub use crate::palette::Palette;
+pub use crate::ppu::{Color, Display, PixelGrid, Ppu, Rect, Scaling};
+pub use crate::profile::EmulationProfile;
+pub use crate::quirks::{Platform, Quirks};
+pub use crate::scheduler::{run_frame, Scheduler};
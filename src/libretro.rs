@@ -0,0 +1,415 @@
+// libretro core: build with
+// `cargo build --release --lib --no-default-features --features libretro`
+// and load the resulting cdylib (renamed to hachip_libretro.so/.dll/.dylib)
+// in RetroArch. Signatures follow libretro.h, API version 1.
+use crate::cpu::{self, Cpu, PROGRAM_START};
+use crate::emulator::{Emulator, SAMPLES_PER_FRAME, SAMPLE_RATE};
+use crate::headless::HeadlessDisplay;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::quirks::Quirks;
+use crate::scheduler::FRAME_RATE;
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_uint};
+use std::{ptr, slice};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_Y: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+const RETRO_DEVICE_ID_JOYPAD_X: c_uint = 9;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const WHITE: u32 = 0x00FF_FFFF;
+const BLACK: u32 = 0x0000_0000;
+
+// Joypad buttons mapped onto the keys most games use for movement and action.
+const JOYPAD_MAP: [(c_uint, u8); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0xF),
+];
+
+// Keyboard keys (retro_key values, ASCII for these) in the usual hexpad layout.
+const KEYBOARD_MAP: [(c_uint, u8); 16] = [
+    (b'1' as c_uint, 0x1),
+    (b'2' as c_uint, 0x2),
+    (b'3' as c_uint, 0x3),
+    (b'4' as c_uint, 0xC),
+    (b'q' as c_uint, 0x4),
+    (b'w' as c_uint, 0x5),
+    (b'e' as c_uint, 0x6),
+    (b'r' as c_uint, 0xD),
+    (b'a' as c_uint, 0x7),
+    (b's' as c_uint, 0x8),
+    (b'd' as c_uint, 0x9),
+    (b'f' as c_uint, 0xE),
+    (b'z' as c_uint, 0xA),
+    (b'x' as c_uint, 0x0),
+    (b'c' as c_uint, 0xB),
+    (b'v' as c_uint, 0xF),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+struct Core {
+    emulator: Emulator,
+    video: Vec<u32>,
+    // interleaved stereo
+    audio: Vec<i16>,
+}
+
+impl Core {
+    // The size was checked when the game was loaded.
+    fn new(rom: &[u8]) -> Core {
+        let mut emulator = Emulator::new(Box::new(HeadlessDisplay::new()));
+        if let Err(error) = emulator.load_rom(rom) {
+            log::error!("{}", error);
+        }
+        Core {
+            emulator,
+            video: vec![BLACK; WIDTH * HEIGHT],
+            audio: vec![0; SAMPLES_PER_FRAME * 2],
+        }
+    }
+
+    fn render_video(&mut self) {
+        for (pixel, lit) in self.video.iter_mut().zip(self.emulator.framebuffer()) {
+            *pixel = if *lit == 1 { WHITE } else { BLACK };
+        }
+    }
+
+    fn render_audio(&mut self) {
+        for (frame, sample) in self.audio.chunks_exact_mut(2).zip(self.emulator.audio_samples()) {
+            frame[0] = *sample;
+            frame[1] = *sample;
+        }
+    }
+}
+
+thread_local! {
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+    static ENVIRONMENT: Cell<Option<EnvironmentFn>> = const { Cell::new(None) };
+    static VIDEO_REFRESH: Cell<Option<VideoRefreshFn>> = const { Cell::new(None) };
+    static AUDIO_SAMPLE_BATCH: Cell<Option<AudioSampleBatchFn>> = const { Cell::new(None) };
+    static INPUT_POLL: Cell<Option<InputPollFn>> = const { Cell::new(None) };
+    static INPUT_STATE: Cell<Option<InputStateFn>> = const { Cell::new(None) };
+}
+
+fn poll_keys(cpu: &mut Cpu) {
+    if let Some(poll) = INPUT_POLL.with(Cell::get) {
+        unsafe { poll() };
+    }
+    let state = match INPUT_STATE.with(Cell::get) {
+        Some(state) => state,
+        None => return,
+    };
+
+    let mut keys = Vec::new();
+    for (id, key) in JOYPAD_MAP.iter() {
+        if unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, *id) } != 0 {
+            keys.push(*key);
+        }
+    }
+    for (id, key) in KEYBOARD_MAP.iter() {
+        if unsafe { state(0, RETRO_DEVICE_KEYBOARD, 0, *id) } != 0 {
+            keys.push(*key);
+        }
+    }
+    cpu.keypad.press(keys);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    ENVIRONMENT.with(|cell| cell.set(Some(callback)));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    VIDEO_REFRESH.with(|cell| cell.set(Some(callback)));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    AUDIO_SAMPLE_BATCH.with(|cell| cell.set(Some(callback)));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    INPUT_POLL.with(|cell| cell.set(Some(callback)));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    INPUT_STATE.with(|cell| cell.set(Some(callback)));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"hachip\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAME_RATE as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    CORE.with(|core| {
+        if let Some(core) = core.borrow_mut().as_mut() {
+            core.emulator.reset();
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    CORE.with(|core| {
+        let mut core = core.borrow_mut();
+        let core = match core.as_mut() {
+            Some(core) => core,
+            None => return,
+        };
+
+        poll_keys(core.emulator.cpu_mut());
+        if let Err(error) = core.emulator.step_frame() {
+            log::error!("{}", error);
+        }
+
+        core.render_video();
+        if let Some(video_refresh) = VIDEO_REFRESH.with(Cell::get) {
+            let pitch = WIDTH * std::mem::size_of::<u32>();
+            unsafe {
+                video_refresh(core.video.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, pitch)
+            };
+        }
+
+        core.render_audio();
+        if let Some(audio_batch) = AUDIO_SAMPLE_BATCH.with(Cell::get) {
+            unsafe { audio_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME) };
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize(_data: *mut c_void, _size: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unserialize(_data: *const c_void, _size: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a valid `retro_game_info` whose `data`
+/// holds `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if let Some(environment) = ENVIRONMENT.with(Cell::get) {
+        if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+            log::error!("frontend does not support XRGB8888");
+            return false;
+        }
+    }
+
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    if let Err(error) = cpu::check_rom_size(rom.len(), PROGRAM_START as u16, &Quirks::default()) {
+        log::error!("{}", error);
+        return false;
+    }
+    CORE.with(|core| *core.borrow_mut() = Some(Core::new(rom)));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    CORE.with(|core| match core.borrow_mut().as_mut() {
+        Some(core) => core.emulator.cpu_mut().memory_mut().as_mut_ptr() as *mut c_void,
+        None => ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    CORE.with(|core| match core.borrow().as_ref() {
+        Some(core) => core.emulator.cpu().memory().len(),
+        None => 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FRAMES_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn video_refresh(_data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!((width, height, pitch), (64, 32, 256));
+        FRAMES_SEEN.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn loads_and_runs_a_game() {
+        // CLS / JP 0x200
+        let rom = [0x00, 0xE0, 0x12, 0x00];
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+
+        retro_set_video_refresh(video_refresh);
+        assert!(unsafe { retro_load_game(&game) });
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 4096);
+
+        retro_run();
+        retro_run();
+        assert_eq!(FRAMES_SEEN.load(Ordering::SeqCst), 2);
+
+        retro_unload_game();
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 0);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::session;
+use env_logger::filter::{self, Filter};
+use log::{Log, Metadata, Record};
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// What the command line says about logging. `--log <filters>` takes the
+// place of RUST_LOG, with the same syntax (`info`, or per module like
+// `warn,hachip::cpu=debug`); `--log-file <path>` appends the lines to a
+// file instead of stderr. `--trace` lines are logged at trace level, shown
+// without either.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogOptions {
+    pub filters: Option<String>,
+    pub file: Option<PathBuf>,
+    pub trace: bool,
+}
+
+impl LogOptions {
+    // Picks the logging flags out of the arguments, so the logger is up
+    // before the rest of them are parsed. The flags are left in for that.
+    pub fn from_args(args: impl Iterator<Item = String>) -> LogOptions {
+        let mut options = LogOptions::default();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--log" => options.filters = args.next(),
+                "--log-file" => options.file = args.next().map(PathBuf::from),
+                _ if arg.starts_with("--trace") => options.trace = true,
+                _ => {}
+            }
+        }
+        options
+    }
+
+    fn filter(&self) -> Filter {
+        let mut builder = match &self.filters {
+            Some(filters) => {
+                let mut builder = filter::Builder::new();
+                builder.parse(filters);
+                builder
+            }
+            None => filter::Builder::from_env("RUST_LOG"),
+        };
+        if self.trace {
+            builder.filter_module("hachip::trace", log::LevelFilter::Trace);
+        }
+        builder.build()
+    }
+}
+
+// Writes the lines the filter lets through, to stderr or the log file.
+struct Logger {
+    filter: Filter,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.matches(record) {
+            let _ = write_line(&mut **self.out.lock().unwrap(), record);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap().flush();
+    }
+}
+
+// Sets up the global logger. Fails only when the log file can't be opened.
+pub fn init(options: &LogOptions) -> io::Result<()> {
+    let out: Box<dyn Write + Send> = match &options.file {
+        Some(path) => Box::new(LineWriter::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => Box::new(io::stderr()),
+    };
+    let filter = options.filter();
+    let max_level = filter.filter();
+    let logger = Logger {
+        filter,
+        out: Mutex::new(out),
+    };
+    // a second init keeps the first logger
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+    Ok(())
+}
+
+// "2024-05-01 12:30:15.250 INFO  hachip::cpu: ROM loaded"
+fn write_line(out: &mut dyn Write, record: &Record) -> io::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(
+        out,
+        "{}:{:02}.{:03} {:<5} {}: {}",
+        session::format_timestamp(now.as_secs()),
+        now.as_secs() % 60,
+        now.subsec_millis(),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_line, LogOptions};
+    use log::{Level, Record};
+
+    #[test]
+    fn flags_override_the_environment() {
+        let args = ["hachip", "game.ch8", "--log", "warn,hachip::cpu=debug", "--log-file", "hachip.log", "--trace"];
+        let options = LogOptions::from_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(options.filters.as_deref(), Some("warn,hachip::cpu=debug"));
+        assert_eq!(options.file.as_deref(), Some("hachip.log".as_ref()));
+        assert!(options.trace);
+
+        let filter = options.filter();
+        let record = |level, target| Record::builder().level(level).target(target).build();
+        assert!(filter.matches(&record(Level::Debug, "hachip::cpu")));
+        assert!(!filter.matches(&record(Level::Info, "hachip::frontend")));
+        assert!(filter.matches(&record(Level::Trace, "hachip::trace")));
+
+        let mut line = Vec::new();
+        write_line(&mut line, &Record::builder().args(format_args!("ROM loaded")).level(Level::Info).target("hachip::cpu").build())
+            .unwrap();
+        let line = String::from_utf8(line).unwrap();
+        assert!(line.ends_with(" INFO  hachip::cpu: ROM loaded\n"), "{}", line);
+        assert_eq!(line.find(' '), Some(10), "starts with the date");
+    }
+}
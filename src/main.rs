@@ -1,99 +1,1483 @@
-extern crate env_logger;
 extern crate log;
 
-use sdl2::keyboard::Keycode;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Result};
-use crate::cpu::Cpu;
-use sdl2::Sdl;
-use crate::ppu::CanvasWindow;
-use std::{thread, time, env};
-use crate::errors::EmulateCycleError;
-
-mod cpu;
-mod keypad;
-mod ppu;
-mod errors;
+use hachip::autosave;
+use hachip::battery;
+use hachip::bench::Bench;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::bookmarks;
+use hachip::cheats::Cheats;
+use hachip::config::Config;
+use hachip::cpu::{self, Cpu, MEMORY_SIZE, PROGRAM_START};
+use hachip::crt::{CrtEffects, GridLines};
+use hachip::debug_tui;
+use hachip::emulator::Emulator;
+use hachip::frame_pipe;
+use hachip::gdb_stub;
+use hachip::errors::{EmulateCycleError, LoadError};
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::frontend::{self, FrontendOptions};
+use hachip::headless::{self, HeadlessDisplay};
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::input_profile;
+use hachip::keymap::{self, KeyboardLayout, KeymapProfile};
+use hachip::logging::{self, LogOptions};
+use hachip::megachip;
+use hachip::palette::{self, Palette};
+use hachip::patch::Patch;
+use hachip::ppu::{self, Display, Scaling};
+use hachip::playclock;
+use hachip::profile::EmulationProfile;
+use hachip::profiler::Profiler;
+use hachip::montecarlo::{Distribution, Metric, MonteCarlo};
+use hachip::movie::Movie;
+use hachip::netplay::{self, Role};
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::netplay::{Hello, Netplay};
+use hachip::opcodes;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::movie::MovieMode;
+use hachip::quirks::{Platform, Quirks};
+use hachip::recent;
+use hachip::remote_display;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::resume;
+use hachip::rng;
+use hachip::romdb;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::rpl_flags;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::save_slots::{self, SaveSlots};
+use hachip::scheduler::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use hachip::script::Script;
+#[cfg(feature = "scripting")]
+use hachip::scripting::ScriptHooks;
+use hachip::trace::{self, Trace};
+use hachip::verify::Verify;
+use hachip::vip_timing;
+use hachip::session;
+#[cfg(feature = "web")]
+use hachip::web_build::WebBuild;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::present::{PresentHook, PresentHooks, PresentInfo};
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use hachip::session::{SessionEvent, SessionLog};
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use std::cell::Cell;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Read, Result, Write};
+use std::net::TcpListener;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use terminal_size::{Height, Width};
+
+// How often --watch checks the ROM and script for changes.
+const WATCH_POLL: Duration = Duration::from_millis(250);
+
+enum Dump {
+    Text,
+    Hash,
+}
+
+// Window settings are only read when a window frontend is built in.
+#[cfg_attr(not(any(feature = "sdl", feature = "pixels-frontend")), allow(dead_code))]
+struct Options {
+    // Empty when a ROM is picked in the browser instead.
+    rom: String,
+    // Where the ROM browser looks, set when no ROM was given.
+    rom_dir: Option<PathBuf>,
+    // The config file the first-run setup writes, on the first start
+    // without a ROM.
+    setup: Option<PathBuf>,
+    // Neither --platform nor --speed was given, so known ROMs pick them.
+    detect: bool,
+    // Where the ROM is loaded and starts, 0x600 for ETI-660 ROMs.
+    load_address: u16,
+    quirks: Quirks,
+    // Cxkk random seed, from a played back movie or the OS.
+    seed: u64,
+    // Quirky memory and key accesses stop the cpu.
+    strict: bool,
+    // ROMs too large for memory are cut short instead of refused.
+    force: bool,
+    // Applied to the ROM whenever it is read.
+    patch: Option<Patch>,
+    trace: Option<Trace>,
+    // Where the profile report goes, for runs without a window.
+    profile_out: Option<PathBuf>,
+    instructions_per_frame: u32,
+    scaling: Scaling,
+    scale: u32,
+    palette: Palette,
+    keymap: KeymapProfile,
+    // per ROM picked in the ROM browser
+    rom_keymaps: Vec<(String, KeymapProfile)>,
+    // Detected by the frontend when None.
+    layout: Option<KeyboardLayout>,
+    phosphor: Option<Duration>,
+    crt: CrtEffects,
+    show_fps: bool,
+    vsync: bool,
+    // A hexpad for the mouse and touches next to the display.
+    touch_pad: bool,
+    // Zero turns autosaving off.
+    autosave: Duration,
+    // Save the game on closing and offer to continue from it.
+    resume: bool,
+    screenshot_dir: Option<PathBuf>,
+    headless: bool,
+    debug_tui: bool,
+    // Address the GDB stub listens on.
+    gdb: Option<String>,
+    // "-" for stdout, a file descriptor number or a path.
+    frame_pipe: Option<String>,
+    // Address the remote display listens on.
+    serve: Option<String>,
+    netplay: Option<Role>,
+    // Frames of input delay a netplay host asks for.
+    netplay_delay: u32,
+    // Show the watch window next to the game.
+    debug: bool,
+    cycles: u64,
+    dump: Dump,
+    script: Option<String>,
+    watch: bool,
+    record_movie: Option<PathBuf>,
+    playback: Option<Movie>,
+    cheats: Cheats,
+    // Memory kept on disk per ROM.
+    battery: Option<Range<u16>>,
+    #[cfg(feature = "scripting")]
+    hooks: Option<ScriptHooks>,
+}
 
 fn main() {
-    env_logger::init();
-
-    let KEYMAP: HashMap<Keycode, u8> = [
-        (Keycode::Num1, 0x1),
-        (Keycode::Num2, 0x2),
-        (Keycode::Num3, 0x3),
-        (Keycode::Num4, 0xc),
-        (Keycode::Q, 0x4),
-        (Keycode::W, 0x5),
-        (Keycode::E, 0x6),
-        (Keycode::R, 0xd),
-        (Keycode::A, 0x7),
-        (Keycode::S, 0x8),
-        (Keycode::D, 0x9),
-        (Keycode::F, 0xe),
-        (Keycode::Z, 0xa),
-        (Keycode::X, 0x0),
-        (Keycode::C, 0xb),
-        (Keycode::V, 0xf),
-    ].iter().cloned().collect();
-
-    let sdl = sdl2::init().unwrap();
-    let canvas = get_canvas(&sdl);
-    let mut event_pump = sdl.event_pump().unwrap();
-    let mut cpu = match init_cpu(canvas) {
+    let log_options = LogOptions::from_args(env::args());
+    if let Err(error) = logging::init(&log_options) {
+        let path = log_options.file.unwrap_or_default();
+        panic!("Invalid argument: can't open log file {}: {}", path.display(), error);
+    }
+
+    match env::args().nth(1).as_deref() {
+        Some("history") => {
+            print_history(env::args().skip(2));
+            return;
+        }
+        Some("opcodes") => {
+            print_opcodes(env::args().skip(2));
+            return;
+        }
+        Some("recent") => {
+            print_recent();
+            return;
+        }
+        Some("info") => {
+            print_info(env::args().skip(2));
+            return;
+        }
+        Some("montecarlo") => {
+            montecarlo(env::args().skip(2));
+            return;
+        }
+        Some("verify") => {
+            verify(env::args().skip(2));
+            return;
+        }
+        Some("bench") => {
+            bench(env::args().skip(2));
+            return;
+        }
+        #[cfg(feature = "ffi")]
+        Some("c-header") => {
+            print!("{}", hachip::ffi::HEADER);
+            return;
+        }
+        #[cfg(feature = "web")]
+        Some("web") => {
+            web_build(env::args().skip(2));
+            return;
+        }
+        _ => {}
+    }
+
+    let options = parse_args();
+    // refused before any frontend starts, with the way around it
+    if !options.force && options.rom_dir.is_none() {
+        if let Ok(rom) = read_rom(&options) {
+            if let Err(error) = cpu::check_rom_size(rom.len(), options.load_address, &options.quirks) {
+                eprintln!("{}: {}; --force loads the part that fits", options.rom, error);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(movie) = &options.playback {
+        if read_rom(&options).map(|rom| headless::hash(&rom)).ok() != Some(movie.rom_hash) {
+            eprintln!("Warning: the movie was recorded with a different ROM, playback will likely desync");
+        }
+    }
+    if let Some(script) = &options.script {
+        if options.watch {
+            watch_script(&options, script);
+        } else if !run_script(&options, script) {
+            process::exit(1);
+        }
+        return;
+    }
+    if options.headless {
+        run_headless(&options);
+        return;
+    }
+    if options.debug_tui {
+        let mut cpu = match init_cpu(Box::new(HeadlessDisplay::new()), &options) {
+            Ok(cpu) => cpu,
+            Err(error) => panic!("Problem initiating cpu: {:?}", error),
+        };
+        let stdin = io::stdin();
+        let terminal_size = || terminal_size::terminal_size().map(|(Width(columns), Height(rows))| (columns as usize, rows as usize));
+        if let Err(error) = debug_tui::run(&mut cpu, options.instructions_per_frame, options.cheats.clone(), stdin.lock(), io::stdout(), terminal_size) {
+            eprintln!("debugger: {}", error);
+        }
+        write_profile(&cpu, &options);
+        return;
+    }
+    if let Some(address) = &options.gdb {
+        let mut cpu = match init_cpu(Box::new(HeadlessDisplay::new()), &options) {
+            Ok(cpu) => cpu,
+            Err(error) => panic!("Problem initiating cpu: {:?}", error),
+        };
+        if let Err(error) = gdb_stub::serve(&mut cpu, options.instructions_per_frame, address) {
+            eprintln!("gdb stub: {}", error);
+        }
+        write_profile(&cpu, &options);
+        return;
+    }
+    if let Some(target) = &options.frame_pipe {
+        run_frame_pipe(&options, target);
+        return;
+    }
+    if let Some(address) = &options.serve {
+        run_remote_display(&options, address);
+        return;
+    }
+
+    run_window(&options);
+}
+
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+fn frontend_options(options: &Options) -> FrontendOptions {
+    let mut frontend = FrontendOptions::default();
+    frontend.instructions_per_frame = options.instructions_per_frame;
+    frontend.scaling = options.scaling;
+    frontend.scale = options.scale;
+    frontend.palette = options.palette;
+    frontend.keymap = options.keymap;
+    frontend.rom_keymaps = options.rom_keymaps.clone();
+    frontend.layout = options.layout;
+    frontend.phosphor = options.phosphor;
+    frontend.crt = options.crt;
+    frontend.screenshot_dir = options.screenshot_dir.clone();
+    frontend.autosave_interval = options.autosave;
+    frontend.resume = options.resume;
+    frontend.rom_dir = options.rom_dir.clone();
+    frontend.setup = options.setup.clone();
+    frontend.rom_database = options.detect;
+    frontend.truncate_roms = options.force;
+    frontend.watch_window = options.debug;
+    frontend.show_speed = options.show_fps;
+    frontend.vsync = options.vsync;
+    frontend.touch_pad = options.touch_pad;
+    frontend.cheats = options.cheats.clone();
+    frontend.battery = options.battery.clone();
+    #[cfg(feature = "scripting")]
+    {
+        frontend.hooks = options.hooks.clone();
+    }
+    if options.rom_dir.is_none() {
+        // a missing ROM is reported when the cpu loads it
+        if let Ok(rom) = read_rom(options) {
+            if options.autosave > Duration::from_secs(0) {
+                frontend.autosave_dir = autosave::rom_dir(&options.rom, &rom);
+            }
+            frontend.bookmark_dir = bookmarks::rom_dir(&options.rom, &rom);
+            frontend.input_profile = input_profile::for_rom(&rom);
+            frontend.rom_name = Some(frontend::rom_name(Path::new(&options.rom), &rom));
+            frontend.flags_dir = rpl_flags::rom_dir(&options.rom, &rom);
+            if options.resume {
+                frontend.resume_dir = resume::rom_dir(&options.rom, &rom);
+            }
+            frontend.save_slots = save_slots::rom_dir(&options.rom, &rom).map(|dir| SaveSlots::new(dir, &rom));
+            if options.battery.is_some() {
+                frontend.battery_dir = battery::rom_dir(&options.rom, &rom);
+            }
+        }
+    }
+    if let Some(movie) = &options.playback {
+        frontend.movie = Some(MovieMode::playback(movie.clone()));
+    } else if let Some(path) = &options.record_movie {
+        let rom = read_rom(options).unwrap_or_default();
+        let profile = EmulationProfile::new(options.quirks);
+        let movie = Movie::new(&rom, profile, options.seed, options.instructions_per_frame);
+        frontend.movie = Some(MovieMode::record(movie, path.clone()));
+    }
+    frontend
+}
+
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+fn run_window(options: &Options) {
+    let mut session = open_session_log();
+    if options.rom_dir.is_none() {
+        session.record(SessionEvent::RomLoaded(options.rom.clone()));
+        recent::remember(Path::new(&options.rom));
+    }
+
+    let netplay = options.netplay.as_ref().map(|role| start_netplay(options, role));
+    let seed = netplay.as_ref().map(|(_, seed)| *seed);
+    let init = |display: Box<dyn Display>| match init_cpu(display, options) {
+        Ok(mut cpu) => {
+            // the host's seed, for both games to draw the same numbers
+            if let Some(seed) = seed {
+                cpu.seed_rng(seed);
+            }
+            cpu
+        }
+        Err(error) => panic!("Problem initiating cpu: {:?}", error),
+    };
+    let played = Rc::new(Cell::new(Duration::from_secs(0)));
+    let mut hooks = PresentHooks::new();
+    hooks.add(Box::new(PlayTime(played.clone())));
+    let mut frontend = frontend_options(options);
+    frontend.netplay = netplay.map(|(session, _)| session);
+    #[cfg(feature = "sdl")]
+    let result = hachip::sdl::run_with_hooks(&frontend, init, &mut hooks);
+    #[cfg(all(feature = "pixels-frontend", not(feature = "sdl")))]
+    let result = hachip::pixels_frontend::run_with_hooks(&frontend, init, &mut hooks);
+
+    session.record(SessionEvent::Played(played.get().as_secs()));
+    if let Err(error) = result {
+        // error reports carry the settings needed to reproduce them
+        let profile = EmulationProfile::new(options.quirks);
+        session.record(SessionEvent::Error(format!("{} ({})", error.message, profile)));
+        eprintln!("Stopped: {} ({})", describe(&error), profile);
+    }
+}
+
+// Connects to the other player, returning the session and the seed both
+// games run with. Exits when the two can't play together.
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+fn start_netplay(options: &Options, role: &Role) -> (Netplay, u64) {
+    let hello = Hello {
+        rom_hash: read_rom(options).map(|rom| headless::hash(&rom)).unwrap_or_default(),
+        profile: EmulationProfile::new(options.quirks).to_string(),
+        instructions_per_frame: options.instructions_per_frame,
+        seed: options.seed,
+        delay: options.netplay_delay,
+    };
+    match role {
+        Role::Host(address) => eprintln!("Waiting for the other player on {}", address),
+        Role::Join(address) => eprintln!("Joining {}", address),
+    }
+    match Netplay::connect(role, &hello) {
+        Ok((session, agreed)) => (session, agreed.seed),
+        Err(error) => {
+            eprintln!("netplay: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+// Keeps the latest play clock reading for the session log.
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+struct PlayTime(Rc<Cell<Duration>>);
+
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+impl PresentHook for PlayTime {
+    fn on_after_present(&mut self, info: &PresentInfo) {
+        self.0.set(info.played);
+    }
+}
+
+#[cfg(not(any(feature = "sdl", feature = "pixels-frontend")))]
+fn run_window(_options: &Options) {
+    panic!("Invalid argument: built without a window frontend, use --headless");
+}
+
+fn run_headless(options: &Options) {
+    let mut emulator = match init_cpu(Box::new(HeadlessDisplay::new()), options) {
+        Ok(cpu) => Emulator::from_cpu(cpu),
+        Err(error) => panic!("Problem initiating cpu: {:?}", error),
+    };
+    emulator.instructions_per_frame = options.instructions_per_frame;
+    emulator.cheats = options.cheats.clone();
+    #[cfg(feature = "scripting")]
+    {
+        emulator.hooks = options.hooks.clone();
+    }
+
+    let mut violation = None;
+    // a movie plays to its end instead of running for --cycles
+    if let Some(movie) = &options.playback {
+        emulator.cpu_mut().set_rng(movie.random_source());
+        for frame in 0..movie.len() {
+            movie.play(frame, &mut emulator.cpu_mut().keypad);
+            if let Err(error) = emulator.step_frame() {
+                let cpu = emulator.cpu();
+                eprintln!("Stopped in movie frame {}: {} ({})\n{}", frame, describe(&error), cpu.profile(), cpu);
+                violation = error.violation;
+                break;
+            }
+        }
+    }
+
+    let mut cycles = 0;
+    while options.playback.is_none() && cycles < options.cycles {
+        // the last frame only runs what's left of --cycles
+        let instructions = (options.cycles - cycles).min(options.instructions_per_frame as u64);
+        emulator.instructions_per_frame = instructions as u32;
+        match emulator.step_frame() {
+            Ok(executed) => cycles += executed as u64,
+            Err(error) => {
+                let cpu = emulator.cpu();
+                eprintln!("Stopped after {} cycles: {} ({})\n{}", cycles, describe(&error), cpu.profile(), cpu);
+                violation = error.violation;
+                break;
+            }
+        }
+    }
+
+    let framebuffer = emulator.framebuffer();
+    // the Mega-Chip screen while it is on
+    match (&options.dump, emulator.mega_screen()) {
+        (Dump::Text, Some(screen)) => print!("{}", headless::dump_mega_text(screen)),
+        (Dump::Hash, Some(screen)) => println!("{:016x}", headless::hash_mega(screen)),
+        (Dump::Text, None) => print!("{}", headless::dump_text(framebuffer)),
+        (Dump::Hash, None) => println!("{:016x}", headless::hash(framebuffer)),
+    }
+    write_profile(emulator.cpu(), options);
+    // a strict run fails when the ROM did anything it shouldn't rely on
+    if options.strict && violation.is_some() {
+        process::exit(1);
+    }
+}
+
+// Frames go to `target`, keys come from stdin a line at a time.
+fn run_frame_pipe(options: &Options, target: &str) {
+    let mut cpu = match init_cpu(Box::new(HeadlessDisplay::new()), options) {
         Ok(cpu) => cpu,
         Err(error) => panic!("Problem initiating cpu: {:?}", error),
     };
+    let file = match target.strip_prefix("fd:") {
+        _ if target == "-" => None,
+        Some(fd) => Some(open_fd(fd)),
+        None => Some(File::create(target)),
+    };
+    let output: Box<dyn Write> = match file {
+        None => Box::new(io::stdout()),
+        Some(Ok(file)) => Box::new(file),
+        Some(Err(error)) => panic!("Invalid argument: can't open frame pipe {}: {}", target, error),
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            match frame_pipe::Input::parse(&line) {
+                Some(input) => {
+                    if sender.send(input).is_err() {
+                        return;
+                    }
+                }
+                None => log::warn!("frame pipe: ignoring `{}`", line.trim()),
+            }
+        }
+    });
+    if let Err(error) = frame_pipe::serve(&mut cpu, options.instructions_per_frame, BufWriter::new(output), receiver) {
+        eprintln!("frame pipe: {}", error);
+    }
+    write_profile(&cpu, options);
+}
+
+// A descriptor opened for us by whoever started hachip, as `fd:3` names it.
+// It is opened again through /dev/fd rather than taken over, so one that
+// isn't open fails here and ours closing leaves theirs alone.
+fn open_fd(fd: &str) -> Result<File> {
+    let fd: u32 = fd
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file descriptor", fd)))?;
+    if fd == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "stdin is where the keys come from"));
+    }
+    if cfg!(unix) {
+        fs::OpenOptions::new().write(true).open(format!("/dev/fd/{}", fd))
+    } else {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "file descriptors need a Unix system"))
+    }
+}
+
+fn run_remote_display(options: &Options, address: &str) {
+    let mut emulator = match init_cpu(Box::new(HeadlessDisplay::new()), options) {
+        Ok(cpu) => Emulator::from_cpu(cpu),
+        Err(error) => panic!("Problem initiating cpu: {:?}", error),
+    };
+    emulator.instructions_per_frame = options.instructions_per_frame;
+    emulator.cheats = options.cheats.clone();
+    #[cfg(feature = "scripting")]
+    {
+        emulator.hooks = options.hooks.clone();
+    }
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(error) => panic!("Invalid argument: can't listen on {}: {}", address, error),
+    };
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("Serving {} on http://{}/", options.rom, address);
+    }
+    let page = remote_display::viewer_html(options.keymap, options.layout.unwrap_or_default(), &options.palette);
+    if let Err(error) = remote_display::serve(&mut emulator, listener, page) {
+        eprintln!("remote display: {}", error);
+    }
+    write_profile(emulator.cpu(), options);
+}
 
-    'main: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                sdl2::event::Event::Quit { .. } => break 'main,
-                _ => {}
+// The --profile-out report, if one was asked for.
+fn write_profile(cpu: &Cpu, options: &Options) {
+    if let (Some(path), Some(profiler)) = (&options.profile_out, &cpu.profiler) {
+        if let Err(error) = fs::write(path, profiler.to_json(cpu)) {
+            eprintln!("Can't write profile {}: {}", path.display(), error);
+        }
+    }
+}
+
+// The error with the kind of violation, like "... [memory-wrap]".
+fn describe(error: &EmulateCycleError) -> String {
+    match error.violation {
+        Some(violation) => format!("{} [{}]", error.message, violation.name()),
+        None => error.message.clone(),
+    }
+}
+
+// Runs the test script against a fresh cpu and prints the result, with the
+// screen diff of failed screen checks. Returns whether every check passed.
+fn run_script(options: &Options, script_path: &str) -> bool {
+    let script = match Script::load(Path::new(script_path)) {
+        Ok(script) => script,
+        Err(error) => {
+            println!("FAIL {}: {}", script_path, error);
+            return false;
+        }
+    };
+    // while watching, the ROM can be missing halfway through a rebuild
+    let game = match read_rom(options) {
+        Ok(game) => game,
+        Err(error) => {
+            println!("FAIL {}: {}", options.rom, error);
+            return false;
+        }
+    };
+    let mut cpu = match new_cpu(Box::new(HeadlessDisplay::new()), options, game) {
+        Ok(cpu) => cpu,
+        Err(error) => {
+            println!("FAIL {}: {}", options.rom, error);
+            return false;
+        }
+    };
+    let dir = Path::new(script_path).parent().unwrap_or_else(|| Path::new("."));
+
+    let failures = script.run(&mut cpu, options.instructions_per_frame, dir);
+    for failure in &failures {
+        println!("{}:{}: {}", script_path, failure.line, failure.message);
+        if let Some(diff) = &failure.diff {
+            print!("{}", diff);
+        }
+    }
+    if failures.is_empty() {
+        println!("PASS {}", script_path);
+    } else {
+        println!("{}", cpu);
+        println!("FAIL {}: {} check(s) failed ({})", script_path, failures.len(), cpu.profile());
+    }
+    failures.is_empty()
+}
+
+// Re-runs the script whenever the ROM or the script changes, once the
+// files have stopped changing for one poll so half-written builds are
+// skipped. Runs until interrupted.
+fn watch_script(options: &Options, script_path: &str) {
+    let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut seen: Option<(Option<SystemTime>, Option<SystemTime>)> = None;
+    let mut tested = None;
+    loop {
+        let stamps = (modified(&options.rom), modified(script_path));
+        if seen == Some(stamps) && tested != seen {
+            tested = seen;
+            run_script(options, script_path);
+            println!("Watching {} and {} for changes", options.rom, script_path);
+        }
+        seen = Some(stamps);
+        thread::sleep(WATCH_POLL);
+    }
+}
+
+// Only windowed play is logged; headless runs are tooling, not sessions.
+#[cfg(any(feature = "sdl", feature = "pixels-frontend"))]
+fn open_session_log() -> SessionLog {
+    let path = match session::default_log_path() {
+        Some(path) => path,
+        None => return SessionLog::disabled(),
+    };
+    match SessionLog::open(&path) {
+        Ok(log) => log,
+        Err(error) => {
+            log::warn!("session log {}: {}", path.display(), error);
+            SessionLog::disabled()
+        }
+    }
+}
+
+// `hachip history [--limit N] [--by-rom]`: the most recent sessions, newest
+// last, or the play time of each ROM, most played first.
+fn print_history(mut args: impl Iterator<Item = String>) {
+    let mut limit = 20;
+    let mut by_rom = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--by-rom" => by_rom = true,
+            "--limit" => {
+                let value = args.next().expect("Invalid argument: --limit needs a value");
+                limit = match value.parse() {
+                    Ok(limit) => limit,
+                    Err(_) => panic!("Invalid argument: {} is not a number", value),
+                };
             }
+            _ => panic!("Invalid argument: {}", arg),
         }
+    }
 
-        // Create a set of pressed Keys.
-        let keys = event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .filter_map(|x| KEYMAP.get(&x))
-            .cloned()
-            .collect::<Vec<u8>>();
+    let log = match session::default_log_path().map(fs::read_to_string) {
+        Some(Ok(log)) => log,
+        _ => {
+            println!("No sessions recorded yet");
+            return;
+        }
+    };
+    let history = session::read_history(&log);
+    if by_rom {
+        for (rom, played) in session::play_time_by_rom(&history).iter().take(limit) {
+            println!("{:>9}  {}", playclock::format_play_time(*played), rom);
+        }
+        return;
+    }
+    for summary in &history[history.len().saturating_sub(limit)..] {
+        let minutes = summary.played.as_secs() / 60;
+        let mut line = format!(
+            "{}  {:>3}h{:02}m  {}",
+            session::format_timestamp(summary.started),
+            minutes / 60,
+            minutes % 60,
+            summary.rom.as_deref().unwrap_or("-"),
+        );
+        if summary.resets > 0 {
+            line.push_str(&format!("  resets: {}", summary.resets));
+        }
+        if let Some(error) = summary.errors.last() {
+            line.push_str(&format!("  error: {}", error));
+        }
+        println!("{}", line);
+    }
+}
+
+// `hachip recent`: the recently played ROMs, newest first.
+fn print_recent() {
+    let recent = recent::load();
+    if recent.paths().is_empty() {
+        println!("No ROMs played yet");
+    }
+    for (index, path) in recent.paths().iter().enumerate() {
+        let missing = if path.is_file() { "" } else { "  (missing)" };
+        println!("{:2}  {}{}", index + 1, path.display(), missing);
+    }
+}
+
+// `hachip info <rom>...`: the hash of each ROM and what the ROM database
+// knows about it.
+fn print_info(roms: impl Iterator<Item = String>) {
+    for path in roms {
+        match open_rom(&path) {
+            Ok(rom) => {
+                let known = romdb::lookup(&rom).map(|info| info.to_string());
+                println!("{:016x}  {}  {}", headless::hash(&rom), path, known.as_deref().unwrap_or("(unknown)"));
+            }
+            Err(error) => println!("{}: {}", path, error),
+        }
+    }
+}
+
+// `hachip montecarlo <rom> --metric <metric> [--runs <n>] [--frames <n>]
+// [--seed <first seed>] [--platform <name>] [--speed <n>]`
+fn montecarlo(mut args: impl Iterator<Item = String>) {
+    let mut rom = None;
+    let mut metric = None;
+    let (mut runs, mut frames, mut seed) = (None, None, None);
+    let mut platform = Platform::default();
+    let mut speed = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+        let mut number = || {
+            let value = value();
+            value.parse::<u64>().unwrap_or_else(|_| panic!("Invalid argument: {} is not a number", value))
+        };
+        match arg.as_str() {
+            "--runs" => runs = Some(number()),
+            "--frames" => frames = Some(number()),
+            "--seed" => seed = Some(number()),
+            "--speed" => speed = Some(number() as u32),
+            "--metric" => {
+                let text = value();
+                metric = Some(Metric::parse(&text).unwrap_or_else(|| panic!("Invalid argument: unknown metric {}", text)));
+            }
+            "--platform" => {
+                let name = value();
+                platform = Platform::from_name(&name).unwrap_or_else(|| panic!("Invalid argument: unknown platform {}", name));
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => panic!("Invalid argument: {}", arg),
+        }
+    }
+    let rom = rom.expect("Invalid argument: no ROM specified");
+    let metric = metric.expect("Invalid argument: montecarlo needs --metric");
+    let mut montecarlo = match open_rom(&rom) {
+        Ok(rom) => MonteCarlo::new(rom, metric),
+        Err(error) => panic!("Problem reading {}: {}", rom, error),
+    };
+    montecarlo.quirks = platform.quirks();
+    montecarlo.runs = runs.unwrap_or(montecarlo.runs);
+    montecarlo.frames = frames.unwrap_or(montecarlo.frames);
+    montecarlo.first_seed = seed.unwrap_or(montecarlo.first_seed);
+    montecarlo.instructions_per_frame = speed.unwrap_or(montecarlo.instructions_per_frame);
+
+    let results = montecarlo.run();
+    println!("{} after {} frames", metric, montecarlo.frames);
+    print!("{}", Distribution::of(&results));
+    if let Some(run) = results.iter().find(|run| run.value.is_err()) {
+        println!("seed {}: {}", run.seed, run.value.as_ref().unwrap_err());
+    }
+}
+
+// `hachip bench <rom> [--frames <n>] [--speed <n>] [--platform <name>]
+// [--load-address <hex>]`
+fn bench(mut args: impl Iterator<Item = String>) {
+    let mut rom = None;
+    let (mut frames, mut speed) = (None, None);
+    let mut platform = Platform::default();
+    let mut load_address = PROGRAM_START as u16;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+        match arg.as_str() {
+            "--frames" | "--speed" => {
+                let value = value();
+                let number = value.parse::<u64>().unwrap_or_else(|_| panic!("Invalid argument: {} is not a number", value));
+                if arg == "--frames" {
+                    frames = Some(number);
+                } else {
+                    speed = Some(number as u32);
+                }
+            }
+            "--platform" => {
+                let name = value();
+                platform = Platform::from_name(&name).unwrap_or_else(|| panic!("Invalid argument: unknown platform {}", name));
+            }
+            "--load-address" => {
+                let value = value();
+                load_address = match u16::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(address) => address,
+                    Err(_) => panic!("Invalid argument: {} is not a hex address", value),
+                };
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => panic!("Invalid argument: {}", arg),
+        }
+    }
+    let rom = rom.expect("Invalid argument: no ROM specified");
+    let mut bench = match open_rom(&rom) {
+        Ok(rom) => Bench::new(rom),
+        Err(error) => panic!("Problem reading {}: {}", rom, error),
+    };
+    bench.quirks = platform.quirks();
+    bench.frames = frames.unwrap_or(bench.frames);
+    bench.instructions_per_frame = speed.unwrap_or(bench.instructions_per_frame);
+    bench.load_address = load_address;
+    print!("{}", bench.run());
+}
+
+// `hachip verify <rom> <movie> [--load-address <hex>] [--cheats <path>]
+// [--hooks <path>]`: exits with 1 when the two replays of the movie diverge.
+fn verify(mut args: impl Iterator<Item = String>) {
+    let mut paths = Vec::new();
+    let mut load_address = PROGRAM_START as u16;
+    let mut cheats = Cheats::default();
+    #[cfg(feature = "scripting")]
+    let mut hooks = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load-address" => {
+                let value = args.next().expect("Invalid argument: --load-address needs a value");
+                load_address = match u16::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(address) => address,
+                    Err(_) => panic!("Invalid argument: {} is not a hex address", value),
+                };
+            }
+            "--cheats" => {
+                let path = args.next().expect("Invalid argument: --cheats needs a value");
+                cheats = match Cheats::load(Path::new(&path)) {
+                    Ok(cheats) => cheats,
+                    Err(error) => panic!("Invalid argument: cheats {}: {}", path, error),
+                };
+            }
+            #[cfg(feature = "scripting")]
+            "--hooks" => {
+                let path = args.next().expect("Invalid argument: --hooks needs a value");
+                let source = match fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(error) => panic!("Invalid argument: hooks {}: {}", path, error),
+                };
+                if let Err(error) = ScriptHooks::compile(&source) {
+                    panic!("Invalid argument: hooks {}: {}", path, error);
+                }
+                hooks = Some(source);
+            }
+            _ if !arg.starts_with("--") => paths.push(arg),
+            _ => panic!("Invalid argument: {}", arg),
+        }
+    }
+    let (rom_path, movie_path) = match paths.as_slice() {
+        [rom, movie] => (rom, movie),
+        _ => panic!("Invalid argument: verify needs a ROM and a movie"),
+    };
+    let rom = open_rom(rom_path).unwrap_or_else(|error| panic!("Problem reading {}: {}", rom_path, error));
+    let movie = match Movie::load(Path::new(movie_path)) {
+        Ok(movie) => movie,
+        Err(error) => panic!("Invalid argument: movie {}: {}", movie_path, error),
+    };
+    if headless::hash(&rom) != movie.rom_hash {
+        eprintln!("Warning: the movie was recorded with a different ROM");
+    }
+    let mut verify = Verify::new(rom, movie);
+    verify.load_address = load_address;
+    verify.cheats = cheats;
+    #[cfg(feature = "scripting")]
+    {
+        verify.hooks = hooks;
+    }
+    let ([replay, _], divergence) = verify.run();
+    if let Some(divergence) = divergence {
+        println!("{}", divergence);
+        process::exit(1);
+    }
+    match (replay.hashes.last(), &replay.stopped) {
+        (_, Some(stopped)) => println!("{} frames replayed the same twice, both stopped: {}", replay.hashes.len(), stopped),
+        (Some(hash), None) => println!("{} frames replayed the same twice, final state {:016x}", replay.hashes.len(), hash),
+        (None, None) => println!("the movie is empty"),
+    }
+}
+
+// `hachip web build [--rom <path>] [--out <dir>] [--title <text>]`
+#[cfg(feature = "web")]
+fn web_build(mut args: impl Iterator<Item = String>) {
+    if args.next().as_deref() != Some("build") {
+        panic!("Invalid argument: expected web build");
+    }
+    let mut build = WebBuild::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+        match arg.as_str() {
+            "--rom" => build.rom = Some(PathBuf::from(value())),
+            "--out" => build.out_dir = PathBuf::from(value()),
+            "--title" => build.title = value(),
+            _ => panic!("Invalid argument: {}", arg),
+        }
+    }
+    match build.build() {
+        Ok(()) => println!("wrote {}", build.out_dir.display()),
+        Err(error) => {
+            eprintln!("web build: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+// `hachip opcodes [--platform <name>]`: the instruction reference, with
+// quirk dependent behaviour as the platform has it.
+fn print_opcodes(mut args: impl Iterator<Item = String>) {
+    let mut platform = Platform::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().expect("Invalid argument: --platform needs a value");
+                platform = match Platform::from_name(&name) {
+                    Some(platform) => platform,
+                    None => panic!("Invalid argument: unknown platform {}", name),
+                };
+            }
+            _ => panic!("Invalid argument: {}", arg),
+        }
+    }
+    println!("# {}", EmulationProfile::new(platform.quirks()));
+    print!("{}", opcodes::reference(&platform.quirks()));
+}
+
+fn parse_args() -> Options {
+    let mut rom = None;
+    let mut platform = None;
+    let mut instructions_per_frame = None;
+    let mut load_address = None;
+    let mut scaling = None;
+    let mut scale = None;
+    let mut phosphor = None;
+    let mut crt = CrtEffects::default();
+    let mut show_fps = false;
+    let mut vsync = false;
+    let mut touch_pad = false;
+    let mut resume = false;
+    let mut autosave = None;
+    let mut screenshot_dir = None;
+    let mut rom_dir = None;
+    let mut config_path = None;
+    let mut palette = None;
+    let mut foreground = None;
+    let mut background = None;
+    let mut keymap_profile = None;
+    let mut layout = None;
+    let mut invert = false;
+    let mut brightness = None;
+    let mut on_alpha = None;
+    let mut strict = false;
+    let mut wrap_sprites = false;
+    let mut vip_timing = false;
+    let mut display_wait = None;
+    let mut force = false;
+    let mut patch_path = None;
+    let mut patch_base = None;
+    let mut trace: Option<Trace> = None;
+    let mut profile_out = None;
+    let mut headless = false;
+    let mut debug_tui = false;
+    let mut gdb = None;
+    let mut frame_pipe = None;
+    let mut serve = None;
+    let mut cheats = Cheats::default();
+    let mut battery = None;
+    #[cfg(feature = "scripting")]
+    let mut hooks = None;
+    let mut netplay = None;
+    let mut netplay_delay = netplay::DEFAULT_DELAY;
+    let mut debug = false;
+    let mut cycles = 5000;
+    let mut dump = Dump::Text;
+    let mut script = None;
+    let mut watch = false;
+    let mut record_movie = None;
+    let mut playback = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().expect("Invalid argument: --platform needs a value");
+                platform = match Platform::from_name(&name) {
+                    Some(platform) => Some(platform),
+                    None => panic!("Invalid argument: unknown platform {}", name),
+                };
+            }
+            "--speed" => {
+                let value = args.next().expect("Invalid argument: --speed needs a value");
+                instructions_per_frame = match value.parse() {
+                    Ok(speed) if speed > 0 => Some(speed),
+                    _ => panic!("Invalid argument: {} is not an instruction count", value),
+                };
+            }
+            "--load-address" => {
+                let value = args.next().expect("Invalid argument: --load-address needs a value");
+                load_address = match u16::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(address) if address as usize >= PROGRAM_START && (address as usize) < MEMORY_SIZE => Some(address),
+                    _ => panic!("Invalid argument: {} is not a load address", value),
+                };
+            }
+            "--scaling" => {
+                let name = args.next().expect("Invalid argument: --scaling needs a value");
+                scaling = match Scaling::from_name(&name) {
+                    Some(scaling) => Some(scaling),
+                    None => panic!("Invalid argument: unknown scaling {}", name),
+                };
+            }
+            "--scale" => {
+                let value = args.next().expect("Invalid argument: --scale needs a value");
+                scale = match value.parse() {
+                    Ok(scale) if (ppu::MIN_SCALE..=ppu::MAX_SCALE).contains(&scale) => Some(scale),
+                    _ => panic!("Invalid argument: --scale is from {} to {}, got {}", ppu::MIN_SCALE, ppu::MAX_SCALE, value),
+                };
+            }
+            "--phosphor" => {
+                let value = args.next().expect("Invalid argument: --phosphor needs a value");
+                phosphor = match value.parse() {
+                    Ok(0) => None,
+                    Ok(millis) => Some(Duration::from_millis(millis)),
+                    Err(_) => panic!("Invalid argument: {} is not a decay time in ms", value),
+                };
+            }
+            "--crt" => {
+                let names = args.next().expect("Invalid argument: --crt needs a value");
+                crt = match CrtEffects::from_names(&names) {
+                    Some(effects) => CrtEffects {
+                        grid_lines: crt.grid_lines,
+                        ..effects
+                    },
+                    None => panic!("Invalid argument: unknown CRT effect in {}", names),
+                };
+            }
+            "--grid-lines" => {
+                let value = args.next().expect("Invalid argument: --grid-lines needs a value");
+                crt.grid_lines = match GridLines::parse(&value) {
+                    Some(lines) => Some(lines),
+                    None => panic!("Invalid argument: {} is not <alpha percent>[,RRGGBB]", value),
+                };
+            }
+            "--autosave" => {
+                let value = args.next().expect("Invalid argument: --autosave needs a value");
+                autosave = match value.parse() {
+                    Ok(seconds) => Some(seconds),
+                    Err(_) => panic!("Invalid argument: {} is not a number of seconds", value),
+                };
+            }
+            "--screenshot-dir" => {
+                let dir = args.next().expect("Invalid argument: --screenshot-dir needs a value");
+                screenshot_dir = Some(PathBuf::from(dir));
+            }
+            "--rom-dir" => {
+                let dir = args.next().expect("Invalid argument: --rom-dir needs a value");
+                rom_dir = Some(PathBuf::from(dir));
+            }
+            "--config" => {
+                config_path = Some(args.next().expect("Invalid argument: --config needs a value"));
+            }
+            "--palette" => {
+                palette = Some(args.next().expect("Invalid argument: --palette needs a value"));
+            }
+            "--fg" | "--bg" => {
+                let value = args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+                let color = match palette::parse_color(&value) {
+                    Some(color) => color,
+                    None => panic!("Invalid argument: {} is not a RRGGBB color", value),
+                };
+                if arg == "--fg" {
+                    foreground = Some(color);
+                } else {
+                    background = Some(color);
+                }
+            }
+            "--invert" => invert = true,
+            "--brightness" | "--on-alpha" => {
+                let value = args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+                let max = if arg == "--brightness" { palette::MAX_BRIGHTNESS } else { 100 };
+                let percent = match value.parse() {
+                    Ok(percent) if percent <= max => percent,
+                    _ => panic!("Invalid argument: {} is not a percentage from 0 to {}", value, max),
+                };
+                if arg == "--brightness" {
+                    brightness = Some(percent);
+                } else {
+                    on_alpha = Some(percent);
+                }
+            }
+            "--keymap" => {
+                let name = args.next().expect("Invalid argument: --keymap needs a value");
+                keymap_profile = match KeymapProfile::from_name(&name) {
+                    Some(profile) => Some(profile),
+                    None => panic!("Invalid argument: unknown keymap profile {}", name),
+                };
+            }
+            "--layout" => {
+                let name = args.next().expect("Invalid argument: --layout needs a value");
+                layout = match KeyboardLayout::from_name(&name) {
+                    Some(layout) => Some(layout),
+                    None => panic!("Invalid argument: unknown keyboard layout {}", name),
+                };
+            }
+            "--strict" => strict = true,
+            "--wrap-sprites" => wrap_sprites = true,
+            "--display-wait" => {
+                let value = args.next().expect("Invalid argument: --display-wait needs a value");
+                display_wait = match value.as_str() {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => panic!("Invalid argument: --display-wait is on or off, not {}", value),
+                };
+            }
+            "--timing" => {
+                let value = args.next().expect("Invalid argument: --timing needs a value");
+                vip_timing = match value.as_str() {
+                    "vip" => true,
+                    "uniform" => false,
+                    _ => panic!("Invalid argument: --timing is vip or uniform, not {}", value),
+                };
+            }
+            "--force" => force = true,
+            "--apply-patch" => {
+                patch_path = Some(args.next().expect("Invalid argument: --apply-patch needs a value"));
+            }
+            "--patch-base" => {
+                let value = args.next().expect("Invalid argument: --patch-base needs a value");
+                patch_base = match u64::from_str_radix(&value, 16) {
+                    Ok(hash) => Some(hash),
+                    Err(_) => panic!("Invalid argument: {} is not a ROM hash", value),
+                };
+            }
+            // already picked out by LogOptions::from_args
+            "--log" | "--log-file" => {
+                args.next().unwrap_or_else(|| panic!("Invalid argument: {} needs a value", arg));
+            }
+            "--trace" => {
+                trace.get_or_insert_with(Trace::new);
+            }
+            "--trace-range" => {
+                let value = args.next().expect("Invalid argument: --trace-range needs a value");
+                trace.get_or_insert_with(Trace::new).range = match trace::parse_range(&value) {
+                    Some(range) => Some(range),
+                    None => panic!("Invalid argument: {} is not an address range like 0x200..0x300", value),
+                };
+            }
+            "--trace-opcodes" => {
+                let value = args.next().expect("Invalid argument: --trace-opcodes needs a value");
+                trace.get_or_insert_with(Trace::new).mnemonics = match trace::parse_mnemonics(&value) {
+                    Some(mnemonics) => mnemonics,
+                    None => panic!("Invalid argument: unknown mnemonic in {}", value),
+                };
+            }
+            "--profile-out" => {
+                let path = args.next().expect("Invalid argument: --profile-out needs a value");
+                profile_out = Some(PathBuf::from(path));
+            }
+            "--headless" => headless = true,
+            "--debug-tui" => debug_tui = true,
+            "--gdb" => {
+                let value = args.next().expect("Invalid argument: --gdb needs an address");
+                // ":3333" listens on localhost only
+                gdb = Some(if value.starts_with(':') { format!("127.0.0.1{}", value) } else { value });
+            }
+            "--frame-pipe" => {
+                frame_pipe = Some(args.next().expect("Invalid argument: --frame-pipe needs -, fd:<number> or a path"));
+            }
+            "--serve" => {
+                let value = args.next().expect("Invalid argument: --serve needs an address");
+                // ":8080" listens on every interface, for viewers on other machines
+                serve = Some(if value.starts_with(':') { format!("0.0.0.0{}", value) } else { value });
+            }
+            "--netplay-host" => {
+                let value = args.next().expect("Invalid argument: --netplay-host needs an address");
+                // ":7000" takes players from other machines
+                netplay = Some(Role::Host(if value.starts_with(':') { format!("0.0.0.0{}", value) } else { value }));
+            }
+            "--netplay-join" => {
+                netplay = Some(Role::Join(args.next().expect("Invalid argument: --netplay-join needs an address")));
+            }
+            "--netplay-delay" => {
+                let value = args.next().expect("Invalid argument: --netplay-delay needs a frame count");
+                netplay_delay = match value.parse() {
+                    Ok(frames) => frames,
+                    Err(_) => panic!("Invalid argument: {} is not a frame count", value),
+                };
+            }
+            "--debug" => debug = true,
+            "--show-fps" => show_fps = true,
+            "--vsync" => vsync = true,
+            "--touchpad" => touch_pad = true,
+            "--resume" => resume = true,
+            "--cycles" => {
+                let value = args.next().expect("Invalid argument: --cycles needs a value");
+                cycles = match value.parse() {
+                    Ok(cycles) => cycles,
+                    Err(_) => panic!("Invalid argument: {} is not a cycle count", value),
+                };
+            }
+            "--dump" => {
+                let format = args.next().expect("Invalid argument: --dump needs a value");
+                dump = match format.as_str() {
+                    "text" => Dump::Text,
+                    "hash" => Dump::Hash,
+                    _ => panic!("Invalid argument: unknown dump format {}", format),
+                };
+            }
+            "--script" => {
+                script = Some(args.next().expect("Invalid argument: --script needs a value"));
+            }
+            "--watch" => watch = true,
+            "--record-movie" => {
+                let path = args.next().expect("Invalid argument: --record-movie needs a value");
+                record_movie = Some(PathBuf::from(path));
+            }
+            "--playback" => {
+                let path = args.next().expect("Invalid argument: --playback needs a value");
+                playback = match Movie::load(Path::new(&path)) {
+                    Ok(movie) => Some(movie),
+                    Err(error) => panic!("Invalid argument: movie {}: {}", path, error),
+                };
+            }
+            "--cheats" => {
+                let path = args.next().expect("Invalid argument: --cheats needs a value");
+                cheats = match Cheats::load(Path::new(&path)) {
+                    Ok(cheats) => cheats,
+                    Err(error) => panic!("Invalid argument: cheats {}: {}", path, error),
+                };
+            }
+            "--battery" => {
+                let value = args.next().expect("Invalid argument: --battery needs a value");
+                battery = match battery::parse_range(&value) {
+                    Some(range) => Some(range),
+                    None => panic!("Invalid argument: {} is not a memory range like 0xE00..0x1000", value),
+                };
+            }
+            #[cfg(feature = "scripting")]
+            "--hooks" => {
+                let path = args.next().expect("Invalid argument: --hooks needs a value");
+                hooks = match ScriptHooks::load(Path::new(&path)) {
+                    Ok(script) => Some(script),
+                    Err(error) => panic!("Invalid argument: hooks {}: {}", path, error),
+                };
+            }
+            _ => rom = Some(arg),
+        }
+    }
 
-        cpu.keypad.press(keys);
-        cpu.execute_cycle();
+    // the first start without a ROM or a config file begins with the setup
+    let setup = match (&config_path, &rom) {
+        (None, None) => Config::default_path().filter(|path| !path.exists()),
+        _ => None,
+    };
 
-        let display_sync = time::Duration::from_millis(8);
-        thread::sleep(display_sync);
+    // Command line options win over the config file.
+    let mut config = load_config(config_path);
+    let scaling = scaling.or(config.scaling).unwrap_or_default();
+    let scale = scale.or(config.scale).unwrap_or(ppu::DEFAULT_SCALE);
+    config.palette = palette.or(config.palette);
+    config.foreground = foreground.or(config.foreground);
+    config.background = background.or(config.background);
+    config.invert = if invert { Some(true) } else { config.invert };
+    config.brightness = brightness.or(config.brightness);
+    config.on_alpha = on_alpha.or(config.on_alpha);
+    let autosave = match autosave.or(config.autosave) {
+        Some(seconds) => Duration::from_secs(seconds),
+        None => autosave::DEFAULT_INTERVAL,
+    };
+    let resume = resume || config.resume.unwrap_or(false);
+    let screenshot_dir = screenshot_dir.or_else(|| session::data_dir().map(|dir| dir.join("screenshots")));
+    let palette = match config.display_palette() {
+        Some(palette) => palette,
+        None => panic!("Invalid argument: unknown palette {}", config.palette.unwrap_or_default()),
+    };
+
+    // windowed runs hand the cpu to the frontend for good
+    if profile_out.is_some() && !(headless || debug_tui || gdb.is_some() || frame_pipe.is_some() || serve.is_some()) {
+        panic!("Invalid argument: --profile-out needs --headless, --debug-tui, --gdb, --frame-pipe or --serve");
+    }
+
+    // without a ROM the window starts with the ROM browser
+    let (rom, rom_dir) = match rom {
+        Some(rom) => (rom, None),
+        None if headless || debug_tui || gdb.is_some() || frame_pipe.is_some() || serve.is_some() || netplay.is_some() || script.is_some() || playback.is_some() || record_movie.is_some() => {
+            panic!("Invalid argument: no ROM specified")
+        }
+        None => (String::new(), Some(rom_dir.or(config.roms).unwrap_or_else(|| PathBuf::from(".")))),
+    };
+
+    // --keymap wins over the config's choices, even for ROMs picked later
+    let rom_keymaps = if keymap_profile.is_some() { Vec::new() } else { config.rom_keymaps.clone() };
+    let keymap = keymap_profile
+        .or_else(|| keymap::for_rom(&rom_keymaps, Path::new(&rom)))
+        .or(config.keymap)
+        .unwrap_or_default();
+    let layout = layout.or(config.layout);
+
+    // a patch is checked against the ROM it was made for, when known
+    let patch = patch_path.map(|path| match Patch::load(Path::new(&path)) {
+        Ok(patch) => patch,
+        Err(error) => panic!("Invalid argument: patch {}", error),
+    });
+    if patch.is_some() {
+        if rom_dir.is_some() {
+            panic!("Invalid argument: --apply-patch needs a ROM");
+        }
+        let hash = open_rom(&rom).map(|rom| headless::hash(&rom)).ok();
+        match patch_base {
+            Some(base) if hash.is_some_and(|hash| hash != base) => {
+                panic!("Invalid argument: the patch is for the ROM with hash {:016x}, {} has {:016x}", base, rom, hash.unwrap_or_default())
+            }
+            Some(_) => {}
+            None => log::warn!("--patch-base not given, applying the patch without checking the ROM"),
+        }
+    }
+
+    // a known ROM runs with the settings it needs unless they were given
+    let detect = platform.is_none() && instructions_per_frame.is_none() && load_address.is_none();
+    let detected = match rom_dir {
+        None if detect => open_rom(&rom).ok().and_then(|rom| romdb::detect(&rom)),
+        _ => None,
+    };
+    let platform = platform.or(detected.map(|info| info.platform)).unwrap_or_default();
+    if platform.quirks().megachip && (frame_pipe.is_some() || serve.is_some()) {
+        panic!("Invalid argument: --frame-pipe and --serve send 64x32 frames, which --platform megachip doesn't draw");
+    }
+    // timed by cycles, the speed only caps the instructions per frame
+    let default_speed = if vip_timing {
+        vip_timing::MAX_INSTRUCTIONS_PER_FRAME
+    } else if platform.quirks().megachip {
+        megachip::INSTRUCTIONS_PER_FRAME
+    } else {
+        DEFAULT_INSTRUCTIONS_PER_FRAME
+    };
+    let mut instructions_per_frame = instructions_per_frame
+        .or(detected.filter(|_| !vip_timing).map(|info| info.instructions_per_frame))
+        .unwrap_or(default_speed);
+    let load_address = load_address
+        .or(detected.map(|info| info.load_address))
+        .unwrap_or(PROGRAM_START as u16);
+
+    // a movie replays with the settings it was recorded with
+    let mut quirks = platform.quirks();
+    if wrap_sprites {
+        quirks.clip_sprites = false;
+    }
+    quirks.display_wait = display_wait.unwrap_or(quirks.display_wait);
+    quirks.vip_timing = vip_timing;
+    let seed = match &playback {
+        Some(movie) => {
+            quirks = movie.profile.quirks;
+            instructions_per_frame = movie.instructions_per_frame;
+            movie.seed
+        }
+        None => rng::random_seed(),
+    };
+    if playback.is_some() && record_movie.is_some() {
+        panic!("Invalid argument: --record-movie and --playback can't be combined");
+    }
+    if watch && script.is_none() {
+        panic!("Invalid argument: --watch needs a --script to re-run");
+    }
+
+    Options {
+        rom,
+        rom_dir,
+        setup,
+        detect,
+        load_address,
+        quirks,
+        seed,
+        strict,
+        force,
+        patch,
+        trace,
+        profile_out,
+        instructions_per_frame,
+        scaling,
+        scale,
+        palette,
+        keymap,
+        rom_keymaps,
+        layout,
+        phosphor,
+        crt,
+        show_fps,
+        vsync,
+        touch_pad,
+        autosave,
+        resume,
+        screenshot_dir,
+        headless,
+        debug_tui,
+        gdb,
+        frame_pipe,
+        serve,
+        netplay,
+        netplay_delay,
+        debug,
+        cycles,
+        dump,
+        script,
+        watch,
+        record_movie,
+        playback,
+        cheats,
+        battery,
+        #[cfg(feature = "scripting")]
+        hooks,
     }
 }
 
-fn init_cpu(canvas: Canvas<Window>) -> Result<Cpu> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("Invalid argument: no ROM specified")
+// An explicit --config must exist; the default one is optional.
+fn load_config(path: Option<String>) -> Config {
+    let (path, required) = match path {
+        Some(path) => (Path::new(&path).to_path_buf(), true),
+        None => match Config::default_path() {
+            Some(path) => (path, false),
+            None => return Config::default(),
+        },
+    };
+    if !required && !path.exists() {
+        return Config::default();
     }
+    match Config::load(&path) {
+        Ok(config) => config,
+        Err(error) => panic!("Invalid config {}: {}", path.display(), error),
+    }
+}
 
-    let rom = args.get(1).unwrap();
-    let ppu = ppu::Ppu::new(Box::new(CanvasWindow::new(canvas)));
-    let mut cpu = cpu::Cpu::new(Box::new(ppu));
-    let game = open_rom(rom).unwrap();
+fn init_cpu(display: Box<dyn Display>, options: &Options) -> Result<Cpu> {
+    // the ROM browser loads the picked ROM itself
+    let game = match options.rom_dir {
+        Some(_) => Vec::new(),
+        None => read_rom(options).unwrap(),
+    };
+    new_cpu(display, options, game).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+}
+
+fn new_cpu(display: Box<dyn Display>, options: &Options, game: Vec<u8>) -> std::result::Result<Cpu, LoadError> {
+    let mut cpu = Cpu::new(display);
+    cpu.quirks = options.quirks;
+    cpu.seed_rng(options.seed);
+    cpu.strict = options.strict;
+    cpu.load_address = options.load_address;
+    cpu.trace = options.trace.clone();
+    if options.profile_out.is_some() {
+        cpu.profiler = Some(Profiler::new(0));
+    }
     cpu.reset();
-    cpu.load(game);
+    if options.force {
+        cpu.load_truncated(game);
+    } else {
+        cpu.load(game)?;
+    }
+    Ok(cpu)
+}
 
-    Result::Ok(cpu)
+// The ROM as it runs, with the patch applied.
+fn read_rom(options: &Options) -> Result<Vec<u8>> {
+    let rom = open_rom(&options.rom)?;
+    Ok(match &options.patch {
+        Some(patch) => patch.apply(&rom),
+        None => rom,
+    })
 }
 
 fn open_rom(file_name: &str) -> Result<Vec<u8>> {
-    println!("load_game() {}", file_name);
+    log::info!("load_game() {}", file_name);
 
     let file_metadata = std::fs::metadata(file_name)?;
-    println!("{} is {} bytes in size", file_name, file_metadata.len());
+    log::info!("{} is {} bytes in size", file_name, file_metadata.len());
 
     let mut f = File::open(file_name)?;
     let mut buffer: Vec<u8> = vec![0; file_metadata.len() as usize];
@@ -101,19 +1485,3 @@ fn open_rom(file_name: &str) -> Result<Vec<u8>> {
 
     Ok(buffer)
 }
-
-fn get_canvas(sdl: &Sdl) -> Canvas<Window> {
-    let video_subsystem = sdl.video().unwrap();
-    let _window = video_subsystem
-        .window("hachip", ppu::FRAME_WIDTH, ppu::FRAME_HEIGHT)
-        .resizable()
-        .build()
-        .unwrap();
-    let canvas: Canvas<Window> = _window
-        .into_canvas()
-        // .present_vsync() //< this means the screen cannot
-        // render faster than your display rate (usually 60Hz or 144Hz)
-        .build()
-        .unwrap();
-    canvas
-}
@@ -0,0 +1,466 @@
+// Mega-Chip, Revival Studios' extension of SUPER-CHIP: a 256x192 screen
+// in colours from a palette the ROM loads, sprites of any size with a
+// palette index per pixel that blend into what is under them, and 8-bit
+// sampled sound. ROMs switch the screen on with 0011 and back with 0010.
+// Sprites go to a buffer that 00E0 shows and clears, so only finished
+// frames are seen. The cpu keeps one of these with the megachip quirk.
+use std::mem;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+// 01nn nnnn loads I with 24 bits, so ROMs may fill that much
+pub const MEMORY_SIZE: usize = 0x100_0000;
+// The demos are written for a fast machine; unless `--speed` says
+// otherwise they get this many instructions a frame.
+pub const INSTRUCTIONS_PER_FRAME: u32 = 3000;
+// the palette entry font glyphs are drawn in, white until loaded
+const FONT_COLOR: u8 = 255;
+// sample bytes are unsigned, 0x80 is silence; at the square wave's volume
+const SAMPLE_VOLUME: i32 = 0x1000 / 0x80;
+// bytes of to_bytes
+pub(crate) const STATE_SIZE: usize = 1 + 256 * 4 + 2 + 2 + 3 + WIDTH * HEIGHT * 9 + 20;
+
+// How 080n mixes sprite pixels with the screen under them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Blend {
+    #[default]
+    Normal,
+    // the sprite at 25%, 50% and 75% over the screen
+    Quarter,
+    Half,
+    ThreeQuarters,
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    pub const ALL: [Blend; 6] = [Blend::Normal, Blend::Quarter, Blend::Half, Blend::ThreeQuarters, Blend::Add, Blend::Multiply];
+
+    // The mode of 080n, None past 5.
+    pub fn from_nibble(n: u8) -> Option<Blend> {
+        Blend::ALL.get(n as usize).copied()
+    }
+
+    fn mix(self, sprite: u32, screen: u32) -> u32 {
+        let channel = |shift: u32| {
+            let (s, d) = (sprite >> shift & 0xFF, screen >> shift & 0xFF);
+            let mixed = match self {
+                Blend::Normal => s,
+                Blend::Quarter => (s + d * 3) / 4,
+                Blend::Half => (s + d) / 2,
+                Blend::ThreeQuarters => (s * 3 + d) / 4,
+                Blend::Add => (s + d).min(0xFF),
+                Blend::Multiply => s * d / 0xFF,
+            };
+            mixed << shift
+        };
+        0xFF00_0000 | channel(16) | channel(8) | channel(0)
+    }
+}
+
+// A 060n sample playing: `length` unsigned bytes from `start`, `rate` of
+// them a second. `position` counts bytes in 16.16 fixed point, so a replay
+// renders the same sound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sample {
+    start: u32,
+    length: u32,
+    rate: u16,
+    looped: bool,
+    position: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MegaChip {
+    on: bool,
+    // 0xAARRGGBB; index 0 is transparent whatever it holds
+    palette: [u32; 256],
+    sprite_width: usize,
+    sprite_height: usize,
+    blend: Blend,
+    // palette index sprites collide with, none when 0
+    collision_color: u8,
+    // 05nn, how bright 00E0 shows the buffer
+    alpha: u8,
+    // the buffer sprites go to, as colours and as the indices they were
+    // drawn with, for collisions
+    pixels: Vec<u32>,
+    indices: Vec<u8>,
+    // what 00E0 last showed
+    screen: Vec<u32>,
+    sound: Option<Sample>,
+    // where the sprite being drawn is gathered, kept so drawing doesn't
+    // allocate; always empty between draws
+    sprite: Vec<u8>,
+}
+
+impl Default for MegaChip {
+    fn default() -> MegaChip {
+        MegaChip::new()
+    }
+}
+
+impl MegaChip {
+    pub fn new() -> MegaChip {
+        MegaChip {
+            on: false,
+            palette: [0xFFFF_FFFF; 256],
+            sprite_width: 8,
+            sprite_height: 8,
+            blend: Blend::Normal,
+            collision_color: 0,
+            alpha: 0xFF,
+            pixels: vec![0; WIDTH * HEIGHT],
+            indices: vec![0; WIDTH * HEIGHT],
+            screen: vec![0; WIDTH * HEIGHT],
+            sound: None,
+            sprite: Vec::new(),
+        }
+    }
+
+    // Whether the 256x192 screen is up (0011) rather than the CHIP-8 one.
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    // The picture 00E0 last showed, 0xAARRGGBB row by row.
+    pub fn screen(&self) -> &[u32] {
+        &self.screen
+    }
+
+    pub fn palette(&self) -> &[u32; 256] {
+        &self.palette
+    }
+
+    pub fn sprite_size(&self) -> (usize, usize) {
+        (self.sprite_width, self.sprite_height)
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.sound.is_some()
+    }
+
+    // Switching either way starts on a black screen.
+    pub(crate) fn set_on(&mut self, on: bool) {
+        self.on = on;
+        self.pixels.fill(0);
+        self.indices.fill(0);
+        self.screen.fill(0);
+    }
+
+    // 02nn: `colors` holds 4 bytes, ARGB, per entry from index 1 on.
+    pub(crate) fn load_palette(&mut self, colors: &[u8]) {
+        for (entry, color) in self.palette[1..].iter_mut().zip(colors.chunks_exact(4)) {
+            *entry = u32::from_be_bytes([color[0], color[1], color[2], color[3]]);
+        }
+    }
+
+    // 03nn and 04nn, where 0 means 256.
+    pub(crate) fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = if width == 0 { 256 } else { width as usize };
+    }
+
+    pub(crate) fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = if height == 0 { 256 } else { height as usize };
+    }
+
+    pub(crate) fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha;
+    }
+
+    pub(crate) fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+
+    pub(crate) fn set_collision_color(&mut self, index: u8) {
+        self.collision_color = index;
+    }
+
+    // Draws `sprite`, a palette index per pixel `width` to a row, into the
+    // buffer at (x, y), cut off at the edges. Index 0 is transparent.
+    // Returns whether a pixel went over one of the collision colour.
+    pub(crate) fn draw(&mut self, x: usize, y: usize, width: usize, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        for (row, line) in sprite.chunks(width.max(1)).enumerate().take(HEIGHT.saturating_sub(y)) {
+            for (column, &index) in line.iter().enumerate().take(WIDTH.saturating_sub(x)) {
+                if index == 0 {
+                    continue;
+                }
+                let at = (y + row) * WIDTH + x + column;
+                collision |= self.collision_color != 0 && self.indices[at] == self.collision_color;
+                self.pixels[at] = self.blend.mix(self.palette[index as usize], self.pixels[at]);
+                self.indices[at] = index;
+            }
+        }
+        collision
+    }
+
+    // The sprite buffer, for the cpu to fill from memory and hand back to
+    // `draw_buffer`.
+    pub(crate) fn take_sprite_buffer(&mut self) -> Vec<u8> {
+        mem::take(&mut self.sprite)
+    }
+
+    // `draw` of a sprite gathered in the sprite buffer, which is kept for
+    // the next one.
+    pub(crate) fn draw_buffer(&mut self, x: usize, y: usize, width: usize, mut sprite: Vec<u8>) -> bool {
+        let collision = self.draw(x, y, width, &sprite);
+        sprite.clear();
+        self.sprite = sprite;
+        collision
+    }
+
+    // A CHIP-8 font glyph, a bit per pixel, in the font colour.
+    pub(crate) fn draw_glyph(&mut self, x: usize, y: usize, rows: &[u8]) -> bool {
+        let mut sprite = self.take_sprite_buffer();
+        let pixels = rows.iter().flat_map(|row| (0..8).map(move |bit| if row & 0x80 >> bit != 0 { FONT_COLOR } else { 0 }));
+        sprite.extend(pixels);
+        self.draw_buffer(x, y, 8, sprite)
+    }
+
+    // 00E0: shows the buffer at the 05nn alpha and clears it for the next
+    // frame.
+    pub(crate) fn show(&mut self) {
+        let alpha = self.alpha as u32;
+        for (shown, pixel) in self.screen.iter_mut().zip(self.pixels.iter()) {
+            let channel = |shift: u32| ((pixel >> shift & 0xFF) * alpha / 0xFF) << shift;
+            *shown = 0xFF00_0000 | channel(16) | channel(8) | channel(0);
+        }
+        self.pixels.fill(0);
+        self.indices.fill(0);
+    }
+
+    // 00Bn: moves the buffer up `lines` rows, black coming in below.
+    pub(crate) fn scroll_up(&mut self, lines: usize) {
+        let lines = lines.min(HEIGHT);
+        self.pixels.copy_within(lines * WIDTH.., 0);
+        self.indices.copy_within(lines * WIDTH.., 0);
+        self.pixels[(HEIGHT - lines) * WIDTH..].fill(0);
+        self.indices[(HEIGHT - lines) * WIDTH..].fill(0);
+    }
+
+    // 060n with `header` the 6 bytes at I: the rate, 16 bits, the length,
+    // 24 bits, and a spare byte; the sample follows at I + 6.
+    pub(crate) fn play(&mut self, address: u32, header: [u8; 6], looped: bool) {
+        self.sound = Some(Sample {
+            start: address + 6,
+            length: u32::from_be_bytes([0, header[2], header[3], header[4]]),
+            rate: u16::from_be_bytes([header[0], header[1]]),
+            looped,
+            position: 0,
+        });
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.sound = None;
+    }
+
+    // Adds the playing sample to `out`, resampled to `sample_rate`, and
+    // moves it on. Returns whether one played.
+    pub(crate) fn render_sound(&mut self, memory: &[u8], out: &mut [i16], sample_rate: u32) -> bool {
+        let sample = match &mut self.sound {
+            Some(sample) => sample,
+            None => return false,
+        };
+        let step = ((sample.rate as u64) << 16) / sample_rate.max(1) as u64;
+        let end = (sample.length as u64) << 16;
+        for out in out.iter_mut() {
+            if sample.position >= end {
+                if !sample.looped || end == 0 {
+                    self.sound = None;
+                    return true;
+                }
+                sample.position %= end;
+            }
+            let address = sample.start as usize + (sample.position >> 16) as usize;
+            let byte = memory.get(address).copied().unwrap_or(0x80);
+            *out = out.saturating_add(((byte as i32 - 0x80) * SAMPLE_VOLUME) as i16);
+            sample.position += step;
+        }
+        true
+    }
+
+    // For save states and, fed straight to the hasher, the state hash;
+    // STATE_SIZE bytes, big-endian.
+    pub(crate) fn to_bytes(&self, bytes: &mut impl Extend<u8>) {
+        bytes.extend([self.on as u8]);
+        for color in self.palette.iter() {
+            bytes.extend(color.to_be_bytes());
+        }
+        bytes.extend((self.sprite_width as u16).to_be_bytes());
+        bytes.extend((self.sprite_height as u16).to_be_bytes());
+        let blend = Blend::ALL.iter().position(|blend| *blend == self.blend).unwrap_or(0);
+        bytes.extend([blend as u8, self.collision_color, self.alpha]);
+        for pixel in self.pixels.iter().chain(self.screen.iter()) {
+            bytes.extend(pixel.to_be_bytes());
+        }
+        bytes.extend(self.indices.iter().copied());
+        let sample = self.sound.unwrap_or(Sample {
+            start: 0,
+            length: 0,
+            rate: 0,
+            looped: false,
+            position: 0,
+        });
+        bytes.extend([self.sound.is_some() as u8]);
+        bytes.extend(sample.start.to_be_bytes());
+        bytes.extend(sample.length.to_be_bytes());
+        bytes.extend(sample.rate.to_be_bytes());
+        bytes.extend([sample.looped as u8]);
+        bytes.extend(sample.position.to_be_bytes());
+    }
+
+    // None unless `bytes` is STATE_SIZE long with known values.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<MegaChip> {
+        if bytes.len() != STATE_SIZE {
+            return None;
+        }
+        let mut offset = 0;
+        let mut take = |count: usize| {
+            offset += count;
+            &bytes[offset - count..offset]
+        };
+        let word = |bytes: &[u8]| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let mut mega = MegaChip::new();
+        mega.on = take(1)[0] != 0;
+        for color in mega.palette.iter_mut() {
+            *color = word(take(4));
+        }
+        let sizes = take(4);
+        mega.sprite_width = u16::from_be_bytes([sizes[0], sizes[1]]) as usize;
+        mega.sprite_height = u16::from_be_bytes([sizes[2], sizes[3]]) as usize;
+        let settings = take(3);
+        mega.blend = Blend::from_nibble(settings[0])?;
+        mega.collision_color = settings[1];
+        mega.alpha = settings[2];
+        for pixel in mega.pixels.iter_mut().chain(mega.screen.iter_mut()) {
+            *pixel = word(take(4));
+        }
+        mega.indices.copy_from_slice(take(WIDTH * HEIGHT));
+        let playing = take(1)[0] != 0;
+        let start = word(take(4));
+        let length = word(take(4));
+        let rate = take(2);
+        let looped = take(1)[0] != 0;
+        let mut position = [0; 8];
+        position.copy_from_slice(take(8));
+        if playing {
+            mega.sound = Some(Sample {
+                start,
+                length,
+                rate: u16::from_be_bytes([rate[0], rate[1]]),
+                looped,
+                position: u64::from_be_bytes(position),
+            });
+        }
+        let valid = (1..=256).contains(&mega.sprite_width) && (1..=256).contains(&mega.sprite_height);
+        valid.then_some(mega)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blend, MegaChip, HEIGHT, STATE_SIZE, WIDTH};
+
+    #[test]
+    fn draws_indexed_sprites_blended_and_cut_off() {
+        let mut mega = MegaChip::new();
+        mega.set_on(true);
+        // 1 red, 2 blue
+        mega.load_palette(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF]);
+        assert!(!mega.draw(10, 20, 2, &[1, 0, 2, 1]));
+        mega.show();
+        assert_eq!(mega.screen()[20 * WIDTH + 10], 0xFFFF_0000);
+        assert_eq!(mega.screen()[20 * WIDTH + 11], 0xFF00_0000, "0 is transparent");
+        assert_eq!(mega.screen()[21 * WIDTH + 10], 0xFF00_00FF);
+        assert!(mega.pixels.iter().all(|pixel| *pixel == 0), "00E0 clears the buffer");
+
+        mega.draw(0, 0, 1, &[1]);
+        mega.set_blend(Blend::Half);
+        mega.draw(0, 0, 1, &[2]);
+        mega.set_blend(Blend::Add);
+        mega.draw(1, 0, 1, &[1]);
+        mega.draw(1, 0, 1, &[2]);
+        mega.set_alpha(0x80);
+        mega.show();
+        assert_eq!(mega.screen()[0], 0xFF3F_003F, "half red, half blue, at half brightness");
+        assert_eq!(mega.screen()[1], 0xFF80_0080);
+
+        // across the corner, only the inside is drawn
+        mega.set_blend(Blend::Normal);
+        mega.draw(WIDTH - 1, HEIGHT - 1, 2, &[1, 1, 1, 1]);
+        assert_eq!(mega.indices.iter().filter(|index| **index != 0).count(), 1);
+
+        // the sprite buffer is handed back empty, keeping its room
+        let mut sprite = mega.take_sprite_buffer();
+        sprite.extend_from_slice(&[1, 2]);
+        mega.draw_buffer(4, 4, 2, sprite);
+        mega.draw_glyph(8, 8, &[0xF0, 0x90]);
+        assert_eq!(mega.indices[4 * WIDTH + 5], 2);
+        assert!(mega.sprite.is_empty() && mega.sprite.capacity() >= 16);
+    }
+
+    #[test]
+    fn collides_only_with_the_collision_colour() {
+        let mut mega = MegaChip::new();
+        mega.draw(0, 0, 2, &[1, 2]);
+        assert!(!mega.draw(0, 0, 2, &[3, 3]), "no collision colour, no collisions");
+        mega.set_collision_color(3);
+        assert!(!mega.draw(0, 1, 2, &[3, 3]));
+        assert!(mega.draw(1, 1, 1, &[4]));
+        assert!(!mega.draw(1, 1, 1, &[4]), "the 3 was drawn over");
+        mega.draw_glyph(8, 8, &[0xF0]);
+        assert_eq!(mega.indices[8 * WIDTH + 8..8 * WIDTH + 16], [255, 255, 255, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn scrolls_the_buffer_up() {
+        let mut mega = MegaChip::new();
+        mega.draw(0, 5, 1, &[1]);
+        mega.scroll_up(4);
+        assert_eq!(mega.indices[WIDTH], 1);
+        assert_eq!(mega.indices[5 * WIDTH], 0);
+        mega.scroll_up(HEIGHT + 1);
+        assert!(mega.indices.iter().all(|index| *index == 0));
+    }
+
+    #[test]
+    fn plays_samples_once_or_looped() {
+        let mut mega = MegaChip::new();
+        // 22050Hz, 3 bytes
+        let memory = [0x56, 0x22, 0x00, 0x00, 0x03, 0x00, 0xFF, 0x80, 0x00];
+        let header = [memory[0], memory[1], memory[2], memory[3], memory[4], memory[5]];
+        mega.play(0, header, false);
+        let mut out = [0; 8];
+        assert!(mega.render_sound(&memory, &mut out, 44100));
+        let loud = (0x7F * super::SAMPLE_VOLUME) as i16;
+        assert_eq!(out, [loud, loud, 0, 0, -loud - 32, -loud - 32, 0, 0]);
+        assert!(!mega.sound_playing(), "played once");
+        assert!(!mega.render_sound(&memory, &mut out, 44100));
+
+        mega.play(0, header, true);
+        let mut out = [0; 8];
+        mega.render_sound(&memory, &mut out, 44100);
+        assert_eq!(out[6], loud, "started over");
+        assert!(mega.sound_playing());
+        mega.stop();
+        assert!(!mega.sound_playing());
+    }
+
+    #[test]
+    fn state_round_trips_through_bytes() {
+        let mut mega = MegaChip::new();
+        mega.set_on(true);
+        mega.set_sprite_width(0);
+        mega.set_sprite_height(16);
+        mega.set_blend(Blend::Multiply);
+        mega.draw(3, 4, 1, &[7]);
+        mega.play(0x12345, [0x10, 0x00, 0x00, 0x01, 0x00, 0x00], true);
+        let mut bytes = Vec::new();
+        mega.to_bytes(&mut bytes);
+        assert_eq!(bytes.len(), STATE_SIZE);
+        assert_eq!(MegaChip::from_bytes(&bytes), Some(mega));
+        assert_eq!(MegaChip::from_bytes(&bytes[1..]), None);
+    }
+}
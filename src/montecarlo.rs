@@ -0,0 +1,315 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::headless::HeadlessDisplay;
+use crate::quirks::Quirks;
+use crate::scheduler::{self, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::thread;
+
+// Histogram rows at most; wider ranges are grouped.
+const MAX_BUCKETS: usize = 16;
+// Characters in the longest histogram bar.
+const BAR_WIDTH: usize = 40;
+
+// What a run is measured by when it ends: "mem[0x3A0]" (a byte of memory,
+// hex with 0x or decimal), "V3", "I" or "PC".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    Memory(u16),
+    V(usize),
+    I,
+    Pc,
+}
+
+impl Metric {
+    pub fn parse(text: &str) -> Option<Metric> {
+        let text = text.trim();
+        if let Some(address) = text.strip_prefix("mem[").and_then(|rest| rest.strip_suffix(']')) {
+            let address = match address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")) {
+                Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                None => address.parse().ok()?,
+            };
+            return ((address as usize) < MEMORY_SIZE).then_some(Metric::Memory(address));
+        }
+        match text.to_ascii_uppercase().as_str() {
+            "I" => Some(Metric::I),
+            "PC" => Some(Metric::Pc),
+            register => match register.strip_prefix('V') {
+                Some(x) if x.len() == 1 => Some(Metric::V(usize::from_str_radix(x, 16).ok()?)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn measure(self, cpu: &Cpu) -> u16 {
+        match self {
+            Metric::Memory(address) => cpu.memory()[address as usize] as u16,
+            Metric::V(x) => cpu.registers()[x] as u16,
+            Metric::I => cpu.i() as u16,
+            Metric::Pc => cpu.pc(),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::Memory(address) => write!(f, "mem[{:#05X}]", address),
+            Metric::V(x) => write!(f, "V{:X}", x),
+            Metric::I => write!(f, "I"),
+            Metric::Pc => write!(f, "PC"),
+        }
+    }
+}
+
+// `hachip montecarlo`: the same ROM run headless `runs` times, each with its
+// own seed for Cxkk (`first_seed`, then counting up), spread over the
+// machine's cores. Runs only depend on their seed, so the results are the
+// same for any number of threads.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct MonteCarlo {
+    pub rom: Vec<u8>,
+    pub runs: u64,
+    pub frames: u64,
+    pub metric: Metric,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub first_seed: u64,
+}
+
+// One run: its seed and the metric at the end, or why the cpu stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    pub seed: u64,
+    pub value: Result<u16, String>,
+}
+
+impl MonteCarlo {
+    pub fn new(rom: Vec<u8>, metric: Metric) -> MonteCarlo {
+        MonteCarlo {
+            rom,
+            runs: 100,
+            frames: 3600,
+            metric,
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            first_seed: 1,
+        }
+    }
+
+    // Every run, in seed order. The runs of a thread that panicked count as
+    // stopped, with the panic as the reason.
+    pub fn run(&self) -> Vec<Run> {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get()) as u64;
+        let per_thread = self.runs.div_ceil(threads.max(1)).max(1);
+        let seeds: Vec<u64> = (0..self.runs).map(|run| self.first_seed.wrapping_add(run)).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = seeds
+                .chunks(per_thread as usize)
+                .map(|seeds| {
+                    let worker = scope.spawn(move || seeds.iter().map(|seed| self.run_one(*seed)).collect::<Vec<Run>>());
+                    (seeds, worker)
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|(seeds, worker)| {
+                    worker.join().unwrap_or_else(|panic| {
+                        let reason = format!("panicked: {}", panic_message(panic.as_ref()));
+                        seeds
+                            .iter()
+                            .map(|seed| Run {
+                                seed: *seed,
+                                value: Err(reason.clone()),
+                            })
+                            .collect()
+                    })
+                })
+                .collect()
+        })
+    }
+
+    pub fn run_one(&self, seed: u64) -> Run {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = self.quirks;
+        cpu.seed_rng(seed);
+        cpu.reset();
+        let value = cpu.load(self.rom.clone()).map_err(|error| error.to_string()).and_then(|()| {
+            for frame in 0..self.frames {
+                if let Err(error) = scheduler::run_frame(&mut cpu, self.instructions_per_frame) {
+                    return Err(format!("stopped in frame {}: {}", frame, error));
+                }
+            }
+            Ok(self.metric.measure(&cpu))
+        });
+        Run { seed, value }
+    }
+}
+
+// What a panic was raised with, when it was a message.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "no message",
+    }
+}
+
+// The spread of a metric over the runs that finished.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution {
+    // values by how many runs ended with them
+    counts: BTreeMap<u16, u64>,
+    stopped: u64,
+}
+
+impl Distribution {
+    pub fn of(runs: &[Run]) -> Distribution {
+        let mut counts = BTreeMap::new();
+        for value in runs.iter().filter_map(|run| run.value.as_ref().ok()) {
+            *counts.entry(*value).or_insert(0) += 1;
+        }
+        Distribution {
+            counts,
+            stopped: runs.iter().filter(|run| run.value.is_err()).count() as u64,
+        }
+    }
+
+    pub fn finished(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn min(&self) -> Option<u16> {
+        self.counts.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<u16> {
+        self.counts.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> f64 {
+        let sum: f64 = self.counts.iter().map(|(value, count)| *value as f64 * *count as f64).sum();
+        sum / self.finished().max(1) as f64
+    }
+
+    // The lower median for an even number of runs.
+    pub fn median(&self) -> Option<u16> {
+        let middle = self.finished().checked_sub(1)? / 2;
+        let mut seen = 0;
+        self.counts.iter().find_map(|(value, count)| {
+            seen += count;
+            (seen > middle).then_some(*value)
+        })
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        let mean = self.mean();
+        let squares: f64 = self
+            .counts
+            .iter()
+            .map(|(value, count)| (*value as f64 - mean).powi(2) * *count as f64)
+            .sum();
+        (squares / self.finished().max(1) as f64).sqrt()
+    }
+
+    // (first value, last value, runs) per row, at most MAX_BUCKETS rows of
+    // equal width.
+    pub fn histogram(&self) -> Vec<(u16, u16, u64)> {
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min as usize, max as usize),
+            _ => return Vec::new(),
+        };
+        let width = (max - min + 1).div_ceil(MAX_BUCKETS);
+        let mut buckets: Vec<(u16, u16, u64)> = (min..=max)
+            .step_by(width)
+            .map(|first| (first as u16, (first + width - 1).min(max) as u16, 0))
+            .collect();
+        for (value, count) in &self.counts {
+            buckets[(*value as usize - min) / width].2 += count;
+        }
+        buckets
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "runs {}", self.finished() + self.stopped)?;
+        if self.stopped > 0 {
+            write!(f, " ({} stopped)", self.stopped)?;
+        }
+        let (min, max, median) = match (self.min(), self.max(), self.median()) {
+            (Some(min), Some(max), Some(median)) => (min, max, median),
+            _ => return writeln!(f),
+        };
+        writeln!(
+            f,
+            "  min {}  max {}  mean {:.2}  median {}  stddev {:.2}",
+            min,
+            max,
+            self.mean(),
+            median,
+            self.standard_deviation()
+        )?;
+        let histogram = self.histogram();
+        let most = histogram.iter().map(|(_, _, count)| *count).max().unwrap_or(1);
+        for (first, last, count) in histogram {
+            let label = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+            let bar = "#".repeat((count as usize * BAR_WIDTH).div_ceil(most as usize));
+            writeln!(f, "{:>11}  {:<width$}  {}", label, bar, count, width = BAR_WIDTH)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Distribution, Metric, MonteCarlo, Run};
+
+    #[test]
+    fn runs_are_seeded_and_summarized() {
+        assert_eq!(Metric::parse("mem[0x3A0]"), Some(Metric::Memory(0x3A0)));
+        assert_eq!(Metric::parse("mem[928]"), Some(Metric::Memory(0x3A0)));
+        assert_eq!(Metric::parse("vf"), Some(Metric::V(0xF)));
+        assert_eq!(Metric::parse("mem[0x1000]"), None);
+        assert_eq!(Metric::Memory(0x3A0).to_string(), "mem[0x3A0]");
+
+        // LD I, 0x300 / RND V0, 0x0F / LD [I], V0 / JP 0x206
+        let rom = vec![0xA3, 0x00, 0xC0, 0x0F, 0xF0, 0x55, 0x12, 0x06];
+        let mut montecarlo = MonteCarlo::new(rom, Metric::Memory(0x300));
+        montecarlo.runs = 40;
+        montecarlo.frames = 2;
+        let runs = montecarlo.run();
+        assert_eq!(runs.len(), 40);
+        assert_eq!(runs[7].seed, 8);
+        assert_eq!(runs[7], montecarlo.run_one(8), "the same whichever thread ran it");
+        assert_eq!(runs, montecarlo.run());
+        assert!(runs.iter().all(|run| run.value.as_ref().is_ok_and(|value| *value < 16)));
+
+        let values = [3, 1, 4, 1, 5];
+        let mut runs: Vec<Run> = values
+            .iter()
+            .enumerate()
+            .map(|(seed, value)| Run {
+                seed: seed as u64,
+                value: Ok(*value),
+            })
+            .collect();
+        runs.push(Run {
+            seed: 9,
+            value: Err("stopped".to_string()),
+        });
+        let distribution = Distribution::of(&runs);
+        assert_eq!((distribution.min(), distribution.max(), distribution.median()), (Some(1), Some(5), Some(3)));
+        assert_eq!(distribution.mean(), 2.8);
+        assert_eq!(distribution.histogram(), [(1, 1, 2), (2, 2, 0), (3, 3, 1), (4, 4, 1), (5, 5, 1)]);
+        let report = distribution.to_string();
+        assert!(report.starts_with("runs 6 (1 stopped)  min 1  max 5  mean 2.80  median 3  stddev 1.60\n"), "{}", report);
+        assert!(report.contains(&format!("          1  {}  2", "#".repeat(40))));
+
+        let wide = Distribution::of(&[0, 255].iter().map(|value| Run { seed: 0, value: Ok(*value) }).collect::<Vec<_>>());
+        assert_eq!(wide.histogram().len(), 16);
+        assert_eq!(wide.histogram()[15], (240, 255, 1));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::cpu::Cpu;
+use crate::errors::MovieError;
+use crate::headless;
+use crate::keypad::Keypad;
+use crate::profile::EmulationProfile;
+use crate::rng::{Draw, RandomSource, Replay, Rng};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "hachip-movie 2";
+// without random draws
+const HEADER_V1: &str = "hachip-movie 1";
+
+// An input recording: the keypad state of every frame since the ROM was
+// reset, with everything else a replay depends on. Replaying it on the
+// same ROM with the same profile and speed reproduces the run exactly.
+// Random bytes come from the logged draws, so the replay doesn't depend on
+// the generator that made them; the seed only matters for movies without
+// them.
+//
+// Saved as text:
+//   hachip-movie 2
+//   rom <FNV hash of the ROM>
+//   profile platform=vip quirks=...
+//   seed <Cxkk random seed>
+//   speed <instructions per frame>
+//   <keypad mask, bit n = key n, 4 hex digits> <frames>   (repeated)
+//   rnd <frame> <byte, 2 hex digits>                      (repeated)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub profile: EmulationProfile,
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+    frames: Vec<u16>,
+    pub draws: Vec<Draw>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], profile: EmulationProfile, seed: u64, instructions_per_frame: u32) -> Movie {
+        Movie {
+            rom_hash: headless::hash(rom),
+            profile,
+            seed,
+            instructions_per_frame,
+            frames: Vec::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    // Where a replay gets its random bytes.
+    pub fn random_source(&self) -> Box<dyn RandomSource> {
+        if self.draws.is_empty() {
+            Box::new(Rng::new(self.seed))
+        } else {
+            Box::new(Replay::new(self.draws.clone(), Rng::new(self.seed)))
+        }
+    }
+
+    // Appends the keypad state of the next frame.
+    pub fn record(&mut self, keypad: &Keypad) {
+        self.frames.push(keypad_mask(keypad));
+    }
+
+    // Sets the keypad to its state in `frame`. Returns false past the end.
+    pub fn play(&self, frame: usize, keypad: &mut Keypad) -> bool {
+        match self.frames.get(frame) {
+            Some(mask) => {
+                set_keypad(keypad, *mask);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Length in frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\nrom {:016x}\nprofile {}\nseed {}\nspeed {}\n",
+            HEADER, self.rom_hash, self.profile, self.seed, self.instructions_per_frame
+        );
+        let mut index = 0;
+        while index < self.frames.len() {
+            let mask = self.frames[index];
+            let count = self.frames[index..].iter().take_while(|frame| **frame == mask).count();
+            text.push_str(&format!("{:04x} {}\n", mask, count));
+            index += count;
+        }
+        for draw in &self.draws {
+            text.push_str(&format!("rnd {} {:02x}\n", draw.frame, draw.value));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines();
+        let header = lines.next();
+        if header != Some(HEADER) && header != Some(HEADER_V1) {
+            return Err(error("not a hachip movie".to_string()));
+        }
+        let mut field = |name: &str| -> Result<String, MovieError> {
+            let line = lines.next().unwrap_or_default();
+            match line.strip_prefix(name).and_then(|rest| rest.strip_prefix(' ')) {
+                Some(value) => Ok(value.to_string()),
+                None => Err(error(format!("expected `{}`, found `{}`", name, line))),
+            }
+        };
+        let rom_hash = field("rom")?;
+        let rom_hash = u64::from_str_radix(&rom_hash, 16).map_err(|_| error(format!("bad ROM hash {}", rom_hash)))?;
+        let profile = field("profile")?;
+        let profile = EmulationProfile::parse(&profile).ok_or_else(|| error(format!("bad profile {}", profile)))?;
+        let seed = field("seed")?;
+        let seed = seed.parse().map_err(|_| error(format!("bad seed {}", seed)))?;
+        let speed = field("speed")?;
+        let instructions_per_frame = speed.parse().map_err(|_| error(format!("bad speed {}", speed)))?;
+
+        let mut frames = Vec::new();
+        let mut draws = Vec::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            if let Some(draw) = line.strip_prefix("rnd ") {
+                let draw = draw.split_once(' ').and_then(|(frame, value)| {
+                    Some(Draw {
+                        frame: frame.parse().ok()?,
+                        value: u8::from_str_radix(value, 16).ok()?,
+                    })
+                });
+                match draw {
+                    Some(draw) => draws.push(draw),
+                    None => return Err(error(format!("bad random draw `{}`", line))),
+                }
+                continue;
+            }
+            let run = line.split_once(' ').and_then(|(mask, count)| {
+                Some((u16::from_str_radix(mask, 16).ok()?, count.parse::<usize>().ok()?))
+            });
+            match run {
+                Some((mask, count)) => frames.resize(frames.len() + count, mask),
+                None => return Err(error(format!("bad input line `{}`", line))),
+            }
+        }
+        Ok(Movie {
+            rom_hash,
+            profile,
+            seed,
+            instructions_per_frame,
+            frames,
+            draws,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, MovieError> {
+        let text = fs::read_to_string(path).map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+// The keys held down, bit n for key n.
+pub fn keypad_mask(keypad: &Keypad) -> u16 {
+    (0..16).filter(|key| keypad.is_key_down(*key)).fold(0, |mask, key| mask | 1 << key)
+}
+
+pub fn set_keypad(keypad: &mut Keypad, mask: u16) {
+    for key in 0..16 {
+        keypad.set_key(key, mask & 1 << key != 0);
+    }
+}
+
+fn error(message: String) -> MovieError {
+    MovieError { message }
+}
+
+// A movie being recorded or played back by a frontend.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum MovieMode {
+    // Recorded frame by frame, saved to the path when the frontend exits.
+    Record(Movie, PathBuf),
+    // Drives the keypad until it ends, then live input takes over.
+    Playback(Movie, usize),
+}
+
+impl MovieMode {
+    pub fn record(movie: Movie, path: PathBuf) -> MovieMode {
+        MovieMode::Record(movie, path)
+    }
+
+    pub fn playback(movie: Movie) -> MovieMode {
+        MovieMode::Playback(movie, 0)
+    }
+
+    // Call once on the freshly loaded cpu: a recording logs its random
+    // draws, a playback replays them.
+    pub fn start(&self, cpu: &mut Cpu) {
+        match self {
+            MovieMode::Record(..) => cpu.log_draws(),
+            MovieMode::Playback(movie, _) => cpu.set_rng(movie.random_source()),
+        }
+    }
+
+    // Call before running each frame.
+    pub fn frame(&mut self, cpu: &mut Cpu) {
+        match self {
+            MovieMode::Record(movie, _) => {
+                movie.draws.append(&mut cpu.take_draws());
+                movie.record(&cpu.keypad);
+            }
+            MovieMode::Playback(movie, next) => {
+                if movie.play(*next, &mut cpu.keypad) {
+                    *next += 1;
+                } else if *next == movie.len() {
+                    log::info!("movie playback finished after {} frames", next);
+                    *next += 1;
+                }
+            }
+        }
+    }
+
+    // Saves a recording, logging the outcome.
+    pub fn finish(&mut self, cpu: &mut Cpu) {
+        if let MovieMode::Record(movie, path) = self {
+            movie.draws.append(&mut cpu.take_draws());
+            match movie.save(path) {
+                Ok(()) => log::info!("saved {} frame movie {}", movie.len(), path.display()),
+                Err(error) => log::warn!("saving movie {} failed: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieMode};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::profile::EmulationProfile;
+    use crate::quirks::Quirks;
+    use crate::scheduler;
+    use std::path::PathBuf;
+
+    // RND V0, 0xFF / SKNP V1 / ADD V2, 1 / JP 0x200
+    const ROM: [u8; 8] = [0xC0, 0xFF, 0xE1, 0xA1, 0x72, 0x01, 0x12, 0x00];
+
+    fn new_cpu(seed: u64) -> Cpu {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(ROM.to_vec()).unwrap();
+        cpu.seed_rng(seed);
+        cpu
+    }
+
+    fn run(movie: &Movie, seed: u64) -> Vec<u8> {
+        let mut cpu = new_cpu(seed);
+        let mut mode = MovieMode::playback(movie.clone());
+        mode.start(&mut cpu);
+        for _ in 0..movie.len() {
+            mode.frame(&mut cpu);
+            scheduler::run_frame(&mut cpu, movie.instructions_per_frame).unwrap();
+        }
+        cpu.registers()[..3].to_vec()
+    }
+
+    #[test]
+    fn replays_are_deterministic_and_survive_saving() {
+        let movie = Movie::new(&ROM, EmulationProfile::new(Quirks::default()), 7, 10);
+        let mut cpu = new_cpu(movie.seed);
+        let mut mode = MovieMode::record(movie, PathBuf::new());
+        mode.start(&mut cpu);
+        for frame in 0..30 {
+            cpu.keypad.set_key(0, frame >= 10);
+            mode.frame(&mut cpu);
+            scheduler::run_frame(&mut cpu, 10).unwrap();
+        }
+        let recorded = cpu.registers()[..3].to_vec();
+        let mut movie = match mode {
+            MovieMode::Record(movie, _) => movie,
+            _ => unreachable!(),
+        };
+        movie.draws.append(&mut cpu.take_draws());
+
+        let text = movie.to_text();
+        assert!(text.contains("0000 10\n0001 20\nrnd 0 "), "{}", text);
+        let loaded = Movie::parse(&text).unwrap();
+        assert_eq!(loaded, movie);
+        assert_eq!(run(&loaded, 7), recorded);
+        // the logged draws replace the generator
+        assert_eq!(run(&loaded, 8), recorded);
+        assert!(Movie::parse("hachip-movie 1\nrom zz").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Frames of input delay unless the host picks another; each side's keys
+// take this long to reach the game, to hide the network's latency.
+pub const DEFAULT_DELAY: u32 = 2;
+const MAGIC: &[u8; 4] = b"HCNP";
+const VERSION: u8 = 2;
+// A peer silent for this long (paused, gone) ends the session.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Which end of the connection this instance is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    // listen on this address for the other player
+    Host(String),
+    // connect to the host at this address
+    Join(String),
+}
+
+// What both sides must agree on for their games to stay in step. The
+// host's seed and delay are the ones played with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub rom_hash: u64,
+    // as printed by EmulationProfile
+    pub profile: String,
+    pub instructions_per_frame: u32,
+    pub seed: u64,
+    pub delay: u32,
+}
+
+impl Hello {
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.extend_from_slice(&self.instructions_per_frame.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.delay.to_le_bytes());
+        bytes.extend_from_slice(&(self.profile.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.profile.as_bytes());
+        writer.write_all(&bytes)
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Hello> {
+        let mut head = [0; 31];
+        reader.read_exact(&mut head)?;
+        if &head[..4] != MAGIC || head[4] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the other side isn't a compatible hachip"));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes([head[at], head[at + 1], head[at + 2], head[at + 3]]);
+        let u64_at = |at: usize| (u32_at(at) as u64) | (u32_at(at + 4) as u64) << 32;
+        let mut profile = vec![0; u16::from_le_bytes([head[29], head[30]]) as usize];
+        reader.read_exact(&mut profile)?;
+        Ok(Hello {
+            rom_hash: u64_at(5),
+            instructions_per_frame: u32_at(13),
+            seed: u64_at(17),
+            delay: u32_at(25),
+            profile: String::from_utf8_lossy(&profile).to_string(),
+        })
+    }
+
+    // Why the other side's game would drift from ours, if it would.
+    fn mismatch(&self, other: &Hello) -> Option<String> {
+        if self.rom_hash != other.rom_hash {
+            Some("the other side runs a different ROM".to_string())
+        } else if self.profile != other.profile {
+            Some(format!("the other side runs {}, this side {}", other.profile, self.profile))
+        } else if self.instructions_per_frame != other.instructions_per_frame {
+            Some(format!(
+                "the other side runs {} instructions per frame, this side {}",
+                other.instructions_per_frame, self.instructions_per_frame
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+struct Link {
+    stream: TcpStream,
+    frame: u64,
+    // keys sent but not played yet, oldest first
+    local: VecDeque<u16>,
+    remote: VecDeque<u16>,
+}
+
+// `--netplay-host` / `--netplay-join`: two instances playing one game in
+// lockstep. Before every frame each side sends its keys and waits for the
+// other's, and both run the frame with the keys of both, `delay` frames
+// after they were pressed. With the same ROM, settings and seed that keeps
+// the games identical; a hash of the machine state goes along to notice
+// when they aren't. Clones share the connection.
+#[derive(Clone)]
+pub struct Netplay {
+    link: Arc<Mutex<Link>>,
+}
+
+impl Netplay {
+    // Waits for the other player (or connects to the host) and checks both
+    // run the same game. Returns the settings to play with.
+    pub fn connect(role: &Role, hello: &Hello) -> io::Result<(Netplay, Hello)> {
+        match role {
+            Role::Host(address) => {
+                let listener = TcpListener::bind(address)?;
+                let (stream, peer) = listener.accept()?;
+                log::info!("netplay: {} joined", peer);
+                Netplay::start(stream, true, hello)
+            }
+            Role::Join(address) => Netplay::start(TcpStream::connect(address)?, false, hello),
+        }
+    }
+
+    fn start(mut stream: TcpStream, host: bool, hello: &Hello) -> io::Result<(Netplay, Hello)> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        hello.write(&mut stream)?;
+        let other = Hello::read(&mut stream)?;
+        if let Some(mismatch) = hello.mismatch(&other) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, mismatch));
+        }
+        let agreed = if host { hello.clone() } else { other };
+        let delay: VecDeque<u16> = (0..agreed.delay).map(|_| 0).collect();
+        let link = Link {
+            stream,
+            frame: 0,
+            local: delay.clone(),
+            remote: delay,
+        };
+        let netplay = Netplay {
+            link: Arc::new(Mutex::new(link)),
+        };
+        Ok((netplay, agreed))
+    }
+
+    // Call before every frame with this side's keys and its
+    // `Cpu::state_hash`; returns the keys to run the frame with. Fails when
+    // the other side goes away or its state differs.
+    pub fn exchange(&self, keys: &[bool; 16], state: u64) -> io::Result<[bool; 16]> {
+        let mut link = self.link.lock().unwrap();
+        let pressed = keys.iter().enumerate().fold(0u16, |bits, (key, down)| bits | (*down as u16) << key);
+        let mut message = [0; 10];
+        message[..2].copy_from_slice(&pressed.to_le_bytes());
+        message[2..].copy_from_slice(&state.to_le_bytes());
+        link.stream.write_all(&message)?;
+        let mut reply = [0; 10];
+        link.stream.read_exact(&mut reply)?;
+
+        let frame = link.frame;
+        link.frame += 1;
+        if reply[2..] != message[2..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the games drifted apart at frame {}", frame)));
+        }
+        link.local.push_back(pressed);
+        link.remote.push_back(u16::from_le_bytes([reply[0], reply[1]]));
+        let both = link.local.pop_front().unwrap_or(0) | link.remote.pop_front().unwrap_or(0);
+        let mut keys = [false; 16];
+        for (key, down) in keys.iter_mut().enumerate() {
+            *down = both & (1 << key) != 0;
+        }
+        Ok(keys)
+    }
+}
+
+impl PartialEq for Netplay {
+    fn eq(&self, other: &Netplay) -> bool {
+        Arc::ptr_eq(&self.link, &other.link)
+    }
+}
+
+impl fmt::Debug for Netplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Netplay {{ frame: {} }}", self.link.lock().unwrap().frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hello, Netplay, Role};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    fn hello(seed: u64) -> Hello {
+        Hello {
+            rom_hash: 0xC0FFEE,
+            profile: "vip".to_string(),
+            instructions_per_frame: 10,
+            seed,
+            delay: 2,
+        }
+    }
+
+    #[test]
+    fn plays_both_sides_keys_in_lockstep() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let guest = thread::spawn(move || {
+            let (netplay, agreed) = Netplay::connect(&Role::Join(address), &hello(7)).unwrap();
+            assert_eq!(agreed.seed, 1, "the host's seed");
+            let mut keys = [false; 16];
+            keys[0xC] = true;
+            (0..3).map(|frame| netplay.exchange(&keys, frame).unwrap()).collect::<Vec<_>>()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let (netplay, agreed) = Netplay::start(stream, true, &hello(1)).unwrap();
+        assert_eq!(agreed, hello(1));
+        let mut keys = [false; 16];
+        keys[0x1] = true;
+        let host: Vec<[bool; 16]> = (0..3).map(|frame| netplay.exchange(&keys, frame).unwrap()).collect();
+        assert_eq!(host, guest.join().unwrap(), "both sides play the same keys");
+        assert_eq!(host[1], [false; 16], "pressed keys wait out the delay");
+        assert!(host[2][0x1] && host[2][0xC]);
+
+        let address = listener.local_addr().unwrap().to_string();
+        let guest = thread::spawn(move || {
+            let (netplay, _) = Netplay::connect(&Role::Join(address), &hello(1)).unwrap();
+            netplay.exchange(&[false; 16], 2).unwrap_err().to_string()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let (netplay, _) = Netplay::start(stream, true, &hello(1)).unwrap();
+        let error = netplay.exchange(&[false; 16], 3).unwrap_err().to_string();
+        assert_eq!(error, "the games drifted apart at frame 0", "a different screen");
+        assert_eq!(guest.join().unwrap(), error);
+
+        let address = listener.local_addr().unwrap();
+        let guest = thread::spawn(move || {
+            let other = Hello {
+                profile: "schip".to_string(),
+                ..hello(1)
+            };
+            Netplay::start(TcpStream::connect(address).unwrap(), false, &other).is_err()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let error = Netplay::start(stream, true, &hello(1)).unwrap_err().to_string();
+        assert_eq!(error, "the other side runs schip, this side vip");
+        assert!(guest.join().unwrap());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+
+// Metadata of every instruction the cpu decodes, for the `hachip opcodes`
+// reference and tooling. Tests in cpu.rs and instruction.rs check that the
+// table accepts exactly the opcodes the decoder does.
+pub struct Opcode {
+    // nibble pattern like "8xy4"
+    pub pattern: &'static str,
+    // an opcode matches when opcode & mask == value
+    pub mask: u16,
+    pub value: u16,
+    pub mnemonic: &'static str,
+    pub description: &'static str,
+    // the quirks that change it
+    pub quirks: &'static [QuirkNote],
+}
+
+// How a quirk changes an instruction.
+pub struct QuirkNote {
+    // name as in the emulation profile
+    pub name: &'static str,
+    pub enabled: &'static str,
+    pub disabled: &'static str,
+    pub flag: fn(&Quirks) -> bool,
+}
+
+impl QuirkNote {
+    pub fn behaviour(&self, quirks: &Quirks) -> &'static str {
+        if (self.flag)(quirks) {
+            self.enabled
+        } else {
+            self.disabled
+        }
+    }
+}
+
+const fn op(pattern: &'static str, mask: u16, value: u16, mnemonic: &'static str, description: &'static str) -> Opcode {
+    Opcode {
+        pattern,
+        mask,
+        value,
+        mnemonic,
+        description,
+        quirks: &[],
+    }
+}
+
+const VF_RESET: QuirkNote = QuirkNote {
+    name: "vf-reset",
+    enabled: "VF is reset to 0",
+    disabled: "VF is left unchanged",
+    flag: |quirks| quirks.vf_reset,
+};
+
+const VF_RESULT: QuirkNote = QuirkNote {
+    name: "vf-result",
+    enabled: "with x = F, VF keeps the result",
+    disabled: "with x = F, VF keeps the flag",
+    flag: |quirks| quirks.vf_result,
+};
+
+const SHIFT_VY: QuirkNote = QuirkNote {
+    name: "shift-vy",
+    enabled: "shifts Vy into Vx",
+    disabled: "shifts Vx in place",
+    flag: |quirks| quirks.shift_vy,
+};
+
+const LOAD_STORE: QuirkNote = QuirkNote {
+    name: "load-store-increment-i",
+    enabled: "I is left at I + x + 1",
+    disabled: "I is left unchanged",
+    flag: |quirks| quirks.load_store_increment_i,
+};
+
+const INCREMENT_BY_X: QuirkNote = QuirkNote {
+    name: "increment-i-by-x",
+    enabled: "with load-store-increment-i, I is left at I + x",
+    disabled: "with load-store-increment-i, I is left at I + x + 1",
+    flag: |quirks| quirks.increment_i_by_x,
+};
+
+// Mega-Chip's own instructions.
+const MEGACHIP: QuirkNote = QuirkNote {
+    name: "megachip",
+    enabled: "supported",
+    disabled: "rejected as an unknown opcode",
+    flag: |quirks| quirks.megachip,
+};
+
+const fn mega(pattern: &'static str, mask: u16, value: u16, mnemonic: &'static str, description: &'static str) -> Opcode {
+    with_quirks(op(pattern, mask, value, mnemonic, description), &[MEGACHIP])
+}
+
+const fn with_quirks(opcode: Opcode, quirks: &'static [QuirkNote]) -> Opcode {
+    Opcode {
+        quirks,
+        ..opcode
+    }
+}
+
+pub const OPCODES: [Opcode; 48] = [
+    mega("0010", 0xFFFF, 0x0010, "MEGAOFF", "Switch back to the CHIP-8 screen"),
+    mega("0011", 0xFFFF, 0x0011, "MEGAON", "Switch to the 256x192 Mega-Chip screen"),
+    mega("00Bn", 0xFFF0, 0x00B0, "SCRU n", "Scroll the Mega-Chip screen up n lines"),
+    with_quirks(
+        op("00E0", 0xFFFF, 0x00E0, "CLS", "Clear the display"),
+        &[QuirkNote {
+            name: "megachip",
+            enabled: "on the Mega-Chip screen, shows what was drawn and clears it for the next frame",
+            disabled: "clears the CHIP-8 screen",
+            flag: |quirks| quirks.megachip,
+        }],
+    ),
+    op("00EE", 0xFFFF, 0x00EE, "RET", "Return from a subroutine"),
+    mega("01nn", 0xFF00, 0x0100, "LDHI nn", "Set I = nn and the 16 bits after the instruction"),
+    mega("02nn", 0xFF00, 0x0200, "LDPAL nn", "Load nn palette colours, 4 bytes ARGB each, from I"),
+    mega("03nn", 0xFF00, 0x0300, "SPRW nn", "Set the sprite width to nn, 0 for 256"),
+    mega("04nn", 0xFF00, 0x0400, "SPRH nn", "Set the sprite height to nn, 0 for 256"),
+    mega("05nn", 0xFF00, 0x0500, "ALPHA nn", "Set the brightness of the Mega-Chip screen"),
+    mega("060n", 0xFFF0, 0x0600, "DIGISND n", "Play the sample at I, looping when n = 0"),
+    mega("0700", 0xFFFF, 0x0700, "STOPSND", "Stop the sample"),
+    // modes past 5 draw normally
+    mega("080n", 0xFFF0, 0x0800, "BMODE n", "Blend sprites normally, at 25/50/75%, added or multiplied (n = 0-5)"),
+    mega("09nn", 0xFF00, 0x0900, "CCOL nn", "Set the colour sprites collide with"),
+    op("1nnn", 0xF000, 0x1000, "JP nnn", "Jump to nnn"),
+    op("2nnn", 0xF000, 0x2000, "CALL nnn", "Call the subroutine at nnn"),
+    op("3xkk", 0xF000, 0x3000, "SE Vx, kk", "Skip the next instruction if Vx == kk"),
+    op("4xkk", 0xF000, 0x4000, "SNE Vx, kk", "Skip the next instruction if Vx != kk"),
+    // the decoder ignores the last nibble of 5xy0 and 9xy0
+    op("5xy0", 0xF000, 0x5000, "SE Vx, Vy", "Skip the next instruction if Vx == Vy"),
+    op("6xkk", 0xF000, 0x6000, "LD Vx, kk", "Set Vx = kk"),
+    op("7xkk", 0xF000, 0x7000, "ADD Vx, kk", "Set Vx = Vx + kk, VF unchanged"),
+    op("8xy0", 0xF00F, 0x8000, "LD Vx, Vy", "Set Vx = Vy"),
+    with_quirks(op("8xy1", 0xF00F, 0x8001, "OR Vx, Vy", "Set Vx = Vx | Vy"), &[VF_RESET]),
+    with_quirks(op("8xy2", 0xF00F, 0x8002, "AND Vx, Vy", "Set Vx = Vx & Vy"), &[VF_RESET]),
+    with_quirks(op("8xy3", 0xF00F, 0x8003, "XOR Vx, Vy", "Set Vx = Vx ^ Vy"), &[VF_RESET]),
+    with_quirks(op("8xy4", 0xF00F, 0x8004, "ADD Vx, Vy", "Set Vx = Vx + Vy, VF = carry"), &[VF_RESULT]),
+    with_quirks(op("8xy5", 0xF00F, 0x8005, "SUB Vx, Vy", "Set Vx = Vx - Vy, VF = not borrow"), &[VF_RESULT]),
+    with_quirks(op("8xy6", 0xF00F, 0x8006, "SHR Vx", "Set Vx = Vx >> 1, VF = shifted out bit"), &[SHIFT_VY, VF_RESULT]),
+    with_quirks(op("8xy7", 0xF00F, 0x8007, "SUBN Vx, Vy", "Set Vx = Vy - Vx, VF = not borrow"), &[VF_RESULT]),
+    with_quirks(op("8xyE", 0xF00F, 0x800E, "SHL Vx", "Set Vx = Vx << 1, VF = shifted out bit"), &[SHIFT_VY, VF_RESULT]),
+    op("9xy0", 0xF000, 0x9000, "SNE Vx, Vy", "Skip the next instruction if Vx != Vy"),
+    op("Annn", 0xF000, 0xA000, "LD I, nnn", "Set I = nnn"),
+    with_quirks(
+        op("Bnnn", 0xF000, 0xB000, "JP V0, nnn", "Jump to nnn + V0"),
+        &[QuirkNote {
+            name: "jump-vx",
+            enabled: "read as Bxnn, jumps to xnn + Vx",
+            disabled: "jumps to nnn + V0",
+            flag: |quirks| quirks.jump_vx,
+        }],
+    ),
+    op("Cxkk", 0xF000, 0xC000, "RND Vx, kk", "Set Vx = random byte & kk"),
+    with_quirks(
+        op("Dxyn", 0xF000, 0xD000, "DRW Vx, Vy, n", "Draw n sprite rows from I at (Vx, Vy), VF = collision"),
+        &[
+            QuirkNote {
+                name: "display-wait",
+                enabled: "waits for the vertical blank first",
+                disabled: "draws immediately",
+                flag: |quirks| quirks.display_wait,
+            },
+            QuirkNote {
+                name: "clip-sprites",
+                enabled: "sprites are cut off at the edges",
+                disabled: "sprites wrap around the edges",
+                flag: |quirks| quirks.clip_sprites,
+            },
+            QuirkNote {
+                name: "megachip",
+                enabled: "on the Mega-Chip screen, draws the SPRW x SPRH palette indices at I, cut off at the edges",
+                disabled: "draws on the CHIP-8 screen",
+                flag: |quirks| quirks.megachip,
+            },
+        ],
+    ),
+    op("Ex9E", 0xF0FF, 0xE09E, "SKP Vx", "Skip the next instruction if key Vx is down"),
+    op("ExA1", 0xF0FF, 0xE0A1, "SKNP Vx", "Skip the next instruction if key Vx is up"),
+    op("Fx07", 0xF0FF, 0xF007, "LD Vx, DT", "Set Vx = delay timer"),
+    op("Fx0A", 0xF0FF, 0xF00A, "LD Vx, K", "Wait for a key to be pressed and released, store it in Vx"),
+    op("Fx15", 0xF0FF, 0xF015, "LD DT, Vx", "Set delay timer = Vx"),
+    op("Fx18", 0xF0FF, 0xF018, "LD ST, Vx", "Set sound timer = Vx"),
+    op("Fx1E", 0xF0FF, 0xF01E, "ADD I, Vx", "Set I = I + Vx"),
+    op("Fx29", 0xF0FF, 0xF029, "LD F, Vx", "Set I = address of the font glyph for Vx"),
+    op("Fx33", 0xF0FF, 0xF033, "LD B, Vx", "Store the BCD digits of Vx at I, I+1, I+2"),
+    with_quirks(op("Fx55", 0xF0FF, 0xF055, "LD [I], Vx", "Store V0..Vx at I"), &[LOAD_STORE, INCREMENT_BY_X]),
+    with_quirks(op("Fx65", 0xF0FF, 0xF065, "LD Vx, [I]", "Load V0..Vx from I"), &[LOAD_STORE, INCREMENT_BY_X]),
+    // SUPER-CHIP's, kept on disk per ROM by window frontends
+    op("Fx75", 0xF0FF, 0xF075, "LD R, Vx", "Store V0..Vx in the RPL user flags"),
+    op("Fx85", 0xF0FF, 0xF085, "LD Vx, R", "Load V0..Vx from the RPL user flags"),
+];
+
+// The table entry decoding `opcode`, None for opcodes the cpu rejects.
+pub fn lookup(opcode: u16) -> Option<&'static Opcode> {
+    OPCODES.iter().find(|entry| opcode & entry.mask == entry.value)
+}
+
+// Assembly text of an opcode, like "ADD V3, #01", or "DW #XXXX" for data
+// the cpu would reject.
+pub fn disassemble(opcode: u16) -> String {
+    match Instruction::decode(opcode) {
+        Some(instruction) => instruction.to_string(),
+        None => format!("DW #{:04X}", opcode),
+    }
+}
+
+// The opcode table as text, with quirk dependent behaviour resolved for
+// `quirks`.
+pub fn reference(quirks: &Quirks) -> String {
+    let mut text = String::new();
+    for entry in OPCODES.iter() {
+        text.push_str(&format!("{}  {:<15}{}", entry.pattern, entry.mnemonic, entry.description));
+        for quirk in entry.quirks {
+            text.push_str(&format!("; {} ({})", quirk.behaviour(quirks), quirk.name));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    #[test]
+    fn disassembles_operands() {
+        assert_eq!(disassemble(0x7301), "ADD V3, #01");
+        assert_eq!(disassemble(0xD125), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0x2ABC), "CALL #ABC");
+        assert_eq!(disassemble(0xF265), "LD V2, [I]");
+        assert_eq!(disassemble(0xE1FF), "DW #E1FF");
+    }
+}
@@ -0,0 +1,123 @@
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::text::{self, Overlay, LINE_HEIGHT};
+use std::time::{Duration, Instant};
+
+// How long a message stays up.
+pub const SHOW_TIME: Duration = Duration::from_secs(2);
+// Lines a message may wrap to; the rest is cut.
+const MAX_LINES: usize = 2;
+
+// Transient messages for hotkeys ("PAUSED", "SPEED 200%"), drawn over the
+// bottom of the game as dark text on a lit band, and a corner box that
+// stays up until replaced (the speed meter). Frontends mix `pixel` into
+// what they upload, so the framebuffer, screenshots and recordings never
+// see either.
+#[derive(Clone, Default)]
+pub struct Osd {
+    // the band with the message, and when it went up
+    shown: Option<(Overlay, Instant)>,
+    // first row of the band
+    top: usize,
+    // the corner box, and its width and height
+    corner: Option<(Overlay, usize, usize)>,
+    changed: bool,
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd::default()
+    }
+
+    // Replaces whatever is shown.
+    pub fn show(&mut self, message: &str) {
+        self.show_at(message, Instant::now());
+    }
+
+    fn show_at(&mut self, message: &str, now: Instant) {
+        log::info!("{}", message);
+        let lines: Vec<String> = text::wrap(message, WIDTH - 2).into_iter().take(MAX_LINES).collect();
+        self.top = HEIGHT - lines.len() * LINE_HEIGHT - 1;
+        let mut overlay = Overlay::default();
+        let mut renderer = overlay.text().inverted();
+        renderer.fill(0, self.top, WIDTH, HEIGHT - self.top);
+        for (index, line) in lines.iter().enumerate() {
+            renderer.draw_text(1, (self.top + 1 + index * LINE_HEIGHT) as i32, line);
+        }
+        self.shown = Some((overlay, now));
+        self.changed = true;
+    }
+
+    // Puts `text` in a box at the top left, or takes the box down.
+    pub fn set_corner(&mut self, text: Option<&str>) {
+        self.corner = text.map(|text| {
+            let width = text::text_width(text) + 2;
+            let height = text::text_height(text) + 2;
+            let mut overlay = Overlay::default();
+            let mut renderer = overlay.text().inverted();
+            renderer.fill(0, 0, width, height);
+            renderer.draw_text(1, 1, text);
+            (overlay, width, height)
+        });
+        self.changed = true;
+    }
+
+    // Takes down an expired message. Returns whether what is shown changed
+    // since the last call, for the frontend to redraw.
+    pub fn update(&mut self) -> bool {
+        self.update_at(Instant::now())
+    }
+
+    fn update_at(&mut self, now: Instant) -> bool {
+        if let Some((_, shown)) = &self.shown {
+            if now.duration_since(*shown) >= SHOW_TIME {
+                self.shown = None;
+                self.changed = true;
+            }
+        }
+        std::mem::take(&mut self.changed)
+    }
+
+    // The message's pixel at (x, y), None where the game shows through.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        match (&self.corner, &self.shown) {
+            (Some((overlay, width, height)), _) if x < *width && y < *height => overlay.pixels().get(y * WIDTH + x).copied(),
+            (_, Some((overlay, _))) if y >= self.top => overlay.pixels().get(y * WIDTH + x).copied(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Osd, SHOW_TIME};
+    use crate::ppu::HEIGHT;
+    use std::time::Instant;
+
+    #[test]
+    fn shows_a_message_for_a_while() {
+        let mut osd = Osd::new();
+        assert!(!osd.update());
+        assert_eq!(osd.pixel(0, HEIGHT - 1), None);
+
+        let now = Instant::now();
+        osd.show_at("state saved to slot 1", now);
+        assert!(osd.update_at(now));
+        assert!(!osd.update_at(now), "changed once");
+        // two lines on a lit band, the game above it
+        assert_eq!(osd.pixel(0, HEIGHT - 14), None);
+        assert_eq!(osd.pixel(0, HEIGHT - 13), Some(1));
+        assert_eq!(osd.pixel(2, HEIGHT - 12), Some(0), "the S of STATE");
+        assert_eq!(osd.pixel(2, HEIGHT - 6), Some(0), "the S of SLOT");
+
+        assert!(osd.update_at(now + SHOW_TIME));
+        assert_eq!(osd.pixel(0, HEIGHT - 1), None);
+
+        osd.set_corner(Some("60 FPS"));
+        assert!(osd.update_at(now + SHOW_TIME));
+        assert_eq!(osd.pixel(0, 0), Some(1));
+        assert_eq!(osd.pixel(0, 20), None, "below the box");
+        assert!(!osd.update_at(now + SHOW_TIME * 10), "stays up");
+        osd.set_corner(None);
+        assert_eq!(osd.pixel(0, 0), None);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::assets;
+use crate::ppu::Color;
+
+// Colors for each framebuffer value: 0 is the background and 1 a lit pixel.
+// XO-CHIP's second plane will use 2 (plane 2 only) and 3 (both planes); the
+// two-color palettes repeat the foreground there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub fn new(background: Color, foreground: Color) -> Palette {
+        Palette {
+            colors: [background, foreground, foreground, foreground],
+        }
+    }
+
+    pub fn from_rgb(colors: [u32; 4]) -> Palette {
+        Palette {
+            colors: [
+                Color::from_rgb(colors[0]),
+                Color::from_rgb(colors[1]),
+                Color::from_rgb(colors[2]),
+                Color::from_rgb(colors[3]),
+            ],
+        }
+    }
+
+    // One of the palettes bundled in assets/palettes.hex.
+    pub fn builtin(name: &str) -> Option<Palette> {
+        assets::palette(name).map(Palette::from_rgb)
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    pub fn set_background(&mut self, color: Color) {
+        self.colors[0] = color;
+    }
+
+    pub fn set_foreground(&mut self, color: Color) {
+        self.colors[1] = color;
+    }
+
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[(pixel & 0x3) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new(Color::rgb(0, 0, 0), Color::rgb(255, 255, 255))
+    }
+}
+
+// Looks applied to the palette before drawing, for users who find full
+// white pixels harsh or prefer the dark-on-light look of some classic
+// setups: inverted video swaps the background and the lit color, `on_alpha`
+// blends lit pixels into the background and `brightness` scales every color.
+// Both are percentages; 100 leaves colors as they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Adjustments {
+    pub inverted: bool,
+    // 0 to MAX_BRIGHTNESS
+    pub brightness: u8,
+    // 0 to 100
+    pub on_alpha: u8,
+}
+
+pub const MAX_BRIGHTNESS: u8 = 200;
+
+impl Adjustments {
+    pub fn apply(&self, palette: &Palette) -> Palette {
+        let (background, foreground) = (palette.background(), palette.foreground());
+        let mut colors = palette.colors;
+        if self.inverted {
+            // lit colors equal to the foreground (two-color palettes) turn
+            // into the background too
+            for color in colors.iter_mut() {
+                *color = if *color == background {
+                    foreground
+                } else if *color == foreground {
+                    background
+                } else {
+                    *color
+                };
+            }
+        }
+        let background = colors[0];
+        for color in colors[1..].iter_mut() {
+            *color = mix(background, *color, self.on_alpha as u32, 100);
+        }
+        for color in colors.iter_mut() {
+            let scale = |channel: u8| (channel as u32 * self.brightness as u32 / 100).min(255) as u8;
+            *color = Color::rgb(scale(color.r), scale(color.g), scale(color.b));
+        }
+        Palette { colors }
+    }
+}
+
+impl Default for Adjustments {
+    fn default() -> Adjustments {
+        Adjustments {
+            inverted: false,
+            brightness: 100,
+            on_alpha: 100,
+        }
+    }
+}
+
+// `from` blended into `to` by amount/total.
+pub fn mix(from: Color, to: Color, amount: u32, total: u32) -> Color {
+    let channel = |from: u8, to: u8| ((from as u32 * (total - amount) + to as u32 * amount) / total) as u8;
+    Color::rgb(channel(from.r, to.r), channel(from.g, to.g), channel(from.b, to.b))
+}
+
+// Parses "RRGGBB", optionally prefixed with '#'.
+pub fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(Color::from_rgb)
+}
+
+// Parses a palette line: a background and a foreground color, optionally
+// followed by the plane 2 and both-planes colors.
+pub fn parse_palette(value: &str) -> Option<Palette> {
+    let colors = value
+        .split_whitespace()
+        .map(parse_color)
+        .collect::<Option<Vec<Color>>>()?;
+    match colors.len() {
+        2 => Some(Palette::new(colors[0], colors[1])),
+        4 => Some(Palette {
+            colors: [colors[0], colors[1], colors[2], colors[3]],
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_color, parse_palette, Adjustments, Palette};
+    use crate::ppu::Color;
+
+    #[test]
+    fn builtin_palettes_match_the_default() {
+        assert_eq!(Palette::builtin("classic"), Some(Palette::default()));
+        assert_eq!(Palette::builtin("amber").unwrap().color(1), Color::rgb(0xFF, 0xB0, 0x00));
+        assert_eq!(Palette::builtin("missing"), None);
+    }
+
+    #[test]
+    fn parses_colors_and_palettes() {
+        assert_eq!(parse_color("#102030"), Some(Color::rgb(0x10, 0x20, 0x30)));
+        assert_eq!(parse_color("FFFFFF"), Some(Color::rgb(0xFF, 0xFF, 0xFF)));
+        assert_eq!(parse_color("FFF"), None);
+        assert_eq!(parse_color("GGGGGG"), None);
+
+        let palette = parse_palette("000000 #00FF00").unwrap();
+        assert_eq!(palette.color(3), Color::rgb(0, 0xFF, 0));
+        assert_eq!(parse_palette("000000 00FF00 0000FF FF0000").unwrap().color(2), Color::rgb(0, 0, 0xFF));
+        assert_eq!(parse_palette("000000"), None);
+    }
+
+    #[test]
+    fn adjustments_invert_dim_and_fade_lit_pixels() {
+        let palette = Palette::new(Color::rgb(0, 0, 0), Color::rgb(200, 100, 0));
+        assert_eq!(Adjustments::default().apply(&palette), palette);
+
+        let mut adjustments = Adjustments {
+            inverted: true,
+            ..Adjustments::default()
+        };
+        let inverted = adjustments.apply(&palette);
+        assert_eq!(inverted.background(), Color::rgb(200, 100, 0));
+        assert_eq!(inverted.color(3), Color::rgb(0, 0, 0), "every plane is inverted");
+
+        adjustments.inverted = false;
+        adjustments.on_alpha = 50;
+        adjustments.brightness = 150;
+        let adjusted = adjustments.apply(&palette);
+        assert_eq!(adjusted.foreground(), Color::rgb(150, 75, 0));
+        assert_eq!(adjusted.background(), Color::rgb(0, 0, 0));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::errors::PatchError;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"PATCH";
+const END: &[u8] = b"EOF";
+
+// A ROM patch in the IPS format: "PATCH", then records of a 3-byte offset,
+// a 2-byte length and that many bytes (a zero length is a run instead: a
+// 2-byte count and the byte to repeat), then "EOF" and optionally the
+// 3-byte length to truncate the result to. Offsets past the end of the ROM
+// grow it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patch {
+    // offset and bytes, runs expanded
+    records: Vec<(usize, Vec<u8>)>,
+    truncate: Option<usize>,
+}
+
+impl Patch {
+    pub fn parse(bytes: &[u8]) -> Result<Patch, PatchError> {
+        let mut rest = bytes.strip_prefix(MAGIC).ok_or_else(|| error("not an IPS patch".to_string()))?;
+        let mut records = Vec::new();
+        loop {
+            if let Some(tail) = rest.strip_prefix(END) {
+                let truncate = match tail.len() {
+                    0 => None,
+                    3 => Some(be(tail)),
+                    _ => return Err(error(format!("{} unexpected bytes after EOF", tail.len()))),
+                };
+                return Ok(Patch { records, truncate });
+            }
+            let offset = be(take(&mut rest, 3)?);
+            let data = match be(take(&mut rest, 2)?) {
+                0 => {
+                    let count = be(take(&mut rest, 2)?);
+                    vec![take(&mut rest, 1)?[0]; count]
+                }
+                length => take(&mut rest, length)?.to_vec(),
+            };
+            records.push((offset, data));
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Patch, PatchError> {
+        let bytes = fs::read(path).map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+        Patch::parse(&bytes)
+    }
+
+    pub fn apply(&self, rom: &[u8]) -> Vec<u8> {
+        let mut patched = rom.to_vec();
+        for (offset, data) in &self.records {
+            let end = offset + data.len();
+            if end > patched.len() {
+                patched.resize(end, 0);
+            }
+            patched[*offset..end].copy_from_slice(data);
+        }
+        if let Some(length) = self.truncate {
+            patched.truncate(length);
+        }
+        patched
+    }
+}
+
+// The next `count` bytes, or an error if the patch ends first.
+fn take<'a>(rest: &mut &'a [u8], count: usize) -> Result<&'a [u8], PatchError> {
+    if rest.len() < count {
+        return Err(error("patch ends in the middle of a record".to_string()));
+    }
+    let (taken, tail) = rest.split_at(count);
+    *rest = tail;
+    Ok(taken)
+}
+
+// A big-endian number.
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, byte| value << 8 | *byte as usize)
+}
+
+fn error(message: String) -> PatchError {
+    PatchError { message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Patch;
+
+    #[test]
+    fn applies_records_runs_and_truncation() {
+        let mut bytes = b"PATCH".to_vec();
+        // 2 bytes at 1
+        bytes.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        // a run of 3 0xCC at 5, past the end of the ROM
+        bytes.extend_from_slice(&[0, 0, 5, 0, 0, 0, 3, 0xCC]);
+        bytes.extend_from_slice(b"EOF");
+        let patch = Patch::parse(&bytes).unwrap();
+        assert_eq!(patch.apply(&[1, 2, 3, 4]), vec![1, 0xAA, 0xBB, 4, 0, 0xCC, 0xCC, 0xCC]);
+
+        bytes.extend_from_slice(&[0, 0, 3]);
+        assert_eq!(Patch::parse(&bytes).unwrap().apply(&[1, 2, 3, 4]), vec![1, 0xAA, 0xBB]);
+
+        assert!(Patch::parse(b"PATCH\0\0\x01\0\x05\xAA").is_err(), "cut short");
+        assert!(Patch::parse(b"hachip").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::bookmarks::{self, Bookmarks, MAX_NAME};
+use crate::cpu::Cpu;
+use crate::palette::Palette;
+use crate::ppu::{Display, HEIGHT, WIDTH};
+use crate::state::SaveState;
+use crate::text::{Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+
+// Menu rows under the title line.
+const ROWS: usize = HEIGHT / LINE_HEIGHT - 1;
+// Rows before the bookmarks.
+const RESUME: usize = 0;
+const NEW_BOOKMARK: usize = 1;
+const FIRST_BOOKMARK: usize = 2;
+
+// The menu F3 opens over a paused game: resume, bookmark the game under a
+// typed name, or jump to one of the ROM's bookmarks. It draws on the
+// emulated display, so the game is kept aside and put back on closing.
+pub struct PauseMenu {
+    game: SaveState,
+    bookmarks: Option<Bookmarks>,
+    names: Vec<String>,
+    selected: usize,
+    top: usize,
+    input: UiInput,
+    // the name typed so far while naming a new bookmark
+    naming: Option<String>,
+}
+
+impl PauseMenu {
+    // Without a bookmark directory only resuming is offered.
+    pub fn open(cpu: &Cpu, bookmarks: Option<Bookmarks>) -> PauseMenu {
+        PauseMenu {
+            game: cpu.save_state(),
+            names: bookmarks.as_ref().map(Bookmarks::names).unwrap_or_default(),
+            bookmarks,
+            selected: RESUME,
+            top: 0,
+            input: UiInput::new(),
+            naming: None,
+        }
+    }
+
+    // The game as it was when the menu opened.
+    pub fn game(&self) -> &SaveState {
+        &self.game
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_naming(&self) -> bool {
+        self.naming.is_some()
+    }
+
+    // Returns false once the menu has closed, with the cpu back in the game
+    // or at the picked bookmark.
+    pub fn key_down(&mut self, key: UiKey, cpu: &mut Cpu, palette: &Palette) -> bool {
+        if let Some(name) = &mut self.naming {
+            // typed text arrives through `type_char`, so hexpad keys are ignored
+            match key {
+                UiKey::Enter => self.add_bookmark(palette),
+                UiKey::Escape => self.naming = None,
+                UiKey::Backspace => {
+                    name.pop();
+                }
+                _ => {}
+            }
+            return true;
+        }
+        match self.input.key_down(key) {
+            Some(action) => self.act(action, cpu),
+            None => true,
+        }
+    }
+
+    // A character typed on the keyboard, used while naming a bookmark.
+    pub fn type_char(&mut self, c: char) {
+        if let Some(name) = &mut self.naming {
+            if bookmarks::is_name_char(c) && name.len() < MAX_NAME {
+                name.push(c);
+            }
+        }
+    }
+
+    fn act(&mut self, action: UiAction, cpu: &mut Cpu) -> bool {
+        match action {
+            UiAction::Confirm if self.selected == RESUME => {}
+            UiAction::Confirm if self.selected == NEW_BOOKMARK => {
+                if self.bookmarks.is_some() {
+                    self.naming = Some(String::new());
+                } else {
+                    log::info!("bookmarks are off, no data directory");
+                }
+                return true;
+            }
+            UiAction::Confirm => {
+                let name = &self.names[self.selected - FIRST_BOOKMARK];
+                let bookmarks = self.bookmarks.as_ref().expect("bookmarks are listed from their directory");
+                match bookmarks.load(name) {
+                    Ok(state) => {
+                        cpu.load_state(&state);
+                        log::info!("jumped to bookmark {}", name);
+                        return false;
+                    }
+                    Err(error) => {
+                        log::warn!("bookmark {}: {}", name, error);
+                        return true;
+                    }
+                }
+            }
+            UiAction::Back => {}
+            _ => {
+                self.move_selection(action);
+                return true;
+            }
+        }
+        cpu.load_state(&self.game);
+        false
+    }
+
+    fn add_bookmark(&mut self, palette: &Palette) {
+        let name = self.naming.take().unwrap_or_default();
+        let bookmarks = match &self.bookmarks {
+            Some(bookmarks) => bookmarks,
+            None => return,
+        };
+        match bookmarks.save(&name, &self.game, palette) {
+            Ok(path) => {
+                log::info!("saved bookmark {}", path.display());
+                self.names = bookmarks.names();
+                if let Some(index) = self.names.iter().position(|saved| *saved == name) {
+                    self.selected = FIRST_BOOKMARK + index;
+                    self.scroll();
+                }
+            }
+            Err(error) => log::warn!("bookmark {}: {}", name, error),
+        }
+    }
+
+    fn move_selection(&mut self, action: UiAction) {
+        let last = FIRST_BOOKMARK + self.names.len() - 1;
+        match action {
+            UiAction::Up => self.selected = self.selected.saturating_sub(1),
+            UiAction::Down => self.selected = (self.selected + 1).min(last),
+            UiAction::Left => self.selected = self.selected.saturating_sub(ROWS),
+            UiAction::Right => self.selected = (self.selected + ROWS).min(last),
+            _ => {}
+        }
+        self.scroll();
+    }
+
+    // Keeps the selection on screen.
+    fn scroll(&mut self) {
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + ROWS {
+            self.top = self.selected + 1 - ROWS;
+        }
+    }
+
+    // Draws the menu, or the name being typed, over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        match &self.naming {
+            Some(name) => {
+                text.draw_text(0, 0, "BOOKMARK NAME");
+                text.draw_text(0, LINE_HEIGHT as i32, &format!("{}_", name));
+            }
+            None => {
+                text.draw_text(0, 0, "PAUSED");
+                let rows = ["RESUME", "NEW BOOKMARK"].iter().copied().chain(self.names.iter().map(String::as_str));
+                for (row, label) in rows.enumerate().skip(self.top).take(ROWS) {
+                    let y = ((row - self.top + 1) * LINE_HEIGHT) as i32;
+                    let marker = if row == self.selected { '>' } else { ' ' };
+                    text.draw_text(0, y, &format!("{}{}", marker, label));
+                }
+            }
+        }
+
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+    }
+}
+
+impl UiScreen for PauseMenu {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.move_selection(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PauseMenu;
+    use crate::bookmarks::Bookmarks;
+    use crate::cpu::Cpu;
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::palette::Palette;
+    use crate::ui_input::UiKey;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn bookmarks_the_game_and_jumps_back() {
+        let dir = env::temp_dir().join(format!("hachip-pause-menu-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let palette = Palette::default();
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // ADD V0, 1 / JP 0x200
+        cpu.load(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu.step().unwrap();
+
+        let mut menu = PauseMenu::open(&cpu, Some(Bookmarks::new(dir.clone())));
+        menu.render(cpu.display_mut());
+        assert!(headless::dump_text(cpu.display().framebuffer()).contains('#'));
+        assert!(menu.key_down(UiKey::Down, &mut cpu, &palette));
+        assert!(menu.key_down(UiKey::Enter, &mut cpu, &palette));
+        assert!(menu.is_naming());
+        for c in "boss!".chars() {
+            menu.type_char(c);
+        }
+        assert!(menu.key_down(UiKey::Hex(0x5), &mut cpu, &palette), "hexpad keys type nothing");
+        assert!(menu.key_down(UiKey::Enter, &mut cpu, &palette));
+        assert_eq!(menu.names(), ["boss"]);
+        assert!(!menu.key_down(UiKey::Escape, &mut cpu, &palette), "Escape resumes");
+        assert_eq!(cpu.registers()[0], 1);
+        assert!(!cpu.display().framebuffer().contains(&1), "the game's screen is back");
+
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        let mut menu = PauseMenu::open(&cpu, Some(Bookmarks::new(dir.clone())));
+        assert!(menu.key_down(UiKey::Down, &mut cpu, &palette));
+        assert!(menu.key_down(UiKey::Down, &mut cpu, &palette));
+        assert!(!menu.key_down(UiKey::Enter, &mut cpu, &palette));
+        assert_eq!(cpu.registers()[0], 1, "back at the bookmark");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use crate::palette::Palette;
+use crate::ppu::{Color, HEIGHT, WIDTH};
+use crate::scheduler::FRAME_RATE;
+use std::time::Duration;
+
+// Ghosting filter against flicker: games erase sprites by XOR-drawing them
+// again every frame, so a pixel that turns off keeps glowing and fades to
+// the background over the decay time instead of vanishing at once. Only the
+// displayed colors change; the framebuffer the cpu sees stays exact.
+#[derive(Clone)]
+pub struct Phosphor {
+    // Brightness lost per 60Hz frame, out of 255.
+    step: u8,
+    levels: [u8; WIDTH * HEIGHT],
+    // Last lit value of each pixel, to fade in its own color.
+    values: [u8; WIDTH * HEIGHT],
+}
+
+impl Phosphor {
+    pub fn new(decay: Duration) -> Phosphor {
+        let frames = (decay.as_secs_f32() * FRAME_RATE as f32).max(1.0);
+        Phosphor {
+            step: (255.0 / frames).ceil().min(255.0) as u8,
+            levels: [0; WIDTH * HEIGHT],
+            values: [0; WIDTH * HEIGHT],
+        }
+    }
+
+    // Advances one frame. Returns true while any pixel is still fading, so
+    // frontends redraw even when the framebuffer did not change.
+    pub fn update(&mut self, framebuffer: &[u8]) -> bool {
+        let mut fading = false;
+        for (index, pixel) in framebuffer.iter().enumerate() {
+            if *pixel != 0 {
+                self.levels[index] = 255;
+                self.values[index] = *pixel;
+            } else if self.levels[index] > 0 {
+                self.levels[index] = self.levels[index].saturating_sub(self.step);
+                fading = true;
+            }
+        }
+        fading
+    }
+
+    pub fn color(&self, index: usize, pixel: u8, palette: &Palette) -> Color {
+        if pixel != 0 {
+            return palette.color(pixel);
+        }
+        let background = palette.background();
+        let lit = palette.color(self.values[index]);
+        let level = self.levels[index] as u32;
+        let mix = |from: u8, to: u8| ((from as u32 * (255 - level) + to as u32 * level) / 255) as u8;
+        Color::rgb(mix(background.r, lit.r), mix(background.g, lit.g), mix(background.b, lit.b))
+    }
+}
+
+// Display color of framebuffer pixel `index`, through the filter if there
+// is one.
+pub fn pixel_color(framebuffer: &[u8], index: usize, palette: &Palette, phosphor: Option<&Phosphor>) -> Color {
+    match phosphor {
+        Some(phosphor) => phosphor.color(index, framebuffer[index], palette),
+        None => palette.color(framebuffer[index]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Phosphor;
+    use crate::palette::Palette;
+    use crate::ppu::{Color, HEIGHT, WIDTH};
+    use std::time::Duration;
+
+    #[test]
+    fn turned_off_pixels_fade_out() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(Duration::from_millis(50));
+        let mut framebuffer = [0; WIDTH * HEIGHT];
+
+        framebuffer[0] = 1;
+        assert!(!phosphor.update(&framebuffer));
+        assert_eq!(phosphor.color(0, 1, &palette), Color::rgb(255, 255, 255));
+
+        framebuffer[0] = 0;
+        assert!(phosphor.update(&framebuffer));
+        let dimmed = phosphor.color(0, 0, &palette);
+        assert!(dimmed.r > 0 && dimmed.r < 255, "{:?}", dimmed);
+
+        // 50ms is three frames
+        phosphor.update(&framebuffer);
+        phosphor.update(&framebuffer);
+        assert_eq!(phosphor.color(0, 0, &palette), palette.background());
+        assert!(!phosphor.update(&framebuffer));
+    }
+}
@@ -0,0 +1,838 @@
+use crate::assets;
+use crate::autosave::Autosave;
+use crate::bookmarks::Bookmarks;
+use crate::command::{self, Command, RunState};
+use crate::cpu::Cpu;
+use crate::crt::CrtEffects;
+use crate::emulator::Status;
+use crate::error_screen::{ErrorScreen, Recovery};
+use crate::errors::EmulateCycleError;
+use crate::frontend::{self, FrontendOptions, LoopMode};
+use crate::input_macro::InputMacro;
+use crate::input_mapping::InputMapping;
+use crate::input_profile::InputProfile;
+use crate::keymap::{HostKey, KeyboardLayout, KeymapProfile};
+use crate::keypad::Keypad;
+use crate::launcher::{Choice, Launcher};
+use crate::megachip;
+use crate::osd::Osd;
+use crate::palette::Palette;
+use crate::pause_menu::PauseMenu;
+use crate::phosphor::{self, Phosphor};
+use crate::ppu::{self, Display, Ppu, Scaling, HEIGHT, WIDTH};
+use crate::playclock::PlayClock;
+use crate::present::PresentHooks;
+use crate::recent;
+use crate::recording::{self, Recording};
+use crate::rpl_flags::FlagStore;
+use crate::scheduler::{FrameLimiter, Scheduler, SpeedMeter};
+use crate::resume::{Resume, ResumePrompt};
+use crate::screenshot;
+use crate::slot_picker::SlotPicker;
+use crate::ui_input::{UiKey, UiScreen};
+use crate::watch_panel::{WatchPanel, PANEL_HEIGHT, PANEL_SCALE, PANEL_WIDTH};
+use crate::wizard::{Outcome, Wizard};
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Instant;
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::{Fullscreen, Icon, Window, WindowBuilder};
+
+// Scales the framebuffer into the RGBA frame of a `pixels` surface the size
+// of the window. `pixels` itself only scales by whole multiples, so the
+// viewport is applied here.
+#[allow(clippy::too_many_arguments)]
+fn upload(
+    display: &mut dyn Display,
+    frame: &mut [u8],
+    width: u32,
+    scaling: Scaling,
+    palette: &Palette,
+    phosphor: Option<&Phosphor>,
+    osd: Option<&Osd>,
+    effects: CrtEffects,
+) {
+    let height = (frame.len() / 4) as u32 / width.max(1);
+    let viewport = scaling.viewport(width, height);
+    let framebuffer = display.framebuffer();
+    let source = |x: usize, y: usize| match osd.and_then(|osd| osd.pixel(x, y)) {
+        Some(pixel) => palette.color(pixel),
+        None => phosphor::pixel_color(framebuffer, y * WIDTH + x, palette, phosphor),
+    };
+    for (y, row) in frame.chunks_exact_mut(width as usize * 4).enumerate() {
+        let vy = y as i64 - viewport.y as i64;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let vx = x as i64 - viewport.x as i64;
+            let inside = vx >= 0 && vy >= 0 && vx < viewport.w as i64 && vy < viewport.h as i64;
+            let (vx, vy, vw, vh) = (vx as usize, vy as usize, viewport.w as usize, viewport.h as usize);
+            let color = if !inside {
+                palette.background()
+            } else if effects.enabled() {
+                effects.sample(vx, vy, vw, vh, source)
+            } else {
+                source(vx * WIDTH / vw, vy * HEIGHT / vh)
+            };
+            pixel.copy_from_slice(&[color.r, color.g, color.b, 0xFF]);
+        }
+    }
+    display.clear_dirty();
+}
+
+// upload for the Mega-Chip screen, without the effects and the OSD, which
+// are for the CHIP-8 one.
+fn upload_mega(screen: &[u32], frame: &mut [u8], width: u32, scaling: Scaling, palette: &Palette) {
+    let height = (frame.len() / 4) as u32 / width.max(1);
+    let viewport = scaling.fit(width, height, megachip::WIDTH as u32, megachip::HEIGHT as u32);
+    for (y, row) in frame.chunks_exact_mut(width as usize * 4).enumerate() {
+        let vy = y as i64 - viewport.y as i64;
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let vx = x as i64 - viewport.x as i64;
+            if vx >= 0 && vy >= 0 && vx < viewport.w as i64 && vy < viewport.h as i64 {
+                let x = vx as usize * megachip::WIDTH / viewport.w as usize;
+                let y = vy as usize * megachip::HEIGHT / viewport.h as usize;
+                let [_, r, g, b] = screen[y * megachip::WIDTH + x].to_be_bytes();
+                pixel.copy_from_slice(&[r, g, b, 0xFF]);
+            } else {
+                let color = palette.background();
+                pixel.copy_from_slice(&[color.r, color.g, color.b, 0xFF]);
+            }
+        }
+    }
+}
+
+// The `--debug` watch window. `pixels` scales the panel to the window by
+// itself. Declared surface first so it is dropped before its window.
+struct WatchWindow {
+    pixels: Pixels,
+    window: Window,
+    panel: WatchPanel,
+}
+
+impl WatchWindow {
+    fn new(event_loop: &EventLoop<()>) -> WatchWindow {
+        let window = WindowBuilder::new()
+            .with_title("hachip watch")
+            .with_inner_size(LogicalSize::new(PANEL_WIDTH as u32 * PANEL_SCALE, PANEL_HEIGHT as u32 * PANEL_SCALE))
+            .build(event_loop)
+            .unwrap();
+        let size = window.inner_size();
+        let surface = SurfaceTexture::new(size.width, size.height, &window);
+        // the game window paces the loop, this one mustn't wait on its own
+        let pixels = PixelsBuilder::new(PANEL_WIDTH as u32, PANEL_HEIGHT as u32, surface)
+            .enable_vsync(false)
+            .build()
+            .unwrap();
+        WatchWindow {
+            pixels,
+            window,
+            panel: WatchPanel::new(),
+        }
+    }
+
+    fn show(&mut self, cpu: &Cpu, palette: &Palette) {
+        self.panel.update(cpu);
+        for (pixel, color) in self.pixels.frame_mut().chunks_exact_mut(4).zip(self.panel.colors(palette)) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b, 0xFF]);
+        }
+        if let Err(error) = self.pixels.render() {
+            log::error!("render failed: {}", error);
+        }
+    }
+}
+
+// winit key codes for the profile's host keys. They follow the layout, so
+// the keys are found through `layout` (QWERTY unless given, as winit
+// doesn't tell), with the ROM's input profile on top.
+pub fn keymap(profile: KeymapProfile, layout: Option<KeyboardLayout>, input: Option<&InputProfile>) -> HashMap<VirtualKeyCode, u8> {
+    let layout = layout.unwrap_or_default();
+    let keys = match input {
+        Some(input) => input.apply(&profile.layout_keys(layout)),
+        None => profile.layout_keys(layout),
+    };
+    keys.iter()
+        .filter_map(|(host_key, key)| Some((keycode(layout.label(*host_key))?, *key)))
+        .collect()
+}
+
+// winit key codes of the input mapping's macro keys, to the numbers the
+// mapping knows them by.
+fn macro_keys(mapping: &InputMapping, layout: Option<KeyboardLayout>) -> HashMap<VirtualKeyCode, usize> {
+    let layout = layout.unwrap_or_default();
+    mapping
+        .host_keys()
+        .enumerate()
+        .filter_map(|(index, host_key)| Some((keycode(layout.label(host_key))?, index)))
+        .collect()
+}
+
+fn keycode(host_key: HostKey) -> Option<VirtualKeyCode> {
+    Some(match host_key {
+        HostKey::Char(c) => match c.to_ascii_uppercase() {
+            '0' => VirtualKeyCode::Key0,
+            '1' => VirtualKeyCode::Key1,
+            '2' => VirtualKeyCode::Key2,
+            '3' => VirtualKeyCode::Key3,
+            '4' => VirtualKeyCode::Key4,
+            '5' => VirtualKeyCode::Key5,
+            '6' => VirtualKeyCode::Key6,
+            '7' => VirtualKeyCode::Key7,
+            '8' => VirtualKeyCode::Key8,
+            '9' => VirtualKeyCode::Key9,
+            'A' => VirtualKeyCode::A,
+            'B' => VirtualKeyCode::B,
+            'C' => VirtualKeyCode::C,
+            'D' => VirtualKeyCode::D,
+            'E' => VirtualKeyCode::E,
+            'F' => VirtualKeyCode::F,
+            'G' => VirtualKeyCode::G,
+            'H' => VirtualKeyCode::H,
+            'I' => VirtualKeyCode::I,
+            'J' => VirtualKeyCode::J,
+            'K' => VirtualKeyCode::K,
+            'L' => VirtualKeyCode::L,
+            'M' => VirtualKeyCode::M,
+            'N' => VirtualKeyCode::N,
+            'O' => VirtualKeyCode::O,
+            'P' => VirtualKeyCode::P,
+            'Q' => VirtualKeyCode::Q,
+            'R' => VirtualKeyCode::R,
+            'S' => VirtualKeyCode::S,
+            'T' => VirtualKeyCode::T,
+            'U' => VirtualKeyCode::U,
+            'V' => VirtualKeyCode::V,
+            'W' => VirtualKeyCode::W,
+            'X' => VirtualKeyCode::X,
+            'Y' => VirtualKeyCode::Y,
+            'Z' => VirtualKeyCode::Z,
+            '\'' => VirtualKeyCode::Apostrophe,
+            ',' => VirtualKeyCode::Comma,
+            '.' => VirtualKeyCode::Period,
+            ';' => VirtualKeyCode::Semicolon,
+            ' ' => VirtualKeyCode::Space,
+            _ => return None,
+        },
+        HostKey::Numpad(c) => match c {
+            '0' => VirtualKeyCode::Numpad0,
+            '1' => VirtualKeyCode::Numpad1,
+            '2' => VirtualKeyCode::Numpad2,
+            '3' => VirtualKeyCode::Numpad3,
+            '4' => VirtualKeyCode::Numpad4,
+            '5' => VirtualKeyCode::Numpad5,
+            '6' => VirtualKeyCode::Numpad6,
+            '7' => VirtualKeyCode::Numpad7,
+            '8' => VirtualKeyCode::Numpad8,
+            '9' => VirtualKeyCode::Numpad9,
+            '/' => VirtualKeyCode::NumpadDivide,
+            '*' => VirtualKeyCode::NumpadMultiply,
+            '-' => VirtualKeyCode::NumpadSubtract,
+            '+' => VirtualKeyCode::NumpadAdd,
+            '.' => VirtualKeyCode::NumpadDecimal,
+            _ => return None,
+        },
+        HostKey::NumpadEnter => VirtualKeyCode::NumpadEnter,
+        HostKey::Up => VirtualKeyCode::Up,
+        HostKey::Down => VirtualKeyCode::Down,
+        HostKey::Left => VirtualKeyCode::Left,
+        HostKey::Right => VirtualKeyCode::Right,
+    })
+}
+
+// Navigation keys first, then the hexpad mapping.
+pub fn ui_key(key: VirtualKeyCode, keymap: &HashMap<VirtualKeyCode, u8>) -> Option<UiKey> {
+    match key {
+        VirtualKeyCode::Up => Some(UiKey::Up),
+        VirtualKeyCode::Down => Some(UiKey::Down),
+        VirtualKeyCode::Left => Some(UiKey::Left),
+        VirtualKeyCode::Right => Some(UiKey::Right),
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(UiKey::Enter),
+        VirtualKeyCode::Escape => Some(UiKey::Escape),
+        VirtualKeyCode::Back => Some(UiKey::Backspace),
+        _ => keymap.get(&key).map(|key| UiKey::Hex(*key)),
+    }
+}
+
+// Opens a winit window and runs the cpu built by `init_cpu` until it is
+// closed or the cpu fails.
+pub fn run<F>(options: &FrontendOptions, init_cpu: F) -> Result<(), EmulateCycleError>
+where
+    F: FnOnce(Box<dyn Display>) -> Cpu,
+{
+    run_with_hooks(options, init_cpu, &mut PresentHooks::new())
+}
+
+// `run`, calling `hooks` around every present.
+// The window showing the display `scale` times.
+fn window_size(scale: u32) -> LogicalSize<u32> {
+    let (width, height) = ppu::frame_size(scale);
+    LogicalSize::new(width, height)
+}
+
+pub fn run_with_hooks<F>(options: &FrontendOptions, init_cpu: F, hooks: &mut PresentHooks) -> Result<(), EmulateCycleError>
+where
+    F: FnOnce(Box<dyn Display>) -> Cpu,
+{
+    let mut event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("hachip")
+        .with_inner_size(window_size(options.scale))
+        .with_window_icon(Icon::from_rgba(assets::APP_ICON.to_vec(), assets::APP_ICON_SIZE, assets::APP_ICON_SIZE).ok())
+        .build(&event_loop)
+        .unwrap();
+    let size = window.inner_size();
+    let surface = SurfaceTexture::new(size.width, size.height, &window);
+    let mut pixels = PixelsBuilder::new(size.width, size.height, surface)
+        .enable_vsync(options.vsync)
+        .build()
+        .unwrap();
+    let mut buffer_width = size.width;
+
+    let mut cpu = init_cpu(Box::new(Ppu::new()));
+    let set_up;
+    let options = match &options.setup {
+        Some(path) => match setup(path, &mut event_loop, &mut pixels, &mut buffer_width, &mut cpu, options) {
+            Some(options) => {
+                set_up = options;
+                &set_up
+            }
+            None => return Ok(()),
+        },
+        None => options,
+    };
+    let mut emulator = frontend::emulator(cpu, options);
+    let mut keymap = keymap(options.keymap, options.layout, options.input_profile.as_ref());
+    let mut mapping = options.input_profile.as_ref().map(InputProfile::mapping).unwrap_or_default();
+    let mut mapped_keys = macro_keys(&mapping, options.layout);
+    if options.touch_pad {
+        log::warn!("--touchpad needs the SDL window; playing without it");
+    }
+    let layout = options.layout.unwrap_or_default();
+    log::info!("keyboard layout {}: the hexpad is on {}", layout.name(), options.keymap.describe(layout));
+    let mut watch = if options.watch_window { Some(WatchWindow::new(&event_loop)) } else { None };
+    let mut battery = frontend::restore_battery(emulator.cpu_mut(), options);
+    let mut state = RunState {
+        palette: options.palette,
+        paused: false,
+        advance: 0,
+        crt_on: options.crt.tube(),
+        program: frontend::program(emulator.cpu()),
+        osd: Osd::new(),
+        speed: SpeedMeter::new(),
+        show_speed: options.show_speed,
+    };
+    let commands = options.commands.clone();
+    let mut clock = PlayClock::new();
+    let mut title = String::new();
+    let mut rom_name = options.rom_name.clone();
+    let mut scheduler = Scheduler::new(options.instructions_per_frame);
+    let mut limiter = FrameLimiter::new(options.vsync);
+    let mut phosphor = options.phosphor.map(Phosphor::new);
+    let mut fading = false;
+    let crt = if options.crt.tube() {
+        options.crt
+    } else {
+        CrtEffects {
+            grid_lines: options.crt.grid_lines,
+            ..CrtEffects::ALL
+        }
+    };
+    let mut held_hotkeys = HashSet::new();
+    let mut modifiers = ModifiersState::empty();
+    let mut scale = options.scale;
+    // stretching letterboxes in fullscreen
+    let mut scaling = options.scaling;
+    let capture_dir = options.screenshot_dir.as_deref();
+    let mut recording: Option<Recording> = None;
+    let mut movie = options.movie.clone();
+    if let Some(movie) = &movie {
+        movie.start(emulator.cpu_mut());
+    }
+    let mut input_macro = InputMacro::new();
+    let mut netplay = options.netplay.clone();
+    let mut live_keys = Keypad::new();
+    let mut autosave = options
+        .autosave_dir
+        .clone()
+        .map(|dir| Autosave::new(dir, options.autosave_interval));
+    let mut bookmarks = options.bookmark_dir.clone().map(Bookmarks::new);
+    let mut flags = options.flags_dir.as_deref().map(FlagStore::new);
+    if let Some(flags) = &flags {
+        flags.restore(emulator.cpu_mut());
+    }
+    let mut save_slots = options.save_slots.clone();
+    let mut pause_menu: Option<PauseMenu> = None;
+    let mut slot_picker: Option<SlotPicker> = None;
+    let mut resume = options.resume_dir.clone().map(Resume::new);
+    let mut resume_prompt = resume.as_ref().and_then(|resume| ResumePrompt::offer(emulator.cpu(), resume));
+    let mut error_screen: Option<ErrorScreen> = None;
+    let mut launcher = options.rom_dir.as_deref().map(|dir| Launcher::new(dir, recent::load().paths()));
+
+    // run_return rather than run so the caller gets control back on exit.
+    event_loop.run_return(|event, _, control_flow| match event {
+        // closing the watch window leaves the game running
+        Event::WindowEvent { window_id, event } if watch.as_ref().is_some_and(|watch| watch.window.id() == window_id) => {
+            match event {
+                WindowEvent::CloseRequested => watch = None,
+                WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    if let Some(watch) = &mut watch {
+                        if let Err(error) = watch.pixels.resize_surface(size.width, size.height) {
+                            log::error!("resize failed: {}", error);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                resize(&mut pixels, size);
+                buffer_width = size.width;
+                emulator.cpu_mut().display_mut().invalidate();
+            }
+            // moved to a display of another DPI, the window keeps its
+            // logical size and the buffer follows its pixels
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                resize(&mut pixels, *new_inner_size);
+                buffer_width = new_inner_size.width;
+                emulator.cpu_mut().display_mut().invalidate();
+            }
+            WindowEvent::ModifiersChanged(state) => modifiers = state,
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput { virtual_keycode: Some(key), state: key_state, .. },
+                ..
+            } => {
+                let pressed = key_state == ElementState::Pressed;
+                // winit repeats held keys, hotkeys only act on the first press
+                let first_press = pressed && held_hotkeys.insert(key);
+                if !pressed {
+                    held_hotkeys.remove(&key);
+                }
+                // F11 and Alt+Enter switch to borderless fullscreen, which
+                // keeps the screen's resolution, and back
+                if first_press && (key == VirtualKeyCode::F11 || (key == VirtualKeyCode::Return && modifiers.alt())) {
+                    if window.fullscreen().is_some() {
+                        window.set_fullscreen(None);
+                        scaling = options.scaling;
+                    } else {
+                        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                        scaling = options.scaling.fullscreen();
+                    }
+                    emulator.cpu_mut().display_mut().invalidate();
+                    return;
+                }
+                // Ctrl+= and Ctrl+- grow and shrink the window a step of
+                // the scale, Resized then fits the buffer to it
+                if first_press && modifiers.ctrl() && (key == VirtualKeyCode::Equals || key == VirtualKeyCode::Minus) {
+                    scale = frontend::step_scale(scale, key == VirtualKeyCode::Equals);
+                    if window.fullscreen().is_none() {
+                        window.set_inner_size(window_size(scale));
+                    }
+                    state.osd.show(&format!("WINDOW {}X", scale));
+                    return;
+                }
+                if let Some(menu) = &mut launcher {
+                    let choice = match ui_key(key, &keymap) {
+                        Some(key) if first_press => menu.key_down(key),
+                        Some(key) if !pressed => {
+                            menu.key_up(key);
+                            None
+                        }
+                        _ => None,
+                    };
+                    match choice {
+                        Some(Choice::Rom(path)) => match frontend::launch(emulator.cpu_mut(), &path, options) {
+                            Ok(launched) => {
+                                state.program = launched.program;
+                                autosave = launched.autosave;
+                                flags = launched.flags;
+                                battery = launched.battery;
+                                bookmarks = launched.bookmarks;
+                                save_slots = launched.save_slots;
+                                resume = launched.resume;
+                                resume_prompt = resume.as_ref().and_then(|resume| ResumePrompt::offer(emulator.cpu(), resume));
+                                keymap = self::keymap(launched.keymap, options.layout, launched.input_profile.as_ref());
+                                mapping = launched.input_profile.as_ref().map(InputProfile::mapping).unwrap_or_default();
+                                rom_name = Some(launched.rom_name);
+                                mapped_keys = macro_keys(&mapping, options.layout);
+                                launcher = None;
+                            }
+                            Err(error) => {
+                                log::error!("{}: {}", path.display(), error);
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        },
+                        Some(_) => *control_flow = ControlFlow::Exit,
+                        None => {}
+                    }
+                    return;
+                }
+                if let Some(screen) = &mut error_screen {
+                    let recovery = match ui_key(key, &keymap) {
+                        Some(key) if first_press => screen.key_down(key, emulator.cpu_mut()),
+                        Some(key) if !pressed => {
+                            screen.key_up(key);
+                            None
+                        }
+                        _ => None,
+                    };
+                    match recovery {
+                        // the error is returned below
+                        Some(Recovery::Quit) => *control_flow = ControlFlow::Exit,
+                        Some(recovery) => {
+                            if recovery == Recovery::Reset {
+                                commands.push(Command::Reset);
+                            }
+                            error_screen = None;
+                        }
+                        None => {}
+                    }
+                    return;
+                }
+                if let Some(menu) = &mut pause_menu {
+                    let open = match ui_key(key, &keymap) {
+                        Some(key) if first_press => menu.key_down(key, emulator.cpu_mut(), &state.palette),
+                        Some(key) if !pressed => {
+                            menu.key_up(key);
+                            true
+                        }
+                        _ => true,
+                    };
+                    if !open {
+                        pause_menu = None;
+                    }
+                    return;
+                }
+                if let Some(prompt) = &mut resume_prompt {
+                    let open = match ui_key(key, &keymap) {
+                        Some(key) if first_press => prompt.key_down(key, emulator.cpu_mut()),
+                        Some(key) if !pressed => {
+                            prompt.key_up(key);
+                            true
+                        }
+                        _ => true,
+                    };
+                    if !open {
+                        resume_prompt = None;
+                    }
+                    return;
+                }
+                if let Some(picker) = &mut slot_picker {
+                    let picked = match ui_key(key, &keymap) {
+                        Some(key) if first_press => picker.key_down(key, emulator.cpu_mut()),
+                        Some(key) if !pressed => {
+                            picker.key_up(key);
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(picked) = picked {
+                        if let Some(message) = picked.message() {
+                            state.osd.show(&message);
+                        }
+                        slot_picker = None;
+                    }
+                    return;
+                }
+                match key {
+                    VirtualKeyCode::Tab if !pressed => commands.push(Command::FastForward(false)),
+                    _ if !first_press => {}
+                    VirtualKeyCode::Tab => commands.push(Command::FastForward(true)),
+                    VirtualKeyCode::LBracket => commands.push(Command::TimeScale(scheduler.time_scale / 2)),
+                    VirtualKeyCode::RBracket => commands.push(Command::TimeScale(scheduler.time_scale * 2)),
+                    VirtualKeyCode::Escape => *control_flow = ControlFlow::Exit,
+                    VirtualKeyCode::F1 => match &save_slots {
+                        Some(slots) => slot_picker = Some(SlotPicker::open(emulator.cpu(), slots.clone())),
+                        None => log::info!("save slots are off, no data directory"),
+                    },
+                    VirtualKeyCode::F3 => pause_menu = Some(PauseMenu::open(emulator.cpu(), bookmarks.clone())),
+                    VirtualKeyCode::P => commands.push(Command::TogglePause),
+                    VirtualKeyCode::F2 => commands.push(Command::Reset),
+                    VirtualKeyCode::F4 => commands.push(Command::ToggleSpeedMeter),
+                    VirtualKeyCode::F5 => {
+                        input_macro.toggle_recording();
+                        state.osd.show(if input_macro.is_recording() { "RECORDING MACRO" } else { "MACRO RECORDED" });
+                    }
+                    VirtualKeyCode::F6 => input_macro.play(),
+                    VirtualKeyCode::F7 => commands.push(Command::AdvanceFrame),
+                    VirtualKeyCode::F8 => commands.push(Command::ToggleCrt),
+                    VirtualKeyCode::F9 => {
+                        if let Some(autosave) = &autosave {
+                            let restored = autosave.restore_latest(emulator.cpu_mut());
+                            state.osd.show(if restored { "AUTOSAVE LOADED" } else { "NO AUTOSAVE" });
+                        }
+                    }
+                    VirtualKeyCode::F10 => {
+                        recording::toggle(&mut recording, capture_dir, &state.palette, emulator.cpu().profile());
+                        if capture_dir.is_some() {
+                            state.osd.show(if recording.is_some() { "RECORDING" } else { "RECORDING SAVED" });
+                        }
+                    }
+                    VirtualKeyCode::F12 => {
+                        screenshot::capture(capture_dir, emulator.cpu(), &state.palette);
+                        if capture_dir.is_some() {
+                            state.osd.show("SCREENSHOT SAVED");
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(index) = keymap.get(&key) {
+                    live_keys.set_key(*index, key_state == ElementState::Pressed);
+                }
+                if let Some(index) = mapped_keys.get(&key) {
+                    mapping.set_held(*index, key_state == ElementState::Pressed);
+                }
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                if let Some(menu) = &mut pause_menu {
+                    menu.type_char(c);
+                }
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared => {
+            if let Some(menu) = &mut launcher {
+                menu.frame();
+                menu.render(emulator.cpu_mut().display_mut());
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &options.palette, None, None, CrtEffects::default());
+                if let Err(error) = pixels.render() {
+                    log::error!("render failed: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                scheduler.frames_due();
+                *control_flow = wait(frontend::loop_mode(false, Some(menu.idle())), &scheduler, &mut limiter);
+                return;
+            }
+            // the game stands still under the menu, its time isn't caught up on
+            if let Some(screen) = &mut error_screen {
+                screen.frame();
+                screen.render(emulator.cpu_mut().display_mut());
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &state.palette, None, None, CrtEffects::default());
+                if let Err(error) = pixels.render() {
+                    log::error!("render failed: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                scheduler.frames_due();
+                *control_flow = wait(frontend::loop_mode(false, Some(screen.idle())), &scheduler, &mut limiter);
+                return;
+            }
+            if let Some(menu) = &mut pause_menu {
+                menu.frame();
+                menu.render(emulator.cpu_mut().display_mut());
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &state.palette, None, None, CrtEffects::default());
+                if let Err(error) = pixels.render() {
+                    log::error!("render failed: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                scheduler.frames_due();
+                *control_flow = wait(frontend::loop_mode(state.paused, Some(menu.idle())), &scheduler, &mut limiter);
+                return;
+            }
+            if let Some(prompt) = &mut resume_prompt {
+                prompt.frame();
+                prompt.render(emulator.cpu_mut().display_mut());
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &state.palette, None, None, CrtEffects::default());
+                if let Err(error) = pixels.render() {
+                    log::error!("render failed: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                scheduler.frames_due();
+                *control_flow = wait(frontend::loop_mode(false, Some(prompt.idle())), &scheduler, &mut limiter);
+                return;
+            }
+            if let Some(picker) = &mut slot_picker {
+                picker.frame();
+                picker.render(emulator.cpu_mut().display_mut());
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &state.palette, None, None, CrtEffects::default());
+                if let Err(error) = pixels.render() {
+                    log::error!("render failed: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                scheduler.frames_due();
+                *control_flow = wait(frontend::loop_mode(state.paused, Some(picker.idle())), &scheduler, &mut limiter);
+                return;
+            }
+            command::apply_pending(&commands, emulator.cpu_mut(), &mut scheduler, &mut state, movie.as_ref());
+            let frames = state.frames_to_run(scheduler.frames_due());
+            for _ in 0..frames {
+                for (key, &pressed) in live_keys.keys.iter().enumerate() {
+                    emulator.key_event(key as u8, pressed);
+                }
+                let cpu = emulator.cpu_mut();
+                mapping.frame(&mut cpu.keypad);
+                input_macro.frame(&mut cpu.keypad);
+                frontend::netplay_frame(&mut netplay, cpu);
+                if let Some(movie) = &mut movie {
+                    movie.frame(cpu);
+                }
+                emulator.instructions_per_frame = scheduler.instructions_per_frame;
+                match emulator.step_frame() {
+                    Ok(executed) => state.speed.frame(executed),
+                    Err(error) => {
+                        error_screen = Some(ErrorScreen::open(emulator.cpu(), error));
+                        return;
+                    }
+                }
+                recording::record_frame(&mut recording, emulator.framebuffer(), capture_dir);
+                if let Some(phosphor) = &mut phosphor {
+                    fading = phosphor.update(emulator.framebuffer());
+                }
+            }
+            clock.advance(frames);
+            if let Some(autosave) = &mut autosave {
+                autosave.tick(emulator.cpu());
+            }
+            if let Some(flags) = &flags {
+                flags.tick(emulator.cpu_mut());
+            }
+            let status = Status {
+                rom_name: rom_name.clone(),
+                paused: state.paused,
+                speed: scheduler.scale(),
+                played: clock.played(),
+            };
+            let new_title = status.title();
+            if new_title != title {
+                window.set_title(&new_title);
+                title = new_title;
+            }
+            state.update_speed();
+            if let Some(screen) = emulator.mega_screen() {
+                upload_mega(screen, pixels.frame_mut(), buffer_width, scaling, &state.palette);
+            } else if fading || state.osd.update() || emulator.cpu().display().dirty_rows().contains(&true) {
+                let effects = if state.crt_on { crt } else { crt.without_tube() };
+                upload(emulator.cpu_mut().display_mut(), pixels.frame_mut(), buffer_width, scaling, &state.palette, phosphor.as_ref(), Some(&state.osd), effects);
+            }
+            hooks.before_present(emulator.cpu(), clock.played());
+            if let Err(error) = pixels.render() {
+                log::error!("render failed: {}", error);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            state.speed.present();
+            hooks.after_present(emulator.cpu(), clock.played());
+            if let Some(watch) = &mut watch {
+                watch.show(emulator.cpu(), &state.palette);
+            }
+            *control_flow = wait(frontend::loop_mode(state.paused, None), &scheduler, &mut limiter);
+        }
+        _ => {}
+    });
+    recording::stop(&mut recording, capture_dir);
+    if let Some(movie) = &mut movie {
+        movie.finish(emulator.cpu_mut());
+    }
+    if let Some(battery) = &battery {
+        battery.finish(emulator.cpu());
+    }
+    // menus keep the game aside, nothing was played under the resume
+    // prompt, and a failed game isn't worth resuming
+    if let Some(resume) = &resume {
+        if resume_prompt.is_none() && error_screen.is_none() {
+            let game = pause_menu.as_ref().map(PauseMenu::game).or_else(|| slot_picker.as_ref().map(SlotPicker::game));
+            resume.finish(&game.cloned().unwrap_or_else(|| emulator.cpu().save_state()));
+        }
+    }
+    match error_screen {
+        Some(screen) => Err(screen.into_error()),
+        None => Ok(()),
+    }
+}
+
+// Fits the surface and the frame to a resized window.
+fn resize(pixels: &mut Pixels, size: PhysicalSize<u32>) {
+    if let Err(error) = pixels.resize_surface(size.width, size.height) {
+        log::error!("resize failed: {}", error);
+    }
+    if let Err(error) = pixels.resize_buffer(size.width, size.height) {
+        log::error!("resize failed: {}", error);
+    }
+}
+
+// Shows the first-run setup, returning the options it makes, or None if
+// the window was closed.
+fn setup(
+    path: &Path,
+    event_loop: &mut EventLoop<()>,
+    pixels: &mut Pixels,
+    buffer_width: &mut u32,
+    cpu: &mut Cpu,
+    options: &FrontendOptions,
+) -> Option<FrontendOptions> {
+    let keymap = keymap(options.keymap, options.layout, None);
+    let mut wizard = Wizard::new(options.rom_dir.as_deref().unwrap_or_else(|| Path::new(".")));
+    let mut held = HashSet::new();
+    let mut scheduler = Scheduler::new(options.instructions_per_frame);
+    let mut limiter = FrameLimiter::new(options.vsync);
+    let mut outcome = None;
+    event_loop.run_return(|event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => {
+                outcome = Some(Outcome::Quit);
+                *control_flow = ControlFlow::Exit;
+            }
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                resize(pixels, size);
+                *buffer_width = size.width;
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput { virtual_keycode: Some(key), state: key_state, .. },
+                ..
+            } => {
+                let pressed = key_state == ElementState::Pressed;
+                let first_press = pressed && held.insert(key);
+                if !pressed {
+                    held.remove(&key);
+                }
+                let picked = match ui_key(key, &keymap) {
+                    Some(key) if first_press => wizard.key_down(key),
+                    Some(key) if !pressed => {
+                        wizard.key_up(key);
+                        None
+                    }
+                    _ => None,
+                };
+                if picked.is_some() {
+                    outcome = picked;
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared => {
+            wizard.frame();
+            wizard.render(cpu.display_mut());
+            let palette = wizard.palette();
+            upload(cpu.display_mut(), pixels.frame_mut(), *buffer_width, wizard.scaling(), &palette, None, None, CrtEffects::default());
+            if let Err(error) = pixels.render() {
+                log::error!("render failed: {}", error);
+                outcome = Some(Outcome::Quit);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            scheduler.frames_due();
+            *control_flow = wait(frontend::loop_mode(false, Some(wizard.idle())), &scheduler, &mut limiter);
+        }
+        _ => {}
+    });
+    match outcome {
+        Some(Outcome::Done(config)) => Some(frontend::finish_setup(options, &config, path)),
+        _ => None,
+    }
+}
+
+// Until the next frame while running, returning to poll for the events
+// that came in meanwhile; idle, until an event or a while later for queued
+// commands.
+fn wait(mode: LoopMode, scheduler: &Scheduler, limiter: &mut FrameLimiter) -> ControlFlow {
+    match mode {
+        LoopMode::Running => {
+            limiter.wait(scheduler);
+            ControlFlow::Poll
+        }
+        LoopMode::Idle => ControlFlow::WaitUntil(Instant::now() + frontend::IDLE_WAKE),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::scheduler::FRAME_RATE;
+use std::time::Duration;
+
+// Time the player has spent playing: counted from emulated frames, so
+// pauses add nothing, and divided by the emulation speed so fast-forward
+// and slow motion count as the real time they took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayClock {
+    played: Duration,
+    speed: f64,
+}
+
+impl Default for PlayClock {
+    fn default() -> PlayClock {
+        PlayClock::new()
+    }
+}
+
+impl PlayClock {
+    pub fn new() -> PlayClock {
+        PlayClock {
+            played: Duration::from_secs(0),
+            speed: 1.0,
+        }
+    }
+
+    // Multiple of normal speed the following frames run at.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    // Call with the frames emulated since the last call.
+    pub fn advance(&mut self, frames: u32) {
+        self.played += Duration::from_secs_f64(frames as f64 / FRAME_RATE as f64 / self.speed);
+    }
+
+    pub fn played(&self) -> Duration {
+        self.played
+    }
+}
+
+// "H:MM:SS", or "M:SS" under an hour.
+pub fn format_play_time(played: Duration) -> String {
+    let seconds = played.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_play_time, PlayClock};
+    use std::time::Duration;
+
+    #[test]
+    fn counts_frames_scaled_by_speed() {
+        let mut clock = PlayClock::new();
+        clock.advance(120);
+        clock.advance(0);
+        assert_eq!(clock.played(), Duration::from_secs(2));
+
+        clock.set_speed(4.0);
+        clock.advance(240);
+        assert_eq!(clock.played(), Duration::from_secs(3), "fast-forward counts as the real time it took");
+
+        assert_eq!(format_play_time(Duration::from_secs(75)), "1:15");
+        assert_eq!(format_play_time(Duration::from_secs(3725)), "1:02:05");
+    }
+}
@@ -1,83 +1,182 @@
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use crate::palette::Palette;
+use std::slice::ChunksExact;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
 
-pub const FRAME_WIDTH: u32 = 640;
-pub const FRAME_HEIGHT: u32 = 320;
+// Times the display is enlarged in a new window, unless `--scale` says
+// otherwise, from MIN_SCALE to MAX_SCALE.
+pub const DEFAULT_SCALE: u32 = 10;
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 20;
 
-const FACTOR: usize = 10;
+// The size of the display enlarged `scale` times.
+pub fn frame_size(scale: u32) -> (u32, u32) {
+    (WIDTH as u32 * scale, HEIGHT as u32 * scale)
+}
 
-pub trait PixelGrid {
-    fn set_draw_color(&mut self, color: Color);
-    fn clear(&mut self);
-    fn present(&mut self);
-    fn fill_rect(&mut self, rect: Rect) -> Result<(), String>;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
-pub struct CanvasWindow {
-    canvas: Canvas<Window>
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    // From 0xRRGGBB.
+    pub const fn from_rgb(rgb: u32) -> Color {
+        Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
 }
-impl CanvasWindow {
-    pub fn new(canvas: Canvas<Window>) -> CanvasWindow {
-        CanvasWindow {
-            canvas
-        }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+impl Rect {
+    pub fn new(x: i32, y: i32, w: u32, h: u32) -> Rect {
+        Rect { x, y, w, h }
     }
 }
-impl PixelGrid for CanvasWindow {
-    fn set_draw_color(&mut self, color: Color) {
-        self.canvas.set_draw_color(color);
+
+// How a window frontend fits the 64x32 display into its window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum Scaling {
+    // Largest whole multiple of the display that fits, centered.
+    #[default]
+    Integer,
+    // Fills the window, ignoring the 2:1 aspect ratio.
+    Stretch,
+    // Largest 2:1 rectangle that fits, centered between black bars.
+    Letterbox,
+}
+
+impl Scaling {
+    pub const ALL: [Scaling; 3] = [Scaling::Integer, Scaling::Stretch, Scaling::Letterbox];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaling::Integer => "integer",
+            Scaling::Stretch => "stretch",
+            Scaling::Letterbox => "letterbox",
+        }
     }
-    fn clear(&mut self) {
-        self.canvas.clear();
+
+    pub fn from_name(name: &str) -> Option<Scaling> {
+        match name.to_ascii_lowercase().as_str() {
+            "integer" => Some(Scaling::Integer),
+            "stretch" => Some(Scaling::Stretch),
+            "letterbox" => Some(Scaling::Letterbox),
+            _ => None,
+        }
     }
-    fn present(&mut self) {
-        self.canvas.present();
+
+    // The scaling in fullscreen, where the display keeps its 2:1 aspect:
+    // stretching letterboxes instead.
+    pub fn fullscreen(self) -> Scaling {
+        match self {
+            Scaling::Stretch => Scaling::Letterbox,
+            scaling => scaling,
+        }
+    }
+
+    // Where the display goes in a window of the given size.
+    pub fn viewport(self, width: u32, height: u32) -> Rect {
+        self.fit(width, height, WIDTH as u32, HEIGHT as u32)
     }
-    fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
-        self.canvas.fill_rect(rect)
+
+    // Where a picture of display_width x display_height goes in a window of
+    // the given size, for screens other than the CHIP-8 one.
+    pub fn fit(self, width: u32, height: u32, display_width: u32, display_height: u32) -> Rect {
+        let (w, h) = match self {
+            Scaling::Integer => {
+                let factor = (width / display_width).min(height / display_height).max(1);
+                (display_width * factor, display_height * factor)
+            }
+            Scaling::Stretch => (width, height),
+            Scaling::Letterbox => {
+                if width * display_height > height * display_width {
+                    (height * display_width / display_height, height)
+                } else {
+                    (width, width * display_height / display_width)
+                }
+            }
+        };
+        Rect::new((width as i32 - w as i32) / 2, (height as i32 - h as i32) / 2, w, h)
     }
 }
 
-pub trait Display {
+// Drawing surface of a frontend (HTML canvas, ...), filled by `render`.
+pub trait PixelGrid {
+    fn set_draw_color(&mut self, color: Color);
+    fn clear(&mut self);
+    fn present(&mut self);
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String>;
+}
+
+pub trait Display: Send {
     fn cls(&mut self);
-    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool;
+    // XORs the sprite in at (x, y), which wrap around the screen, and
+    // returns whether a lit pixel was turned off. With `clip`, the parts
+    // past the right and bottom edges are left out instead of wrapping too.
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool;
     fn set_pixel(&mut self, x: usize, y: usize, val: u8);
     fn get_pixel(&mut self, x: usize, y: usize) -> bool;
+    // A byte per pixel, row by row, 1 for lit.
+    fn framebuffer(&self) -> &[u8];
+    fn rows(&self) -> ChunksExact<'_, u8> {
+        self.framebuffer().chunks_exact(WIDTH)
+    }
+    // One flag per row, set when the row changed since the last `clear_dirty`.
+    fn dirty_rows(&self) -> &[bool];
+    fn clear_dirty(&mut self);
+    // Marks every row dirty, e.g. to repaint in new colors.
+    fn invalidate(&mut self);
 }
+
+// The logical 64x32 framebuffer. Drawing only updates memory; frontends
+// render the whole frame once per 60Hz tick from `framebuffer`.
 pub struct Ppu {
-    memory: [u8; 2048],
-    canvas: Box<dyn PixelGrid>
+    memory: [u8; WIDTH * HEIGHT],
+    dirty: [bool; HEIGHT],
 }
 impl Ppu {
-    pub fn new(canvas: Box<dyn PixelGrid>) -> Ppu {
+    pub fn new() -> Ppu {
         Ppu {
-            memory: [0; 2048],
-            canvas,
+            memory: [0; WIDTH * HEIGHT],
+            // nothing has been shown yet, so the first frame is drawn in full
+            dirty: [true; HEIGHT],
         }
     }
 }
 
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
 impl Display for Ppu {
     fn cls(&mut self) {
-        self.memory = [0; 2048];
-        let black = Color::RGB(0, 0, 0);
-        self.canvas.set_draw_color(black);
-        self.canvas.clear();
-        self.canvas.present();
+        self.memory = [0; WIDTH * HEIGHT];
+        self.invalidate();
     }
 
-    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
-        let rows = sprite.len();
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
         let mut collision = false;
-        for (j, _) in sprite.iter().enumerate().take(rows) {
-            let row = &sprite[j];
+        for (j, row) in sprite.iter().enumerate() {
             for i in 0..8 {
                 let new_value = row >> (7 - i) & 0x01;
-                if new_value == 1 {
+                let outside = x + i >= WIDTH || y + j >= HEIGHT;
+                if new_value == 1 && !(clip && outside) {
                     let xi = (x + i) % WIDTH;
                     let yj = (y + j) % HEIGHT;
                     let old_value = self.get_pixel(xi, yj);
@@ -89,27 +188,58 @@ impl Display for Ppu {
                 }
             }
         }
-        self.canvas.present();
-        return collision;
+        collision
     }
 
     fn set_pixel(&mut self, x: usize, y: usize, val: u8) {
-        self.memory[x + y * WIDTH] = val;
-        let col = if val == 1
-            { Color::RGB(255, 255, 255) }
-            else
-            { Color::RGB(0, 0, 0) };
-        self.canvas.set_draw_color(col);
-        self.canvas.fill_rect(Rect::new(
-            (x * FACTOR) as i32,
-            (y * FACTOR) as i32,
-            FACTOR as u32,
-            FACTOR as u32)).unwrap();
+        let pixel = &mut self.memory[x + y * WIDTH];
+        if *pixel != val {
+            *pixel = val;
+            self.dirty[y] = true;
+        }
     }
 
     fn get_pixel(&mut self, x: usize, y: usize) -> bool {
         self.memory[x + y * WIDTH] == 1
     }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn dirty_rows(&self) -> &[bool] {
+        &self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = [false; HEIGHT];
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = [true; HEIGHT];
+    }
+}
+
+// Redraws the rows of `display` that changed since the last render onto
+// `grid`, `scale` grid pixels to a display pixel, and presents it.
+pub fn render(display: &mut dyn Display, grid: &mut dyn PixelGrid, palette: &Palette, scale: u32) {
+    let factor = scale as usize;
+    if !display.dirty_rows().contains(&true) {
+        return;
+    }
+    let dirty = display.dirty_rows();
+    for (y, row) in display.rows().enumerate().filter(|(y, _)| dirty[*y]) {
+        for (x, pixel) in row.iter().enumerate() {
+            grid.set_draw_color(palette.color(*pixel));
+            grid.fill_rect(Rect::new(
+                (x * factor) as i32,
+                (y * factor) as i32,
+                scale,
+                scale)).unwrap();
+        }
+    }
+    grid.present();
+    display.clear_dirty();
 }
 
 pub static FONT_SET: [u8; 80] = [
@@ -133,85 +263,163 @@ pub static FONT_SET: [u8; 80] = [
 
 #[cfg(test)]
 mod tests {
-    use super::Ppu;
-    use crate::ppu::{PixelGrid, Display};
-    use sdl2::pixels::Color;
-    use sdl2::rect::Rect;
+    use super::{render, Ppu, Scaling};
+    use crate::palette::Palette;
+    use crate::ppu::{frame_size, Color, Display, PixelGrid, Rect, DEFAULT_SCALE, HEIGHT, WIDTH};
 
-    pub struct PixelGridMock {}
+    // Records the rects filled and how often the grid was presented.
+    #[derive(Default)]
+    pub struct PixelGridMock {
+        rects: Vec<Rect>,
+        presents: usize,
+    }
     impl PixelGrid for PixelGridMock {
-        fn set_draw_color(&mut self, color: Color) {}
+        fn set_draw_color(&mut self, _color: Color) {}
         fn clear(&mut self) {}
-        fn present(&mut self) {}
+        fn present(&mut self) {
+            self.presents += 1;
+        }
         fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+            self.rects.push(rect);
             Result::Ok(())
         }
     }
-    
-    fn make_pixel_grid() -> Box<dyn PixelGrid> {
-        Box::new(PixelGridMock{})
-    }
 
     #[test]
     fn set_pixel() {
-        let mut Ppu = Ppu::new(make_pixel_grid());
+        let mut ppu = Ppu::new();
 
-        Ppu.set_pixel(1, 1, 1);
+        ppu.set_pixel(1, 1, 1);
 
-        assert_eq!(true, Ppu.get_pixel(1, 1));
+        assert!(ppu.get_pixel(1, 1));
     }
 
     #[test]
     fn cls() {
-        let mut Ppu = Ppu::new(make_pixel_grid());
+        let mut ppu = Ppu::new();
 
-        Ppu.set_pixel(1, 1, 1);
-        Ppu.cls();
+        ppu.set_pixel(1, 1, 1);
+        ppu.cls();
 
-        assert_eq!(false, Ppu.get_pixel(1, 1));
+        assert!(!ppu.get_pixel(1, 1));
     }
 
     #[test]
     fn draw() {
-        let mut Ppu = Ppu::new(make_pixel_grid());
+        let mut ppu = Ppu::new();
 
         let sprite: [u8; 2] = [0b00110011, 0b11001010];
 
-        Ppu.draw(0, 0, &sprite);
+        ppu.draw(0, 0, &sprite, false);
 
-        assert_eq!(false, Ppu.get_pixel(0, 0));
-        assert_eq!(false, Ppu.get_pixel(1, 0));
-        assert_eq!(true, Ppu.get_pixel(2, 0));
-        assert_eq!(true, Ppu.get_pixel(3, 0));
-        assert_eq!(false, Ppu.get_pixel(4, 0));
-        assert_eq!(false, Ppu.get_pixel(5, 0));
-        assert_eq!(true, Ppu.get_pixel(6, 0));
-        assert_eq!(true, Ppu.get_pixel(7, 0));
+        assert!(!ppu.get_pixel(0, 0));
+        assert!(!ppu.get_pixel(1, 0));
+        assert!(ppu.get_pixel(2, 0));
+        assert!(ppu.get_pixel(3, 0));
+        assert!(!ppu.get_pixel(4, 0));
+        assert!(!ppu.get_pixel(5, 0));
+        assert!(ppu.get_pixel(6, 0));
+        assert!(ppu.get_pixel(7, 0));
 
-        assert_eq!(true, Ppu.get_pixel(0, 1));
-        assert_eq!(true, Ppu.get_pixel(1, 1));
-        assert_eq!(false, Ppu.get_pixel(2, 1));
-        assert_eq!(false, Ppu.get_pixel(3, 1));
-        assert_eq!(true, Ppu.get_pixel(4, 1));
-        assert_eq!(false, Ppu.get_pixel(5, 1));
-        assert_eq!(true, Ppu.get_pixel(6, 1));
-        assert_eq!(false, Ppu.get_pixel(7, 1));
+        assert!(ppu.get_pixel(0, 1));
+        assert!(ppu.get_pixel(1, 1));
+        assert!(!ppu.get_pixel(2, 1));
+        assert!(!ppu.get_pixel(3, 1));
+        assert!(ppu.get_pixel(4, 1));
+        assert!(!ppu.get_pixel(5, 1));
+        assert!(ppu.get_pixel(6, 1));
+        assert!(!ppu.get_pixel(7, 1));
     }
 
     #[test]
     fn draw_detects_collisions() {
-        let mut Ppu = Ppu::new(make_pixel_grid());
+        let mut ppu = Ppu::new();
 
         let mut sprite: [u8; 1] = [0b00110000];
-        let mut collision = Ppu.draw(0, 0, &sprite);
-        assert_eq!(false, collision);
+        let mut collision = ppu.draw(0, 0, &sprite, false);
+        assert!(!collision);
 
         sprite = [0b00000011];
-        collision = Ppu.draw(0, 0, &sprite);
-        assert_eq!(false, collision);
+        collision = ppu.draw(0, 0, &sprite, false);
+        assert!(!collision);
 
         sprite = [0b00000001];
-        collision = Ppu.draw(0, 0, &sprite);
-        assert_eq!(true, collision);
+        collision = ppu.draw(0, 0, &sprite, false);
+        assert!(collision);
+    }
+
+    #[test]
+    fn draw_wraps_or_clips_at_the_edges() {
+        let mut ppu = Ppu::new();
+        ppu.draw(WIDTH - 2, HEIGHT - 1, &[0xF0, 0x80], false);
+        assert!(ppu.get_pixel(WIDTH - 1, HEIGHT - 1));
+        assert!(ppu.get_pixel(1, HEIGHT - 1), "wrapped to the left edge");
+        assert!(ppu.get_pixel(WIDTH - 2, 0), "wrapped to the top edge");
+
+        let mut ppu = Ppu::new();
+        ppu.draw(WIDTH - 2, HEIGHT - 1, &[0xF0, 0x80], true);
+        assert!(ppu.get_pixel(WIDTH - 1, HEIGHT - 1));
+        assert_eq!(ppu.framebuffer().iter().filter(|pixel| **pixel == 1).count(), 2, "the rest is clipped");
+
+        ppu.draw(WIDTH + 3, HEIGHT + 4, &[0x80], true);
+        assert!(ppu.get_pixel(3, 4), "the position wraps either way");
+    }
+
+    #[test]
+    fn framebuffer_reads_without_rendering() {
+        let mut ppu = Ppu::new();
+        ppu.set_pixel(3, 1, 1);
+        assert_eq!(ppu.framebuffer()[WIDTH + 3], 1);
+        let display: &dyn Display = &ppu;
+        assert_eq!(display.rows().len(), HEIGHT);
+        assert_eq!(display.rows().nth(1).unwrap()[..5], [0, 0, 0, 1, 0]);
+        assert!(ppu.dirty_rows()[1], "still to be rendered");
+    }
+
+    #[test]
+    fn set_pixel_marks_row_dirty() {
+        let mut ppu = Ppu::new();
+        ppu.clear_dirty();
+
+        ppu.set_pixel(3, 5, 0);
+        assert!(!ppu.dirty_rows().contains(&true), "unchanged pixel");
+
+        ppu.set_pixel(3, 5, 1);
+        assert!(ppu.dirty_rows()[5]);
+        assert_eq!(ppu.dirty_rows().iter().filter(|dirty| **dirty).count(), 1);
+    }
+
+    #[test]
+    fn render_draws_dirty_rows_once() {
+        let mut ppu = Ppu::new();
+        let mut grid = PixelGridMock::default();
+
+        render(&mut ppu, &mut grid, &Palette::default(), DEFAULT_SCALE);
+        assert_eq!(grid.rects.len(), WIDTH * HEIGHT, "first frame is drawn in full");
+        assert_eq!(grid.presents, 1);
+
+        ppu.draw(0, 2, &[0xFF], false);
+        grid.rects.clear();
+        render(&mut ppu, &mut grid, &Palette::default(), DEFAULT_SCALE);
+        assert_eq!(grid.rects.len(), WIDTH);
+        assert!(grid.rects.iter().all(|rect| rect.y == 20 && rect.x < frame_size(DEFAULT_SCALE).0 as i32));
+        assert_eq!(grid.presents, 2);
+
+        render(&mut ppu, &mut grid, &Palette::default(), DEFAULT_SCALE);
+        assert_eq!(grid.presents, 2, "nothing changed");
+    }
+
+    #[test]
+    fn scaling_viewports() {
+        assert_eq!(Scaling::Integer.viewport(700, 400), Rect::new(30, 40, 640, 320));
+        assert_eq!(Scaling::Integer.viewport(32, 16), Rect::new(-16, -8, 64, 32));
+        assert_eq!(Scaling::Stretch.viewport(700, 400), Rect::new(0, 0, 700, 400));
+        assert_eq!(Scaling::Letterbox.viewport(700, 400), Rect::new(0, 25, 700, 350));
+        assert_eq!(Scaling::Letterbox.viewport(900, 400), Rect::new(50, 0, 800, 400));
+        assert_eq!(Scaling::Integer.fit(640, 320, 256, 192), Rect::new(192, 64, 256, 192));
+        assert_eq!(Scaling::Letterbox.fit(640, 320, 256, 192), Rect::new(107, 0, 426, 320));
+        // a 1366x768 screen
+        assert_eq!(Scaling::Integer.fullscreen().viewport(1366, 768), Rect::new(11, 48, 1344, 672));
+        assert_eq!(Scaling::Stretch.fullscreen().viewport(1366, 768), Rect::new(0, 42, 1366, 683));
     }
 }
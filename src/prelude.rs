@@ -0,0 +1,13 @@
+// Everything a frontend needs to drive the emulator:
+// `use hachip::prelude::*;`
+pub use crate::cpu::Cpu;
+pub use crate::emulator::Emulator;
+pub use crate::errors::EmulateCycleError;
+pub use crate::frontend::FrontendOptions;
+pub use crate::headless::HeadlessDisplay;
+pub use crate::keypad::Keypad;
+pub use crate::palette::Palette;
+pub use crate::ppu::{Color, Display, PixelGrid, Ppu, Rect, Scaling};
+pub use crate::profile::EmulationProfile;
+pub use crate::quirks::{Platform, Quirks};
+pub use crate::scheduler::{run_frame, Scheduler};
@@ -0,0 +1,118 @@
+use crate::cpu::Cpu;
+use std::time::{Duration, Instant};
+
+// What a hook sees around a present: the emulation clock, the wall clock
+// at the moment of the call, the play clock and the framebuffer being shown.
+pub struct PresentInfo<'a> {
+    // 60Hz frames emulated since the cpu was reset
+    pub frame: u64,
+    // `frame` as emulated time
+    pub emulated: Duration,
+    pub wall: Instant,
+    // time played this session, see `PlayClock`
+    pub played: Duration,
+    pub framebuffer: &'a [u8],
+}
+
+// Observes presents, so capture sinks, statistics and latency measurements
+// don't each need their own place in the render path. Both methods default
+// to doing nothing.
+pub trait PresentHook {
+    fn on_before_present(&mut self, _info: &PresentInfo) {}
+
+    fn on_after_present(&mut self, _info: &PresentInfo) {}
+}
+
+// The hooks a frontend calls, in the order they were added.
+#[derive(Default)]
+pub struct PresentHooks {
+    hooks: Vec<Box<dyn PresentHook>>,
+}
+
+impl PresentHooks {
+    pub fn new() -> PresentHooks {
+        PresentHooks::default()
+    }
+
+    pub fn add(&mut self, hook: Box<dyn PresentHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn before_present(&mut self, cpu: &Cpu, played: Duration) {
+        if !self.hooks.is_empty() {
+            self.notify_before(&info(cpu, played));
+        }
+    }
+
+    pub fn after_present(&mut self, cpu: &Cpu, played: Duration) {
+        if !self.hooks.is_empty() {
+            self.notify_after(&info(cpu, played));
+        }
+    }
+
+    // The same for frontends without the cpu at hand, see cpu_thread.
+    pub fn notify_before(&mut self, info: &PresentInfo) {
+        self.hooks.iter_mut().for_each(|hook| hook.on_before_present(info));
+    }
+
+    pub fn notify_after(&mut self, info: &PresentInfo) {
+        self.hooks.iter_mut().for_each(|hook| hook.on_after_present(info));
+    }
+}
+
+fn info(cpu: &Cpu, played: Duration) -> PresentInfo<'_> {
+    PresentInfo {
+        frame: cpu.frame_count(),
+        emulated: cpu.emulated_time(),
+        wall: Instant::now(),
+        played,
+        framebuffer: cpu.display().framebuffer(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PresentHook, PresentHooks, PresentInfo};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::scheduler;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    struct Log(Rc<RefCell<Vec<String>>>);
+
+    impl PresentHook for Log {
+        fn on_before_present(&mut self, info: &PresentInfo) {
+            self.0.borrow_mut().push(format!("before {} {:?}", info.frame, info.emulated));
+        }
+
+        fn on_after_present(&mut self, info: &PresentInfo) {
+            self.0.borrow_mut().push(format!("after {} {:?}", info.frame, info.played));
+        }
+    }
+
+    #[test]
+    fn hooks_see_the_emulation_clock() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // JP 0x200
+        cpu.load(vec![0x12, 0x00]).unwrap();
+        for _ in 0..3 {
+            scheduler::run_frame(&mut cpu, 10).unwrap();
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut hooks = PresentHooks::new();
+        hooks.add(Box::new(Log(log.clone())));
+        hooks.before_present(&cpu, Duration::from_secs(1));
+        hooks.after_present(&cpu, Duration::from_secs(1));
+
+        assert_eq!(*log.borrow(), ["before 3 50ms", "after 3 1s"]);
+        assert_eq!(cpu.emulated_time(), Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::quirks::{Platform, Quirks};
+use std::fmt;
+
+// Quirk names in the serialized form, in field order.
+const QUIRK_NAMES: [&str; 10] = [
+    "load-store-increment-i",
+    "jump-vx",
+    "vf-reset",
+    "display-wait",
+    "vf-result",
+    "clip-sprites",
+    "increment-i-by-x",
+    "vip-timing",
+    "shift-vy",
+    "megachip",
+];
+
+// The interpreter settings a run used, written into every artifact
+// (screenshots, recordings, save states, error reports) so they can be
+// reproduced without guessing the configuration. Serialized as one line:
+//
+//   platform=vip quirks=load-store-increment-i,vf-reset,display-wait
+//
+// `platform` is the platform whose quirks match, or `custom`; `quirks` lists
+// the enabled ones, or `none`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct EmulationProfile {
+    pub platform: Option<Platform>,
+    pub quirks: Quirks,
+}
+
+impl EmulationProfile {
+    pub fn new(quirks: Quirks) -> EmulationProfile {
+        EmulationProfile {
+            platform: Platform::ALL.iter().copied().find(|platform| platform.quirks() == quirks),
+            quirks,
+        }
+    }
+
+    // Reads the serialized form back. The quirks decide the profile; the
+    // platform name is informational.
+    pub fn parse(text: &str) -> Option<EmulationProfile> {
+        let mut quirks = None;
+        for field in text.split_whitespace() {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "platform" => {}
+                "quirks" => quirks = Some(parse_quirks(value)?),
+                _ => return None,
+            }
+        }
+        quirks.map(EmulationProfile::new)
+    }
+
+    fn flags(&self) -> [bool; 10] {
+        let quirks = &self.quirks;
+        [
+            quirks.load_store_increment_i,
+            quirks.jump_vx,
+            quirks.vf_reset,
+            quirks.display_wait,
+            quirks.vf_result,
+            quirks.clip_sprites,
+            quirks.increment_i_by_x,
+            quirks.vip_timing,
+            quirks.shift_vy,
+            quirks.megachip,
+        ]
+    }
+}
+
+impl fmt::Display for EmulationProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let platform = self.platform.map_or("custom", Platform::name);
+        let enabled: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .zip(self.flags().iter())
+            .filter(|(_, on)| **on)
+            .map(|(name, _)| *name)
+            .collect();
+        let quirks = if enabled.is_empty() { "none".to_string() } else { enabled.join(",") };
+        write!(f, "platform={} quirks={}", platform, quirks)
+    }
+}
+
+fn parse_quirks(value: &str) -> Option<Quirks> {
+    let mut flags = [false; 10];
+    if value != "none" {
+        for name in value.split(',') {
+            let index = QUIRK_NAMES.iter().position(|known| *known == name)?;
+            flags[index] = true;
+        }
+    }
+    Some(Quirks {
+        load_store_increment_i: flags[0],
+        jump_vx: flags[1],
+        vf_reset: flags[2],
+        display_wait: flags[3],
+        vf_result: flags[4],
+        clip_sprites: flags[5],
+        increment_i_by_x: flags[6],
+        vip_timing: flags[7],
+        shift_vy: flags[8],
+        megachip: flags[9],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmulationProfile;
+    use crate::quirks::Platform;
+
+    #[test]
+    fn round_trips_and_names_the_platform() {
+        let vip = EmulationProfile::new(Platform::Vip.quirks());
+        assert_eq!(vip.to_string(), "platform=vip quirks=load-store-increment-i,vf-reset,display-wait,clip-sprites,shift-vy");
+        assert_eq!(EmulationProfile::parse(&vip.to_string()), Some(vip));
+        let mega = EmulationProfile::new(Platform::MegaChip.quirks());
+        assert!(mega.to_string().starts_with("platform=megachip quirks="));
+        assert!(mega.to_string().ends_with(",megachip"));
+        assert_eq!(EmulationProfile::parse(&mega.to_string()), Some(mega));
+
+        let mut quirks = Platform::Schip.quirks();
+        quirks.jump_vx = false;
+        quirks.clip_sprites = false;
+        let custom = EmulationProfile::new(quirks);
+        assert_eq!(custom.to_string(), "platform=custom quirks=none");
+        assert_eq!(EmulationProfile::parse("platform=custom quirks=none"), Some(custom));
+        assert_eq!(EmulationProfile::parse("quirks=warp-speed"), None);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::cpu::{Cpu, MEMORY_SIZE};
+use crate::opcodes;
+use crate::trace;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+// A backward jump taken while profiling, from the end of a loop back to
+// its start.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loop {
+    pub start: u16,
+    pub end: u16,
+    pub iterations: u64,
+}
+
+// Counts executed instructions per address and per mnemonic, and taken
+// backward jumps, for `profile` in the debugger and `--profile-out`.
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    counts: Vec<u64>,
+    mix: BTreeMap<&'static str, u64>,
+    // (start, end) of loops by times taken
+    loops: HashMap<(u16, u16), u64>,
+    instructions: u64,
+    start_frame: u64,
+}
+
+impl Profiler {
+    // `frame` is the cpu's frame count, to report frames profiled.
+    pub fn new(frame: u64) -> Profiler {
+        Profiler {
+            counts: vec![0; MEMORY_SIZE],
+            mix: BTreeMap::new(),
+            loops: HashMap::new(),
+            instructions: 0,
+            start_frame: frame,
+        }
+    }
+
+    // Called by the cpu after executing `opcode` at `pc`, with PC after it.
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        self.instructions += 1;
+        self.counts[pc as usize % MEMORY_SIZE] += 1;
+        *self.mix.entry(trace::mnemonic_of(opcode)).or_insert(0) += 1;
+        let jump = opcode & 0xF000 == 0x1000 || opcode & 0xF000 == 0xB000;
+        if jump && next_pc <= pc {
+            *self.loops.entry((next_pc, pc)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts.get(address as usize).copied().unwrap_or(0)
+    }
+
+    // The `limit` most executed addresses with their counts.
+    pub fn hot_spots(&self, limit: usize) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = (0..MEMORY_SIZE as u16)
+            .map(|address| (address, self.count(address)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        spots.sort_by_key(|(address, count)| (Reverse(*count), *address));
+        spots.truncate(limit);
+        spots
+    }
+
+    pub fn hot_loops(&self, limit: usize) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self
+            .loops
+            .iter()
+            .map(|((start, end), iterations)| Loop {
+                start: *start,
+                end: *end,
+                iterations: *iterations,
+            })
+            .collect();
+        loops.sort_by_key(|found| (Reverse(found.iterations), found.start, found.end));
+        loops.truncate(limit);
+        loops
+    }
+
+    // Mnemonics by how often they ran, most first.
+    pub fn mix(&self) -> Vec<(&'static str, u64)> {
+        let mut mix: Vec<(&'static str, u64)> = self.mix.iter().map(|(mnemonic, count)| (*mnemonic, *count)).collect();
+        mix.sort_by_key(|(mnemonic, count)| (Reverse(*count), *mnemonic));
+        mix
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.instructions.max(1) as f64
+    }
+
+    // A few lines for the debugger: totals, hot spots, loops and the mix.
+    pub fn summary(&self, cpu: &Cpu) -> String {
+        let mut lines = vec![format!(
+            "{} instructions in {} frames",
+            self.instructions,
+            cpu.frame_count().saturating_sub(self.start_frame)
+        )];
+        for (address, count) in self.hot_spots(5) {
+            lines.push(format!(
+                "{:04X} {:>5.1}%  {}",
+                address,
+                self.percent(count),
+                instruction_at(cpu, address)
+            ));
+        }
+        for found in self.hot_loops(3) {
+            lines.push(format!("loop {:04X}-{:04X} x{}", found.start, found.end, found.iterations));
+        }
+        let mix: Vec<String> = self
+            .mix()
+            .iter()
+            .take(6)
+            .map(|(mnemonic, count)| format!("{} {:.0}%", mnemonic, self.percent(*count)))
+            .collect();
+        lines.push(format!("mix: {}", mix.join(", ")));
+        lines.join("\n")
+    }
+
+    // The full report, as written by `--profile-out`.
+    pub fn to_json(&self, cpu: &Cpu) -> String {
+        let spots: Vec<String> = self
+            .hot_spots(MEMORY_SIZE)
+            .iter()
+            .map(|(address, count)| {
+                format!(
+                    "    {{\"address\": {}, \"count\": {}, \"percent\": {:.2}, \"instruction\": \"{}\"}}",
+                    address,
+                    count,
+                    self.percent(*count),
+                    instruction_at(cpu, *address)
+                )
+            })
+            .collect();
+        let loops: Vec<String> = self
+            .hot_loops(usize::MAX)
+            .iter()
+            .map(|found| {
+                format!(
+                    "    {{\"start\": {}, \"end\": {}, \"iterations\": {}}}",
+                    found.start, found.end, found.iterations
+                )
+            })
+            .collect();
+        let mix: Vec<String> = self
+            .mix()
+            .iter()
+            .map(|(mnemonic, count)| {
+                format!(
+                    "    {{\"mnemonic\": \"{}\", \"count\": {}, \"percent\": {:.2}}}",
+                    mnemonic,
+                    count,
+                    self.percent(*count)
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"instructions\": {},\n  \"frames\": {},\n  \"hot_spots\": [\n{}\n  ],\n  \"hot_loops\": [\n{}\n  ],\n  \"mix\": [\n{}\n  ]\n}}\n",
+            self.instructions,
+            cpu.frame_count().saturating_sub(self.start_frame),
+            spots.join(",\n"),
+            loops.join(",\n"),
+            mix.join(",\n")
+        )
+    }
+}
+
+// The instruction now in memory at `address`.
+fn instruction_at(cpu: &Cpu, address: u16) -> String {
+    match cpu.memory().get(address as usize..address as usize + 2) {
+        Some(bytes) => opcodes::disassemble(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => "?".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Loop, Profiler};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn counts_hot_spots_loops_and_the_mix() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V0, 0 / ADD V0, 1 / SE V0, 4 / JP 0x202 / JP 0x208
+        cpu.load(vec![0x60, 0x00, 0x70, 0x01, 0x30, 0x04, 0x12, 0x02, 0x12, 0x08]).unwrap();
+        cpu.profiler = Some(Profiler::new(cpu.frame_count()));
+        for _ in 0..20 {
+            cpu.step().unwrap();
+        }
+
+        let profiler = cpu.profiler.as_ref().unwrap();
+        assert_eq!(profiler.instructions(), 20);
+        assert_eq!(profiler.count(0x202), 4);
+        assert_eq!(profiler.hot_spots(1), [(0x208, 8)], "the final spin");
+        assert_eq!(
+            profiler.hot_loops(2),
+            [
+                Loop {
+                    start: 0x208,
+                    end: 0x208,
+                    iterations: 8
+                },
+                Loop {
+                    start: 0x202,
+                    end: 0x206,
+                    iterations: 3
+                }
+            ]
+        );
+        assert_eq!(profiler.mix()[0], ("JP", 11));
+        assert!(profiler.summary(&cpu).contains("loop 0202-0206 x3"));
+        let json = profiler.to_json(&cpu);
+        assert!(json.contains("{\"address\": 520, \"count\": 8, \"percent\": 40.00, \"instruction\": \"JP #208\"}"), "{}", json);
+        assert!(json.contains("{\"mnemonic\": \"SE\", \"count\": 4, \"percent\": 20.00}"));
+    }
+}
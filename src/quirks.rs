@@ -0,0 +1,126 @@
+// Behaviours that differ between CHIP-8 interpreters. ROMs are usually
+// written against one of them, so each platform profile bundles the set of
+// quirks its software expects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Quirks {
+    // Fx55/Fx65 leave I pointing past the last register stored or loaded
+    // (I += x + 1) instead of leaving it unchanged.
+    pub load_store_increment_i: bool,
+    // With load_store_increment_i, I is left on the last register (I += x)
+    // rather than past it, as CHIP-48 does.
+    pub increment_i_by_x: bool,
+    // Bnnn is read as Bxnn and jumps to xnn + Vx instead of nnn + V0.
+    pub jump_vx: bool,
+    // 8xy1/8xy2/8xy3 clear VF after the logic operation.
+    pub vf_reset: bool,
+    // 8xy6/8xyE shift Vy into Vx, as the VIP does, instead of shifting Vx
+    // in place and ignoring y.
+    pub shift_vy: bool,
+    // 8xy4-8xyE with x = F leave the result in VF instead of the flag, as
+    // interpreters that write the flag first do.
+    pub vf_result: bool,
+    // Dxyn in lores waits for the vertical blank, so at most one sprite is
+    // drawn per frame.
+    pub display_wait: bool,
+    // Dxyn cuts sprites off at the right and bottom edges instead of
+    // wrapping them around; the position itself wraps either way.
+    pub clip_sprites: bool,
+    // Instructions take the machine cycles they took on the VIP, and a
+    // frame runs as many as fit, instead of a fixed count; see vip_timing.
+    // No platform turns it on by itself.
+    pub vip_timing: bool,
+    // The Mega-Chip instructions (0010-09nn, 00Bn) run instead of being
+    // rejected, and ROMs may fill up to 16MB; see megachip.
+    pub megachip: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum Platform {
+    // original COSMAC VIP interpreter
+    #[default]
+    Vip,
+    // CHIP-48 on the HP48, which SUPER-CHIP grew out of
+    Chip48,
+    // SUPER-CHIP on the HP48
+    Schip,
+    // Mega-Chip, SUPER-CHIP with a 256x192 colour screen and sampled sound
+    MegaChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [Platform::Vip, Platform::Chip48, Platform::Schip, Platform::MegaChip];
+
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Platform::Vip),
+            "chip48" | "chip-48" => Some(Platform::Chip48),
+            "schip" | "superchip" | "super-chip" => Some(Platform::Schip),
+            "megachip" | "mega-chip" => Some(Platform::MegaChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Vip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::Schip => "schip",
+            Platform::MegaChip => "megachip",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks {
+                load_store_increment_i: true,
+                increment_i_by_x: false,
+                jump_vx: false,
+                vf_reset: true,
+                shift_vy: true,
+                vf_result: false,
+                display_wait: true,
+                clip_sprites: true,
+                vip_timing: false,
+                megachip: false,
+            },
+            // jumps with Vx, leaves I one short of where the VIP does after
+            // Fx55/Fx65 and shifts Vx in place
+            Platform::Chip48 => Quirks {
+                load_store_increment_i: true,
+                increment_i_by_x: true,
+                jump_vx: true,
+                vf_reset: false,
+                shift_vy: false,
+                vf_result: false,
+                display_wait: false,
+                clip_sprites: true,
+                vip_timing: false,
+                megachip: false,
+            },
+            Platform::Schip => Quirks {
+                load_store_increment_i: false,
+                increment_i_by_x: false,
+                jump_vx: true,
+                vf_reset: false,
+                shift_vy: false,
+                vf_result: false,
+                display_wait: false,
+                clip_sprites: true,
+                vip_timing: false,
+                megachip: false,
+            },
+            Platform::MegaChip => Quirks {
+                megachip: true,
+                ..Platform::Schip.quirks()
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Platform::default().quirks()
+    }
+}
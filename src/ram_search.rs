@@ -0,0 +1,143 @@
+use crate::cpu::Cpu;
+
+// Most addresses `watch` keeps an eye on at once.
+pub const MAX_WATCHED: usize = 16;
+
+// How a search narrows the candidates, comparing each byte with the value
+// it had at the previous search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    // A value (decimal, or hex with 0x) or one of `changed`, `unchanged`,
+    // `increased` and `decreased`.
+    pub fn parse(text: &str) -> Option<Filter> {
+        match text {
+            "changed" => Some(Filter::Changed),
+            "unchanged" => Some(Filter::Unchanged),
+            "increased" => Some(Filter::Increased),
+            "decreased" => Some(Filter::Decreased),
+            _ => match text.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                None => text.parse().ok(),
+            }
+            .map(Filter::Equal),
+        }
+    }
+
+    fn keeps(&self, before: u8, now: u8) -> bool {
+        match self {
+            Filter::Equal(value) => now == *value,
+            Filter::Changed => now != before,
+            Filter::Unchanged => now == before,
+            Filter::Increased => now > before,
+            Filter::Decreased => now < before,
+        }
+    }
+}
+
+// Finding where a game keeps its score or lives: search for the value
+// shown, play a bit, narrow down by the new value or by how the byte
+// moved, until few addresses are left, then watch those.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RamSearch {
+    // None before the first search, when every address is a candidate
+    candidates: Option<Vec<u16>>,
+    // memory at the last search
+    previous: Vec<u8>,
+    watched: Vec<u16>,
+}
+
+impl RamSearch {
+    pub fn new() -> RamSearch {
+        RamSearch::default()
+    }
+
+    // Keeps the candidates `filter` holds for, returning how many are left.
+    // The first search looks at all of memory, comparing with memory as it
+    // was at `reset` (or as it is, without one).
+    pub fn search(&mut self, cpu: &Cpu, filter: Filter) -> usize {
+        let memory = cpu.memory();
+        if self.previous.len() != memory.len() {
+            self.previous = memory.to_vec();
+        }
+        let previous = &self.previous;
+        let candidates = match self.candidates.take() {
+            Some(candidates) => candidates,
+            None => (0..memory.len() as u16).collect(),
+        };
+        let candidates: Vec<u16> = candidates
+            .into_iter()
+            .filter(|address| filter.keeps(previous[*address as usize], memory[*address as usize]))
+            .collect();
+        let count = candidates.len();
+        self.candidates = Some(candidates);
+        self.previous.copy_from_slice(memory);
+        count
+    }
+
+    // Starts over with every address, remembering memory as it is now.
+    pub fn reset(&mut self, cpu: &Cpu) {
+        self.candidates = None;
+        self.previous = cpu.memory().to_vec();
+    }
+
+    // None before the first search.
+    pub fn candidates(&self) -> Option<&[u16]> {
+        self.candidates.as_deref()
+    }
+
+    // Watches the first MAX_WATCHED candidates, returning how many.
+    pub fn watch_candidates(&mut self) -> usize {
+        self.watched = self.candidates.iter().flatten().take(MAX_WATCHED).copied().collect();
+        self.watched.len()
+    }
+
+    pub fn unwatch(&mut self) {
+        self.watched.clear();
+    }
+
+    pub fn watched(&self) -> &[u16] {
+        &self.watched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, RamSearch};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn narrows_down_to_the_score() {
+        assert_eq!(Filter::parse("0x1F"), Some(Filter::Equal(31)));
+        assert_eq!(Filter::parse("increased"), Some(Filter::Increased));
+        assert_eq!(Filter::parse("256"), None);
+
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        let mut search = RamSearch::new();
+        assert_eq!(search.candidates(), None);
+        search.reset(&cpu);
+        // the score at 3A0 and a byte at 3B0 that also goes to 3
+        cpu.memory_mut()[0x3A0] = 3;
+        cpu.memory_mut()[0x3B0] = 3;
+        assert_eq!(search.search(&cpu, Filter::Equal(3)), 2);
+        cpu.memory_mut()[0x3A0] = 5;
+        cpu.memory_mut()[0x3B0] = 1;
+        assert_eq!(search.search(&cpu, Filter::Increased), 1);
+        assert_eq!(search.search(&cpu, Filter::Unchanged), 1);
+        assert_eq!(search.candidates(), Some(&[0x3A0][..]));
+        assert_eq!(search.watch_candidates(), 1);
+        assert_eq!(search.watched(), [0x3A0]);
+
+        search.reset(&cpu);
+        assert_eq!(search.search(&cpu, Filter::Changed), 0);
+        assert_eq!(search.watched(), [0x3A0], "watching outlives a new search");
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::Config;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// ROMs kept in the list.
+pub const LIMIT: usize = 10;
+
+// The most recently played ROMs, newest first, kept in a `recent` file next
+// to the config file with one path per line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    pub fn new() -> RecentRoms {
+        RecentRoms::default()
+    }
+
+    pub fn parse(text: &str) -> RecentRoms {
+        let mut recent = RecentRoms::new();
+        for line in text.lines().rev().filter(|line| !line.trim().is_empty()) {
+            recent.add(Path::new(line));
+        }
+        recent
+    }
+
+    pub fn to_text(&self) -> String {
+        self.paths.iter().map(|path| format!("{}\n", path.display())).collect()
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    // Moves `rom` to the front, dropping the oldest past LIMIT.
+    pub fn add(&mut self, rom: &Path) {
+        self.paths.retain(|path| path != rom);
+        self.paths.insert(0, rom.to_path_buf());
+        self.paths.truncate(LIMIT);
+    }
+
+    pub fn load(path: &Path) -> io::Result<RecentRoms> {
+        Ok(RecentRoms::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Config::default_path().and_then(|config| Some(config.parent()?.join("recent")))
+}
+
+// The saved list, empty when there is none yet.
+pub fn load() -> RecentRoms {
+    default_path()
+        .and_then(|path| RecentRoms::load(&path).ok())
+        .unwrap_or_default()
+}
+
+// Puts a ROM that was just loaded at the front of the saved list. The path
+// is stored absolute so it works from any directory; failures are logged.
+pub fn remember(rom: &Path) {
+    let path = match default_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let mut recent = load();
+    recent.add(&fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf()));
+    if let Err(error) = recent.save(&path) {
+        log::warn!("recent ROMs {}: {}", path.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecentRoms, LIMIT};
+    use std::path::Path;
+
+    #[test]
+    fn newest_first_without_duplicates() {
+        let mut recent = RecentRoms::parse("/roms/pong.ch8\n/roms/ufo.ch8\n");
+        recent.add(Path::new("/roms/ufo.ch8"));
+        assert_eq!(recent.to_text(), "/roms/ufo.ch8\n/roms/pong.ch8\n");
+        assert_eq!(RecentRoms::parse(&recent.to_text()), recent);
+
+        for index in 0..LIMIT {
+            recent.add(Path::new(&format!("/roms/{}.ch8", index)));
+        }
+        assert_eq!(recent.paths().len(), LIMIT);
+        assert_eq!(recent.paths()[0], Path::new("/roms/9.ch8"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::palette::Palette;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::profile::EmulationProfile;
+use crate::scheduler::FRAME_RATE;
+use crate::screenshot;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Output pixels per display pixel; smaller than screenshots to keep
+// recordings light to share.
+pub const SCALE: usize = 4;
+// Recording stops by itself after two minutes to bound memory use.
+pub const MAX_FRAMES: u32 = 2 * 60 * FRAME_RATE;
+
+// Captures the framebuffer once per 60Hz frame and encodes the result as an
+// animated PNG. Runs of identical frames are kept once with a longer delay.
+pub struct Recording {
+    palette: Palette,
+    profile: EmulationProfile,
+    // framebuffer and how many frames it stayed on screen
+    frames: Vec<(Vec<u8>, u16)>,
+    length: u32,
+}
+
+impl Recording {
+    pub fn new(palette: Palette, profile: EmulationProfile) -> Recording {
+        Recording {
+            palette,
+            profile,
+            frames: Vec::new(),
+            length: 0,
+        }
+    }
+
+    // Adds a frame. Returns false once the recording is full.
+    pub fn push(&mut self, framebuffer: &[u8]) -> bool {
+        if self.full() {
+            return false;
+        }
+        self.length += 1;
+        match self.frames.last_mut() {
+            Some((last, count)) if last.as_slice() == framebuffer && *count < u16::MAX => *count += 1,
+            _ => self.frames.push((framebuffer.to_vec(), 1)),
+        }
+        true
+    }
+
+    pub fn full(&self) -> bool {
+        self.length >= MAX_FRAMES
+    }
+
+    // Length in 60Hz frames.
+    pub fn len(&self) -> u32 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn encode_apng(&self, scale: usize) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = screenshot::new_encoder(&mut png, (WIDTH * scale, HEIGHT * scale), &self.profile);
+        // writing to memory can't fail, and an empty recording is one blank frame
+        encoder.set_animated(self.frames.len().max(1) as u32, 0).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        if self.frames.is_empty() {
+            writer.write_image_data(&screenshot::rgb_image(&[0; WIDTH * HEIGHT], &self.palette, scale)).unwrap();
+        }
+        for (framebuffer, count) in &self.frames {
+            writer.set_frame_delay(*count, FRAME_RATE as u16).unwrap();
+            writer.write_image_data(&screenshot::rgb_image(framebuffer, &self.palette, scale)).unwrap();
+        }
+        writer.finish().unwrap();
+        png
+    }
+
+    // Writes the recording into `dir` as recording-<unix time>.png.
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let path = screenshot::new_path(dir, "recording")?;
+        fs::write(&path, self.encode_apng(SCALE))?;
+        Ok(path)
+    }
+}
+
+// Starts a recording, or stops and saves the running one, from a frontend
+// hotkey. Logs the outcome.
+pub fn toggle(recording: &mut Option<Recording>, dir: Option<&Path>, palette: &Palette, profile: EmulationProfile) {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            log::info!("recording is off, no screenshot directory");
+            return;
+        }
+    };
+    if recording.is_some() {
+        stop(recording, Some(dir));
+    } else if profile.quirks.megachip {
+        // recordings are of the 64x32 screen, which Mega-Chip ROMs leave
+        log::info!("recording isn't available for Mega-Chip ROMs");
+    } else {
+        log::info!("recording started");
+        *recording = Some(Recording::new(*palette, profile));
+    }
+}
+
+// Adds a frame to the running recording, if any, and saves it once full.
+pub fn record_frame(recording: &mut Option<Recording>, framebuffer: &[u8], dir: Option<&Path>) {
+    if let Some(active) = recording {
+        active.push(framebuffer);
+        if active.full() {
+            log::info!("recording reached its maximum length");
+            stop(recording, dir);
+        }
+    }
+}
+
+// Saves and ends the running recording, if any, logging where it went.
+pub fn stop(recording: &mut Option<Recording>, dir: Option<&Path>) {
+    let (done, dir) = match (recording.take(), dir) {
+        (Some(done), Some(dir)) => (done, dir),
+        _ => return,
+    };
+    match done.save(dir) {
+        Ok(path) => log::info!("saved {} frame recording {}", done.len(), path.display()),
+        Err(error) => log::warn!("recording to {} failed: {}", dir.display(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recording;
+    use crate::palette::Palette;
+    use crate::ppu::{HEIGHT, WIDTH};
+    use crate::profile::EmulationProfile;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn identical_frames_become_one_longer_frame() {
+        let mut recording = Recording::new(Palette::default(), EmulationProfile::new(Quirks::default()));
+        let mut framebuffer = [0; WIDTH * HEIGHT];
+        recording.push(&framebuffer);
+        recording.push(&framebuffer);
+        framebuffer[0] = 1;
+        recording.push(&framebuffer);
+        assert_eq!(recording.len(), 3);
+
+        let png = recording.encode_apng(1);
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, 2);
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buffer).unwrap();
+        let first = reader.info().frame_control.unwrap();
+        assert_eq!((first.delay_num, first.delay_den), (2, 60));
+        reader.next_frame(&mut buffer).unwrap();
+        assert_eq!(&buffer[..3], [255, 255, 255]);
+    }
+}
@@ -0,0 +1,441 @@
+use crate::emulator::Emulator;
+use crate::frame_pipe::Input;
+use crate::keymap::{KeyboardLayout, KeymapProfile};
+use crate::palette::Palette;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::scheduler::Scheduler;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// Appended to the client's key to answer the handshake (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+// A viewer that can't take a frame for this long is dropped, so it can't
+// stall the others.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// The server listens on every interface, so whatever a client sends is
+// capped: key messages are a few bytes and fit a one-byte length, and the
+// handshake is a request line and a handful of short headers.
+const MAX_MESSAGE: u64 = 125;
+const MAX_LINE: usize = 4096;
+const MAX_HEADERS: usize = 32;
+
+// What the connection threads hand the emulator loop.
+enum Event {
+    Viewer(TcpStream),
+    Key(u8, bool),
+}
+
+// `--serve <address>`: runs the ROM in real time without a window and
+// serves a viewer page at / that connects back over a WebSocket at /ws.
+// Every viewer gets the screen whenever it changes, as a binary message of
+// WIDTH * HEIGHT bits, row by row and most significant bit first, and can
+// send "press 5" / "release 5" text messages. Runs until the cpu stops.
+pub fn serve(emulator: &mut Emulator, listener: TcpListener, page: String) -> io::Result<()> {
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (sender, page) = (sender.clone(), page.clone());
+            thread::spawn(move || {
+                if let Err(error) = connect(stream, &page, sender) {
+                    log::warn!("remote display: {}", error);
+                }
+            });
+        }
+    });
+
+    let mut viewers: Vec<TcpStream> = Vec::new();
+    let mut screen = pack(emulator.framebuffer());
+    let mut scheduler = Scheduler::new(emulator.instructions_per_frame);
+    loop {
+        for _ in 0..scheduler.frames_due() {
+            loop {
+                match events.try_recv() {
+                    Ok(Event::Viewer(mut viewer)) => {
+                        if write_message(&mut viewer, OPCODE_BINARY, &screen).is_ok() {
+                            viewers.push(viewer);
+                        }
+                    }
+                    Ok(Event::Key(key, pressed)) => emulator.key_event(key, pressed),
+                    Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            emulator.step_frame().map_err(|error| io::Error::other(error.to_string()))?;
+            let packed = pack(emulator.framebuffer());
+            if packed != screen {
+                viewers.retain_mut(|viewer| write_message(viewer, OPCODE_BINARY, &packed).is_ok());
+                screen = packed;
+            }
+        }
+        thread::sleep(scheduler.time_to_next_frame());
+    }
+}
+
+// The viewer, with keys from `keymap` on `layout` and colors from `palette`.
+pub fn viewer_html(keymap: KeymapProfile, layout: KeyboardLayout, palette: &Palette) -> String {
+    let rgb = |pixel| {
+        let color = palette.color(pixel);
+        format!("{}, {}, {}", color.r, color.g, color.b)
+    };
+    VIEWER
+        .replace("{width}", &WIDTH.to_string())
+        .replace("{height}", &HEIGHT.to_string())
+        .replace("{keys}", &keymap.to_js(layout))
+        .replace("{hexpad}", &keymap.describe(layout))
+        .replace("{off}", &rgb(0))
+        .replace("{on}", &rgb(1))
+}
+
+// A bit per pixel, eight to a byte.
+pub fn pack(framebuffer: &[u8]) -> Vec<u8> {
+    framebuffer
+        .chunks(8)
+        .map(|pixels| pixels.iter().enumerate().fold(0, |byte, (bit, pixel)| byte | ((*pixel != 0) as u8) << (7 - bit)))
+        .collect()
+}
+
+// Answers one HTTP request: the page, or the WebSocket whose key messages
+// are passed on until the viewer goes away.
+fn connect(stream: TcpStream, page: &str, events: Sender<Event>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let request_line = read_line(&mut reader)?;
+    let mut key = None;
+    for count in 0.. {
+        let header = read_line(&mut reader)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("more than {} headers", MAX_HEADERS)));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    match (path, key) {
+        ("/ws", Some(key)) => {
+            write!(
+                writer,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )?;
+            writer.set_nodelay(true)?;
+            writer.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            if events.send(Event::Viewer(writer)).is_err() {
+                return Ok(());
+            }
+            while let Some((opcode, payload)) = read_message(&mut reader, MAX_MESSAGE)? {
+                match opcode {
+                    OPCODE_TEXT => match Input::parse(&String::from_utf8_lossy(&payload)) {
+                        Some(Input::Key(key, pressed)) => {
+                            if events.send(Event::Key(key, pressed)).is_err() {
+                                return Ok(());
+                            }
+                        }
+                        // viewers can't stop the server
+                        _ => log::warn!("remote display: ignoring `{}`", String::from_utf8_lossy(&payload)),
+                    },
+                    OPCODE_CLOSE => return Ok(()),
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+        ("/", _) => write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            page.len(),
+            page
+        ),
+        _ => write!(writer, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+// One line of the request, empty once the connection closes. Longer ones
+// than MAX_LINE fail the request.
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE as u64 + 1).read_line(&mut line)?;
+    if line.len() > MAX_LINE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a request line is over {} bytes", MAX_LINE)));
+    }
+    Ok(line)
+}
+
+// One unfragmented message from a viewer, unmasked; None once the
+// connection closes. One longer than `max_length` fails, closing it.
+fn read_message(reader: &mut impl Read, max_length: u64) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut head = [0; 2];
+    match reader.read_exact(&mut head) {
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = match head[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > max_length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a message of {} bytes is over the {} byte limit", length, max_length),
+        ));
+    }
+    let mut mask = [0; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut payload)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+    Ok(Some((head[0] & 0x0F, payload)))
+}
+
+// Servers send unmasked, unfragmented messages.
+fn write_message(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut message = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => message.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            message.push(126);
+            message.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            message.push(127);
+            message.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    message.extend_from_slice(payload);
+    writer.write_all(&message)
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | (*byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+const VIEWER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>hachip</title>
+<style>
+  body { margin: 0; background: #111; color: #ccc; font-family: sans-serif; text-align: center; }
+  canvas { width: 100%; max-width: 960px; image-rendering: pixelated; margin-top: 2em; }
+</style>
+</head>
+<body>
+<canvas width="{width}" height="{height}"></canvas>
+<p>Connecting...</p>
+<script>
+const canvas = document.querySelector("canvas");
+const context = canvas.getContext("2d");
+const image = context.createImageData(canvas.width, canvas.height);
+const message = document.querySelector("p");
+const colors = [[{off}, 255], [{on}, 255]];
+
+const socket = new WebSocket(`ws://${location.host}/ws`);
+socket.binaryType = "arraybuffer";
+socket.onopen = () => { message.textContent = "Keys {hexpad} play the hexpad."; };
+socket.onclose = () => { message.textContent = "Disconnected."; };
+socket.onmessage = (event) => {
+  const bits = new Uint8Array(event.data);
+  for (let pixel = 0; pixel < canvas.width * canvas.height; pixel++) {
+    image.data.set(colors[(bits[pixel >> 3] >> (7 - (pixel & 7))) & 1], pixel * 4);
+  }
+  context.putImageData(image, 0, 0);
+};
+
+const keys = {keys};
+const send = (event, action) => {
+  const key = keys[event.code];
+  if (key === undefined) { return; }
+  event.preventDefault();
+  if (!event.repeat && socket.readyState === WebSocket.OPEN) { socket.send(`${action} ${key.toString(16)}`); }
+};
+document.addEventListener("keydown", (event) => send(event, "press"));
+document.addEventListener("keyup", (event) => send(event, "release"));
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        accept_key, connect, pack, read_line, read_message, serve, viewer_html, write_message, MAX_HEADERS, MAX_LINE,
+        MAX_MESSAGE, OPCODE_BINARY, OPCODE_TEXT,
+    };
+    use crate::emulator::Emulator;
+    use crate::headless::HeadlessDisplay;
+    use crate::keymap::{KeyboardLayout, KeymapProfile};
+    use crate::palette::Palette;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn streams_the_screen_and_takes_keys_over_a_websocket() {
+        // the example from RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(pack(&[1, 0, 0, 0, 0, 0, 0, 1, 0, 1]), [0x81, 0x40]);
+        let page = viewer_html(KeymapProfile::Standard, KeyboardLayout::Azerty, &Palette::default());
+        assert!(page.contains("\"KeyQ\": 4") && page.contains("width=\"64\""));
+        assert!(page.contains("Keys 1 2 3 4 / A Z E R / Q S D F / W X C V play"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut emulator = Emulator::new(Box::new(HeadlessDisplay::new()));
+            // waits for 5, then draws a 5: LD V0, 5 / SKP V0 / JP 0x202 / LD F, V0 / DRW V0, V0, 5 / JP 0x20A
+            emulator.load_rom(&[0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0A]).unwrap();
+            serve(&mut emulator, listener, "<html>".to_string())
+        });
+
+        let mut page = String::new();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        stream.read_to_string(&mut page).unwrap();
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n") && page.ends_with("\r\n\r\n<html>"), "{}", page);
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer
+            .write_all(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            reader.read_line(&mut response).unwrap();
+        }
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        // a masked "press 5", as browsers send it
+        let mask = [1, 2, 3, 4];
+        let mut message = vec![0x80 | OPCODE_TEXT, 0x80 | 7];
+        message.extend_from_slice(&mask);
+        message.extend(b"press 5".iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+        writer.write_all(&message).unwrap();
+        loop {
+            let (opcode, screen) = read_message(&mut reader, 256).unwrap().unwrap();
+            assert_eq!(opcode, OPCODE_BINARY);
+            // the 5's top row, at (5, 5)
+            if screen.len() == 256 && screen[5 * 8] == 0b0000_0111 && screen[5 * 8 + 1] == 0b1000_0000 {
+                break;
+            }
+        }
+
+        let mut written = Vec::new();
+        write_message(&mut written, OPCODE_BINARY, &[0; 300]).unwrap();
+        assert_eq!(written[..4], [0x82, 126, 0x01, 0x2C]);
+        assert_eq!(read_message(&mut &written[..], 300).unwrap(), Some((OPCODE_BINARY, vec![0; 300])));
+    }
+
+    #[test]
+    fn refuses_long_messages_lines_and_header_lists() {
+        let mut written = Vec::new();
+        write_message(&mut written, OPCODE_TEXT, &[b'a'; MAX_MESSAGE as usize + 1]).unwrap();
+        assert!(read_message(&mut &written[..], MAX_MESSAGE).is_err());
+        // a 64-bit length is refused before anything is read for it
+        let huge = [0x80 | OPCODE_TEXT, 127, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(read_message(&mut &huge[..], MAX_MESSAGE).is_err());
+
+        let line = format!("{}\r\n", "a".repeat(MAX_LINE));
+        assert!(read_line(&mut line.as_bytes()).is_err());
+        assert_eq!(read_line(&mut &line.as_bytes()[2..]).unwrap().len(), MAX_LINE);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut request = "GET / HTTP/1.1\r\n".to_string();
+            request += &"X-Padding: a\r\n".repeat(MAX_HEADERS + 1);
+            let _ = stream.write_all(request.as_bytes());
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let (sender, _events) = mpsc::channel();
+        assert!(connect(stream, "<html>", sender).is_err());
+    }
+}
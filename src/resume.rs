@@ -0,0 +1,193 @@
+use crate::cpu::Cpu;
+use crate::ppu::{Display, WIDTH};
+use crate::session;
+use crate::state::SaveState;
+use crate::text::{Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const FILE: &str = "resume.state";
+const CONTINUE: usize = 0;
+const NEW_GAME: usize = 1;
+
+// `--resume` (`resume = true` in the config): the game is saved when the
+// window closes, and the next launch of the ROM offers to continue from
+// there. One state per ROM, replaced on every close.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resume {
+    dir: PathBuf,
+}
+
+impl Resume {
+    pub fn new(dir: PathBuf) -> Resume {
+        Resume { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn save(&self, state: &SaveState) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(FILE);
+        session::write_atomically(&path, &state.to_bytes())?;
+        Ok(path)
+    }
+
+    // The state saved on the last close, if any. One that can't be read is
+    // logged and not offered.
+    pub fn load(&self) -> Option<SaveState> {
+        let path = self.dir.join(FILE);
+        let state = fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|bytes| SaveState::from_bytes(&bytes).map_err(|error| error.message));
+        match state {
+            Ok(state) => Some(state),
+            Err(_) if !path.exists() => None,
+            Err(error) => {
+                log::warn!("resume state {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    // Saves the game; call once the window closes. Failing to save is
+    // logged and otherwise ignored.
+    pub fn finish(&self, game: &SaveState) {
+        match self.save(game) {
+            Ok(path) => log::info!("saved the game to resume to {}", path.display()),
+            Err(error) => log::warn!("saving the game to resume to {} failed: {}", self.dir.display(), error),
+        }
+    }
+}
+
+// Resume state directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("resume", rom_path, rom)
+}
+
+// What a window shows on launching a ROM with a saved game to resume:
+// continue from it, or start a new game. Back starts a new game too. Like
+// the pause menu it draws on the emulated display, so the new game is kept
+// aside while it is up.
+pub struct ResumePrompt {
+    game: SaveState,
+    saved: SaveState,
+    selected: usize,
+    input: UiInput,
+}
+
+impl ResumePrompt {
+    // None when there is nothing to resume.
+    pub fn offer(cpu: &Cpu, resume: &Resume) -> Option<ResumePrompt> {
+        Some(ResumePrompt {
+            game: cpu.save_state(),
+            saved: resume.load()?,
+            selected: CONTINUE,
+            input: UiInput::new(),
+        })
+    }
+
+    // Returns false once the prompt has closed, with the cpu at the saved
+    // game or back at the start.
+    pub fn key_down(&mut self, key: UiKey, cpu: &mut Cpu) -> bool {
+        match self.input.key_down(key) {
+            Some(UiAction::Confirm) if self.selected == CONTINUE => {
+                cpu.load_state(&self.saved);
+                log::info!("resumed the saved game");
+            }
+            Some(UiAction::Confirm) | Some(UiAction::Back) => cpu.load_state(&self.game),
+            Some(action) => {
+                self.move_selection(action);
+                return true;
+            }
+            None => return true,
+        }
+        false
+    }
+
+    fn move_selection(&mut self, action: UiAction) {
+        match action {
+            UiAction::Up => self.selected = CONTINUE,
+            UiAction::Down => self.selected = NEW_GAME,
+            _ => {}
+        }
+    }
+
+    // Draws the prompt over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        text.draw_text(0, 0, "CONTINUE WHERE");
+        text.draw_text(0, LINE_HEIGHT as i32, "YOU LEFT OFF?");
+        for (row, label) in ["CONTINUE", "NEW GAME"].iter().enumerate() {
+            let marker = if row == self.selected { '>' } else { ' ' };
+            text.draw_text(0, ((row + 3) * LINE_HEIGHT) as i32, &format!("{}{}", marker, label));
+        }
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+    }
+}
+
+impl UiScreen for ResumePrompt {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.move_selection(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Resume, ResumePrompt};
+    use crate::cpu::Cpu;
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::ui_input::UiKey;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn offers_the_game_saved_on_close() {
+        let dir = env::temp_dir().join(format!("hachip-resume-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let resume = Resume::new(dir.clone());
+        let new_cpu = || {
+            let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+            cpu.reset();
+            // ADD V0, 1 / JP 0x200
+            cpu.load(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+            cpu
+        };
+        let mut cpu = new_cpu();
+        assert!(ResumePrompt::offer(&cpu, &resume).is_none(), "nothing saved yet");
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        resume.finish(&cpu.save_state());
+
+        let mut cpu = new_cpu();
+        let mut prompt = ResumePrompt::offer(&cpu, &resume).unwrap();
+        prompt.render(cpu.display_mut());
+        assert!(headless::dump_text(cpu.display().framebuffer()).contains('#'));
+        assert!(!prompt.key_down(UiKey::Enter, &mut cpu));
+        assert_eq!(cpu.registers()[0], 3, "continued");
+
+        let mut cpu = new_cpu();
+        let mut prompt = ResumePrompt::offer(&cpu, &resume).unwrap();
+        assert!(prompt.key_down(UiKey::Down, &mut cpu));
+        assert!(!prompt.key_down(UiKey::Enter, &mut cpu));
+        assert_eq!(cpu.registers()[0], 0, "a new game");
+        assert!(!cpu.display().framebuffer().contains(&1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
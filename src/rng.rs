@@ -0,0 +1,116 @@
+// Where Cxkk gets its random bytes. `frame` is the emulation clock at the
+// draw, which a source replaying a log uses to notice a desync.
+pub trait RandomSource: Send {
+    fn draw(&mut self, frame: u64) -> u8;
+}
+
+// One logged random byte and the frame it was drawn in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Draw {
+    pub frame: u64,
+    pub value: u8,
+}
+
+// The default source. Seedable so a recorded movie replays exactly; seeded
+// from the OS otherwise. SplitMix64, which is fast and fine with any seed
+// including zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn from_entropy() -> Rng {
+        Rng::new(random_seed())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+impl RandomSource for Rng {
+    fn draw(&mut self, _frame: u64) -> u8 {
+        self.next_u8()
+    }
+}
+
+// Hands out the bytes of a draw log in order, so a run replays whatever
+// generator produced it. Past the end of the log `fallback` takes over.
+pub struct Replay {
+    draws: Vec<Draw>,
+    next: usize,
+    fallback: Rng,
+    desynced: bool,
+}
+
+impl Replay {
+    pub fn new(draws: Vec<Draw>, fallback: Rng) -> Replay {
+        Replay {
+            draws,
+            next: 0,
+            fallback,
+            desynced: false,
+        }
+    }
+}
+
+impl RandomSource for Replay {
+    fn draw(&mut self, frame: u64) -> u8 {
+        let draw = match self.draws.get(self.next) {
+            Some(draw) => *draw,
+            None => return self.fallback.next_u8(),
+        };
+        self.next += 1;
+        if draw.frame != frame && !self.desynced {
+            log::warn!("random draw logged in frame {} replayed in frame {}, replay desynced", draw.frame, frame);
+            self.desynced = true;
+        }
+        if self.next == self.draws.len() {
+            log::info!("draw log finished after {} draws", self.next);
+        }
+        draw.value
+    }
+}
+
+// A seed from the OS random source.
+pub fn random_seed() -> u64 {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).unwrap();
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Draw, RandomSource, Replay, Rng};
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        let first: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+        let second: Vec<u8> = (0..16).map(|_| b.next_u8()).collect();
+        assert_eq!(first, second);
+        assert_ne!(Rng::new(0).next_u64(), Rng::new(1).next_u64());
+    }
+
+    #[test]
+    fn replay_returns_the_log_then_falls_back() {
+        let draws = vec![Draw { frame: 0, value: 7 }, Draw { frame: 2, value: 9 }];
+        let mut replay = Replay::new(draws, Rng::new(1));
+        assert_eq!(replay.draw(0), 7);
+        assert_eq!(replay.draw(2), 9);
+        assert_eq!(replay.draw(3), Rng::new(1).next_u8());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::assets::ROM_DATABASE;
+use crate::cpu::PROGRAM_START;
+use crate::headless;
+use crate::quirks::Platform;
+use std::fmt;
+
+// What the embedded database (assets/romdb.txt) knows about a ROM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct RomInfo {
+    pub hash: u64,
+    pub title: &'static str,
+    pub author: &'static str,
+    pub platform: Platform,
+    pub instructions_per_frame: u32,
+    pub load_address: u16,
+}
+
+impl fmt::Display for RomInfo {
+    // "Pong by Paul Vervalin (vip, 10 instructions per frame)", with the load
+    // address when it isn't the usual one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} by {} ({}, {} instructions per frame",
+            self.title,
+            self.author,
+            self.platform.name(),
+            self.instructions_per_frame
+        )?;
+        if self.load_address as usize != PROGRAM_START {
+            write!(f, ", loaded at {:#X}", self.load_address)?;
+        }
+        write!(f, ")")
+    }
+}
+
+// The database entry for a ROM, matched by its FNV-1a hash.
+pub fn lookup(rom: &[u8]) -> Option<RomInfo> {
+    let hash = headless::hash(rom);
+    ROM_DATABASE
+        .iter()
+        .find(|entry| entry.0 == hash)
+        .and_then(|&(hash, platform, instructions_per_frame, load_address, title, author)| {
+            Some(RomInfo {
+                hash,
+                title,
+                author,
+                platform: Platform::from_name(platform)?,
+                instructions_per_frame,
+                load_address,
+            })
+        })
+}
+
+// Looks the ROM up and logs what was detected.
+pub fn detect(rom: &[u8]) -> Option<RomInfo> {
+    let info = lookup(rom)?;
+    log::info!("detected {}", info);
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lookup;
+    use crate::assets::{rom, ROM_DATABASE};
+    use crate::quirks::Platform;
+
+    #[test]
+    fn finds_known_roms_by_hash() {
+        let info = lookup(rom("keypad").unwrap()).unwrap();
+        assert_eq!(info.title, "Keypad Test");
+        assert_eq!(info.platform, Platform::Vip);
+        assert_eq!(info.instructions_per_frame, 10);
+        assert_eq!(info.load_address, 0x200);
+        assert_eq!(lookup(&[0x12, 0x00]), None);
+
+        for entry in ROM_DATABASE {
+            assert!(Platform::from_name(entry.1).is_some(), "unknown platform {}", entry.1);
+            assert!(entry.3 >= 0x200 && entry.3 < 0x1000, "load address {:X}", entry.3);
+        }
+    }
+}
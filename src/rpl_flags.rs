@@ -0,0 +1,96 @@
+use crate::cpu::Cpu;
+use crate::session;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const FILE: &str = "flags.bin";
+
+// Keeps a ROM's RPL user flags (Fx75/Fx85) in a file of its own, so games
+// that save their high scores there remember them across runs, as they did
+// on the HP48.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlagStore {
+    dir: PathBuf,
+}
+
+impl FlagStore {
+    pub fn new(dir: &Path) -> FlagStore {
+        FlagStore { dir: dir.to_path_buf() }
+    }
+
+    // Puts the saved flags into the cpu, if there are any. A file that
+    // can't be read is logged and the flags start out clear.
+    pub fn restore(&self, cpu: &mut Cpu) {
+        let path = self.dir.join(FILE);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let mut flags = [0; 16];
+                let count = bytes.len().min(flags.len());
+                flags[..count].copy_from_slice(&bytes[..count]);
+                cpu.set_rpl_flags(flags);
+                log::info!("loaded the RPL flags from {}", path.display());
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => log::warn!("RPL flags {}: {}", path.display(), error),
+        }
+    }
+
+    pub fn save(&self, flags: &[u8; 16]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(FILE);
+        session::write_atomically(&path, flags)
+    }
+
+    // Saves the flags once Fx75 wrote them; call after every frame.
+    // Failing to save is logged and otherwise ignored.
+    pub fn tick(&self, cpu: &mut Cpu) {
+        if !cpu.take_rpl_flags_written() {
+            return;
+        }
+        if let Err(error) = self.save(&cpu.rpl_flags()) {
+            log::warn!("saving the RPL flags to {} failed: {}", self.dir.display(), error);
+        }
+    }
+}
+
+// RPL flag directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("flags", rom_path, rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlagStore;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn remembers_the_flags_across_runs() {
+        let dir = env::temp_dir().join(format!("hachip-rpl-flags-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = FlagStore::new(&dir);
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V0, 42 / LD V1, 7 / LD R, V1
+        cpu.load(vec![0x60, 0x2A, 0x61, 0x07, 0xF1, 0x75]).unwrap();
+        store.restore(&mut cpu);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        store.tick(&mut cpu);
+        assert!(!dir.exists(), "nothing to save yet");
+        cpu.step().unwrap();
+        store.tick(&mut cpu);
+
+        let mut next_run = Cpu::new(Box::new(HeadlessDisplay::new()));
+        next_run.reset();
+        // LD V1, R
+        next_run.load(vec![0xF1, 0x85]).unwrap();
+        store.restore(&mut next_run);
+        next_run.step().unwrap();
+        assert_eq!(next_run.registers()[..3], [42, 7, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use crate::headless;
+use crate::session;
+use crate::state::SaveState;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Numbered slots per ROM, 0 to SLOTS - 1.
+pub const SLOTS: usize = 10;
+
+// What a slot holds besides the state. Its screen, the state's
+// framebuffer, is the thumbnail the slot picker shows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlotInfo {
+    // seconds since the Unix epoch
+    pub saved: u64,
+    pub rom_hash: u64,
+    pub framebuffer: Vec<u8>,
+}
+
+// The numbered save states of one ROM, each kept as slot-<n>.state with a
+// slot-<n>.info next to it:
+//
+//   saved = 1760620800
+//   rom = 8c6d0f1e2a3b4c5d
+//
+// A state is only loaded into the ROM it was saved from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveSlots {
+    dir: PathBuf,
+    rom_hash: u64,
+}
+
+impl SaveSlots {
+    pub fn new(dir: PathBuf, rom: &[u8]) -> SaveSlots {
+        SaveSlots {
+            dir,
+            rom_hash: headless::hash(rom),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Replaces what the slot held.
+    pub fn save(&self, slot: usize, state: &SaveState) -> io::Result<PathBuf> {
+        if slot >= SLOTS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("there is no slot {}", slot)));
+        }
+        fs::create_dir_all(&self.dir)?;
+        let saved = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path = self.path(slot);
+        fs::write(&path, state.to_bytes())?;
+        let info = format!("saved = {}\nrom = {:016x}\n", saved, self.rom_hash);
+        fs::write(path.with_extension("info"), info)?;
+        Ok(path)
+    }
+
+    // Refuses states of another ROM, and ones without an info file to tell.
+    pub fn load(&self, slot: usize) -> io::Result<SaveState> {
+        let (_, rom_hash) = self.read_info(slot)?;
+        if rom_hash != self.rom_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("slot {} was saved from another ROM", slot),
+            ));
+        }
+        self.read_state(slot)
+    }
+
+    // None for an empty slot, or one that can't be read.
+    pub fn info(&self, slot: usize) -> Option<SlotInfo> {
+        let (saved, rom_hash) = self.read_info(slot).ok()?;
+        let state = self.read_state(slot).ok()?;
+        Some(SlotInfo {
+            saved,
+            rom_hash,
+            framebuffer: state.framebuffer,
+        })
+    }
+
+    // Whether the slot holds a state of this ROM.
+    pub fn is_compatible(&self, info: &SlotInfo) -> bool {
+        info.rom_hash == self.rom_hash
+    }
+
+    fn path(&self, slot: usize) -> PathBuf {
+        self.dir.join(format!("slot-{}.state", slot))
+    }
+
+    fn read_state(&self, slot: usize) -> io::Result<SaveState> {
+        let bytes = fs::read(self.path(slot))?;
+        SaveState::from_bytes(&bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.message))
+    }
+
+    fn read_info(&self, slot: usize) -> io::Result<(u64, u64)> {
+        let path = self.path(slot).with_extension("info");
+        let text = fs::read_to_string(&path)?;
+        let mut saved = None;
+        let mut rom_hash = None;
+        for line in text.lines() {
+            match line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) {
+                Some(("saved", value)) => saved = value.parse().ok(),
+                Some(("rom", value)) => rom_hash = u64::from_str_radix(value, 16).ok(),
+                _ => {}
+            }
+        }
+        match (saved, rom_hash) {
+            (Some(saved), Some(rom_hash)) => Ok((saved, rom_hash)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is incomplete", path.display()))),
+        }
+    }
+}
+
+// Save slot directory of a ROM.
+pub fn rom_dir(rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    session::rom_data_dir("slots", rom_path, rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SaveSlots;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn keeps_states_with_their_rom() {
+        let dir = env::temp_dir().join(format!("hachip-save-slots-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rom = vec![0x60, 0x2A, 0x12, 0x02];
+        let slots = SaveSlots::new(dir.clone(), &rom);
+        assert_eq!(slots.info(3), None);
+        assert!(slots.load(3).is_err());
+
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(rom.clone()).unwrap();
+        cpu.step().unwrap();
+        slots.save(3, &cpu.save_state()).unwrap();
+        assert!(slots.save(10, &cpu.save_state()).is_err());
+        let info = slots.info(3).unwrap();
+        assert!(info.saved > 0);
+        assert!(slots.is_compatible(&info));
+        assert_eq!(info.framebuffer, cpu.display().framebuffer());
+
+        let mut restored = Cpu::new(Box::new(HeadlessDisplay::new()));
+        restored.load_state(&slots.load(3).unwrap());
+        assert_eq!(restored.registers()[0], 0x2A);
+
+        let other = SaveSlots::new(dir.clone(), &[0x12, 0x00]);
+        assert!(!other.is_compatible(&info));
+        assert_eq!(other.load(3).unwrap_err().to_string(), "slot 3 was saved from another ROM");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
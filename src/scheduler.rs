@@ -0,0 +1,382 @@
+use crate::cpu::Cpu;
+use crate::errors::EmulateCycleError;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Timers and the display run at 60Hz; the cpu runs a fixed number of
+// instructions inside each of those frames.
+pub const FRAME_RATE: u32 = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+// Catching up after a stall (window drag, breakpoint) is capped so the
+// emulator doesn't fast-forward through the missed time.
+const MAX_FRAMES_PER_UPDATE: u32 = 4;
+
+// Bounds of the time scale, in percent; halving and doubling steps
+// through the powers of two between them.
+pub const MIN_TIME_SCALE: u32 = 25;
+pub const MAX_TIME_SCALE: u32 = 800;
+// How much faster time runs while fast-forwarding.
+pub const FAST_FORWARD: u32 = 4;
+
+// How often the speed meter reports.
+const METER_PERIOD: Duration = Duration::from_secs(1);
+
+// Bounds of how early the frame limiter stops sleeping to spin. Sleeps
+// overshoot by up to a timer tick, a millisecond or two on most systems.
+const MIN_SPIN_MARGIN: Duration = Duration::from_micros(200);
+const MAX_SPIN_MARGIN: Duration = Duration::from_millis(4);
+
+// Time is kept in nanoseconds multiplied by FRAME_RATE, so a frame is
+// exactly one second of these units and no rounding error builds up.
+const FRAME: u64 = 1_000_000_000;
+
+// Runs one frame: up to `instructions` cycles, cut short when a draw waits
+// for the vertical blank, followed by the vblank itself.
+// Returns the number of instructions executed.
+pub fn run_frame(cpu: &mut Cpu, instructions: u32) -> Result<u32, EmulateCycleError> {
+    let mut executed = 0;
+    while executed < instructions {
+        cpu.step()?;
+        executed += 1;
+        if cpu.waiting_for_vblank() {
+            break;
+        }
+    }
+    cpu.vblank();
+    Ok(executed)
+}
+
+// Fixed-timestep clock for real-time frontends. The frame math is integer
+// only; targets without `Instant` (or floats) can feed `advance` from their
+// own timer instead of calling `frames_due`.
+pub struct Scheduler {
+    pub instructions_per_frame: u32,
+    // Emulated time per real time in percent, timers and display included:
+    // 50 is slow motion, 200 runs two frames in the time of one.
+    pub time_scale: u32,
+    // Runs FAST_FORWARD times the time scale while set.
+    pub fast_forward: bool,
+    // time owed since the last whole frame, in FRAME units
+    accumulator: u64,
+    last_update: Instant,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_frame: u32) -> Scheduler {
+        Scheduler {
+            instructions_per_frame,
+            time_scale: 100,
+            fast_forward: false,
+            accumulator: 0,
+            last_update: Instant::now(),
+        }
+    }
+
+    // Number of whole frames that became due since the last call.
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+        self.advance(elapsed.as_nanos().min(u64::MAX as u128) as u64)
+    }
+
+    // Adds `nanos` of elapsed time and returns the whole frames now due.
+    pub fn advance(&mut self, nanos: u64) -> u32 {
+        let scaled = nanos.saturating_mul(FRAME_RATE as u64).saturating_mul(self.scale() as u64) / 100;
+        self.accumulator = self.accumulator.saturating_add(scaled);
+        let frames = self.accumulator / FRAME;
+        self.accumulator %= FRAME;
+        // a fast clock owes more frames per update without having stalled
+        let cap = MAX_FRAMES_PER_UPDATE as u64 * (self.scale() as u64).div_ceil(100);
+        frames.min(cap) as u32
+    }
+
+    pub fn time_to_next_frame(&self) -> Duration {
+        // rounded up, so waking up after it finds the frame due
+        let scaled = (FRAME_RATE * self.scale()) as u64;
+        Duration::from_nanos(((FRAME - self.accumulator) * 100).div_ceil(scaled))
+    }
+
+    // When the next frame is due. Deadlines follow the frame clock, not
+    // the time the last frame happened to run at.
+    pub fn next_deadline(&self) -> Instant {
+        self.last_update + self.time_to_next_frame()
+    }
+
+    // The time scale in effect, fast-forward included.
+    pub fn scale(&self) -> u32 {
+        if self.fast_forward {
+            self.time_scale * FAST_FORWARD
+        } else {
+            self.time_scale
+        }
+    }
+}
+
+// Waits out the time to the next frame. Sleeping alone wakes up late by
+// whatever the OS adds, so it sleeps until shortly before the deadline and
+// spins the rest; the margin follows how late sleeps actually are.
+pub struct FrameLimiter {
+    // presenting blocks until the display's refresh, which paces the loop
+    vsync: bool,
+    spin_margin: Duration,
+}
+
+impl FrameLimiter {
+    pub fn new(vsync: bool) -> FrameLimiter {
+        FrameLimiter {
+            vsync,
+            spin_margin: MAX_SPIN_MARGIN / 2,
+        }
+    }
+
+    // Returns when the scheduler's next frame is due, or right away with
+    // vsync.
+    pub fn wait(&mut self, scheduler: &Scheduler) {
+        if !self.vsync {
+            self.wait_until(scheduler.next_deadline());
+        }
+    }
+
+    pub fn wait_until(&mut self, deadline: Instant) {
+        let now = Instant::now();
+        if let Some(sleep) = deadline.checked_duration_since(now).and_then(|left| left.checked_sub(self.spin_margin)) {
+            thread::sleep(sleep);
+            // grow to a late wake-up at once, shrink back slowly
+            let late = Instant::now().saturating_duration_since(now + sleep);
+            self.spin_margin = if late > self.spin_margin { late } else { (self.spin_margin * 15 + late) / 16 };
+            self.spin_margin = self.spin_margin.clamp(MIN_SPIN_MARGIN, MAX_SPIN_MARGIN);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+// What the speed meter saw over its last period.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Speed {
+    // frames shown per second
+    pub fps: u32,
+    pub instructions_per_second: u64,
+    // emulated frames against real time, 100 at full speed
+    pub percent: u32,
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} FPS {}%\n{} IPS", self.fps, self.percent, self.instructions_per_second)
+    }
+}
+
+// Counts presented frames, emulated frames and instructions, reporting
+// them per second about once a second.
+pub struct SpeedMeter {
+    since: Instant,
+    presents: u64,
+    frames: u64,
+    instructions: u64,
+}
+
+impl SpeedMeter {
+    pub fn new() -> SpeedMeter {
+        SpeedMeter {
+            since: Instant::now(),
+            presents: 0,
+            frames: 0,
+            instructions: 0,
+        }
+    }
+
+    // An emulated frame that ran `instructions`.
+    pub fn frame(&mut self, instructions: u32) {
+        self.frames += 1;
+        self.instructions += instructions as u64;
+    }
+
+    pub fn present(&mut self) {
+        self.presents += 1;
+    }
+
+    // The speed since the last report, once a period has passed.
+    pub fn measure(&mut self) -> Option<Speed> {
+        self.measure_at(Instant::now())
+    }
+
+    fn measure_at(&mut self, now: Instant) -> Option<Speed> {
+        let elapsed = now.duration_since(self.since);
+        if elapsed < METER_PERIOD {
+            return None;
+        }
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        let per_second = |count: u64| (count as u128 * 1_000_000_000 / nanos as u128) as u64;
+        let speed = Speed {
+            fps: per_second(self.presents) as u32,
+            instructions_per_second: per_second(self.instructions),
+            percent: (per_second(self.frames * 100) / FRAME_RATE as u64) as u32,
+        };
+        *self = SpeedMeter {
+            since: now,
+            presents: 0,
+            frames: 0,
+            instructions: 0,
+        };
+        Some(speed)
+    }
+}
+
+impl Default for SpeedMeter {
+    fn default() -> SpeedMeter {
+        SpeedMeter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_frame, FrameLimiter, Scheduler, Speed, SpeedMeter, MAX_SPIN_MARGIN, MIN_SPIN_MARGIN};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::megachip::{self, MegaChip};
+    use crate::quirks::Platform;
+    use std::mem;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // LD V0, 1 / DRW V0, V0, 1 / JP 0x200
+    const DRAW_LOOP: [u8; 6] = [0x60, 0x01, 0xD0, 0x01, 0x12, 0x00];
+
+    fn make_cpu(platform: Platform) -> Cpu {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = platform.quirks();
+        cpu.reset();
+        cpu.load(DRAW_LOOP.to_vec()).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn frame_ends_at_draw_with_display_wait() {
+        let mut cpu = make_cpu(Platform::Vip);
+
+        assert_eq!(run_frame(&mut cpu, 10).unwrap(), 2, "stops after the draw");
+        assert_eq!(run_frame(&mut cpu, 10).unwrap(), 3, "one draw per frame");
+    }
+
+    #[test]
+    fn frame_runs_all_instructions_without_display_wait() {
+        let mut cpu = make_cpu(Platform::Schip);
+
+        assert_eq!(run_frame(&mut cpu, 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn frame_runs_what_fits_in_its_cycles_with_vip_timing() {
+        let mut cpu = make_cpu(Platform::Schip);
+        cpu.quirks.vip_timing = true;
+
+        assert_eq!(run_frame(&mut cpu, 1000).unwrap(), 35);
+        assert_eq!(run_frame(&mut cpu, 1000).unwrap(), 33, "the overrun is made up for");
+        assert_eq!(run_frame(&mut cpu, 10).unwrap(), 10, "the speed still caps it");
+    }
+
+    #[test]
+    fn advance_keeps_exact_frame_time() {
+        let mut scheduler = Scheduler::new(10);
+        let frames: u32 = (0..1000).map(|_| scheduler.advance(1_000_000)).sum();
+        assert_eq!(frames, 60, "a second of 1ms steps is 60 frames, without drift");
+        assert_eq!(scheduler.time_to_next_frame(), Duration::from_nanos(16_666_667));
+        assert_eq!(scheduler.advance(1_000_000_000), 4, "catching up is capped");
+    }
+
+    // The core runs in a fixed amount of memory: the cpu, its memory and
+    // the display are a few KB together, so it fits a static budget on
+    // small targets. Mega-Chip adds its screen buffers, a fixed 432KB more.
+    // That running frames allocates nothing is checked in
+    // tests/allocations.rs.
+    #[test]
+    fn frames_fit_the_memory_budget() {
+        const BUDGET: usize = 7 * 1024;
+        const MEGA_BUDGET: usize = BUDGET + 440 * 1024;
+        let footprint = |cpu: &Cpu| {
+            // the palette and settings are counted twice, a few hundred bytes
+            let mega = cpu.mega().map_or(0, |_| mem::size_of::<MegaChip>() + megachip::STATE_SIZE);
+            mem::size_of::<Cpu>() + cpu.memory().len() + mem::size_of::<HeadlessDisplay>() + mega
+        };
+
+        let cpu = make_cpu(Platform::Schip);
+        assert!(footprint(&cpu) <= BUDGET, "cpu, memory and display take {} bytes", footprint(&cpu));
+        // MEGAON brings in the Mega-Chip buffers
+        let mut cpu = make_cpu(Platform::MegaChip);
+        cpu.step_with_opcode(0x0011).unwrap();
+        assert!(footprint(&cpu) <= MEGA_BUDGET, "with Mega-Chip they take {} bytes", footprint(&cpu));
+    }
+
+    #[test]
+    fn time_scale_stretches_the_frame_time() {
+        let mut scheduler = Scheduler::new(10);
+        scheduler.time_scale = 50;
+        let frames: u32 = (0..1000).map(|_| scheduler.advance(1_000_000)).sum();
+        assert_eq!(frames, 30, "half speed");
+        assert_eq!(scheduler.time_to_next_frame(), Duration::from_nanos(33_333_334));
+
+        scheduler.time_scale = 200;
+        scheduler.fast_forward = true;
+        assert_eq!(scheduler.scale(), 800);
+        let frames: u32 = (0..1000).map(|_| scheduler.advance(1_000_000)).sum();
+        assert_eq!(frames, 480);
+        assert_eq!(scheduler.advance(1_000_000_000), 32, "the cap grows with the scale");
+    }
+
+    #[test]
+    fn speed_meter_reports_per_second() {
+        let mut meter = SpeedMeter::new();
+        let start = meter.since;
+        for _ in 0..60 {
+            meter.frame(10);
+        }
+        for _ in 0..30 {
+            meter.present();
+        }
+        assert_eq!(meter.measure_at(start + Duration::from_millis(500)), None, "before a second");
+        let speed = meter.measure_at(start + Duration::from_secs(2)).unwrap();
+        assert_eq!(
+            speed,
+            Speed {
+                fps: 15,
+                instructions_per_second: 300,
+                percent: 50,
+            }
+        );
+        assert_eq!(speed.to_string(), "15 FPS 50%\n300 IPS");
+        assert_eq!(meter.measure_at(start + Duration::from_secs(3)).unwrap(), Speed::default(), "counts start over");
+    }
+
+    #[test]
+    fn limiter_wakes_up_at_the_deadline() {
+        let mut limiter = FrameLimiter::new(false);
+        for _ in 0..5 {
+            let deadline = Instant::now() + Duration::from_millis(5);
+            limiter.wait_until(deadline);
+            assert!(Instant::now() >= deadline);
+            assert!((MIN_SPIN_MARGIN..=MAX_SPIN_MARGIN).contains(&limiter.spin_margin));
+        }
+
+        let scheduler = Scheduler::new(10);
+        let start = Instant::now();
+        FrameLimiter::new(true).wait(&scheduler);
+        assert!(start.elapsed() < Duration::from_millis(5), "vsync paces the loop instead");
+        FrameLimiter::new(false).wait(&scheduler);
+        assert!(Instant::now() >= scheduler.next_deadline());
+    }
+
+    #[test]
+    fn frames_due_follows_the_clock() {
+        let mut scheduler = Scheduler::new(10);
+        assert_eq!(scheduler.frames_due(), 0);
+
+        thread::sleep(Duration::from_millis(40));
+        let frames = scheduler.frames_due();
+        assert!(frames >= 2, "{} frames due after 40ms", frames);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::cpu::Cpu;
+use crate::megachip;
+use crate::palette::Palette;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::profile::EmulationProfile;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Output pixels per display pixel, the same as the default window.
+pub const SCALE: usize = 10;
+// The same for the Mega-Chip screen, which comes out about as large.
+pub const MEGA_SCALE: usize = 2;
+// tEXt chunk holding the emulation profile in screenshots and recordings.
+pub const PROFILE_KEYWORD: &str = "hachip-profile";
+
+// Encodes the framebuffer as an RGB PNG in the palette's colors, each
+// display pixel scaled up to a `scale` x `scale` block.
+pub fn encode_png(framebuffer: &[u8], palette: &Palette, scale: usize, profile: &EmulationProfile) -> Vec<u8> {
+    encode_rgb(&rgb_image(framebuffer, palette, scale), (WIDTH * scale, HEIGHT * scale), profile)
+}
+
+// The Mega-Chip screen, 0xAARRGGBB pixels, as an RGB PNG the same way.
+pub fn encode_mega_png(screen: &[u32], scale: usize, profile: &EmulationProfile) -> Vec<u8> {
+    let (width, height) = (megachip::WIDTH * scale, megachip::HEIGHT * scale);
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let [_, r, g, b] = screen[y / scale * megachip::WIDTH + x / scale].to_be_bytes();
+            data.extend_from_slice(&[r, g, b]);
+        }
+    }
+    encode_rgb(&data, (width, height), profile)
+}
+
+fn encode_rgb(data: &[u8], size: (usize, usize), profile: &EmulationProfile) -> Vec<u8> {
+    let mut png = Vec::new();
+    let encoder = new_encoder(&mut png, size, profile);
+    // writing to memory can't fail
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+    writer.finish().unwrap();
+    png
+}
+
+// PNG encoder for a `size` image of the display, tagged with the profile.
+pub(crate) fn new_encoder<'a>(
+    png: &'a mut Vec<u8>,
+    size: (usize, usize),
+    profile: &EmulationProfile,
+) -> png::Encoder<'a, &'a mut Vec<u8>> {
+    let mut encoder = png::Encoder::new(png, size.0 as u32, size.1 as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // the profile is plain ASCII, always a valid text chunk
+    encoder.add_text_chunk(PROFILE_KEYWORD.to_string(), profile.to_string()).unwrap();
+    encoder
+}
+
+// RGB bytes of the framebuffer scaled up with nearest-neighbor.
+pub(crate) fn rgb_image(framebuffer: &[u8], palette: &Palette, scale: usize) -> Vec<u8> {
+    let (width, height) = (WIDTH * scale, HEIGHT * scale);
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let color = palette.color(framebuffer[y / scale * WIDTH + x / scale]);
+            data.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+    data
+}
+
+// Writes a screenshot into `dir`, creating it if needed, as
+// hachip-<unix time>.png. It is of the Mega-Chip screen while that is on.
+pub fn save(dir: &Path, cpu: &Cpu, palette: &Palette) -> io::Result<PathBuf> {
+    let path = new_path(dir, "hachip")?;
+    let png = match cpu.mega().filter(|mega| mega.on()) {
+        Some(mega) => encode_mega_png(mega.screen(), MEGA_SCALE, &cpu.profile()),
+        None => encode_png(cpu.display().framebuffer(), palette, SCALE, &cpu.profile()),
+    };
+    fs::write(&path, png)?;
+    Ok(path)
+}
+
+// A new <prefix>-<unix time>.png in `dir`, with a counter added when that
+// already exists. Creates the directory.
+pub(crate) fn new_path(dir: &Path, prefix: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut path = dir.join(format!("{}-{}.png", prefix, seconds));
+    let mut count = 1;
+    while path.exists() {
+        count += 1;
+        path = dir.join(format!("{}-{}-{}.png", prefix, seconds, count));
+    }
+    Ok(path)
+}
+
+// Saves a screenshot from a frontend hotkey, logging the outcome.
+pub fn capture(dir: Option<&Path>, cpu: &Cpu, palette: &Palette) {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            log::info!("screenshots are off, no screenshot directory");
+            return;
+        }
+    };
+    match save(dir, cpu, palette) {
+        Ok(path) => log::info!("saved screenshot {}", path.display()),
+        Err(error) => log::warn!("screenshot to {} failed: {}", dir.display(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_mega_png, encode_png};
+    use crate::megachip;
+    use crate::palette::Palette;
+    use crate::ppu::{Color, HEIGHT, WIDTH};
+    use crate::profile::EmulationProfile;
+    use crate::quirks::Platform;
+
+    #[test]
+    fn encodes_scaled_up_pixels_in_palette_colors() {
+        let palette = Palette::new(Color::rgb(0, 0, 0), Color::rgb(255, 128, 0));
+        let mut framebuffer = [0; WIDTH * HEIGHT];
+        framebuffer[1] = 1;
+
+        let profile = EmulationProfile::new(Platform::Schip.quirks());
+        let png = encode_png(&framebuffer, &palette, 2, &profile);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let text = &reader.info().uncompressed_latin1_text[0];
+        assert_eq!((text.keyword.as_str(), text.text.as_str()), (super::PROFILE_KEYWORD, "platform=schip quirks=jump-vx,clip-sprites"));
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((info.width, info.height), (WIDTH as u32 * 2, HEIGHT as u32 * 2));
+
+        let pixel = |x: usize, y: usize| &buffer[(y * WIDTH * 2 + x) * 3..][..3];
+        assert_eq!(pixel(1, 1), [0, 0, 0]);
+        assert_eq!(pixel(2, 0), [255, 128, 0]);
+        assert_eq!(pixel(3, 1), [255, 128, 0]);
+        assert_eq!(pixel(4, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn encodes_the_mega_chip_screen_in_its_colours() {
+        let mut screen = vec![0xFF00_0000; megachip::WIDTH * megachip::HEIGHT];
+        screen[megachip::WIDTH + 1] = 0xFF10_2030;
+
+        let profile = EmulationProfile::new(Platform::MegaChip.quirks());
+        let png = encode_mega_png(&screen, 2, &profile);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((info.width, info.height), (megachip::WIDTH as u32 * 2, megachip::HEIGHT as u32 * 2));
+
+        let pixel = |x: usize, y: usize| &buffer[(y * megachip::WIDTH * 2 + x) * 3..][..3];
+        assert_eq!(pixel(1, 1), [0, 0, 0]);
+        assert_eq!(pixel(2, 2), [0x10, 0x20, 0x30]);
+        assert_eq!(pixel(3, 3), [0x10, 0x20, 0x30]);
+        assert_eq!(pixel(4, 2), [0, 0, 0]);
+    }
+}
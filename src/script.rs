@@ -0,0 +1,275 @@
+use crate::cpu::Cpu;
+use crate::errors::ScriptError;
+use crate::headless;
+use crate::ppu::WIDTH;
+use crate::scheduler;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Test scripts drive a ROM headlessly and check the result, one command
+// per line ('#' starts a comment):
+//
+//   frames 60              run 60 frames
+//   press 5 / release 5    hold or let go of a key
+//   expect hash <16 hex>   framebuffer hash, as printed by --dump hash
+//   expect screen <file>   framebuffer as in --dump text, relative to the script
+//   expect V3 0x42         register V0-VF, I or PC
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    Frames(u32),
+    Key(u8, bool),
+    ExpectHash(u64),
+    ExpectScreen(String),
+    ExpectRegister(Register, u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+        }
+    }
+}
+
+pub struct Script {
+    // commands with their line numbers
+    commands: Vec<(usize, Command)>,
+}
+
+// A failed expectation. `diff` shows the screen with '+' for pixels lit
+// that should be dark and '-' for pixels dark that should be lit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub line: usize,
+    pub message: String,
+    pub diff: Option<String>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, ScriptError> {
+        let mut commands = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| ScriptError {
+                line: index + 1,
+                message,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let command = match words.as_slice() {
+                ["frames", count] => {
+                    let count = parse_number(count).ok_or_else(|| error(format!("bad frame count {}", count)))?;
+                    Command::Frames(count as u32)
+                }
+                ["press", key] | ["release", key] => {
+                    let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16);
+                    let key = key.ok_or_else(|| error(format!("{} is not a key 0-F", words[1])))?;
+                    Command::Key(key, words[0] == "press")
+                }
+                ["expect", "hash", hash] => {
+                    let hash = u64::from_str_radix(hash, 16).map_err(|_| error(format!("bad hash {}", hash)))?;
+                    Command::ExpectHash(hash)
+                }
+                ["expect", "screen", file] => Command::ExpectScreen(file.to_string()),
+                ["expect", register, value] => {
+                    let register = parse_register(register).ok_or_else(|| error(format!("unknown register {}", register)))?;
+                    let value = parse_number(value).ok_or_else(|| error(format!("bad value {}", value)))?;
+                    Command::ExpectRegister(register, value as u16)
+                }
+                _ => return Err(error(format!("unknown command `{}`", line))),
+            };
+            commands.push((index + 1, command));
+        }
+        Ok(Script { commands })
+    }
+
+    pub fn load(path: &Path) -> Result<Script, ScriptError> {
+        let text = fs::read_to_string(path).map_err(|error| ScriptError {
+            line: 0,
+            message: format!("{}: {}", path.display(), error),
+        })?;
+        Script::parse(&text)
+    }
+
+    // Runs the script against a freshly loaded cpu. Expectation files are
+    // looked up in `dir`. Returns every failed expectation; a cpu error ends
+    // the run.
+    pub fn run(&self, cpu: &mut Cpu, instructions_per_frame: u32, dir: &Path) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        for (line, command) in &self.commands {
+            let fail = |message: String, diff: Option<String>| Failure {
+                line: *line,
+                message,
+                diff,
+            };
+            match command {
+                Command::Frames(count) => {
+                    for frame in 0..*count {
+                        if let Err(error) = scheduler::run_frame(cpu, instructions_per_frame) {
+                            failures.push(fail(format!("cpu stopped in frame {}: {}", frame, error), None));
+                            return failures;
+                        }
+                    }
+                }
+                Command::Key(key, pressed) => cpu.keypad.set_key(*key, *pressed),
+                Command::ExpectHash(expected) => {
+                    let framebuffer = cpu.display().framebuffer();
+                    let actual = headless::hash(framebuffer);
+                    if actual != *expected {
+                        let message = format!("screen hash is {:016x}, expected {:016x}", actual, expected);
+                        failures.push(fail(message, Some(headless::dump_text(framebuffer))));
+                    }
+                }
+                Command::ExpectScreen(file) => {
+                    let path = dir.join(file);
+                    let expected = match fs::read_to_string(&path) {
+                        Ok(expected) => expected,
+                        Err(error) => {
+                            failures.push(fail(format!("{}: {}", path.display(), error), None));
+                            continue;
+                        }
+                    };
+                    let actual = headless::dump_text(cpu.display().framebuffer());
+                    if let Some(diff) = screen_diff(&expected, &actual) {
+                        failures.push(fail(format!("screen differs from {}", file), Some(diff)));
+                    }
+                }
+                Command::ExpectRegister(register, expected) => {
+                    let actual = match register {
+                        Register::V(x) => cpu.registers()[*x] as u16,
+                        Register::I => cpu.i() as u16,
+                        Register::Pc => cpu.pc(),
+                    };
+                    if actual != *expected {
+                        let message = format!("{} is {:#X}, expected {:#X}", register, actual, expected);
+                        failures.push(fail(message, None));
+                    }
+                }
+            }
+        }
+        failures
+    }
+}
+
+// Marks the differences between two text dumps, None if they match.
+pub fn screen_diff(expected: &str, actual: &str) -> Option<String> {
+    let expected_rows: Vec<&str> = expected.lines().collect();
+    let mut diff = String::new();
+    let mut differs = expected_rows.len() != actual.lines().count();
+    for (y, row) in actual.lines().enumerate() {
+        let expected_row: Vec<char> = expected_rows.get(y).map_or(vec![], |row| row.chars().collect());
+        for (x, pixel) in row.chars().enumerate() {
+            let marker = match (expected_row.get(x), pixel) {
+                (Some('.'), '#') => '+',
+                (Some('#'), '.') => '-',
+                (Some(_), _) => pixel,
+                (None, '#') => '+',
+                (None, _) => pixel,
+            };
+            differs |= marker == '+' || marker == '-';
+            diff.push(marker);
+        }
+        differs |= expected_row.len() != WIDTH;
+        diff.push('\n');
+    }
+    if differs {
+        Some(diff)
+    } else {
+        None
+    }
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_register(name: &str) -> Option<Register> {
+    match name.to_ascii_uppercase().as_str() {
+        "I" => Some(Register::I),
+        "PC" => Some(Register::Pc),
+        name if name.len() == 2 && name.starts_with('V') => {
+            usize::from_str_radix(&name[1..], 16).ok().map(Register::V)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{screen_diff, Script};
+    use crate::cpu::Cpu;
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::ppu::{HEIGHT, WIDTH};
+    use std::path::Path;
+
+    // LD V3, 0x42 / DRW V0, V0, 5 (the "0" glyph) / JP 0x204
+    const ROM: [u8; 6] = [0x63, 0x42, 0xD0, 0x05, 0x12, 0x04];
+
+    fn make_cpu() -> Cpu {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(ROM.to_vec()).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn passing_and_failing_expectations() {
+        let mut cpu = make_cpu();
+        let script = Script::parse(
+            "frames 2\n\
+             expect V3 0x42   # set by the first instruction\n\
+             expect pc 0x204\n\
+             expect VF 1\n\
+             press a\n",
+        )
+        .unwrap();
+
+        let failures = script.run(&mut cpu, 10, Path::new("."));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].line, 4);
+        assert!(cpu.keypad.is_key_down(0xA));
+    }
+
+    #[test]
+    fn hash_failures_show_the_screen() {
+        let mut cpu = make_cpu();
+        let script = Script::parse("frames 1\nexpect hash 0000000000000000").unwrap();
+
+        let failures = script.run(&mut cpu, 10, Path::new("."));
+        assert!(failures[0].diff.as_ref().unwrap().starts_with("####."));
+    }
+
+    #[test]
+    fn diff_marks_extra_and_missing_pixels() {
+        let blank = headless::dump_text(&[0; WIDTH * HEIGHT]);
+        let mut lit = [0; WIDTH * HEIGHT];
+        lit[0] = 1;
+        let one = headless::dump_text(&lit);
+
+        assert_eq!(screen_diff(&blank, &blank), None);
+        assert!(screen_diff(&blank, &one).unwrap().starts_with("+."));
+        assert!(screen_diff(&one, &blank).unwrap().starts_with("-."));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let error = Script::parse("frames 1\npress G").err().unwrap();
+        assert_eq!(error.line, 2);
+        assert!(Script::parse("expect V3").is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use crate::cpu::Cpu;
+use crate::errors::{EmulateCycleError, HookError};
+use crate::headless::HeadlessDisplay;
+use crate::state::SaveState;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Scope, AST};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// `--hooks <file.rhai>`: a Rhai script run along with the game, for cheats,
+// trainers and checks without rebuilding. Its top-level code runs once
+// before the first frame. These functions are called when it defines them:
+//
+//   on_frame()                 after every frame
+//   on_breakpoint(pc)          before the instruction at a breakpoint runs
+//   on_write(address, value)   after an instruction writes a memory byte
+//
+// and these look at and change the machine:
+//
+//   peek(address) / poke(address, value)    memory
+//   v(x) / set_v(x, value)                  registers V0-VF
+//   i() / set_i(value), pc() / set_pc(value)
+//   frame()                                 frames since the reset
+//   breakpoint(address) / clear_breakpoint(address)
+//
+// A script error is logged and the game runs on without the hooks.
+#[derive(Clone)]
+pub struct ScriptHooks {
+    inner: Arc<Mutex<Hooks>>,
+}
+
+struct Hooks {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    // the hook functions the script defines
+    defined: Vec<String>,
+    machine: Arc<Mutex<Machine>>,
+    started: bool,
+    failed: bool,
+}
+
+// The cpu as the script sees it during a call, written back if changed.
+struct Machine {
+    state: SaveState,
+    frame: u64,
+    changed: bool,
+    breakpoints: Vec<u16>,
+}
+
+type Outcome<T> = Result<T, Box<EvalAltResult>>;
+
+impl Machine {
+    fn byte(&mut self, address: i64) -> Outcome<&mut u8> {
+        match usize::try_from(address) {
+            Ok(index) if index < self.state.memory.len() => Ok(&mut self.state.memory[index]),
+            _ => Err(format!("address {:#x} is outside memory", address).into()),
+        }
+    }
+
+    fn register(&mut self, x: i64) -> Outcome<&mut u8> {
+        match usize::try_from(x) {
+            Ok(index) if index < self.state.v.len() => Ok(&mut self.state.v[index]),
+            _ => Err(format!("there is no register V{}", x).into()),
+        }
+    }
+}
+
+impl ScriptHooks {
+    pub fn compile(source: &str) -> Result<ScriptHooks, HookError> {
+        let machine = Arc::new(Mutex::new(Machine {
+            // replaced before every call
+            state: Cpu::new(Box::new(HeadlessDisplay::new())).save_state(),
+            frame: 0,
+            changed: false,
+            breakpoints: Vec::new(),
+        }));
+        let engine = engine(&machine);
+        let ast = engine.compile(source).map_err(|error| HookError {
+            message: error.to_string(),
+        })?;
+        let defined = ast
+            .iter_functions()
+            .filter(|function| match function.name {
+                "on_frame" => function.params.is_empty(),
+                "on_breakpoint" => function.params.len() == 1,
+                "on_write" => function.params.len() == 2,
+                _ => false,
+            })
+            .map(|function| function.name.to_string())
+            .collect();
+        let hooks = Hooks {
+            engine,
+            ast,
+            scope: Scope::new(),
+            defined,
+            machine,
+            started: false,
+            failed: false,
+        };
+        Ok(ScriptHooks {
+            inner: Arc::new(Mutex::new(hooks)),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<ScriptHooks, HookError> {
+        match fs::read_to_string(path) {
+            Ok(source) => ScriptHooks::compile(&source),
+            Err(error) => Err(HookError {
+                message: format!("{}: {}", path.display(), error),
+            }),
+        }
+    }
+
+    // scheduler::run_frame with the hooks called along the way.
+    pub fn run_frame(&self, cpu: &mut Cpu, instructions: u32) -> Result<u32, EmulateCycleError> {
+        let mut hooks = self.inner.lock().unwrap();
+        if !hooks.started {
+            hooks.started = true;
+            hooks.call(cpu, "the script", |engine, scope, ast| engine.run_ast_with_scope(scope, ast));
+        }
+        let on_breakpoint = hooks.defines("on_breakpoint");
+        let on_write = hooks.defines("on_write");
+
+        let mut executed = 0;
+        while executed < instructions {
+            let pc = cpu.pc();
+            if on_breakpoint && hooks.machine.lock().unwrap().breakpoints.contains(&pc) {
+                hooks.call_fn(cpu, "on_breakpoint", (pc as i64,));
+            }
+            if on_write {
+                cpu.log_memory_accesses();
+            }
+            cpu.step()?;
+            executed += 1;
+            if on_write {
+                for access in cpu.take_memory_accesses().into_iter().filter(|access| access.write) {
+                    let value = cpu.memory().get(access.address as usize).copied().unwrap_or_default();
+                    hooks.call_fn(cpu, "on_write", (access.address as i64, value as i64));
+                }
+            }
+            if cpu.waiting_for_vblank() {
+                break;
+            }
+        }
+        cpu.vblank();
+        if hooks.defines("on_frame") {
+            hooks.call_fn(cpu, "on_frame", ());
+        }
+        Ok(executed)
+    }
+}
+
+impl Hooks {
+    fn defines(&self, name: &str) -> bool {
+        !self.failed && self.defined.iter().any(|defined| defined == name)
+    }
+
+    fn call_fn(&mut self, cpu: &mut Cpu, name: &str, args: impl FuncArgs) {
+        // the top-level code ran once already
+        let options = CallFnOptions::new().eval_ast(false);
+        self.call(cpu, name, |engine, scope, ast| {
+            engine.call_fn_with_options::<Dynamic>(options, scope, ast, name, args).map(|_| ())
+        });
+    }
+
+    fn call(&mut self, cpu: &mut Cpu, name: &str, call: impl FnOnce(&Engine, &mut Scope<'static>, &AST) -> Outcome<()>) {
+        if self.failed {
+            return;
+        }
+        {
+            let mut machine = self.machine.lock().unwrap();
+            machine.state = cpu.save_state();
+            machine.frame = cpu.frame_count();
+            machine.changed = false;
+        }
+        let result = call(&self.engine, &mut self.scope, &self.ast);
+        let machine = self.machine.lock().unwrap();
+        if machine.changed {
+            cpu.load_state(&machine.state);
+        }
+        if let Err(error) = result {
+            log::error!("hooks: {} failed: {}; running on without them", name, error);
+            self.failed = true;
+        }
+    }
+}
+
+fn engine(machine: &Arc<Mutex<Machine>>) -> Engine {
+    let mut engine = Engine::new();
+    let shared = machine.clone();
+    engine.register_fn("peek", move |address: i64| -> Outcome<i64> {
+        Ok(*shared.lock().unwrap().byte(address)? as i64)
+    });
+    let shared = machine.clone();
+    engine.register_fn("poke", move |address: i64, value: i64| -> Outcome<()> {
+        let mut machine = shared.lock().unwrap();
+        *machine.byte(address)? = value as u8;
+        machine.changed = true;
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("v", move |x: i64| -> Outcome<i64> { Ok(*shared.lock().unwrap().register(x)? as i64) });
+    let shared = machine.clone();
+    engine.register_fn("set_v", move |x: i64, value: i64| -> Outcome<()> {
+        let mut machine = shared.lock().unwrap();
+        *machine.register(x)? = value as u8;
+        machine.changed = true;
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("i", move || shared.lock().unwrap().state.i as i64);
+    let shared = machine.clone();
+    engine.register_fn("set_i", move |value: i64| {
+        let mut machine = shared.lock().unwrap();
+        machine.state.i = value as u32 & 0xFF_FFFF;
+        machine.changed = true;
+    });
+    let shared = machine.clone();
+    engine.register_fn("pc", move || shared.lock().unwrap().state.pc as i64);
+    let shared = machine.clone();
+    engine.register_fn("set_pc", move |value: i64| {
+        let mut machine = shared.lock().unwrap();
+        machine.state.pc = value as u16;
+        machine.changed = true;
+    });
+    let shared = machine.clone();
+    engine.register_fn("frame", move || shared.lock().unwrap().frame as i64);
+    let shared = machine.clone();
+    engine.register_fn("breakpoint", move |address: i64| {
+        let mut machine = shared.lock().unwrap();
+        if !machine.breakpoints.contains(&(address as u16)) {
+            machine.breakpoints.push(address as u16);
+        }
+    });
+    let shared = machine.clone();
+    engine.register_fn("clear_breakpoint", move |address: i64| {
+        shared.lock().unwrap().breakpoints.retain(|breakpoint| *breakpoint != address as u16);
+    });
+    engine
+}
+
+impl PartialEq for ScriptHooks {
+    fn eq(&self, other: &ScriptHooks) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for ScriptHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ScriptHooks {{ defined: {:?} }}", self.inner.lock().unwrap().defined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptHooks;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+
+    #[test]
+    fn calls_the_hooks_with_the_machine() {
+        assert!(ScriptHooks::compile("fn on_frame( {").is_err());
+
+        let hooks = ScriptHooks::compile(
+            "
+            poke(0x380, 1);
+            breakpoint(0x204);
+            fn on_breakpoint(pc) { set_v(1, pc - 0x200); }
+            fn on_write(address, value) { poke(address + 1, value * 2); }
+            fn on_frame() { if frame() == 2 { set_i(0x123); } }
+            ",
+        )
+        .unwrap();
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD I, 0x300 / LD V0, 7 / LD [I], V0 / JP 0x206
+        cpu.load(vec![0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x06]).unwrap();
+        hooks.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.memory()[0x380], 1, "the top-level code ran");
+        assert_eq!(cpu.registers()[1], 4, "on_breakpoint");
+        assert_eq!(cpu.memory()[0x300..0x302], [7, 14], "on_write");
+        hooks.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(cpu.i(), 0x123, "on_frame");
+
+        let hooks = ScriptHooks::compile("fn on_frame() { poke(0x1000, v(0) + 1); }").unwrap();
+        hooks.run_frame(&mut cpu, 10).unwrap();
+        assert_eq!(hooks.run_frame(&mut cpu, 10).ok(), Some(10), "a failing script is dropped");
+    }
+}
@@ -0,0 +1,963 @@
+use crate::assets;
+use crate::bookmarks::Bookmarks;
+use crate::command::Command;
+use crate::cpu::Cpu;
+use crate::cpu_thread::{CpuThread, Frame, Machine};
+use crate::crt::{CrtEffects, CRT_SCALE};
+use crate::error_screen::{ErrorScreen, Recovery};
+use crate::emulator::Status;
+use crate::errors::EmulateCycleError;
+use crate::frontend::{self, FrontendOptions, LoopMode};
+use crate::input_mapping::InputMapping;
+use crate::input_profile::InputProfile;
+use crate::keymap::{HostKey, KeyboardLayout, KeymapProfile};
+use crate::keypad::Keypad;
+use crate::launcher::{Choice, Launcher};
+use crate::megachip;
+use crate::osd::Osd;
+use crate::palette::Palette;
+use crate::pause_menu::PauseMenu;
+use crate::phosphor::{self, Phosphor};
+use crate::ppu::{self, Display, Ppu, Scaling, HEIGHT, WIDTH};
+use crate::present::PresentHooks;
+use crate::recent;
+use crate::recording;
+use crate::scheduler;
+use crate::resume::ResumePrompt;
+use crate::screenshot;
+use crate::slot_picker::{Picked, SlotPicker};
+use crate::touch_pad::{self, TouchPad, PAD_SIZE};
+use crate::ui_input::{UiKey, UiScreen};
+use crate::watch_panel::{WatchPanel, PANEL_HEIGHT, PANEL_SCALE, PANEL_WIDTH};
+use crate::wizard::{Outcome, Wizard};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture};
+use sdl2::surface::Surface;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, Sdl};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
+
+// Copies the framebuffer into a streaming RGB24 texture: WIDTH x HEIGHT
+// as is, or CRT_SCALE times that with the effects applied.
+fn upload(
+    framebuffer: &[u8],
+    texture: &mut Texture,
+    palette: &Palette,
+    phosphor: Option<&Phosphor>,
+    osd: Option<&Osd>,
+    effects: CrtEffects,
+) {
+    let source = |x: usize, y: usize| match osd.and_then(|osd| osd.pixel(x, y)) {
+        Some(pixel) => palette.color(pixel),
+        None => phosphor::pixel_color(framebuffer, y * WIDTH + x, palette, phosphor),
+    };
+    let query = texture.query();
+    let (width, height) = (query.width as usize, query.height as usize);
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for y in 0..height {
+                for x in 0..width {
+                    let color = if effects.enabled() {
+                        effects.sample(x, y, width, height, source)
+                    } else {
+                        source(x, y)
+                    };
+                    let offset = y * pitch + x * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+                }
+            }
+        })
+        .unwrap();
+}
+
+// Copies the Mega-Chip screen into its megachip::WIDTH x megachip::HEIGHT
+// RGB24 texture. The CRT effects and the OSD are for the CHIP-8 screen.
+fn upload_mega(screen: &[u32], texture: &mut Texture) {
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (y, row) in screen.chunks_exact(megachip::WIDTH).enumerate() {
+                for (x, color) in row.iter().enumerate() {
+                    let offset = y * pitch + x * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&color.to_be_bytes()[1..]);
+                }
+            }
+        })
+        .unwrap();
+}
+
+// SDL scan codes for the profile's host keys, so the hexpad stays on the
+// same physical keys whatever the keyboard layout, with the ROM's input
+// profile on top.
+pub fn keymap(profile: KeymapProfile, layout: KeyboardLayout, input: Option<&InputProfile>) -> HashMap<Scancode, u8> {
+    let keys = match input {
+        Some(input) => input.apply(&profile.layout_keys(layout)),
+        None => profile.layout_keys(layout),
+    };
+    keys.iter()
+        .filter_map(|(host_key, key)| Some((scancode(*host_key)?, *key)))
+        .collect()
+}
+
+// SDL scan codes of the input mapping's macro keys, to the numbers the
+// mapping knows them by.
+fn macro_keys(mapping: &InputMapping) -> HashMap<Scancode, usize> {
+    mapping
+        .host_keys()
+        .enumerate()
+        .filter_map(|(index, host_key)| Some((scancode(host_key)?, index)))
+        .collect()
+}
+
+fn scancode(host_key: HostKey) -> Option<Scancode> {
+    match host_key {
+        HostKey::Char(' ') => Some(Scancode::Space),
+        HostKey::Char(c) => Scancode::from_name(&c.to_string()),
+        HostKey::Numpad(c) => match c {
+            '0' => Some(Scancode::Kp0),
+            '1' => Some(Scancode::Kp1),
+            '2' => Some(Scancode::Kp2),
+            '3' => Some(Scancode::Kp3),
+            '4' => Some(Scancode::Kp4),
+            '5' => Some(Scancode::Kp5),
+            '6' => Some(Scancode::Kp6),
+            '7' => Some(Scancode::Kp7),
+            '8' => Some(Scancode::Kp8),
+            '9' => Some(Scancode::Kp9),
+            '/' => Some(Scancode::KpDivide),
+            '*' => Some(Scancode::KpMultiply),
+            '-' => Some(Scancode::KpMinus),
+            '+' => Some(Scancode::KpPlus),
+            '.' => Some(Scancode::KpPeriod),
+            _ => None,
+        },
+        HostKey::NumpadEnter => Some(Scancode::KpEnter),
+        HostKey::Up => Some(Scancode::Up),
+        HostKey::Down => Some(Scancode::Down),
+        HostKey::Left => Some(Scancode::Left),
+        HostKey::Right => Some(Scancode::Right),
+    }
+}
+
+// `--layout`, or the layout SDL reports: what the key in the QWERTY Q
+// position types.
+fn keyboard_layout(options: &FrontendOptions) -> KeyboardLayout {
+    options.layout.unwrap_or_else(|| {
+        let q = Keycode::from_scancode(Scancode::Q).and_then(|keycode| keycode.name().chars().next());
+        KeyboardLayout::detect(q.unwrap_or('Q'))
+    })
+}
+
+// Navigation keys first, then the hexpad mapping.
+pub fn ui_key(scancode: Scancode, keymap: &HashMap<Scancode, u8>) -> Option<UiKey> {
+    match scancode {
+        Scancode::Up => Some(UiKey::Up),
+        Scancode::Down => Some(UiKey::Down),
+        Scancode::Left => Some(UiKey::Left),
+        Scancode::Right => Some(UiKey::Right),
+        Scancode::Return | Scancode::KpEnter => Some(UiKey::Enter),
+        Scancode::Escape => Some(UiKey::Escape),
+        Scancode::Backspace => Some(UiKey::Backspace),
+        _ => keymap.get(&scancode).map(|key| UiKey::Hex(*key)),
+    }
+}
+
+// Opens a window and runs the cpu built by `init_cpu` until it is closed or
+// the cpu fails.
+pub fn run<F>(options: &FrontendOptions, init_cpu: F) -> Result<(), EmulateCycleError>
+where
+    F: FnOnce(Box<dyn Display>) -> Cpu,
+{
+    run_with_hooks(options, init_cpu, &mut PresentHooks::new())
+}
+
+// `run`, calling `hooks` around every present.
+pub fn run_with_hooks<F>(options: &FrontendOptions, init_cpu: F, hooks: &mut PresentHooks) -> Result<(), EmulateCycleError>
+where
+    F: FnOnce(Box<dyn Display>) -> Cpu,
+{
+    let sdl = sdl2::init().unwrap();
+    let mut canvas = get_canvas(&sdl, options.vsync, window_size(options.scale, options.touch_pad));
+    let dpi = dpi_factor(&canvas);
+    if dpi > 1 {
+        resize_window(&mut canvas, options.scale * dpi, options.touch_pad);
+    }
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+    let mega_size = (megachip::WIDTH as u32, megachip::HEIGHT as u32);
+    let mut mega_texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, mega_size.0, mega_size.1)
+        .unwrap();
+    let crt_size = ((WIDTH * CRT_SCALE) as u32, (HEIGHT * CRT_SCALE) as u32);
+    let mut crt_texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, crt_size.0, crt_size.1)
+        .unwrap();
+    let mut pad_texture = if options.touch_pad {
+        Some(texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, PAD_SIZE as u32, PAD_SIZE as u32).unwrap())
+    } else {
+        None
+    };
+    let mut touch_pad = TouchPad::new();
+    let mut watch_canvas = if options.watch_window { Some(get_watch_canvas(&sdl)) } else { None };
+    let watch_creator = watch_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let mut watch_texture = watch_creator.as_ref().map(|creator| {
+        creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, PANEL_WIDTH as u32, PANEL_HEIGHT as u32)
+            .unwrap()
+    });
+    let mut watch_panel = WatchPanel::new();
+    let mut event_pump = sdl.event_pump().unwrap();
+    let mut cpu = init_cpu(Box::new(Ppu::new()));
+    let set_up;
+    let options = match &options.setup {
+        Some(path) => match setup(path, &mut event_pump, &mut canvas, &mut texture, &mut cpu, options) {
+            Some(options) => {
+                set_up = options;
+                &set_up
+            }
+            None => return Ok(()),
+        },
+        None => options,
+    };
+    let layout = keyboard_layout(options);
+    log::info!("keyboard layout {}: the hexpad is on {}", layout.name(), options.keymap.describe(layout));
+    let mut keymap = keymap(options.keymap, layout, options.input_profile.as_ref());
+    let mut mapping = options.input_profile.as_ref().map(InputProfile::mapping).unwrap_or_default();
+    let mut mapped_keys = macro_keys(&mapping);
+    let commands = options.commands.clone();
+    let mut title = String::new();
+    let crt = if options.crt.tube() {
+        options.crt
+    } else {
+        CrtEffects {
+            grid_lines: options.crt.grid_lines,
+            ..CrtEffects::ALL
+        }
+    };
+    let capture_dir = options.screenshot_dir.as_deref();
+    let mut live_keys = Keypad::new();
+    let mut sent_keys = live_keys.keys;
+    let mut bookmarks = options.bookmark_dir.clone().map(Bookmarks::new);
+    let mut save_slots = options.save_slots.clone();
+    let mut pause_menu: Option<PauseMenu> = None;
+    let mut slot_picker: Option<SlotPicker> = None;
+    let mut error_screen: Option<ErrorScreen> = None;
+
+    let mut rom_name = options.rom_name.clone();
+    let mut scale = options.scale;
+    let mut launched = None;
+    if let Some(dir) = &options.rom_dir {
+        let path = match pick_rom(dir, &mut event_pump, &mut canvas, &mut texture, &mut cpu, options) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        match frontend::launch(&mut cpu, &path, options) {
+            Ok(mut rom) => {
+                keymap = self::keymap(rom.keymap, layout, rom.input_profile.as_ref());
+                mapping = rom.input_profile.as_ref().map(InputProfile::mapping).unwrap_or_default();
+                mapped_keys = macro_keys(&mapping);
+                rom_name = Some(rom.rom_name.clone());
+                bookmarks = rom.bookmarks.take();
+                save_slots = rom.save_slots.take();
+                launched = Some(rom);
+            }
+            Err(error) => {
+                log::error!("{}: {}", path.display(), error);
+                return Ok(());
+            }
+        }
+    }
+    let mut machine = Machine::new(cpu, options);
+    if let Some(rom) = launched {
+        machine.state.program = rom.program;
+        machine.autosave = rom.autosave;
+        machine.flags = rom.flags;
+        machine.battery = rom.battery;
+        machine.resume = rom.resume;
+    }
+    let mut resume_prompt = machine.resume.as_ref().and_then(|resume| ResumePrompt::offer(machine.emulator.cpu(), resume));
+    let cpu_thread = CpuThread::spawn(machine);
+    if resume_prompt.is_some() {
+        cpu_thread.hold(true);
+    }
+    // the frame on screen
+    let mut shown: Option<Frame> = None;
+
+    'main: loop {
+        // paused or in a still menu, block until something happens
+        let paused = shown.as_ref().is_some_and(|frame| frame.paused);
+        let menu_idle = pause_menu
+            .as_ref()
+            .map(PauseMenu::idle)
+            .or_else(|| slot_picker.as_ref().map(SlotPicker::idle))
+            .or_else(|| resume_prompt.as_ref().map(ResumePrompt::idle))
+            .or_else(|| error_screen.as_ref().map(ErrorScreen::idle));
+        let first = match frontend::loop_mode(paused, menu_idle) {
+            LoopMode::Idle => event_pump.wait_event_timeout(frontend::IDLE_WAKE.as_millis() as u32),
+            LoopMode::Running => None,
+        };
+        let events: Vec<Event> = first.into_iter().chain(event_pump.poll_iter()).collect();
+        for event in events {
+            if fullscreen_key(&event) {
+                toggle_fullscreen(&mut canvas);
+                continue;
+            }
+            if let Some(screen) = &mut error_screen {
+                let recovery = match event {
+                    Event::Quit { .. } => Some(Recovery::Quit),
+                    Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => match ui_key(scancode, &keymap) {
+                        Some(key) => cpu_thread
+                            .with(|machine| screen.key_down(key, machine.emulator.cpu_mut()))
+                            .unwrap_or(Some(Recovery::Quit)),
+                        None => None,
+                    },
+                    Event::KeyUp { scancode: Some(scancode), .. } => {
+                        if let Some(key) = ui_key(scancode, &keymap) {
+                            screen.key_up(key);
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                match recovery {
+                    // the error is returned below
+                    Some(Recovery::Quit) => break 'main,
+                    Some(recovery) => {
+                        if recovery == Recovery::Reset {
+                            commands.push(Command::Reset);
+                        }
+                        error_screen = None;
+                        cpu_thread.hold(false);
+                    }
+                    None => {}
+                }
+                continue;
+            }
+            if let Some(menu) = &mut pause_menu {
+                let open = match event {
+                    Event::Quit { .. } => break 'main,
+                    Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => match ui_key(scancode, &keymap) {
+                        Some(key) => cpu_thread
+                            .with(|machine| menu.key_down(key, machine.emulator.cpu_mut(), &machine.state.palette))
+                            .unwrap_or(false),
+                        None => true,
+                    },
+                    Event::KeyUp { scancode: Some(scancode), .. } => {
+                        if let Some(key) = ui_key(scancode, &keymap) {
+                            menu.key_up(key);
+                        }
+                        true
+                    }
+                    Event::TextInput { text, .. } => {
+                        text.chars().for_each(|c| menu.type_char(c));
+                        true
+                    }
+                    _ => true,
+                };
+                if !open {
+                    pause_menu = None;
+                    cpu_thread.hold(false);
+                }
+                continue;
+            }
+            if let Some(prompt) = &mut resume_prompt {
+                let open = match event {
+                    Event::Quit { .. } => break 'main,
+                    Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => match ui_key(scancode, &keymap) {
+                        Some(key) => cpu_thread.with(|machine| prompt.key_down(key, machine.emulator.cpu_mut())).unwrap_or(false),
+                        None => true,
+                    },
+                    Event::KeyUp { scancode: Some(scancode), .. } => {
+                        if let Some(key) = ui_key(scancode, &keymap) {
+                            prompt.key_up(key);
+                        }
+                        true
+                    }
+                    _ => true,
+                };
+                if !open {
+                    resume_prompt = None;
+                    cpu_thread.hold(false);
+                }
+                continue;
+            }
+            if let Some(picker) = &mut slot_picker {
+                let picked = match event {
+                    Event::Quit { .. } => break 'main,
+                    Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => match ui_key(scancode, &keymap) {
+                        Some(key) => cpu_thread
+                            .with(|machine| {
+                                let picked = picker.key_down(key, machine.emulator.cpu_mut());
+                                if let Some(message) = picked.and_then(|picked| picked.message()) {
+                                    machine.state.osd.show(&message);
+                                }
+                                picked
+                            })
+                            .unwrap_or(Some(Picked::Cancelled)),
+                        None => None,
+                    },
+                    Event::KeyUp { scancode: Some(scancode), .. } => {
+                        if let Some(key) = ui_key(scancode, &keymap) {
+                            picker.key_up(key);
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if picked.is_some() {
+                    slot_picker = None;
+                    cpu_thread.hold(false);
+                }
+                continue;
+            }
+            if pad_texture.is_some() && touch_event(&mut touch_pad, &event, canvas.window().size()) {
+                continue;
+            }
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'main,
+                // with the watch window open, closing either window doesn't quit by itself
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    if window_id == canvas.window().id() {
+                        break 'main;
+                    }
+                    watch_canvas = None;
+                }
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => {
+                    commands.push(Command::TogglePause);
+                }
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    commands.push(Command::FastForward(true));
+                }
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    commands.push(Command::FastForward(false));
+                }
+                Event::KeyDown { keycode: Some(keycode @ (Keycode::Equals | Keycode::Minus)), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) =>
+                {
+                    scale = frontend::step_scale(scale, keycode == Keycode::Equals);
+                    resize_window(&mut canvas, scale * dpi, pad_texture.is_some());
+                    cpu_thread.with(|machine| machine.state.osd.show(&format!("WINDOW {}X", scale)));
+                }
+                Event::KeyDown { keycode: Some(Keycode::LeftBracket), repeat: false, .. } => {
+                    cpu_thread.with(|machine| commands.push(Command::TimeScale(machine.scheduler.time_scale / 2)));
+                }
+                Event::KeyDown { keycode: Some(Keycode::RightBracket), repeat: false, .. } => {
+                    cpu_thread.with(|machine| commands.push(Command::TimeScale(machine.scheduler.time_scale * 2)));
+                }
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => match &save_slots {
+                    Some(slots) => {
+                        slot_picker = cpu_thread.with(|machine| SlotPicker::open(machine.emulator.cpu(), slots.clone()));
+                        cpu_thread.hold(true);
+                    }
+                    None => log::info!("save slots are off, no data directory"),
+                },
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                    commands.push(Command::Reset);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => {
+                    pause_menu = cpu_thread.with(|machine| PauseMenu::open(machine.emulator.cpu(), bookmarks.clone()));
+                    cpu_thread.hold(true);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. } => {
+                    commands.push(Command::ToggleSpeedMeter);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    cpu_thread.with(|machine| {
+                        machine.input_macro.toggle_recording();
+                        let recording = machine.input_macro.is_recording();
+                        machine.state.osd.show(if recording { "RECORDING MACRO" } else { "MACRO RECORDED" });
+                    });
+                }
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    cpu_thread.with(|machine| machine.input_macro.play());
+                }
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    commands.push(Command::AdvanceFrame);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+                    commands.push(Command::ToggleCrt);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    cpu_thread.with(|machine| {
+                        if let Some(autosave) = &machine.autosave {
+                            let restored = autosave.restore_latest(machine.emulator.cpu_mut());
+                            machine.state.osd.show(if restored { "AUTOSAVE LOADED" } else { "NO AUTOSAVE" });
+                        }
+                    });
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    cpu_thread.with(|machine| {
+                        recording::toggle(&mut machine.recording, capture_dir, &machine.state.palette, machine.emulator.cpu().profile());
+                        if capture_dir.is_some() {
+                            let recording = machine.recording.is_some();
+                            machine.state.osd.show(if recording { "RECORDING" } else { "RECORDING SAVED" });
+                        }
+                    });
+                }
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    cpu_thread.with(|machine| {
+                        screenshot::capture(capture_dir, machine.emulator.cpu(), &machine.state.palette);
+                        if capture_dir.is_some() {
+                            machine.state.osd.show("SCREENSHOT SAVED");
+                        }
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // the game stands still under the menu, its time isn't caught up on
+        if let Some(screen) = &mut error_screen {
+            let palette = cpu_thread.with(|machine| {
+                screen.frame();
+                screen.render(machine.emulator.cpu_mut().display_mut());
+                upload(machine.emulator.framebuffer(), &mut texture, &machine.state.palette, None, None, CrtEffects::default());
+                machine.state.palette
+            });
+            let palette = match palette {
+                Some(palette) => palette,
+                None => break 'main,
+            };
+            draw(&mut canvas, &texture, options.scaling, &palette, pad_texture.as_ref());
+            canvas.present();
+            thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+            continue;
+        }
+        if let Some(menu) = &mut pause_menu {
+            let palette = cpu_thread.with(|machine| {
+                menu.frame();
+                menu.render(machine.emulator.cpu_mut().display_mut());
+                upload(machine.emulator.framebuffer(), &mut texture, &machine.state.palette, None, None, CrtEffects::default());
+                machine.state.palette
+            });
+            let palette = match palette {
+                Some(palette) => palette,
+                None => break 'main,
+            };
+            draw(&mut canvas, &texture, options.scaling, &palette, pad_texture.as_ref());
+            canvas.present();
+            thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+            continue;
+        }
+        if let Some(prompt) = &mut resume_prompt {
+            let palette = cpu_thread.with(|machine| {
+                prompt.frame();
+                prompt.render(machine.emulator.cpu_mut().display_mut());
+                upload(machine.emulator.framebuffer(), &mut texture, &machine.state.palette, None, None, CrtEffects::default());
+                machine.state.palette
+            });
+            let palette = match palette {
+                Some(palette) => palette,
+                None => break 'main,
+            };
+            draw(&mut canvas, &texture, options.scaling, &palette, pad_texture.as_ref());
+            canvas.present();
+            thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+            continue;
+        }
+        if let Some(picker) = &mut slot_picker {
+            let palette = cpu_thread.with(|machine| {
+                picker.frame();
+                picker.render(machine.emulator.cpu_mut().display_mut());
+                upload(machine.emulator.framebuffer(), &mut texture, &machine.state.palette, None, None, CrtEffects::default());
+                machine.state.palette
+            });
+            let palette = match palette {
+                Some(palette) => palette,
+                None => break 'main,
+            };
+            draw(&mut canvas, &texture, options.scaling, &palette, pad_texture.as_ref());
+            canvas.present();
+            thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+            continue;
+        }
+
+        // Create a set of pressed Keys.
+        let pressed = event_pump.keyboard_state().pressed_scancodes().collect::<Vec<Scancode>>();
+        let keys = pressed.iter().filter_map(|x| keymap.get(x)).cloned().collect::<Vec<u8>>();
+
+        live_keys.press(keys);
+        for key in touch_pad.keys() {
+            live_keys.set_key(key, true);
+        }
+        for (scancode, index) in &mapped_keys {
+            mapping.set_held(*index, pressed.contains(scancode));
+        }
+        mapping.frame(&mut live_keys);
+        if live_keys.keys != sent_keys {
+            cpu_thread.set_keys(live_keys.keys);
+            sent_keys = live_keys.keys;
+        }
+
+        // Shows the newest frame the cpu thread sent; it sends one whenever
+        // a frame ran or the OSD changed, so a message coming or going
+        // shows while paused too.
+        let wait = if paused { Duration::from_secs(0) } else { Duration::from_secs(1) / scheduler::FRAME_RATE };
+        let frame = match cpu_thread.next_frame(wait) {
+            Ok(frame) => frame,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break 'main,
+        };
+        if frame.failed {
+            // held first, so the game can't run on once the error is taken
+            cpu_thread.hold(true);
+            error_screen = cpu_thread
+                .with(|machine| machine.error.take().map(|error| ErrorScreen::open(machine.emulator.cpu(), error)))
+                .flatten();
+            continue;
+        }
+        let status = Status {
+            rom_name: rom_name.clone(),
+            paused: frame.paused,
+            speed: frame.speed,
+            played: frame.played,
+        };
+        let new_title = status.title();
+        if new_title != title {
+            canvas.window_mut().set_title(&new_title).unwrap();
+            title = new_title;
+        }
+        let effects = if frame.crt_on { crt } else { crt.without_tube() };
+        let (texture, size) = match &frame.mega {
+            Some(screen) => {
+                upload_mega(screen, &mut mega_texture);
+                (&mut mega_texture, mega_size)
+            }
+            None => {
+                let texture = if effects.enabled() { &mut crt_texture } else { &mut texture };
+                upload(&frame.framebuffer, texture, &frame.palette, frame.phosphor.as_ref(), Some(&frame.osd), effects);
+                (texture, (WIDTH as u32, HEIGHT as u32))
+            }
+        };
+        if let Some(pad) = &mut pad_texture {
+            upload_pad(&touch_pad, pad, &frame.palette);
+        }
+        draw_sized(&mut canvas, texture, size, options.scaling, &frame.palette, pad_texture.as_ref());
+        hooks.notify_before(&frame.present_info());
+        canvas.present();
+        cpu_thread.presented();
+        hooks.notify_after(&frame.present_info());
+        if let (Some(watch), Some(texture)) = (&mut watch_canvas, &mut watch_texture) {
+            cpu_thread.with(|machine| watch_panel.update(machine.emulator.cpu()));
+            upload_panel(&watch_panel, texture, &frame.palette);
+            watch.copy(texture, None, None).unwrap();
+            watch.present();
+        }
+        shown = Some(frame);
+    }
+    let mut machine = cpu_thread.stop();
+    machine.finish();
+    // menus keep the game aside, nothing was played under the resume
+    // prompt, and a failed game isn't worth resuming
+    if let Some(resume) = &machine.resume {
+        if resume_prompt.is_none() && error_screen.is_none() && machine.error.is_none() {
+            let game = pause_menu.as_ref().map(PauseMenu::game).or_else(|| slot_picker.as_ref().map(SlotPicker::game));
+            resume.finish(&game.cloned().unwrap_or_else(|| machine.emulator.cpu().save_state()));
+        }
+    }
+    match error_screen.map(ErrorScreen::into_error).or_else(|| machine.error.take()) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+// Copies the watch panel into its PANEL_WIDTH x PANEL_HEIGHT texture.
+fn upload_panel(panel: &WatchPanel, texture: &mut Texture, palette: &Palette) {
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (index, color) in panel.colors(palette).enumerate() {
+                let offset = index / PANEL_WIDTH * pitch + index % PANEL_WIDTH * 3;
+                buffer[offset..offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        })
+        .unwrap();
+}
+
+// Copies the touch pad into its PAD_SIZE x PAD_SIZE texture.
+fn upload_pad(touch_pad: &TouchPad, texture: &mut Texture, palette: &Palette) {
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (index, pixel) in touch_pad.pixels().iter().enumerate() {
+                let color = palette.color(*pixel);
+                let offset = index / PAD_SIZE * pitch + index % PAD_SIZE * 3;
+                buffer[offset..offset + 3].copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        })
+        .unwrap();
+}
+
+// Mouse and touch events on the touch pad. Returns whether the event was
+// one; touches SDL also reports as mouse events are taken once, as touches.
+fn touch_event(touch_pad: &mut TouchPad, event: &Event, (width, height): (u32, u32)) -> bool {
+    const MOUSE: i64 = -1;
+    // SDL_TOUCH_MOUSEID, the `which` of mouse events made up from touches
+    const TOUCH_MOUSE: u32 = u32::MAX;
+    let pad = touch_pad::split(width, height).1;
+    let finger_at = |x: f32, y: f32| touch_pad::key_at(pad, (x * width as f32) as i32, (y * height as f32) as i32);
+    match *event {
+        Event::MouseButtonDown { which, .. } | Event::MouseButtonUp { which, .. } | Event::MouseMotion { which, .. }
+            if which == TOUCH_MOUSE =>
+        {
+            return true;
+        }
+        Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => touch_pad.touch(MOUSE, touch_pad::key_at(pad, x, y)),
+        Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => touch_pad.touch(MOUSE, touch_pad::key_at(pad, x, y)),
+        Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => touch_pad.release(MOUSE),
+        Event::FingerDown { finger_id, x, y, .. } | Event::FingerMotion { finger_id, x, y, .. } => {
+            touch_pad.touch(finger_id, finger_at(x, y))
+        }
+        Event::FingerUp { finger_id, .. } => touch_pad.release(finger_id),
+        _ => return false,
+    }
+    true
+}
+
+// F11 and Alt+Enter.
+fn fullscreen_key(event: &Event) -> bool {
+    match event {
+        Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => true,
+        Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. } => keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        _ => false,
+    }
+}
+
+// Switches between the window and desktop fullscreen, which keeps the
+// screen's resolution; the next draw fits the display to the new size.
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) {
+    let window = canvas.window_mut();
+    let fullscreen = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    if let Err(error) = window.set_fullscreen(fullscreen) {
+        log::warn!("switching fullscreen failed: {}", error);
+    }
+}
+
+// Clears the window and copies the texture into the viewport, with the
+// touch pad beside it when there is one.
+fn draw(canvas: &mut Canvas<Window>, texture: &Texture, scaling: Scaling, palette: &Palette, pad: Option<&Texture>) {
+    draw_sized(canvas, texture, (WIDTH as u32, HEIGHT as u32), scaling, palette, pad);
+}
+
+// draw for a texture showing a screen of `size` pixels.
+fn draw_sized(
+    canvas: &mut Canvas<Window>,
+    texture: &Texture,
+    size: (u32, u32),
+    scaling: Scaling,
+    palette: &Palette,
+    pad: Option<&Texture>,
+) {
+    let (width, height) = canvas.output_size().unwrap();
+    let scaling = match canvas.window().fullscreen_state() {
+        FullscreenType::Off => scaling,
+        _ => scaling.fullscreen(),
+    };
+    let (area, pad_area) = match pad {
+        Some(_) => {
+            let (area, pad_area) = touch_pad::split(width, height);
+            (area, Some(pad_area))
+        }
+        None => (ppu::Rect::new(0, 0, width, height), None),
+    };
+    let viewport = scaling.fit(area.w, area.h, size.0, size.1);
+    let background = palette.background();
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(background.r, background.g, background.b));
+    canvas.clear();
+    let target = sdl2::rect::Rect::new(area.x + viewport.x, area.y + viewport.y, viewport.w, viewport.h);
+    canvas.copy(texture, None, target).unwrap();
+    if let (Some(pad), Some(pad_area)) = (pad, pad_area) {
+        canvas.copy(pad, None, sdl2::rect::Rect::new(pad_area.x, pad_area.y, pad_area.w, pad_area.h)).unwrap();
+    }
+}
+
+// Shows the ROM browser on the cpu's display until a ROM is picked, or
+// returns None when the player quits.
+fn pick_rom(
+    dir: &Path,
+    event_pump: &mut EventPump,
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    cpu: &mut Cpu,
+    options: &FrontendOptions,
+) -> Option<PathBuf> {
+    let keymap = keymap(options.keymap, keyboard_layout(options), None);
+    let mut launcher = Launcher::new(dir, recent::load().paths());
+    loop {
+        let first = if launcher.idle() {
+            event_pump.wait_event_timeout(frontend::IDLE_WAKE.as_millis() as u32)
+        } else {
+            None
+        };
+        let events: Vec<Event> = first.into_iter().chain(event_pump.poll_iter()).collect();
+        for event in events {
+            if fullscreen_key(&event) {
+                toggle_fullscreen(canvas);
+                continue;
+            }
+            let choice = match event {
+                Event::Quit { .. } => Some(Choice::Quit),
+                Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => {
+                    ui_key(scancode, &keymap).and_then(|key| launcher.key_down(key))
+                }
+                Event::KeyUp { scancode: Some(scancode), .. } => {
+                    if let Some(key) = ui_key(scancode, &keymap) {
+                        launcher.key_up(key);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            match choice {
+                Some(Choice::Rom(path)) => return Some(path),
+                Some(_) => return None,
+                None => {}
+            }
+        }
+        launcher.frame();
+        launcher.render(cpu.display_mut());
+        upload(cpu.display().framebuffer(), texture, &options.palette, None, None, CrtEffects::default());
+        draw(canvas, texture, options.scaling, &options.palette, None);
+        canvas.present();
+        thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+    }
+}
+
+// Shows the first-run setup, returning the options it makes, or None if
+// the window was closed.
+fn setup(
+    path: &Path,
+    event_pump: &mut EventPump,
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    cpu: &mut Cpu,
+    options: &FrontendOptions,
+) -> Option<FrontendOptions> {
+    let keymap = keymap(options.keymap, keyboard_layout(options), None);
+    let mut wizard = Wizard::new(options.rom_dir.as_deref().unwrap_or_else(|| Path::new(".")));
+    loop {
+        let first = if wizard.idle() {
+            event_pump.wait_event_timeout(frontend::IDLE_WAKE.as_millis() as u32)
+        } else {
+            None
+        };
+        let events: Vec<Event> = first.into_iter().chain(event_pump.poll_iter()).collect();
+        for event in events {
+            if fullscreen_key(&event) {
+                toggle_fullscreen(canvas);
+                continue;
+            }
+            let outcome = match event {
+                Event::Quit { .. } => Some(Outcome::Quit),
+                Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => {
+                    ui_key(scancode, &keymap).and_then(|key| wizard.key_down(key))
+                }
+                Event::KeyUp { scancode: Some(scancode), .. } => {
+                    if let Some(key) = ui_key(scancode, &keymap) {
+                        wizard.key_up(key);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            match outcome {
+                Some(Outcome::Done(config)) => return Some(frontend::finish_setup(options, &config, path)),
+                Some(_) => return None,
+                None => {}
+            }
+        }
+        wizard.frame();
+        wizard.render(cpu.display_mut());
+        let palette = wizard.palette();
+        upload(cpu.display().framebuffer(), texture, &palette, None, None, CrtEffects::default());
+        draw(canvas, texture, wizard.scaling(), &palette, None);
+        canvas.present();
+        thread::sleep(Duration::from_secs(1) / scheduler::FRAME_RATE);
+    }
+}
+
+// The window showing the display `scale` times; the touch pad gets a
+// square at the side.
+fn window_size(scale: u32, touch_pad: bool) -> (u32, u32) {
+    let (width, height) = ppu::frame_size(scale);
+    if touch_pad {
+        (width + height, height)
+    } else {
+        (width, height)
+    }
+}
+
+// Resizes the window for the scale Ctrl+= and Ctrl+- picked. A fullscreen
+// window keeps the screen's size.
+fn resize_window(canvas: &mut Canvas<Window>, scale: u32, touch_pad: bool) {
+    let window = canvas.window_mut();
+    if window.fullscreen_state() != FullscreenType::Off {
+        return;
+    }
+    let (width, height) = window_size(scale, touch_pad);
+    if let Err(error) = window.set_size(width, height) {
+        log::warn!("resizing the window failed: {}", error);
+    }
+}
+
+// How many times larger than asked the window has to be on a HiDPI display
+// to not come out tiny. Systems that scale windows themselves (macOS,
+// Wayland) hand out a drawable larger than the window instead, which draw
+// already fills, so they get 1.
+fn dpi_factor(canvas: &Canvas<Window>) -> u32 {
+    let window = canvas.window();
+    if canvas.output_size() != Ok(window.size()) {
+        return 1;
+    }
+    let dpi = window
+        .display_index()
+        .and_then(|display| window.subsystem().display_dpi(display));
+    match dpi {
+        Ok((dpi, _, _)) => frontend::dpi_factor(dpi),
+        Err(_) => 1,
+    }
+}
+
+fn get_canvas(sdl: &Sdl, vsync: bool, (width, height): (u32, u32)) -> Canvas<Window> {
+    let video_subsystem = sdl.video().unwrap();
+    // CHIP-8 pixels are scaled up as blocks, never blurred
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+    let mut window = video_subsystem
+        .window("hachip", width, height)
+        .resizable()
+        .allow_highdpi()
+        .build()
+        .unwrap();
+    let mut icon = assets::APP_ICON;
+    let size = assets::APP_ICON_SIZE;
+    match Surface::from_data(&mut icon, size, size, size * 4, PixelFormatEnum::RGBA32) {
+        Ok(surface) => window.set_icon(surface),
+        Err(error) => log::warn!("no window icon: {}", error),
+    }
+    let builder = window.into_canvas();
+    // with vsync, presents wait for the display's refresh (usually 60Hz or
+    // 144Hz) and the frame limiter doesn't
+    let builder = if vsync { builder.present_vsync() } else { builder };
+    builder.build().unwrap()
+}
+
+// The `--debug` watch window, the panel enlarged PANEL_SCALE times.
+fn get_watch_canvas(sdl: &Sdl) -> Canvas<Window> {
+    let window = sdl
+        .video()
+        .unwrap()
+        .window("hachip watch", PANEL_WIDTH as u32 * PANEL_SCALE, PANEL_HEIGHT as u32 * PANEL_SCALE)
+        .resizable()
+        .build()
+        .unwrap();
+    window.into_canvas().build().unwrap()
+}
@@ -0,0 +1,269 @@
+use crate::headless;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const LOG_FILE: &str = "sessions.log";
+
+// Something that happened while playing, in the order it happened.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    RomLoaded(String),
+    Reset,
+    SaveState(u32),
+    LoadState(u32),
+    Error(String),
+    // Written when the session is dropped, with the play time in seconds.
+    Ended(u64),
+    // Seconds on the play clock, not counting pauses, when the ROM stopped.
+    Played(u64),
+}
+
+impl SessionEvent {
+    fn to_fields(&self) -> (&'static str, String) {
+        match self {
+            SessionEvent::RomLoaded(rom) => ("rom", rom.clone()),
+            SessionEvent::Reset => ("reset", String::new()),
+            SessionEvent::SaveState(slot) => ("save", slot.to_string()),
+            SessionEvent::LoadState(slot) => ("load", slot.to_string()),
+            SessionEvent::Error(message) => ("error", message.clone()),
+            SessionEvent::Ended(seconds) => ("end", seconds.to_string()),
+            SessionEvent::Played(seconds) => ("played", seconds.to_string()),
+        }
+    }
+
+    fn from_fields(kind: &str, detail: &str) -> Option<SessionEvent> {
+        match kind {
+            "rom" => Some(SessionEvent::RomLoaded(detail.to_string())),
+            "reset" => Some(SessionEvent::Reset),
+            "save" => detail.parse().ok().map(SessionEvent::SaveState),
+            "load" => detail.parse().ok().map(SessionEvent::LoadState),
+            "error" => Some(SessionEvent::Error(detail.to_string())),
+            "end" => detail.parse().ok().map(SessionEvent::Ended),
+            "played" => detail.parse().ok().map(SessionEvent::Played),
+            _ => None,
+        }
+    }
+}
+
+// Appends the events of one run to the session log, one tab-separated line
+// per event: `<unix seconds> <session id> <event> <detail>`. Failing to
+// write only logs a warning; losing history must not stop the emulator.
+pub struct SessionLog {
+    file: Option<File>,
+    id: u64,
+    started: Instant,
+}
+
+impl SessionLog {
+    pub fn open(path: &Path) -> io::Result<SessionLog> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(SessionLog {
+            file: Some(file),
+            id: now().as_millis() as u64,
+            started: Instant::now(),
+        })
+    }
+
+    // A log that records nothing.
+    pub fn disabled() -> SessionLog {
+        SessionLog {
+            file: None,
+            id: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, event: SessionEvent) {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let (kind, detail) = event.to_fields();
+        // tabs and newlines would break the line format
+        let detail: String = detail.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+        let line = format!("{}\t{}\t{}\t{}\n", now().as_secs(), self.id, kind, detail);
+        if let Err(error) = file.write_all(line.as_bytes()) {
+            log::warn!("session log: {}", error);
+            self.file = None;
+        }
+    }
+}
+
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        let played = self.started.elapsed().as_secs();
+        self.record(SessionEvent::Ended(played));
+    }
+}
+
+// One session read back from the log.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionSummary {
+    pub started: u64,
+    pub rom: Option<String>,
+    pub played: Duration,
+    // the play clock, zero for sessions logged before it existed
+    pub play_time: Duration,
+    pub resets: u32,
+    pub errors: Vec<String>,
+}
+
+// Sessions in the log, oldest first. A session without an end event (the
+// process was killed) counts as played until its last event.
+pub fn read_history(log: &str) -> Vec<SessionSummary> {
+    let mut sessions: Vec<(u64, SessionSummary)> = Vec::new();
+    for line in log.lines() {
+        let fields: Vec<&str> = line.splitn(4, '\t').collect();
+        if fields.len() != 4 {
+            continue;
+        }
+        let (time, id) = match (fields[0].parse::<u64>(), fields[1].parse::<u64>()) {
+            (Ok(time), Ok(id)) => (time, id),
+            _ => continue,
+        };
+        let event = match SessionEvent::from_fields(fields[2], fields[3]) {
+            Some(event) => event,
+            None => continue,
+        };
+
+        let index = match sessions.iter().position(|(session, _)| *session == id) {
+            Some(index) => index,
+            None => {
+                let summary = SessionSummary {
+                    started: time,
+                    ..SessionSummary::default()
+                };
+                sessions.push((id, summary));
+                sessions.len() - 1
+            }
+        };
+        let summary = &mut sessions[index].1;
+        summary.played = Duration::from_secs(time.saturating_sub(summary.started));
+        match event {
+            SessionEvent::RomLoaded(rom) => summary.rom = Some(rom),
+            SessionEvent::Reset => summary.resets += 1,
+            SessionEvent::Error(message) => summary.errors.push(message),
+            SessionEvent::Ended(seconds) => summary.played = Duration::from_secs(seconds),
+            SessionEvent::Played(seconds) => summary.play_time += Duration::from_secs(seconds),
+            _ => {}
+        }
+    }
+    sessions.into_iter().map(|(_, summary)| summary).collect()
+}
+
+// Total play clock time per ROM, most played first.
+pub fn play_time_by_rom(history: &[SessionSummary]) -> Vec<(String, Duration)> {
+    let mut totals: Vec<(String, Duration)> = Vec::new();
+    for summary in history {
+        let rom = match &summary.rom {
+            Some(rom) => rom,
+            None => continue,
+        };
+        match totals.iter_mut().find(|(total_rom, _)| total_rom == rom) {
+            Some((_, total)) => *total += summary.play_time,
+            None => totals.push((rom.clone(), summary.play_time)),
+        }
+    }
+    totals.sort_by_key(|(_, played)| std::cmp::Reverse(*played));
+    totals
+}
+
+// $XDG_DATA_HOME/hachip, falling back to %APPDATA% and then ~/.local/share.
+pub fn data_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+        .map(|dir| dir.join("hachip"))
+}
+
+// A ROM's directory under `data_dir()/<kind>`: named after the file and a
+// hash of its contents, so a rebuilt ROM with the same name doesn't pick up
+// stale files.
+pub fn rom_data_dir(kind: &str, rom_path: &str, rom: &[u8]) -> Option<PathBuf> {
+    let stem = Path::new(rom_path).file_stem()?.to_string_lossy().into_owned();
+    let name = format!("{}-{:016x}", stem, headless::hash(rom));
+    data_dir().map(|dir| dir.join(kind).join(name))
+}
+
+// Writes `bytes` to a .partial file beside `path` and renames it over
+// `path`, so a crash mid-write keeps the previous file.
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+pub fn default_log_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(LOG_FILE))
+}
+
+// "YYYY-MM-DD HH:MM" in UTC.
+pub fn format_timestamp(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86400) as i64;
+    let minutes = unix_seconds % 86400 / 60;
+    // days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_timestamp, play_time_by_rom, read_history, SessionEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn history_groups_events_by_session() {
+        let log = "100\t1\trom\tpong.ch8\n\
+                   100\t2\trom\ttetris.ch8\n\
+                   130\t1\treset\t\n\
+                   160\t1\terror\tunknown opcode\n\
+                   190\t1\tplayed\t75\n\
+                   190\t1\tend\t90\n\
+                   garbage line\n\
+                   400\t2\tsave\t1\n\
+                   500\t3\trom\tpong.ch8\n\
+                   520\t3\tplayed\t20\n";
+        let history = read_history(log);
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].rom.as_deref(), Some("pong.ch8"));
+        assert_eq!(history[0].played, Duration::from_secs(90));
+        assert_eq!(history[0].resets, 1);
+        assert_eq!(history[0].errors, vec!["unknown opcode"]);
+        assert_eq!(history[1].played, Duration::from_secs(300), "no end event");
+        assert_eq!(
+            play_time_by_rom(&history),
+            vec![("pong.ch8".to_string(), Duration::from_secs(95)), ("tetris.ch8".to_string(), Duration::from_secs(0))]
+        );
+    }
+
+    #[test]
+    fn events_round_trip_and_timestamps_format() {
+        let event = SessionEvent::LoadState(3);
+        let (kind, detail) = event.to_fields();
+        assert_eq!(SessionEvent::from_fields(kind, &detail), Some(event));
+
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400 + 3660), "2000-02-29 01:01");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::cpu::Cpu;
+use crate::ppu::{Display, HEIGHT, WIDTH};
+use crate::save_slots::{SaveSlots, SlotInfo, SLOTS};
+use crate::session;
+use crate::state::SaveState;
+use crate::text::{Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+
+// The thumbnail is the slot's screen at half size, framed, between the
+// title and the date.
+const THUMB_WIDTH: usize = WIDTH / 2;
+const THUMB_HEIGHT: usize = HEIGHT / 2;
+const THUMB_X: usize = (WIDTH - THUMB_WIDTH) / 2;
+const THUMB_Y: usize = LINE_HEIGHT + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotAction {
+    Save,
+    Load,
+}
+
+// How the picker closed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Picked {
+    Saved(usize),
+    Loaded(usize),
+    Cancelled,
+}
+
+impl Picked {
+    // What the OSD says about it.
+    pub fn message(self) -> Option<String> {
+        match self {
+            Picked::Saved(slot) => Some(format!("STATE SAVED TO SLOT {}", slot)),
+            Picked::Loaded(slot) => Some(format!("SLOT {} LOADED", slot)),
+            Picked::Cancelled => None,
+        }
+    }
+}
+
+// The overlay F1 opens over a paused game to save it to a numbered slot or
+// load one: Left and Right pick the slot, Up and Down switch between saving
+// and loading. Each slot shows its screen and when it was saved; a slot of
+// another ROM can't be loaded. Like the pause menu it draws on the emulated
+// display, so the game is kept aside while it is up.
+pub struct SlotPicker {
+    game: SaveState,
+    slots: SaveSlots,
+    infos: Vec<Option<SlotInfo>>,
+    slot: usize,
+    action: SlotAction,
+    input: UiInput,
+    // why the last load was refused, shown until the slot changes
+    refused: Option<String>,
+}
+
+impl SlotPicker {
+    pub fn open(cpu: &Cpu, slots: SaveSlots) -> SlotPicker {
+        SlotPicker {
+            game: cpu.save_state(),
+            infos: (0..SLOTS).map(|slot| slots.info(slot)).collect(),
+            slots,
+            slot: 0,
+            action: SlotAction::Save,
+            input: UiInput::new(),
+            refused: None,
+        }
+    }
+
+    // The game as it was when the picker opened.
+    pub fn game(&self) -> &SaveState {
+        &self.game
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn action(&self) -> SlotAction {
+        self.action
+    }
+
+    // Returns how the picker closed, with the cpu back in the game or at
+    // the loaded state.
+    pub fn key_down(&mut self, key: UiKey, cpu: &mut Cpu) -> Option<Picked> {
+        let picked = match self.input.key_down(key)? {
+            UiAction::Confirm => match self.action {
+                SlotAction::Save => match self.slots.save(self.slot, &self.game) {
+                    Ok(path) => {
+                        log::info!("saved state to {}", path.display());
+                        Picked::Saved(self.slot)
+                    }
+                    Err(error) => {
+                        log::warn!("saving to slot {} failed: {}", self.slot, error);
+                        self.refused = Some("SAVE FAILED".to_string());
+                        return None;
+                    }
+                },
+                SlotAction::Load => match self.slots.load(self.slot) {
+                    Ok(state) => {
+                        cpu.load_state(&state);
+                        log::info!("loaded state from slot {}", self.slot);
+                        return Some(Picked::Loaded(self.slot));
+                    }
+                    Err(error) => {
+                        log::warn!("{}", error);
+                        self.refused = Some(match &self.infos[self.slot] {
+                            Some(info) if !self.slots.is_compatible(info) => "OTHER ROM".to_string(),
+                            Some(_) => "UNREADABLE".to_string(),
+                            None => "EMPTY".to_string(),
+                        });
+                        return None;
+                    }
+                },
+            },
+            UiAction::Back => Picked::Cancelled,
+            action => {
+                self.move_selection(action);
+                return None;
+            }
+        };
+        cpu.load_state(&self.game);
+        Some(picked)
+    }
+
+    fn move_selection(&mut self, action: UiAction) {
+        match action {
+            UiAction::Left => self.slot = (self.slot + SLOTS - 1) % SLOTS,
+            UiAction::Right => self.slot = (self.slot + 1) % SLOTS,
+            UiAction::Up | UiAction::Down => {
+                self.action = match self.action {
+                    SlotAction::Save => SlotAction::Load,
+                    SlotAction::Load => SlotAction::Save,
+                }
+            }
+            _ => return,
+        }
+        self.refused = None;
+    }
+
+    // Draws the picker over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        let verb = match self.action {
+            SlotAction::Save => "SAVE",
+            SlotAction::Load => "LOAD",
+        };
+        text.draw_text(0, 0, &format!("<{} SLOT {}>", verb, self.slot));
+        let info = self.infos[self.slot].as_ref();
+        let status = match (&self.refused, info) {
+            (Some(reason), _) => reason.clone(),
+            (None, Some(info)) => session::format_timestamp(info.saved),
+            (None, None) => "EMPTY".to_string(),
+        };
+        text.draw_text(0, (THUMB_Y + THUMB_HEIGHT + 2) as i32, &status);
+
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+        // the frame
+        for x in THUMB_X - 1..=THUMB_X + THUMB_WIDTH {
+            display.set_pixel(x, THUMB_Y - 1, 1);
+            display.set_pixel(x, THUMB_Y + THUMB_HEIGHT, 1);
+        }
+        for y in THUMB_Y..THUMB_Y + THUMB_HEIGHT {
+            display.set_pixel(THUMB_X - 1, y, 1);
+            display.set_pixel(THUMB_X + THUMB_WIDTH, y, 1);
+        }
+        // a thumbnail pixel is lit when any of the four it stands for is
+        if let Some(info) = info {
+            for y in 0..THUMB_HEIGHT {
+                for x in 0..THUMB_WIDTH {
+                    let lit = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .any(|(dx, dy)| info.framebuffer.get((y * 2 + dy) * WIDTH + x * 2 + dx).is_some_and(|pixel| *pixel != 0));
+                    display.set_pixel(THUMB_X + x, THUMB_Y + y, lit as u8);
+                }
+            }
+        }
+    }
+}
+
+impl UiScreen for SlotPicker {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.move_selection(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Picked, SlotAction, SlotPicker};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::save_slots::SaveSlots;
+    use crate::ui_input::UiKey;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn saves_to_a_slot_and_loads_it_back() {
+        let dir = env::temp_dir().join(format!("hachip-slot-picker-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // ADD V0, 1 / JP 0x200
+        let rom = vec![0x70, 0x01, 0x12, 0x00];
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        cpu.load(rom.clone()).unwrap();
+        cpu.step().unwrap();
+
+        let mut picker = SlotPicker::open(&cpu, SaveSlots::new(dir.clone(), &rom));
+        picker.render(cpu.display_mut());
+        assert!(cpu.display().framebuffer().contains(&1));
+        assert_eq!(picker.key_down(UiKey::Left, &mut cpu), None);
+        assert_eq!(picker.slot(), 9, "wraps around");
+        assert_eq!(picker.key_down(UiKey::Enter, &mut cpu), Some(Picked::Saved(9)));
+        assert!(!cpu.display().framebuffer().contains(&1), "the game's screen is back");
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[0], 2);
+        let mut picker = SlotPicker::open(&cpu, SaveSlots::new(dir.clone(), &rom));
+        assert_eq!(picker.key_down(UiKey::Down, &mut cpu), None);
+        assert_eq!(picker.action(), SlotAction::Load);
+        assert_eq!(picker.key_down(UiKey::Enter, &mut cpu), None, "slot 0 is empty");
+        assert_eq!(picker.key_down(UiKey::Left, &mut cpu), None);
+        assert_eq!(picker.key_down(UiKey::Enter, &mut cpu), Some(Picked::Loaded(9)));
+        assert_eq!(cpu.registers()[0], 1);
+
+        let mut picker = SlotPicker::open(&cpu, SaveSlots::new(dir.clone(), &[0x12, 0x00]));
+        picker.key_down(UiKey::Up, &mut cpu);
+        picker.key_down(UiKey::Left, &mut cpu);
+        assert_eq!(picker.key_down(UiKey::Enter, &mut cpu), None, "saved from another ROM");
+        assert_eq!(picker.key_down(UiKey::Escape, &mut cpu), Some(Picked::Cancelled));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use crate::errors::StateError;
+use crate::megachip::{self, MegaChip};
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::profile::EmulationProfile;
+
+// Snapshot file layout (all integers big-endian):
+//   "H8ST" magic, version byte
+//   i: u16, pc: u16, sp: u8, dt: u8, st: u8, vblank_wait: u8
+//   v: 16 bytes, stack: 16 x u16, memory: 4096 bytes
+//   framebuffer: WIDTH * HEIGHT bytes
+//   profile length: u8, profile as text (since version 2)
+//   with Mega-Chip state only (version 3): top byte of i: u8, memory past
+//   the 4096 bytes: u32 length and bytes, MegaChip state
+// States without Mega-Chip state are still written as version 2.
+const MAGIC: &[u8; 4] = b"H8ST";
+const VERSION: u8 = 2;
+const MEGACHIP_VERSION: u8 = 3;
+const MEMORY_SIZE: usize = 4096;
+// size of a version 1 state, everything before the profile
+const SIZE: usize = 4 + 1 + 2 + 2 + 4 + 16 + 32 + MEMORY_SIZE + WIDTH * HEIGHT;
+
+// Everything needed to resume a ROM where it was: registers, timers,
+// memory and the screen. Quirks and keys are settings and input, not state;
+// the profile only records the quirks the state was saved with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveState {
+    pub(crate) i: u32,
+    pub(crate) pc: u16,
+    pub(crate) sp: u8,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) vblank_wait: bool,
+    pub(crate) v: [u8; 16],
+    pub(crate) stack: [u16; 16],
+    pub(crate) memory: Vec<u8>,
+    pub(crate) framebuffer: Vec<u8>,
+    // None for version 1 states
+    pub(crate) profile: Option<EmulationProfile>,
+    // Some for version 3 states
+    pub(crate) mega: Option<MegaChip>,
+}
+
+impl SaveState {
+    pub fn profile(&self) -> Option<EmulationProfile> {
+        self.profile
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let profile = self.profile.map(|profile| profile.to_string()).unwrap_or_default();
+        let mut bytes = Vec::with_capacity(SIZE + 1 + profile.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(if self.mega.is_some() { MEGACHIP_VERSION } else { VERSION });
+        bytes.extend_from_slice(&(self.i as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.pc.to_be_bytes());
+        bytes.extend_from_slice(&[self.sp, self.dt, self.st, self.vblank_wait as u8]);
+        bytes.extend_from_slice(&self.v);
+        for address in self.stack.iter() {
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.memory[..MEMORY_SIZE]);
+        bytes.extend_from_slice(&self.framebuffer);
+        // the serialized profile is far shorter than 256 bytes
+        bytes.push(profile.len() as u8);
+        bytes.extend_from_slice(profile.as_bytes());
+        if let Some(mega) = &self.mega {
+            let extended = &self.memory[MEMORY_SIZE..];
+            bytes.push((self.i >> 16) as u8);
+            bytes.extend_from_slice(&(extended.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(extended);
+            mega.to_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, StateError> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(error("not a hachip save state".to_string()));
+        }
+        let version = bytes[4];
+        if version != 1 && version != VERSION && version != MEGACHIP_VERSION {
+            return Err(error(format!("unsupported save state version {}", version)));
+        }
+        let mut size = match (version, bytes.get(SIZE)) {
+            (1, _) => SIZE,
+            (_, Some(length)) => SIZE + 1 + *length as usize,
+            (_, None) => SIZE + 1,
+        };
+        if version == MEGACHIP_VERSION {
+            size += 1 + 4;
+            if let Some(length) = bytes.get(size - 4..size) {
+                size += u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+            }
+            size += megachip::STATE_SIZE;
+        }
+        if bytes.len() != size {
+            return Err(error(format!("save state is {} bytes, expected {}", bytes.len(), size)));
+        }
+
+        let mut reader = Reader { bytes, offset: 5 };
+        let mut i = reader.u16() as u32;
+        let pc = reader.u16();
+        let registers = reader.take(4);
+        let mut v = [0; 16];
+        v.copy_from_slice(reader.take(16));
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16();
+        }
+        let mut memory = reader.take(MEMORY_SIZE).to_vec();
+        let framebuffer = reader.take(WIDTH * HEIGHT).to_vec();
+        let profile = if version == 1 {
+            None
+        } else {
+            let length = reader.take(1)[0] as usize;
+            let text = String::from_utf8_lossy(reader.take(length)).into_owned();
+            match EmulationProfile::parse(&text) {
+                Some(profile) => Some(profile),
+                None => return Err(error(format!("save state has an unknown profile `{}`", text))),
+            }
+        };
+        let mut mega = None;
+        if version == MEGACHIP_VERSION {
+            i |= (reader.take(1)[0] as u32) << 16;
+            let length = reader.u32() as usize;
+            memory.extend_from_slice(reader.take(length));
+            mega = MegaChip::from_bytes(reader.take(megachip::STATE_SIZE));
+            if mega.is_none() || memory.len() > megachip::MEMORY_SIZE {
+                return Err(error("save state has broken Mega-Chip state".to_string()));
+            }
+        }
+
+        let i_limit = if mega.is_some() { megachip::MEMORY_SIZE } else { MEMORY_SIZE };
+        if registers[0] as usize > stack.len() || pc as usize >= MEMORY_SIZE || i as usize >= i_limit {
+            return Err(error("save state registers are out of range".to_string()));
+        }
+        Ok(SaveState {
+            i,
+            pc,
+            sp: registers[0],
+            dt: registers[1],
+            st: registers[2],
+            vblank_wait: registers[3] != 0,
+            v,
+            stack,
+            memory,
+            framebuffer,
+            profile,
+            mega,
+        })
+    }
+}
+
+fn error(message: String) -> StateError {
+    StateError { message }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> &'a [u8] {
+        let slice = &self.bytes[self.offset..self.offset + count];
+        self.offset += count;
+        slice
+    }
+
+    fn u16(&mut self) -> u16 {
+        let bytes = self.take(2);
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    }
+
+    fn u32(&mut self) -> u32 {
+        let bytes = self.take(4);
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SaveState;
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::quirks::Platform;
+
+    #[test]
+    fn state_round_trips_through_bytes_and_cpu() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // LD V3, 0x42 / DRW V0, V0, 5 (the "0" glyph)
+        cpu.load(vec![0x63, 0x42, 0xD0, 0x05]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let state = cpu.save_state();
+        let bytes = state.to_bytes();
+        assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+        assert_eq!(state.profile(), Some(cpu.profile()));
+
+        let mut other = Cpu::new(Box::new(HeadlessDisplay::new()));
+        other.load_state(&state);
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.display().framebuffer(), cpu.display().framebuffer());
+    }
+
+    #[test]
+    fn mega_chip_state_is_version_3() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = Platform::MegaChip.quirks();
+        cpu.reset();
+        // MEGAON / LDHI I, #010000, in a ROM past the 4K
+        let mut rom = vec![0x00, 0x11, 0x01, 0x01, 0x00, 0x00];
+        rom.resize(0x1000, 0x55);
+        cpu.load(rom).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let state = cpu.save_state();
+        let bytes = state.to_bytes();
+        assert_eq!(bytes[4], 3);
+        assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+        assert!(SaveState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut other = Cpu::new(Box::new(HeadlessDisplay::new()));
+        other.quirks = cpu.quirks;
+        other.load_state(&state);
+        assert_eq!((other.i(), other.memory().len()), (0x10000, 0x1200));
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.state_hash(), cpu.state_hash());
+    }
+
+    #[test]
+    fn rejects_foreign_and_truncated_data() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        let bytes = cpu.save_state().to_bytes();
+
+        assert!(SaveState::from_bytes(b"PNG...").is_err());
+        assert!(SaveState::from_bytes(&bytes[..100]).is_err());
+        let mut newer = bytes.clone();
+        newer[4] = 99;
+        assert!(SaveState::from_bytes(&newer).is_err());
+
+        // version 1 had no profile
+        let mut old = bytes[..super::SIZE].to_vec();
+        old[4] = 1;
+        assert_eq!(SaveState::from_bytes(&old).unwrap().profile(), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::cpu::Cpu;
+use crate::headless::{self, HeadlessDisplay};
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::quirks::Platform;
+use crate::scheduler::{self, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use crate::script;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Cycles a golden runs for unless it says otherwise.
+pub const DEFAULT_CYCLES: u64 = 2000;
+// Seed for Cxkk, so ROMs drawing random sprites still compare.
+const SEED: u64 = 0x5EED;
+
+// Golden-image tests for test ROMs. Every `<name>.golden` in a directory
+// runs `<name>.ch8` next to it headless and compares the screen. A golden
+// file starts with settings, one per line, then the expected screen as
+// printed by --dump text:
+//
+//   rom ../../assets/roms/digits.ch8   the ROM, relative to the golden
+//   platform schip                     vip by default
+//   cycles 5000                        DEFAULT_CYCLES by default
+//   keys 5A                            keys held for the whole run
+//   release 1000                       or only for this many cycles
+//   state 3c1f9a0b2e4d6c58             Cpu::state_hash at the end, if set
+#[derive(Clone, Debug, PartialEq)]
+pub struct Golden {
+    pub rom: Option<PathBuf>,
+    pub platform: Platform,
+    pub cycles: u64,
+    pub keys: Vec<u8>,
+    pub release: Option<u64>,
+    pub state: Option<u64>,
+    pub screen: String,
+}
+
+// A ROM whose screen isn't the golden one, or that couldn't run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub name: String,
+    pub message: String,
+    // the screen with '+' and '-' as in script failures
+    pub diff: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)?;
+        if let Some(diff) = &self.diff {
+            write!(f, "\n{}", diff)?;
+        }
+        Ok(())
+    }
+}
+
+impl Golden {
+    pub fn parse(text: &str) -> Option<Golden> {
+        let mut golden = Golden {
+            screen: String::new(),
+            ..Golden::default()
+        };
+        for line in text.lines() {
+            let line = line.trim_end();
+            match line.split_once(' ') {
+                Some(("rom", path)) => golden.rom = Some(PathBuf::from(path)),
+                Some(("platform", name)) => golden.platform = Platform::from_name(name)?,
+                Some(("cycles", cycles)) => golden.cycles = cycles.parse().ok()?,
+                Some(("keys", keys)) => {
+                    golden.keys = keys.chars().map(|key| key.to_digit(16).map(|key| key as u8)).collect::<Option<_>>()?;
+                }
+                Some(("release", cycles)) => golden.release = Some(cycles.parse().ok()?),
+                Some(("state", hash)) => golden.state = Some(u64::from_str_radix(hash, 16).ok()?),
+                _ if !line.is_empty() && line.chars().all(|c| c == '#' || c == '.') => {
+                    golden.screen.push_str(line);
+                    golden.screen.push('\n');
+                }
+                _ if line.is_empty() => {}
+                _ => return None,
+            }
+        }
+        Some(golden)
+    }
+
+    // The screen and state hash left after running `rom` with these
+    // settings.
+    pub fn run(&self, rom: Vec<u8>) -> Result<(String, u64), String> {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = self.platform.quirks();
+        cpu.seed_rng(SEED);
+        cpu.reset();
+        cpu.load(rom).map_err(|error| error.to_string())?;
+        let mut cycles = 0;
+        while cycles < self.cycles {
+            let held = self.release.is_none_or(|release| cycles < release);
+            cpu.keypad.press(if held { self.keys.clone() } else { Vec::new() });
+            let instructions = (self.cycles - cycles).min(DEFAULT_INSTRUCTIONS_PER_FRAME as u64);
+            match scheduler::run_frame(&mut cpu, instructions as u32) {
+                Ok(executed) => cycles += executed as u64,
+                Err(error) => return Err(format!("stopped after {} cycles: {}", cycles, error)),
+            }
+        }
+        Ok((headless::dump_text(cpu.display().framebuffer()), cpu.state_hash()))
+    }
+}
+
+impl Default for Golden {
+    fn default() -> Golden {
+        Golden {
+            rom: None,
+            platform: Platform::default(),
+            cycles: DEFAULT_CYCLES,
+            keys: Vec::new(),
+            release: None,
+            state: None,
+            screen: headless::dump_text(&[0; WIDTH * HEIGHT]),
+        }
+    }
+}
+
+impl fmt::Display for Golden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(rom) = &self.rom {
+            writeln!(f, "rom {}", rom.display())?;
+        }
+        writeln!(f, "platform {}", self.platform.name())?;
+        writeln!(f, "cycles {}", self.cycles)?;
+        if !self.keys.is_empty() {
+            let keys: String = self.keys.iter().map(|key| format!("{:X}", key)).collect();
+            writeln!(f, "keys {}", keys)?;
+        }
+        if let Some(release) = self.release {
+            writeln!(f, "release {}", release)?;
+        }
+        if let Some(state) = self.state {
+            writeln!(f, "state {:016x}", state)?;
+        }
+        write!(f, "{}", self.screen)
+    }
+}
+
+// Runs every golden in `dir`, returning the ROMs that didn't match. With
+// `bless`, mismatching goldens are rewritten with the current screen and
+// state instead, and ROMs without one get a golden with the default
+// settings: check those by eye before committing them.
+pub fn check_dir(dir: &Path, bless: bool) -> io::Result<Vec<Mismatch>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "golden" || extension == "ch8"))
+        .collect();
+    paths.sort();
+
+    let mut mismatches = Vec::new();
+    for path in &paths {
+        let golden_path = path.with_extension("golden");
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let is_rom = path.extension().is_some_and(|extension| extension == "ch8");
+        // a ROM with a golden is checked through the golden
+        if is_rom && (!bless || golden_path.exists()) {
+            continue;
+        }
+        let mismatch = |message: String, diff: Option<String>| Mismatch {
+            name: name.clone(),
+            message,
+            diff,
+        };
+        let mut golden = if is_rom {
+            Golden::default()
+        } else {
+            match Golden::parse(&fs::read_to_string(&golden_path)?) {
+                Some(golden) => golden,
+                None => {
+                    mismatches.push(mismatch("can't read the golden".to_string(), None));
+                    continue;
+                }
+            }
+        };
+        let rom_path = match &golden.rom {
+            Some(rom) => dir.join(rom),
+            None => path.with_extension("ch8"),
+        };
+        let run = match fs::read(&rom_path) {
+            Ok(rom) => golden.run(rom),
+            Err(error) => Err(format!("{}: {}", rom_path.display(), error)),
+        };
+        let (screen, state) = match run {
+            Ok(run) => run,
+            Err(message) => {
+                mismatches.push(mismatch(message, None));
+                continue;
+            }
+        };
+        let diff = script::screen_diff(&golden.screen, &screen);
+        let state_differs = golden.state.is_some_and(|expected| expected != state);
+        if bless && (is_rom || diff.is_some() || state_differs) {
+            golden.screen = screen;
+            golden.state = Some(state);
+            fs::write(&golden_path, golden.to_string())?;
+        } else if let Some(diff) = diff {
+            mismatches.push(mismatch("screen differs from the golden".to_string(), Some(diff)));
+        } else if state_differs {
+            let message = format!("state differs from the golden: {:016x}", state);
+            mismatches.push(mismatch(message, None));
+        }
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_dir, Golden};
+    use crate::quirks::Platform;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn checks_and_blesses_goldens() {
+        let golden = Golden::parse("platform schip\ncycles 30\nkeys 5A\nrelease 20\n#.\n..\n").unwrap();
+        assert_eq!(golden.platform, Platform::Schip);
+        assert_eq!((golden.cycles, golden.keys.clone(), golden.release), (30, vec![5, 0xA], Some(20)));
+        assert_eq!(golden.screen, "#.\n..\n");
+        assert_eq!(Golden::parse(&golden.to_string()), Some(golden));
+        assert_eq!(Golden::parse("cycles many\n"), None);
+
+        let dir = env::temp_dir().join(format!("hachip-goldens-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // LD V0, 5 / LD F, V0 / DRW V0, V0, 5 / JP 0x206
+        fs::write(dir.join("five.ch8"), [0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]).unwrap();
+        assert_eq!(check_dir(&dir, false).unwrap(), [], "ROMs without goldens are skipped");
+
+        assert_eq!(check_dir(&dir, true).unwrap(), []);
+        let blessed = fs::read_to_string(dir.join("five.golden")).unwrap();
+        assert!(blessed.starts_with("platform vip\ncycles 2000\nstate "));
+        assert!(blessed.contains("\n.....####"), "the 5 drawn at (5, 5): {}", blessed);
+        assert_eq!(check_dir(&dir, false).unwrap(), []);
+
+        fs::write(dir.join("five.ch8"), [0x12, 0x00]).unwrap();
+        let mismatches = check_dir(&dir, false).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].name, "five");
+        assert!(mismatches[0].diff.as_ref().unwrap().contains(".....----..."));
+
+        // same screen, another state: V1 set as well
+        fs::write(dir.join("five.ch8"), [0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x61, 0x01, 0x12, 0x08]).unwrap();
+        let mismatches = check_dir(&dir, false).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].message.starts_with("state differs"), "{}", mismatches[0]);
+        assert_eq!(check_dir(&dir, true).unwrap(), []);
+        assert_eq!(check_dir(&dir, false).unwrap(), []);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use crate::assets::{self, FONT_HEIGHT};
+use crate::ppu::{HEIGHT, WIDTH};
+
+// Every glyph of the built-in font is 3 pixels wide; characters are spaced
+// one pixel apart and lines one pixel apart.
+pub const GLYPH_WIDTH: usize = 3;
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = FONT_HEIGHT + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+// Monochrome layer drawn on top of the emulated display (menus, toasts,
+// debug text). Same layout as the framebuffer: one byte per pixel, 1 is lit.
+#[derive(Clone)]
+pub struct Overlay {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Overlay {
+    pub fn new(width: usize, height: usize) -> Overlay {
+        Overlay {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = 0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.pixels.contains(&1)
+    }
+
+    pub fn text(&mut self) -> TextRenderer<'_> {
+        TextRenderer::new(&mut self.pixels, self.width)
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Overlay {
+        Overlay::new(WIDTH, HEIGHT)
+    }
+}
+
+// Draws text with the built-in font into a one-byte-per-pixel buffer.
+// Anything outside the clip rectangle (the whole buffer by default) is
+// dropped, so text may start off-screen or run past the edge.
+pub struct TextRenderer<'a> {
+    target: &'a mut [u8],
+    width: usize,
+    clip: (usize, usize, usize, usize),
+    value: u8,
+}
+
+impl<'a> TextRenderer<'a> {
+    pub fn new(target: &'a mut [u8], width: usize) -> TextRenderer<'a> {
+        let height = target.len() / width;
+        TextRenderer {
+            target,
+            width,
+            clip: (0, 0, width, height),
+            value: 1,
+        }
+    }
+
+    // Restricts drawing to a rectangle, intersected with the buffer.
+    pub fn clip(mut self, x: usize, y: usize, w: usize, h: usize) -> TextRenderer<'a> {
+        let (_, _, right, bottom) = self.clip;
+        self.clip = (x.min(right), y.min(bottom), (x + w).min(right), (y + h).min(bottom));
+        self
+    }
+
+    // Draws with dark pixels instead of lit ones, e.g. text on a filled box.
+    pub fn inverted(mut self) -> TextRenderer<'a> {
+        self.value = 0;
+        self
+    }
+
+    // Fills a rectangle with the background: dark, or lit when inverted.
+    pub fn fill(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.plot(px as i32, py as i32, 1 - self.value);
+            }
+        }
+    }
+
+    pub fn draw_char(&mut self, x: i32, y: i32, c: char) {
+        for (row, bits) in assets::glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0x80 >> column) != 0 {
+                    self.plot(x + column as i32, y + row as i32, self.value);
+                }
+            }
+        }
+    }
+
+    // Draws `text` from the top-left corner (x, y); '\n' starts a new line
+    // at the same x.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str) {
+        for (line_index, line) in text.lines().enumerate() {
+            let line_y = y + (line_index * LINE_HEIGHT) as i32;
+            for (index, c) in line.chars().enumerate() {
+                self.draw_char(x + (index * ADVANCE) as i32, line_y, c);
+            }
+        }
+    }
+
+    // Draws a single line aligned inside the clip rectangle.
+    pub fn draw_aligned(&mut self, y: i32, text: &str, align: Align) {
+        let (left, _, right, _) = self.clip;
+        let free = (right - left) as i32 - text_width(text) as i32;
+        let x = match align {
+            Align::Left => left as i32,
+            Align::Center => left as i32 + free / 2,
+            Align::Right => left as i32 + free,
+        };
+        self.draw_text(x, y, text);
+    }
+
+    fn plot(&mut self, x: i32, y: i32, value: u8) {
+        let (left, top, right, bottom) = self.clip;
+        if x < left as i32 || y < top as i32 || x >= right as i32 || y >= bottom as i32 {
+            return;
+        }
+        self.target[y as usize * self.width + x as usize] = value;
+    }
+}
+
+// Width in pixels of the longest line, without the trailing spacing.
+pub fn text_width(text: &str) -> usize {
+    text.lines()
+        .map(|line| line.chars().count())
+        .max()
+        .map_or(0, |chars| (chars * ADVANCE).saturating_sub(1))
+}
+
+pub fn text_height(text: &str) -> usize {
+    (text.lines().count() * LINE_HEIGHT).saturating_sub(1)
+}
+
+// Splits `text` into lines no wider than `max_width` pixels, breaking at
+// spaces. Words longer than a line are cut.
+pub fn wrap(text: &str, max_width: usize) -> Vec<String> {
+    let max_chars = ((max_width + 1) / ADVANCE).max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            if !line.is_empty() && line.chars().count() + 1 + word.len() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            while word.len() > max_chars {
+                let rest = word.split_off(max_chars);
+                lines.push(word.into_iter().collect());
+                word = rest;
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{text_width, wrap, Align, Overlay};
+
+    fn row(overlay: &Overlay, y: usize) -> String {
+        overlay.pixels()[y * overlay.width()..(y + 1) * overlay.width()]
+            .iter()
+            .map(|pixel| if *pixel == 1 { '#' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn draws_glyphs_side_by_side() {
+        let mut overlay = Overlay::new(8, 5);
+        overlay.text().draw_text(0, 0, "10");
+
+        assert_eq!(row(&overlay, 0), ".#..###.");
+        assert_eq!(row(&overlay, 1), "##..#.#.");
+    }
+
+    #[test]
+    fn clips_to_buffer_and_rectangle() {
+        let mut overlay = Overlay::new(8, 5);
+        overlay.text().draw_text(-2, 3, "0");
+        assert_eq!(row(&overlay, 3), "#.......");
+        assert_eq!(row(&overlay, 4), "#.......");
+
+        overlay.clear();
+        overlay.text().clip(0, 0, 4, 5).draw_text(4, 0, "0");
+        assert!(overlay.is_empty());
+    }
+
+    #[test]
+    fn aligns_within_clip() {
+        let mut overlay = Overlay::new(9, 5);
+        overlay.text().draw_aligned(0, "0", Align::Center);
+        assert_eq!(row(&overlay, 0), "...###...");
+
+        overlay.clear();
+        overlay.text().draw_aligned(0, "0", Align::Right);
+        assert_eq!(row(&overlay, 0), "......###");
+    }
+
+    #[test]
+    fn measures_and_wraps() {
+        assert_eq!(text_width("ABC"), 11);
+        assert_eq!(text_width("A\nABC"), 11);
+        assert_eq!(wrap("PRESS ANY KEY", 23), vec!["PRESS", "ANY", "KEY"]);
+        assert_eq!(wrap("PRESS ANY KEY", 31), vec!["PRESS", "ANY KEY"]);
+        assert_eq!(wrap("ABCDEFG", 11), vec!["ABC", "DEF", "G"]);
+    }
+}
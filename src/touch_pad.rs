@@ -0,0 +1,130 @@
+use crate::keymap::HEXPAD_ROWS;
+use crate::ppu::{Rect, FONT_SET};
+
+// A key is CELL pixels square with the outline, and the pad one more for the
+// closing outline on the right and bottom.
+const CELL: usize = 8;
+pub const PAD_SIZE: usize = CELL * 4 + 1;
+
+// `--touchpad`: a hexpad drawn next to the display that plays with the
+// mouse or touches, for touch screens and for trying a ROM without finding
+// its keys. Every pointer (the mouse, each finger) holds the key under it,
+// sliding from key to key, so several can be held at once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TouchPad {
+    // (pointer, key) for the pointers on a key
+    touches: Vec<(i64, u8)>,
+}
+
+impl TouchPad {
+    pub fn new() -> TouchPad {
+        TouchPad::default()
+    }
+
+    // A pointer went down or moved to `key`, None when it is off the pad.
+    pub fn touch(&mut self, pointer: i64, key: Option<u8>) {
+        self.release(pointer);
+        if let Some(key) = key {
+            self.touches.push((pointer, key));
+        }
+    }
+
+    pub fn release(&mut self, pointer: i64) {
+        self.touches.retain(|(touching, _)| *touching != pointer);
+    }
+
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.touches.iter().any(|(_, touched)| *touched == key)
+    }
+
+    // The keys held down, to add to the keyboard's.
+    pub fn keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.touches.iter().map(|(_, key)| *key)
+    }
+
+    // The pad as PAD_SIZE x PAD_SIZE pixels, 1 for lit: an outlined key
+    // with its digit from the font, filled in while held.
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = vec![0; PAD_SIZE * PAD_SIZE];
+        for (row, keys) in HEXPAD_ROWS.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let (left, top) = (column * CELL, row * CELL);
+                let held = self.is_key_down(*key);
+                for y in 0..=CELL {
+                    for x in 0..=CELL {
+                        let outline = x == 0 || y == 0 || x == CELL || y == CELL;
+                        // the glyph, 4x5, sits in the middle of the key
+                        let glyph = (2..7).contains(&y)
+                            && (2..6).contains(&x)
+                            && FONT_SET[*key as usize * 5 + y - 2] & (0x80 >> (x - 2)) != 0;
+                        let lit = outline || (held != glyph);
+                        let pixel = &mut pixels[(top + y) * PAD_SIZE + left + x];
+                        *pixel |= lit as u8;
+                    }
+                }
+            }
+        }
+        pixels
+    }
+}
+
+// Where the display and the pad go in a window: the pad is a square at the
+// bottom of a window taller than wide and at the right of others, at most
+// half the window, and the display gets the rest.
+pub fn split(width: u32, height: u32) -> (Rect, Rect) {
+    if height > width {
+        let side = width.min(height / 2);
+        let display = Rect::new(0, 0, width, height - side);
+        (display, Rect::new(((width - side) / 2) as i32, (height - side) as i32, side, side))
+    } else {
+        let side = height.min(width / 2);
+        let display = Rect::new(0, 0, width - side, height);
+        (display, Rect::new((width - side) as i32, ((height - side) / 2) as i32, side, side))
+    }
+}
+
+// The key at a point of the window, if the point is on the pad.
+pub fn key_at(pad: Rect, x: i32, y: i32) -> Option<u8> {
+    let (x, y) = (x - pad.x, y - pad.y);
+    if x < 0 || y < 0 || x >= pad.w as i32 || y >= pad.h as i32 {
+        return None;
+    }
+    let column = x as usize * 4 / pad.w as usize;
+    let row = y as usize * 4 / pad.h as usize;
+    Some(HEXPAD_ROWS[row][column])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key_at, split, TouchPad, PAD_SIZE};
+    use crate::ppu::Rect;
+
+    #[test]
+    fn pointers_hold_the_keys_under_them() {
+        let (display, pad) = split(960, 320);
+        assert_eq!((display, pad), (Rect::new(0, 0, 640, 320), Rect::new(640, 0, 320, 320)));
+        assert_eq!(split(400, 800).1, Rect::new(0, 400, 400, 400), "below on a phone held upright");
+        assert_eq!(key_at(pad, 641, 1), Some(0x1));
+        assert_eq!(key_at(pad, 959, 319), Some(0xF));
+        assert_eq!(key_at(pad, 720, 250), Some(0x0));
+        assert_eq!(key_at(pad, 639, 100), None);
+
+        let mut touch_pad = TouchPad::new();
+        touch_pad.touch(1, key_at(pad, 641, 1));
+        touch_pad.touch(2, key_at(pad, 959, 319));
+        assert_eq!(touch_pad.keys().collect::<Vec<u8>>(), [0x1, 0xF]);
+        // slides onto 2, then off the pad
+        touch_pad.touch(1, key_at(pad, 740, 1));
+        assert!(touch_pad.is_key_down(0x2) && !touch_pad.is_key_down(0x1));
+        touch_pad.touch(1, None);
+        touch_pad.release(2);
+        assert_eq!(touch_pad.keys().count(), 0);
+
+        // 1 is drawn in the first key, and filled in when held
+        let pixels = touch_pad.pixels();
+        assert_eq!(pixels.len(), PAD_SIZE * PAD_SIZE);
+        assert_eq!(&pixels[2 * PAD_SIZE + 2..2 * PAD_SIZE + 6], [0, 0, 1, 0]);
+        touch_pad.touch(1, Some(0x1));
+        assert_eq!(&touch_pad.pixels()[2 * PAD_SIZE + 2..2 * PAD_SIZE + 6], [1, 1, 0, 1]);
+    }
+}
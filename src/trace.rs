@@ -0,0 +1,128 @@
+use crate::opcodes::{self, OPCODES};
+use std::ops::Range;
+
+// Registers and timers before an instruction, to log what it changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u32,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+// Which executed instructions `--trace` logs: those at an address in the
+// range and with one of the mnemonics, everything when both are unset.
+// Lines go to the `hachip::trace` log target at trace level.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub range: Option<Range<u16>>,
+    // first words of mnemonics, like "DRW" or "CALL"
+    pub mnemonics: Vec<String>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        if self.range.as_ref().is_some_and(|range| !range.contains(&pc)) {
+            return false;
+        }
+        self.mnemonics.is_empty() || self.mnemonics.iter().any(|mnemonic| *mnemonic == mnemonic_of(opcode))
+    }
+
+    pub fn log(&self, pc: u16, opcode: u16, before: &Registers, after: &Registers) {
+        log::trace!("{}", line(pc, opcode, before, after));
+    }
+}
+
+// "0204  7301  ADD V3, #01  V3 04->05", listing every register and timer
+// the instruction changed.
+pub fn line(pc: u16, opcode: u16, before: &Registers, after: &Registers) -> String {
+    let mut line = format!("{:04X}  {:04X}  {}", pc, opcode, opcodes::disassemble(opcode));
+    let mut changes = Vec::new();
+    for (index, (old, new)) in before.v.iter().zip(after.v.iter()).enumerate() {
+        if old != new {
+            changes.push(format!("V{:X} {:02X}->{:02X}", index, old, new));
+        }
+    }
+    if before.i != after.i {
+        changes.push(format!("I {:04X}->{:04X}", before.i, after.i));
+    }
+    for (name, old, new) in [("SP", before.sp, after.sp), ("DT", before.dt, after.dt), ("ST", before.st, after.st)].iter() {
+        if old != new {
+            changes.push(format!("{} {:02X}->{:02X}", name, old, new));
+        }
+    }
+    if !changes.is_empty() {
+        line.push_str("  ");
+        line.push_str(&changes.join(" "));
+    }
+    line
+}
+
+// "0x200..0x300", end exclusive, as for `--trace-range`.
+pub fn parse_range(text: &str) -> Option<Range<u16>> {
+    let (start, end) = text.split_once("..")?;
+    let parse = |value: &str| u16::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok();
+    let range = parse(start)?..parse(end)?;
+    if range.is_empty() {
+        return None;
+    }
+    Some(range)
+}
+
+// "DRW,CALL", as for `--trace-opcodes`; None if a name isn't a mnemonic.
+pub fn parse_mnemonics(text: &str) -> Option<Vec<String>> {
+    text.split(',')
+        .map(|name| {
+            let name = name.trim().to_ascii_uppercase();
+            OPCODES
+                .iter()
+                .any(|entry| entry.mnemonic.split(' ').next() == Some(name.as_str()))
+                .then_some(name)
+        })
+        .collect()
+}
+
+pub(crate) fn mnemonic_of(opcode: u16) -> &'static str {
+    opcodes::lookup(opcode)
+        .and_then(|entry| entry.mnemonic.split(' ').next())
+        .unwrap_or("DW")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line, parse_mnemonics, parse_range, Registers, Trace};
+
+    #[test]
+    fn filters_and_describes_instructions() {
+        let trace = Trace {
+            range: parse_range("0x200..0x300"),
+            mnemonics: parse_mnemonics("drw,CALL").unwrap(),
+        };
+        assert_eq!(trace.range, Some(0x200..0x300));
+        assert!(trace.matches(0x2FE, 0xD125));
+        assert!(trace.matches(0x200, 0x2400));
+        assert!(!trace.matches(0x300, 0xD125), "end is exclusive");
+        assert!(!trace.matches(0x202, 0x7301));
+        assert!(Trace::new().matches(0xFFE, 0x0000));
+        assert_eq!(parse_mnemonics("DRW,JUMP"), None);
+        assert_eq!(parse_range("0x300..0x200"), None);
+
+        let before = Registers {
+            v: [0; 16],
+            i: 0x200,
+            sp: 0,
+            dt: 0,
+            st: 0,
+        };
+        let mut after = before;
+        after.v[3] = 5;
+        after.dt = 0x10;
+        assert_eq!(line(0x204, 0x7305, &before, &after), "0204  7305  ADD V3, #05  V3 00->05 DT 00->10");
+        assert_eq!(line(0x206, 0x1206, &before, &before), "0206  1206  JP #206");
+    }
+}
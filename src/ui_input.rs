@@ -0,0 +1,155 @@
+// Navigation input shared by the emulator's own screens (ROM browser, pause
+// menu, key remapping). Frontends translate their key events to `UiKey`;
+// `UiInput` turns those into actions, with key repeat for held directions.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UiAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+// A key as seen by the UI: a dedicated navigation key or a CHIP-8 key
+// reached through the frontend's hexpad mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UiKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Escape,
+    Backspace,
+    Hex(u8),
+}
+
+impl UiKey {
+    // Hexpad keys follow the libretro d-pad layout: 2/8/4/6 move, 5 confirms
+    // and 0 goes back.
+    pub fn action(self) -> Option<UiAction> {
+        match self {
+            UiKey::Up | UiKey::Hex(0x2) => Some(UiAction::Up),
+            UiKey::Down | UiKey::Hex(0x8) => Some(UiAction::Down),
+            UiKey::Left | UiKey::Hex(0x4) => Some(UiAction::Left),
+            UiKey::Right | UiKey::Hex(0x6) => Some(UiAction::Right),
+            UiKey::Enter | UiKey::Hex(0x5) => Some(UiAction::Confirm),
+            UiKey::Escape | UiKey::Backspace | UiKey::Hex(0x0) => Some(UiAction::Back),
+            UiKey::Hex(_) => None,
+        }
+    }
+}
+
+// Frames (at 60Hz) before a held direction starts repeating, and between
+// repeats after that.
+const REPEAT_DELAY: u32 = 24;
+const REPEAT_INTERVAL: u32 = 6;
+
+// The held direction and the frames left until it repeats.
+#[derive(Default)]
+pub struct UiInput {
+    held: Option<(UiKey, UiAction, u32)>,
+}
+
+impl UiInput {
+    pub fn new() -> UiInput {
+        UiInput { held: None }
+    }
+
+    // Action for a key going down. Frontends should skip OS key repeats;
+    // repeating is done by `frame`.
+    pub fn key_down(&mut self, key: UiKey) -> Option<UiAction> {
+        let action = key.action()?;
+        if is_direction(action) {
+            self.held = Some((key, action, REPEAT_DELAY));
+        }
+        Some(action)
+    }
+
+    pub fn key_up(&mut self, key: UiKey) {
+        if let Some((held, _, _)) = self.held {
+            if held == key {
+                self.held = None;
+            }
+        }
+    }
+
+    // Whether `frame` has nothing to repeat, so frames can be skipped.
+    pub fn idle(&self) -> bool {
+        self.held.is_none()
+    }
+
+    // Call once per frame; returns the repeated direction when one is due.
+    pub fn frame(&mut self) -> Option<UiAction> {
+        let (key, action, frames) = self.held?;
+        if frames > 1 {
+            self.held = Some((key, action, frames - 1));
+            None
+        } else {
+            self.held = Some((key, action, REPEAT_INTERVAL));
+            Some(action)
+        }
+    }
+}
+
+// A screen navigated through a `UiInput`. Implementors hand over their input
+// and handle repeated directions; releasing keys, idling and repeating are
+// shared.
+pub trait UiScreen {
+    fn input(&self) -> &UiInput;
+
+    fn input_mut(&mut self) -> &mut UiInput;
+
+    // Handles a direction repeated by a held key.
+    fn repeat(&mut self, action: UiAction);
+
+    fn key_up(&mut self, key: UiKey) {
+        self.input_mut().key_up(key);
+    }
+
+    // Nothing changes until the next key while idle.
+    fn idle(&self) -> bool {
+        self.input().idle()
+    }
+
+    // Call once per frame, for held keys to repeat.
+    fn frame(&mut self) {
+        if let Some(action) = self.input_mut().frame() {
+            self.repeat(action);
+        }
+    }
+}
+
+fn is_direction(action: UiAction) -> bool {
+    matches!(action, UiAction::Up | UiAction::Down | UiAction::Left | UiAction::Right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UiAction, UiInput, UiKey, REPEAT_DELAY, REPEAT_INTERVAL};
+
+    #[test]
+    fn keypad_and_arrows_map_to_the_same_actions() {
+        assert_eq!(UiKey::Up.action(), UiKey::Hex(0x2).action());
+        assert_eq!(UiKey::Hex(0x5).action(), Some(UiAction::Confirm));
+        assert_eq!(UiKey::Escape.action(), Some(UiAction::Back));
+        assert_eq!(UiKey::Hex(0xA).action(), None);
+    }
+
+    #[test]
+    fn held_directions_repeat() {
+        let mut input = UiInput::new();
+        assert_eq!(input.key_down(UiKey::Down), Some(UiAction::Down));
+
+        let repeats = (0..REPEAT_DELAY + REPEAT_INTERVAL).filter_map(|_| input.frame()).count();
+        assert_eq!(repeats, 2);
+
+        input.key_up(UiKey::Down);
+        assert_eq!(input.frame(), None);
+
+        input.key_down(UiKey::Enter);
+        assert!((0..REPEAT_DELAY * 2).all(|_| input.frame().is_none()), "confirm never repeats");
+    }
+}
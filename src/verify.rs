@@ -0,0 +1,177 @@
+use crate::cheats::Cheats;
+use crate::cpu::{Cpu, PROGRAM_START};
+use crate::emulator::Emulator;
+use crate::headless::HeadlessDisplay;
+use crate::movie::Movie;
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptHooks;
+use std::fmt;
+use std::panic;
+use std::thread;
+
+// `hachip verify <rom> <movie>`: the movie replayed twice, on two threads
+// with a cpu each, comparing the state hashes after every frame. The
+// replays are the same by construction, so any difference is the emulator
+// depending on something outside its state (the clock, uninitialised
+// memory, iteration order) and is reported at the first frame it shows.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Verify {
+    pub rom: Vec<u8>,
+    pub movie: Movie,
+    pub load_address: u16,
+    pub cheats: Cheats,
+    // The source of the --hooks script. Each replay compiles its own, so
+    // the two don't share the script's state.
+    #[cfg(feature = "scripting")]
+    pub hooks: Option<String>,
+}
+
+// One replay: the state hash after every frame, and why it stopped early.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay {
+    pub hashes: Vec<u64>,
+    pub stopped: Option<String>,
+}
+
+// Where the replays went apart. A hash is None for a replay that had
+// stopped by then.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub frame: usize,
+    pub hashes: [Option<u64>; 2],
+    pub stopped: [Option<String>; 2],
+}
+
+impl Verify {
+    pub fn new(rom: Vec<u8>, movie: Movie) -> Verify {
+        Verify {
+            rom,
+            movie,
+            load_address: PROGRAM_START as u16,
+            cheats: Cheats::default(),
+            #[cfg(feature = "scripting")]
+            hooks: None,
+        }
+    }
+
+    // Both replays, then where they went apart, if they did. A replay that
+    // panics panics here too, rather than passing for one that matched.
+    pub fn run(&self) -> ([Replay; 2], Option<Divergence>) {
+        let [first, second] = thread::scope(|scope| {
+            let replays = [scope.spawn(|| self.replay()), scope.spawn(|| self.replay())];
+            replays.map(|replay| replay.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+        });
+        let divergence = compare(&first, &second);
+        ([first, second], divergence)
+    }
+
+    // The movie on a fresh cpu, as a headless --playback runs it: through
+    // an Emulator, with the cheats and script hooks.
+    pub fn replay(&self) -> Replay {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = self.movie.profile.quirks;
+        cpu.load_address = self.load_address;
+        cpu.reset();
+        let mut replay = Replay::default();
+        if let Err(error) = cpu.load(self.rom.clone()) {
+            replay.stopped = Some(error.to_string());
+            return replay;
+        }
+        cpu.set_rng(self.movie.random_source());
+        let mut emulator = Emulator::from_cpu(cpu);
+        emulator.instructions_per_frame = self.movie.instructions_per_frame;
+        emulator.cheats = self.cheats.clone();
+        #[cfg(feature = "scripting")]
+        if let Some(source) = &self.hooks {
+            match ScriptHooks::compile(source) {
+                Ok(hooks) => emulator.hooks = Some(hooks),
+                Err(error) => {
+                    replay.stopped = Some(error.to_string());
+                    return replay;
+                }
+            }
+        }
+        for frame in 0..self.movie.len() {
+            self.movie.play(frame, &mut emulator.cpu_mut().keypad);
+            if let Err(error) = emulator.step_frame() {
+                replay.stopped = Some(error.to_string());
+                break;
+            }
+            replay.hashes.push(emulator.cpu().state_hash());
+        }
+        replay
+    }
+}
+
+// The first frame the replays differ in: its hashes, or one stopping where
+// the other didn't or for another reason.
+pub fn compare(first: &Replay, second: &Replay) -> Option<Divergence> {
+    let frames = first.hashes.len().max(second.hashes.len());
+    let frame = (0..frames)
+        .find(|frame| first.hashes.get(*frame) != second.hashes.get(*frame))
+        .or_else(|| (first.stopped != second.stopped).then_some(frames))?;
+    let stopped_at = |replay: &Replay| replay.stopped.clone().filter(|_| frame >= replay.hashes.len());
+    Some(Divergence {
+        frame,
+        hashes: [first.hashes.get(frame).copied(), second.hashes.get(frame).copied()],
+        stopped: [stopped_at(first), stopped_at(second)],
+    })
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the replays diverge in frame {}:", self.frame)?;
+        for (index, (hash, stopped)) in self.hashes.iter().zip(&self.stopped).enumerate() {
+            match (hash, stopped) {
+                (Some(hash), _) => write!(f, "\n  replay {}: state {:016x}", index + 1, hash)?,
+                (None, Some(stopped)) => write!(f, "\n  replay {}: stopped, {}", index + 1, stopped)?,
+                (None, None) => write!(f, "\n  replay {}: ended", index + 1)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, Replay, Verify};
+    use crate::cheats::Cheats;
+    use crate::keypad::Keypad;
+    use crate::movie::Movie;
+    use crate::profile::EmulationProfile;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn replays_agree_and_divergences_are_found() {
+        // RND V0, 0xFF / SKNP V1 / ADD V2, 1 / JP 0x200
+        let rom = vec![0xC0, 0xFF, 0xE1, 0xA1, 0x72, 0x01, 0x12, 0x00];
+        let mut movie = Movie::new(&rom, EmulationProfile::new(Quirks::default()), 7, 10);
+        let mut keypad = Keypad::new();
+        for frame in 0..30 {
+            keypad.set_key(0, frame >= 10);
+            movie.record(&keypad);
+        }
+        let mut verify = Verify::new(rom, movie);
+        let ([first, second], divergence) = verify.run();
+        assert_eq!(divergence, None);
+        assert_eq!(first.hashes.len(), 30);
+        assert_eq!(first, second);
+        // the replays run the cheats, as --playback does
+        verify.cheats = Cheats::parse("300=42").unwrap();
+        let ([cheated, _], divergence) = verify.run();
+        assert_eq!(divergence, None);
+        assert_ne!(cheated.hashes, first.hashes);
+
+        let mut changed = first.clone();
+        changed.hashes[12] ^= 1;
+        assert_eq!(compare(&first, &changed).unwrap().frame, 12);
+        let mut stopped = first.clone();
+        stopped.hashes.truncate(20);
+        stopped.stopped = Some("stack overflow".to_string());
+        let divergence = compare(&first, &stopped).unwrap();
+        assert_eq!(divergence.frame, 20);
+        assert!(divergence.to_string().ends_with("replay 2: stopped, stack overflow"), "{}", divergence);
+        assert_eq!(compare(&Replay::default(), &Replay::default()), None);
+    }
+}
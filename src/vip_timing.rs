@@ -0,0 +1,71 @@
+// Instruction timing of the COSMAC VIP interpreter, for the vip_timing
+// quirk: each instruction takes the machine cycles its routine took on the
+// VIP's 1802, and a frame runs as many as fit in the time between two
+// vertical blanks, so ROMs from the era run at their original speed. The
+// counts include fetching and decoding, and are the typical case where the
+// routine's time depends on the data.
+
+// 1.7609 MHz / 8 clocks per machine cycle / 60 Hz, less what the display's
+// DMA (128 lines of 8 bytes) and the interrupt routine take every frame.
+pub const FRAME_CYCLES: i32 = 3668 - 1024 - 30;
+// Instructions per frame when timing by cycles and no speed was given: more
+// than ever fit, so only the cycles count.
+pub const MAX_INSTRUCTIONS_PER_FRAME: u32 = 200;
+
+// fetching and decoding, and the jump to the routine
+const FETCH: u32 = 40;
+
+// Machine cycles `opcode` took, given whether it skipped the next
+// instruction and Vx after it ran.
+pub fn cycles(opcode: u16, skipped: bool, vx: u8) -> u32 {
+    let x = (opcode >> 8 & 0xF) as u32;
+    let n = (opcode & 0xF) as u32;
+    let skip = if skipped { 4 } else { 0 };
+    FETCH
+        + match opcode & 0xF000 {
+            // clearing goes over the whole display buffer
+            0x0000 if opcode == 0x00E0 => 3078,
+            0x0000 if opcode == 0x00EE => 10,
+            // a machine code routine, which we don't run
+            0x0000 => 0,
+            0x1000 => 12,
+            0x2000 => 26,
+            0x3000 | 0x4000 => 10 + skip,
+            0x5000 | 0x9000 => 14 + skip,
+            0x6000 => 6,
+            0x7000 => 10,
+            0x8000 if n == 0 => 12,
+            0x8000 => 44,
+            0xA000 => 12,
+            0xB000 => 22,
+            0xC000 => 36,
+            // per row: shifting the byte into place and XORing two bytes
+            0xD000 => 26 + n.max(1) * 68,
+            0xE000 => 14 + skip,
+            0xF000 => match opcode & 0xFF {
+                0x1E => 16,
+                0x29 => 16,
+                // counts down each digit
+                0x33 => 80 + 16 * (vx as u32 / 100 + vx as u32 / 10 % 10 + vx as u32 % 10),
+                0x55 | 0x65 => 14 + 14 * (x + 1),
+                _ => 10,
+            },
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cycles, FRAME_CYCLES};
+
+    #[test]
+    fn costs_depend_on_the_work_done() {
+        assert_eq!(cycles(0x6A05, false, 5), 46);
+        assert_eq!(cycles(0x3A05, true, 5), cycles(0x3A05, false, 5) + 4, "a skip costs more");
+        assert!(cycles(0xF533, false, 199) > cycles(0xF533, false, 100));
+        assert!(cycles(0xF755, false, 0) > cycles(0xF055, false, 0));
+        assert!(cycles(0x00E0, false, 0) > FRAME_CYCLES as u32 / 2, "clearing takes most of a frame");
+        // simple instructions run about 50 to the frame
+        assert_eq!(FRAME_CYCLES as u32 / cycles(0x7001, false, 1), 52);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::cpu::Cpu;
+use crate::palette::Palette;
+use crate::ppu::Color;
+use crate::text::{Overlay, ADVANCE, LINE_HEIGHT};
+
+// Room for the cpu state: the timers line, four register rows, up to 16
+// stack levels and the next instruction, at most 40 characters wide.
+const COLUMNS: usize = 40;
+const ROWS: usize = 22;
+// Pixels of margin left and above the text.
+const MARGIN: usize = 1;
+
+pub const PANEL_WIDTH: usize = MARGIN + COLUMNS * ADVANCE;
+pub const PANEL_HEIGHT: usize = MARGIN + ROWS * LINE_HEIGHT;
+// How much the frontends' watch windows enlarge the panel.
+pub const PANEL_SCALE: u32 = 3;
+
+// The registers, timers and call stack drawn with the built-in font, for the
+// `--debug` watch window that frontends keep next to the game.
+pub struct WatchPanel {
+    overlay: Overlay,
+    // the state last drawn, to skip redrawing an unchanged panel
+    shown: String,
+}
+
+impl Default for WatchPanel {
+    fn default() -> WatchPanel {
+        WatchPanel::new()
+    }
+}
+
+impl WatchPanel {
+    pub fn new() -> WatchPanel {
+        WatchPanel {
+            overlay: Overlay::new(PANEL_WIDTH, PANEL_HEIGHT),
+            shown: String::new(),
+        }
+    }
+
+    // Redraws the panel for the cpu as it is now, returning whether anything
+    // changed since the last update.
+    pub fn update(&mut self, cpu: &Cpu) -> bool {
+        let state = cpu.to_string();
+        if state == self.shown {
+            return false;
+        }
+        self.overlay.clear();
+        self.overlay.text().draw_text(MARGIN as i32, MARGIN as i32, &state);
+        self.shown = state;
+        true
+    }
+
+    pub fn overlay(&self) -> &Overlay {
+        &self.overlay
+    }
+
+    // Pixel colors row by row, text in the foreground color.
+    pub fn colors<'a>(&'a self, palette: &'a Palette) -> impl Iterator<Item = Color> + 'a {
+        self.overlay.pixels().iter().map(move |pixel| {
+            if *pixel == 1 {
+                palette.foreground()
+            } else {
+                palette.background()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchPanel, PANEL_HEIGHT, PANEL_WIDTH};
+    use crate::cpu::Cpu;
+    use crate::headless::HeadlessDisplay;
+    use crate::text::Overlay;
+
+    #[test]
+    fn redraws_only_when_the_state_changes() {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.reset();
+        // CALL 0x204 / JP 0x200 / LD V3, 0x42 / JP 0x206
+        cpu.load(vec![0x22, 0x04, 0x12, 0x00, 0x63, 0x42, 0x12, 0x06]).unwrap();
+        let mut panel = WatchPanel::new();
+        assert!(panel.update(&cpu));
+        assert!(!panel.update(&cpu), "nothing ran");
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(panel.update(&cpu));
+        assert_eq!(panel.overlay().width(), PANEL_WIDTH);
+
+        // every line of the state fits in the panel
+        let text = cpu.to_string();
+        assert!(text.contains("stack > 0 0200"), "{}", text);
+        assert!(text.lines().count() <= 22);
+        assert!(text.lines().all(|line| line.len() <= 40), "{}", text);
+
+        let mut expected = Overlay::new(PANEL_WIDTH, PANEL_HEIGHT);
+        expected.text().draw_text(1, 1, &text);
+        assert!(panel.overlay().pixels() == expected.pixels());
+    }
+}
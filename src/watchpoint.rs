@@ -0,0 +1,182 @@
+use crate::quirks::Quirks;
+use std::fmt;
+
+// A data read or write made by an instruction, logged by the cpu while
+// `Cpu::log_memory` is on. Instruction fetches are not included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub write: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // either
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchTarget {
+    Memory(u16),
+    // V0 to VF
+    Register(u8),
+}
+
+// Where the debugger stops when an instruction touches a memory address or
+// a register: "write 0x300", "read V5", or "reg V5" and a bare target for
+// either.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn parse(text: &str) -> Option<Watchpoint> {
+        let mut words = text.split_whitespace();
+        let first = words.next()?;
+        let (kind, target) = match first {
+            "read" => (WatchKind::Read, words.next()?),
+            "write" => (WatchKind::Write, words.next()?),
+            "reg" | "access" => (WatchKind::Access, words.next()?),
+            _ => (WatchKind::Access, first),
+        };
+        if words.next().is_some() {
+            return None;
+        }
+        let target = match target.strip_prefix('V').or_else(|| target.strip_prefix('v')) {
+            Some(register) if register.len() == 1 => WatchTarget::Register(u8::from_str_radix(register, 16).ok()?),
+            _ if first == "reg" => return None,
+            _ => match u16::from_str_radix(target.trim_start_matches("0x"), 16) {
+                Ok(address) if (address as usize) < crate::cpu::MEMORY_SIZE => WatchTarget::Memory(address),
+                _ => return None,
+            },
+        };
+        Some(Watchpoint { target, kind })
+    }
+
+    fn covers(&self, write: bool) -> bool {
+        match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+
+    // Whether one instruction's accesses hit this watchpoint, returning
+    // whether the hit was a write.
+    pub fn hit(&self, memory: &[MemoryAccess], registers: RegisterUse) -> Option<bool> {
+        let hits: Vec<bool> = match self.target {
+            WatchTarget::Memory(address) => memory
+                .iter()
+                .filter(|access| access.address == address)
+                .map(|access| access.write)
+                .collect(),
+            WatchTarget::Register(register) => {
+                let bit = 1 << register;
+                [(registers.read & bit != 0, false), (registers.written & bit != 0, true)]
+                    .iter()
+                    .filter(|(used, _)| *used)
+                    .map(|(_, write)| *write)
+                    .collect()
+            }
+        };
+        // a write is reported over a read of the same instruction
+        hits.iter().rev().copied().find(|write| self.covers(*write))
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        };
+        match self.target {
+            WatchTarget::Memory(address) => write!(f, "{} {:04X}", kind, address),
+            WatchTarget::Register(register) => write!(f, "{} V{:X}", kind, register),
+        }
+    }
+}
+
+// The V registers an instruction reads and writes, one bit per register.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegisterUse {
+    pub read: u16,
+    pub written: u16,
+}
+
+impl RegisterUse {
+    // Decoded from the opcode alone: every CHIP-8 instruction names the
+    // registers it uses. Fx0A counts as writing Vx even while it waits; the
+    // debugger ignores writes of an instruction that left PC unchanged.
+    pub fn of(opcode: u16, quirks: &Quirks) -> RegisterUse {
+        let x = 1u16 << ((opcode >> 8) & 0xF);
+        let y = 1u16 << ((opcode >> 4) & 0xF);
+        let vf = 1u16 << 0xF;
+        // V0 through Vx
+        let up_to_x = (x << 1).wrapping_sub(1);
+        let (read, written) = match opcode & 0xF000 {
+            0x3000 | 0x4000 => (x, 0),
+            0x5000 | 0x9000 => (x | y, 0),
+            0x6000 | 0xC000 => (0, x),
+            0x7000 => (x, x),
+            0x8000 => match opcode & 0xF {
+                0x0 => (y, x),
+                0x1..=0x3 if quirks.vf_reset => (x | y, x | vf),
+                0x1..=0x3 => (x | y, x),
+                0x4 | 0x5 | 0x7 => (x | y, x | vf),
+                0x6 | 0xE if quirks.shift_vy => (y, x | vf),
+                0x6 | 0xE => (x, x | vf),
+                _ => (0, 0),
+            },
+            0xB000 if quirks.jump_vx => (x, 0),
+            0xB000 => (1, 0),
+            0xD000 => (x | y, vf),
+            0xE000 => (x, 0),
+            0xF000 => match opcode & 0xFF {
+                0x07 | 0x0A => (0, x),
+                0x15 | 0x18 | 0x1E | 0x29 | 0x33 => (x, 0),
+                0x55 => (up_to_x, 0),
+                0x65 => (0, up_to_x),
+                _ => (0, 0),
+            },
+            _ => (0, 0),
+        };
+        RegisterUse { read, written }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryAccess, RegisterUse, WatchKind, WatchTarget, Watchpoint};
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn parses_and_matches_accesses() {
+        let write = Watchpoint::parse("write 0x300").unwrap();
+        assert_eq!(write.target, WatchTarget::Memory(0x300));
+        assert_eq!(write.to_string(), "write 0300");
+        let register = Watchpoint::parse("reg V5").unwrap();
+        assert_eq!((register.target, register.kind), (WatchTarget::Register(5), WatchKind::Access));
+        assert_eq!(Watchpoint::parse("read vf").unwrap().target, WatchTarget::Register(0xF));
+        assert_eq!(Watchpoint::parse("reg 300"), None);
+        assert_eq!(Watchpoint::parse("write 0x1000"), None);
+
+        let stored = [MemoryAccess { address: 0x300, write: true }];
+        let loaded = [MemoryAccess { address: 0x300, write: false }];
+        let none = RegisterUse::default();
+        assert_eq!(write.hit(&stored, none), Some(true));
+        assert_eq!(write.hit(&loaded, none), None);
+
+        let quirks = Quirks::default();
+        // LD [I], V5 reads V0-V5; ADD V5, V1 reads and writes V5 and VF
+        assert_eq!(register.hit(&[], RegisterUse::of(0xF555, &quirks)), Some(false));
+        assert_eq!(register.hit(&[], RegisterUse::of(0x8514, &quirks)), Some(true));
+        assert_eq!(register.hit(&[], RegisterUse::of(0xF455, &quirks)), None);
+        assert_eq!(RegisterUse::of(0xFF65, &quirks).written, 0xFFFF);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::emulator::Emulator;
+use crate::palette::Palette;
+use crate::ppu::{self, Color, PixelGrid, Ppu, Rect, DEFAULT_SCALE, MAX_SCALE, MIN_SCALE};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+// Pixel grid drawing into an HTML canvas. The browser presents the canvas on
+// its own, so `present` has nothing to do.
+struct CanvasGrid {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+}
+impl PixelGrid for CanvasGrid {
+    fn set_draw_color(&mut self, color: Color) {
+        self.context.set_fill_style_str(&format!("rgb({}, {}, {})", color.r, color.g, color.b));
+    }
+    fn clear(&mut self) {
+        let (width, height) = (self.canvas.width() as f64, self.canvas.height() as f64);
+        self.context.fill_rect(0.0, 0.0, width, height);
+    }
+    fn present(&mut self) {}
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+        self.context.fill_rect(rect.x as f64, rect.y as f64, rect.w as f64, rect.h as f64);
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+pub struct WebEmulator {
+    emulator: Emulator,
+    grid: CanvasGrid,
+    palette: Palette,
+    scale: u32,
+}
+
+#[wasm_bindgen]
+impl WebEmulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas: HtmlCanvasElement) -> Result<WebEmulator, JsValue> {
+        let (width, height) = ppu::frame_size(DEFAULT_SCALE);
+        canvas.set_width(width);
+        canvas.set_height(height);
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("canvas has no 2d context"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+
+        Ok(WebEmulator {
+            emulator: Emulator::new(Box::new(Ppu::new())),
+            grid: CanvasGrid { canvas, context },
+            palette: Palette::default(),
+            scale: DEFAULT_SCALE,
+        })
+    }
+
+    // Resizes the canvas to show the display `scale` times; false outside
+    // MIN_SCALE to MAX_SCALE.
+    pub fn set_scale(&mut self, scale: u32) -> bool {
+        if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+            return false;
+        }
+        let (width, height) = ppu::frame_size(scale);
+        self.grid.canvas.set_width(width);
+        self.grid.canvas.set_height(height);
+        self.scale = scale;
+        self.emulator.cpu_mut().display_mut().invalidate();
+        true
+    }
+
+    // Throws when the ROM doesn't fit in memory.
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.emulator.load_rom(data).map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        self.emulator.key_event(key, true);
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.emulator.key_event(key, false);
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.emulator.instructions_per_frame = instructions;
+    }
+
+    // Switches to a built-in palette; false if there is none by that name.
+    pub fn set_palette(&mut self, name: &str) -> bool {
+        match Palette::builtin(name) {
+            Some(palette) => {
+                self.palette = palette;
+                self.emulator.cpu_mut().display_mut().invalidate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Runs one 60Hz frame and redraws the canvas; meant to be called from
+    // requestAnimationFrame.
+    pub fn run_frame(&mut self) -> Result<(), JsValue> {
+        self.emulator.step_frame().map_err(|error| JsValue::from_str(&error.message))?;
+        ppu::render(self.emulator.cpu_mut().display_mut(), &mut self.grid, &self.palette, self.scale);
+        Ok(())
+    }
+}
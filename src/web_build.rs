@@ -0,0 +1,199 @@
+use crate::keymap::{KeyboardLayout, KeymapProfile};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Where `hachip web build` writes when no `--out` is given.
+pub const DEFAULT_OUT_DIR: &str = "web-build";
+// The bundled ROM's name inside the folder.
+const ROM_FILE: &str = "rom.ch8";
+
+// `hachip web build`: a folder to publish as-is, with the core compiled to
+// wasm by wasm-pack (pkg/), and an index.html that runs it on a canvas with
+// the standard keymap, loads ROMs dropped on the page and starts the
+// bundled ROM, if any.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct WebBuild {
+    pub out_dir: PathBuf,
+    pub rom: Option<PathBuf>,
+    pub title: String,
+}
+
+impl WebBuild {
+    pub fn new() -> WebBuild {
+        WebBuild {
+            out_dir: PathBuf::from(DEFAULT_OUT_DIR),
+            rom: None,
+            title: "hachip".to_string(),
+        }
+    }
+
+    // Needs wasm-pack and the wasm32-unknown-unknown target, and builds the
+    // sources this binary was built from.
+    pub fn build(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.out_dir)?;
+        let out_dir = self.out_dir.canonicalize()?;
+        let status = Command::new("wasm-pack")
+            .arg("build")
+            .arg(env!("CARGO_MANIFEST_DIR"))
+            .args(["--release", "--target", "web", "--out-name", "hachip", "--out-dir"])
+            .arg(out_dir.join("pkg"))
+            .args(["--", "--no-default-features", "--features", "web"])
+            .status()
+            .map_err(|error| io::Error::new(error.kind(), format!("can't run wasm-pack: {}", error)))?;
+        if !status.success() {
+            return Err(io::Error::other(format!("wasm-pack failed ({})", status)));
+        }
+        // wasm-pack ignores everything it wrote, which would keep the build
+        // out of a gh-pages commit
+        let _ = fs::remove_file(out_dir.join("pkg").join(".gitignore"));
+        self.write_page(&out_dir)
+    }
+
+    // index.html and the bundled ROM.
+    fn write_page(&self, out_dir: &Path) -> io::Result<()> {
+        if let Some(rom) = &self.rom {
+            fs::copy(rom, out_dir.join(ROM_FILE))?;
+        }
+        fs::write(out_dir.join("index.html"), index_html(&self.title, self.rom.is_some()))
+    }
+}
+
+impl Default for WebBuild {
+    fn default() -> WebBuild {
+        WebBuild::new()
+    }
+}
+
+pub fn index_html(title: &str, bundled_rom: bool) -> String {
+    let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let start = if bundled_rom {
+        format!("load(await (await fetch(\"{}\")).arrayBuffer());", ROM_FILE)
+    } else {
+        String::new()
+    };
+    PAGE.replace("{title}", &title)
+        .replace("{keys}", &KeymapProfile::Standard.to_js(KeyboardLayout::Qwerty))
+        .replace("{start}", &start)
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+  body { margin: 0; background: #111; color: #ccc; font-family: sans-serif; text-align: center; }
+  canvas { width: 100%; max-width: 960px; image-rendering: pixelated; margin-top: 2em; }
+  body.dragging canvas { outline: 2px dashed #ccc; }
+  #pad { display: none; grid-template-columns: repeat(4, 1fr); gap: 6px; max-width: 360px; margin: 1em auto; touch-action: none; }
+  #pad button { font-size: 1.5em; padding: 0.6em 0; background: #222; color: #ccc; border: 1px solid #555; user-select: none; }
+  #pad button.held { background: #ccc; color: #111; }
+  @media (pointer: coarse) { #pad { display: grid; } }
+</style>
+</head>
+<body>
+<canvas></canvas>
+<div id="pad"></div>
+<p>Keys 1-4, Q-R, A-F and Z-V play the hexpad. Drop a ROM on the page to play it.</p>
+<script type="module">
+import init, { WebEmulator } from "./pkg/hachip.js";
+
+await init();
+const emulator = new WebEmulator(document.querySelector("canvas"));
+let running = false;
+const load = (data) => {
+  try {
+    emulator.load_rom(new Uint8Array(data));
+    running = true;
+  } catch (error) {
+    alert(error);
+  }
+};
+
+const keys = {keys};
+const key = (event) => keys[event.code];
+document.addEventListener("keydown", (event) => {
+  if (key(event) !== undefined) { emulator.key_down(key(event)); event.preventDefault(); }
+});
+document.addEventListener("keyup", (event) => {
+  if (key(event) !== undefined) { emulator.key_up(key(event)); event.preventDefault(); }
+});
+
+// the on-screen hexpad, shown on touch screens; a finger holds the key under
+// it, sliding from key to key
+const pad = document.getElementById("pad");
+for (const hex of [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF]) {
+  const button = document.createElement("button");
+  button.textContent = hex.toString(16).toUpperCase();
+  const press = (event) => {
+    button.releasePointerCapture(event.pointerId);
+    if (!button.className) { button.className = "held"; emulator.key_down(hex); }
+  };
+  const release = () => {
+    if (button.className) { button.className = ""; emulator.key_up(hex); }
+  };
+  button.addEventListener("pointerdown", press);
+  button.addEventListener("pointerenter", (event) => { if (event.buttons) { press(event); } });
+  for (const type of ["pointerup", "pointerleave", "pointercancel"]) { button.addEventListener(type, release); }
+  pad.append(button);
+}
+
+document.addEventListener("dragover", (event) => { event.preventDefault(); document.body.className = "dragging"; });
+document.addEventListener("dragleave", () => { document.body.className = ""; });
+document.addEventListener("drop", async (event) => {
+  event.preventDefault();
+  document.body.className = "";
+  const file = event.dataTransfer.files[0];
+  if (file) { load(await file.arrayBuffer()); }
+});
+
+const frame = () => {
+  if (running) {
+    try {
+      emulator.run_frame();
+    } catch (error) {
+      running = false;
+      alert(error);
+    }
+  }
+  requestAnimationFrame(frame);
+};
+{start}
+requestAnimationFrame(frame);
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::{index_html, WebBuild};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn writes_a_page_with_the_keymap_and_rom() {
+        let page = index_html("Pong <2>", true);
+        assert!(page.contains("<title>Pong &lt;2&gt;</title>"));
+        assert!(page.contains("\"KeyQ\": 4"));
+        assert!(page.contains("\"KeyV\": 15"));
+        assert!(page.contains("fetch(\"rom.ch8\")"));
+        assert!(!index_html("hachip", false).contains("fetch("));
+
+        let dir = env::temp_dir().join(format!("hachip-web-build-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        fs::write(&rom, [0x12, 0x00]).unwrap();
+        let build = WebBuild {
+            rom: Some(rom),
+            ..WebBuild::new()
+        };
+        build.write_page(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("rom.ch8")).unwrap(), [0x12, 0x00]);
+        assert!(fs::read_to_string(dir.join("index.html")).unwrap().contains("WebEmulator"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use crate::assets;
+use crate::config::Config;
+use crate::keymap::KeymapProfile;
+use crate::launcher;
+use crate::palette::Palette;
+use crate::ppu::{Display, Scaling, HEIGHT, WIDTH};
+use crate::text::{self, Overlay, LINE_HEIGHT};
+use crate::ui_input::{UiAction, UiInput, UiKey, UiScreen};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Rows under the title line.
+const ROWS: usize = HEIGHT / LINE_HEIGHT - 1;
+// Characters that fit next to the selection marker.
+const NAME_CHARS: usize = WIDTH / text::ADVANCE - 1;
+
+// The pages, in order.
+const KEYS: usize = 0;
+const COLORS: usize = 1;
+const ROMS: usize = 2;
+const SCALE: usize = 3;
+const TITLES: [&str; 4] = ["KEYS", "COLORS", "ROMS", "SCALE"];
+
+// How the setup ended.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Outcome {
+    // the settings to save
+    Done(Config),
+    Quit,
+}
+
+// The setup shown on the first start without a config file: a page each
+// for the keymap, the palette, the ROM directory and the window scaling,
+// drawn on the emulated display. Up and down pick, Enter or right goes to
+// the next page and Escape or left back; Escape on the first page skips
+// the rest, keeping the defaults.
+pub struct Wizard {
+    page: usize,
+    // the picked row of every page
+    selected: [usize; 4],
+    palettes: Vec<&'static str>,
+    rom_dirs: Vec<PathBuf>,
+    input: UiInput,
+}
+
+impl Wizard {
+    // `rom_dir` is offered first for ROMs, then its subdirectories that
+    // hold .ch8 files.
+    pub fn new(rom_dir: &Path) -> Wizard {
+        Wizard {
+            page: KEYS,
+            selected: [0; 4],
+            palettes: assets::PALETTES.iter().map(|(name, _)| *name).collect(),
+            rom_dirs: rom_dirs(rom_dir),
+            input: UiInput::new(),
+        }
+    }
+
+    pub fn rom_dirs(&self) -> &[PathBuf] {
+        &self.rom_dirs
+    }
+
+    pub fn key_down(&mut self, key: UiKey) -> Option<Outcome> {
+        let action = self.input.key_down(key)?;
+        self.act(action)
+    }
+
+    fn act(&mut self, action: UiAction) -> Option<Outcome> {
+        let last = self.labels(self.page).len().saturating_sub(1);
+        let selected = &mut self.selected[self.page];
+        match action {
+            UiAction::Up => *selected = selected.saturating_sub(1),
+            UiAction::Down => *selected = (*selected + 1).min(last),
+            UiAction::Confirm | UiAction::Right if self.page == SCALE => return Some(Outcome::Done(self.config())),
+            UiAction::Confirm | UiAction::Right => self.page += 1,
+            UiAction::Back if self.page == KEYS => return Some(Outcome::Done(Config::default())),
+            UiAction::Back | UiAction::Left => self.page = self.page.saturating_sub(1),
+        }
+        None
+    }
+
+    // The palette and scaling picked so far, for the frontend to draw the
+    // setup with.
+    pub fn palette(&self) -> Palette {
+        Palette::builtin(self.palettes[self.selected[COLORS]]).unwrap_or_default()
+    }
+
+    pub fn scaling(&self) -> Scaling {
+        Scaling::ALL[self.selected[SCALE]]
+    }
+
+    // The settings picked so far.
+    pub fn config(&self) -> Config {
+        Config {
+            keymap: Some(KeymapProfile::ALL[self.selected[KEYS]]),
+            palette: Some(self.palettes[self.selected[COLORS]].to_string()),
+            roms: self.rom_dirs.get(self.selected[ROMS]).cloned(),
+            scaling: Some(self.scaling()),
+            ..Config::default()
+        }
+    }
+
+    fn labels(&self, page: usize) -> Vec<String> {
+        match page {
+            KEYS => KeymapProfile::ALL.iter().map(|profile| profile.name().to_string()).collect(),
+            COLORS => self.palettes.iter().map(|name| name.to_string()).collect(),
+            ROMS => self
+                .rom_dirs
+                .iter()
+                .map(|dir| match dir.file_name() {
+                    Some(name) => name.to_string_lossy().to_string(),
+                    None => dir.display().to_string(),
+                })
+                .collect(),
+            _ => Scaling::ALL.iter().map(|scaling| scaling.name().to_string()).collect(),
+        }
+    }
+
+    // Draws the current page over the whole display.
+    pub fn render(&self, display: &mut dyn Display) {
+        let mut overlay = Overlay::default();
+        let mut text = overlay.text();
+        text.draw_text(0, 0, &format!("{} {}/{}", TITLES[self.page], self.page + 1, TITLES.len()));
+        let selected = self.selected[self.page];
+        let top = selected.saturating_sub(ROWS - 1);
+        for (row, label) in self.labels(self.page).iter().enumerate().skip(top).take(ROWS) {
+            let y = ((row - top + 1) * LINE_HEIGHT) as i32;
+            let marker = if row == selected { '>' } else { ' ' };
+            let label: String = label.chars().take(NAME_CHARS).collect();
+            text.draw_text(0, y, &format!("{}{}", marker, label));
+        }
+
+        display.cls();
+        for (index, pixel) in overlay.pixels().iter().enumerate() {
+            display.set_pixel(index % WIDTH, index / WIDTH, *pixel);
+        }
+    }
+}
+
+impl UiScreen for Wizard {
+    fn input(&self) -> &UiInput {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut UiInput {
+        &mut self.input
+    }
+
+    fn repeat(&mut self, action: UiAction) {
+        self.act(action);
+    }
+}
+
+// `dir` and its subdirectories with ROMs in them, sorted by name.
+fn rom_dirs(dir: &Path) -> Vec<PathBuf> {
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut dirs: Vec<PathBuf> = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_dir() && launcher::list_roms(path).is_ok_and(|roms| !roms.is_empty()))
+                .collect()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs.insert(0, dir);
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Wizard};
+    use crate::config::Config;
+    use crate::headless::{self, HeadlessDisplay};
+    use crate::keymap::KeymapProfile;
+    use crate::palette::Palette;
+    use crate::ppu::{Display, Scaling};
+    use crate::ui_input::{UiKey, UiScreen};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn picks_every_setting_and_skips_to_the_defaults() {
+        let dir = env::temp_dir().join(format!("hachip-wizard-{}", std::process::id()));
+        fs::create_dir_all(dir.join("games")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("games").join("pong.ch8"), [0x12, 0x00]).unwrap();
+
+        let mut wizard = Wizard::new(&dir);
+        let dir = fs::canonicalize(&dir).unwrap();
+        assert_eq!(wizard.rom_dirs(), [dir.clone(), dir.join("games")], "only directories with ROMs");
+        assert_eq!(wizard.key_down(UiKey::Escape), Some(Outcome::Done(Config::default())));
+
+        let mut wizard = Wizard::new(&dir);
+        for key in [UiKey::Down, UiKey::Enter, UiKey::Down, UiKey::Enter, UiKey::Down, UiKey::Enter].iter() {
+            assert_eq!(wizard.key_down(*key), None);
+            wizard.key_up(*key);
+        }
+        assert_eq!(wizard.palette(), Palette::builtin("amber").unwrap(), "the setup shows the picked colors");
+        let mut display = HeadlessDisplay::new();
+        wizard.render(&mut display);
+        assert!(headless::dump_text(display.framebuffer()).contains('#'));
+
+        let config = match wizard.key_down(UiKey::Hex(0x5)) {
+            Some(Outcome::Done(config)) => config,
+            outcome => panic!("{:?}", outcome),
+        };
+        assert_eq!(config.keymap, Some(KeymapProfile::TwoPlayer));
+        assert_eq!(config.palette.as_deref(), Some("amber"));
+        assert_eq!(config.roms, Some(dir.join("games")));
+        assert_eq!(config.scaling, Some(Scaling::Integer));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use hachip::cpu::Cpu;
+use hachip::headless::HeadlessDisplay;
+use hachip::quirks::Platform;
+use hachip::scheduler::run_frame;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations of each thread. A global allocator takes over the
+// whole binary, so it lives in a test binary of its own.
+struct CountingAllocator;
+
+thread_local!(static ALLOCATIONS: Cell<usize> = const { Cell::new(0) });
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Frames run in the memory the machine already has, so the core fits a
+// static budget on small targets; Mega-Chip's buffers come with its first
+// frame.
+#[test]
+fn running_frames_allocates_nothing() {
+    let roms: [(Platform, Vec<u8>); 2] = [
+        // RND V0, #FF / DRW V0, V0, 1 / LD DT, V0 / JP 0x200
+        (Platform::Schip, vec![0xC0, 0xFF, 0xD0, 0x01, 0xF0, 0x15, 0x12, 0x00]),
+        // MEGAON / SPRW 2 / SPRH 2, then RND V0, #FF / LD I, 0x300 /
+        // DRW V0, V0, 0 / LD I, 0 / DRW V0, V0, 1 from the font / CLS /
+        // JP 0x206
+        (
+            Platform::MegaChip,
+            vec![
+                0x00, 0x11, 0x03, 0x02, 0x04, 0x02, 0xC0, 0xFF, 0xA3, 0x00, 0xD0, 0x00, 0xA0, 0x00, 0xD0, 0x01, 0x00, 0xE0,
+                0x12, 0x06,
+            ],
+        ),
+    ];
+    for (platform, rom) in roms {
+        let mut cpu = Cpu::new(Box::new(HeadlessDisplay::new()));
+        cpu.quirks = platform.quirks();
+        cpu.reset();
+        cpu.load(rom).unwrap();
+        cpu.seed_rng(1);
+        run_frame(&mut cpu, 100).unwrap();
+        let before = ALLOCATIONS.with(Cell::get);
+        for _ in 0..60 {
+            run_frame(&mut cpu, 100).unwrap();
+        }
+        assert_eq!(ALLOCATIONS.with(Cell::get), before, "running {} frames allocated", platform.name());
+    }
+}
@@ -0,0 +1,14 @@
+use hachip::testing;
+use std::env;
+use std::path::Path;
+
+// Runs the test ROMs in tests/roms against their golden screens. Set
+// HACHIP_BLESS=1 to rewrite mismatching goldens and create missing ones.
+#[test]
+fn test_roms_match_their_goldens() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms");
+    let bless = env::var_os("HACHIP_BLESS").is_some();
+    let mismatches = testing::check_dir(&dir, bless).unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", report.join("\n\n"));
+}